@@ -0,0 +1,420 @@
+//! Operator routes under `/admin`: `GET /admin/backup` exports the whole
+//! store as one JSON document and `POST /admin/restore` puts such a document
+//! back. A restore is validated in full before anything is written, then
+//! its movies are applied as one batch, so the movies are either replaced
+//! entirely or left as they were. `GET /admin/limits` reports the request
+//! limits with what they turned away, and `PATCH /admin/limits` changes the
+//! soft ones while the server runs. With the `metadata` feature,
+//! `GET /admin/outbound` reports the requests sent to other services.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use axum::{extract::State, response::Json};
+use chrono::{DateTime, Utc};
+use movies::model::Person;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::errors::{ApiError, ErrorBody, FieldError};
+use crate::events::EventKind;
+use crate::extract::{JsonBody, QueryParams};
+use crate::limits;
+use crate::people::missing_in;
+use crate::poster;
+use crate::repo::Write;
+use crate::sync::LockExt;
+use crate::{
+    AppState, Config, INVALID_ID, Movie, is_valid_id, refresh_slug, validate_name,
+    validate_new_movie,
+};
+
+/// The only backup format so far; restores refuse any other.
+pub const BACKUP_VERSION: u32 = 1;
+
+pub fn routes(state: AppState) -> OpenApiRouter {
+    let reports = OpenApiRouter::new()
+        .routes(routes!(backup))
+        .routes(routes!(limits_report, adjust_limits))
+        .routes(routes!(poster::report));
+    #[cfg(feature = "metadata")]
+    let reports = reports.routes(routes!(crate::outbound::report));
+
+    OpenApiRouter::new()
+        .nest(
+            "/admin",
+            reports
+                .layer(limits::requests(&state.config))
+                // Backups are uploads, bound only by the limits of every
+                // request.
+                .routes(routes!(restore)),
+        )
+        .with_state(state)
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct Backup {
+    /// Format of the document, `1`.
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    /// Every stored movie, those in the trash included, by id.
+    pub movies: Vec<Movie>,
+    /// Every person the movies may refer to, by id. Missing from backups
+    /// taken before people were stored, which restore none.
+    #[serde(default)]
+    pub people: Vec<Person>,
+}
+
+#[derive(Deserialize, Debug, Default, IntoParams)]
+#[serde(default)]
+#[into_params(parameter_in = Query)]
+pub struct RestoreParams {
+    /// Upsert the movies and people into the store instead of replacing
+    /// it; stored ones missing from the document are kept.
+    merge: bool,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct RestoreReport {
+    /// Movies written from the document.
+    pub restored: usize,
+    /// Stored movies the document did not have, always 0 with `?merge=true`.
+    pub removed: usize,
+}
+
+impl Backup {
+    /// Every problem with the document, checked before anything is written.
+    /// Movies may refer to the document's people and, when merging, to the
+    /// `stored` ones.
+    fn validate(
+        &self,
+        config: &Config,
+        stored: &BTreeMap<String, Person>,
+        merge: bool,
+    ) -> Result<(), ApiError> {
+        if self.version != BACKUP_VERSION {
+            return Err(ApiError::validation(
+                "version",
+                format!(
+                    "unsupported backup version {}, expected {BACKUP_VERSION}",
+                    self.version
+                ),
+            ));
+        }
+
+        let mut errors = Vec::new();
+        let mut people = if merge {
+            stored.clone()
+        } else {
+            BTreeMap::new()
+        };
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        for (index, person) in self.people.iter().enumerate() {
+            let prefix = format!("people[{index}].");
+            if !is_valid_id(&person.id) {
+                errors.push(FieldError::new(format!("{prefix}id"), INVALID_ID));
+            }
+            errors.extend(validate_name(&person.name, format!("{prefix}name")));
+            if let Some(first) = seen.insert(&person.id, index) {
+                errors.push(FieldError::new(
+                    format!("{prefix}id"),
+                    format!("duplicate id {}, also at people[{first}]", person.id),
+                ));
+            }
+            people.insert(person.id.clone(), person.clone());
+        }
+
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        for (index, movie) in self.movies.iter().enumerate() {
+            let prefix = format!("movies[{index}].");
+            errors.extend(validate_new_movie(movie, config, &prefix));
+            errors.extend(missing_in(
+                &people,
+                movie.director_id.as_deref(),
+                &movie.cast,
+                &prefix,
+            ));
+            if let Some(first) = seen.insert(&movie.id, index) {
+                errors.push(FieldError::new(
+                    format!("{prefix}id"),
+                    format!("duplicate id {}, also at movies[{first}]", movie.id),
+                ));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ApiError::Validation(errors))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/backup",
+    tag = "admin",
+    summary = "Export the whole store",
+    responses(
+        (status = OK, description = "Every movie and person, ready for `POST /admin/restore`", body = Backup),
+    )
+)]
+async fn backup(State(state): State<AppState>) -> Result<Json<Backup>, ApiError> {
+    // People are read first, so a person created meanwhile is at worst
+    // missing along with the movies that name them.
+    let people = state.people.all().into_values().collect();
+    let mut movies = state.repo.list().await?;
+    movies.sort_by(|a, b| a.id.cmp(&b.id));
+
+    Ok(Json(Backup {
+        version: BACKUP_VERSION,
+        exported_at: Utc::now(),
+        movies,
+        people,
+    }))
+}
+
+/// Movies are stored exactly as the document has them, versions and
+/// timestamps included; only a missing slug is derived and a missing version
+/// starts at 1. Ratings and posters of movies the restore removes go with
+/// them. Every person a movie names must be in the document or, when
+/// merging, already stored.
+#[utoipa::path(
+    post,
+    path = "/restore",
+    tag = "admin",
+    summary = "Replace the store with a backup",
+    params(
+        RestoreParams,
+    ),
+    request_body = Backup,
+    responses(
+        (status = OK, description = "How many movies were written and removed", body = RestoreReport),
+        (status = UNPROCESSABLE_ENTITY, description = "An unknown version, duplicate ids, invalid movies or people, or movies naming missing people; nothing was written", body = ErrorBody),
+    )
+)]
+async fn restore(
+    QueryParams(params): QueryParams<RestoreParams>,
+    State(state): State<AppState>,
+    JsonBody(backup): JsonBody<Backup>,
+) -> Result<Json<RestoreReport>, ApiError> {
+    let _bulk = state.bulk.write().await;
+    let _changing = state.people.changing().await;
+    let stored_people = state.people.all();
+    backup.validate(&state.config, &stored_people, params.merge)?;
+
+    let previous: HashMap<String, Movie> = state
+        .repo
+        .list()
+        .await?
+        .into_iter()
+        .map(|movie| (movie.id.clone(), movie))
+        .collect();
+
+    let mut movies = backup.movies;
+    for movie in &mut movies {
+        movie.version = movie.version.max(1);
+    }
+    {
+        // A replaced store keeps none of the old slugs; the given ones are
+        // indexed first so derived ones steer clear of them.
+        let mut slugs = state.slugs.write_or_recover();
+        if !params.merge {
+            slugs.clear();
+        }
+        for movie in movies.iter().filter(|movie| !movie.slug.is_empty()) {
+            slugs.insert(movie.slug.clone(), movie.id.clone());
+        }
+        for movie in movies.iter_mut().filter(|movie| movie.slug.is_empty()) {
+            refresh_slug(&mut slugs, None, movie);
+        }
+    }
+
+    let mut writes = Vec::with_capacity(movies.len() + 1);
+    if !params.merge {
+        writes.push(Write::Clear);
+    }
+    writes.extend(movies.iter().cloned().map(Write::Upsert));
+
+    // People go in before the movies naming them and out after the movies
+    // no longer do, so a failure halfway leaves no movie naming no one.
+    let mut people = if params.merge {
+        stored_people.clone()
+    } else {
+        BTreeMap::new()
+    };
+    for person in backup.people {
+        state.repo.put_person(&person).await?;
+        people.insert(person.id.clone(), person);
+    }
+    state.repo.apply(writes).await?;
+    for id in stored_people.keys() {
+        if !people.contains_key(id) {
+            state.repo.delete_person(id).await?;
+        }
+    }
+    state.people.replace(people);
+
+    let restored: HashSet<&str> = movies.iter().map(|movie| movie.id.as_str()).collect();
+    let mut removed = 0;
+    if !params.merge {
+        state.redirects.write_or_recover().clear();
+        for movie in previous.values() {
+            if !restored.contains(movie.id.as_str()) {
+                state.ratings.remove_movie(&movie.id);
+                state.posters.remove(&movie.id).await;
+                state.events.publish(EventKind::Deleted, movie.clone());
+                removed += 1;
+            }
+        }
+    }
+    for movie in &movies {
+        match previous.get(&movie.id) {
+            Some(before) => state.events.publish_update(before, movie.clone()),
+            None => state.events.publish(EventKind::Created, movie.clone()),
+        }
+    }
+    tracing::info!(
+        event = "store.restored",
+        restored = movies.len(),
+        removed,
+        merge = params.merge,
+        "store restored"
+    );
+
+    Ok(Json(RestoreReport {
+        restored: movies.len(),
+        removed,
+    }))
+}
+
+/// A limit with one value for routes taking uploads and one for the rest.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct PerRoute {
+    pub requests: u64,
+    pub uploads: u64,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct RateLimit {
+    /// Requests a client may burst; 0 when limiting is off.
+    pub requests: u32,
+    pub window_secs: u64,
+}
+
+/// Requests turned away by each limit since startup.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Rejected {
+    /// Answered 413 Payload Too Large.
+    pub body_size: u64,
+    /// Answered 504 Gateway Timeout.
+    pub timeout: u64,
+    /// Answered 429 Too Many Requests.
+    pub rate_limit: u64,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct LimitsReport {
+    pub body_size_bytes: PerRoute,
+    pub timeout_secs: PerRoute,
+    pub rate_limit: RateLimit,
+    /// Requests taking longer are logged as slow; 0 when none are.
+    pub slow_request_ms: u64,
+    pub rejected: Rejected,
+    /// Requests that took longer than `slow_request_ms` since startup.
+    pub slow_requests: u64,
+}
+
+/// Body of `PATCH /admin/limits`; limits left out keep their values.
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LimitsPatch {
+    pub rate_limit_requests: Option<u32>,
+    /// At least 1.
+    pub rate_limit_window_secs: Option<u64>,
+    pub slow_request_ms: Option<u64>,
+}
+
+fn limits(state: &AppState) -> LimitsReport {
+    let config = &state.config;
+    let (requests, window) = state.limiter.allowance();
+    LimitsReport {
+        body_size_bytes: PerRoute {
+            requests: config.body_limit as u64,
+            uploads: config.upload_body_limit as u64,
+        },
+        timeout_secs: PerRoute {
+            requests: config.request_timeout.as_secs(),
+            uploads: config.upload_timeout.as_secs(),
+        },
+        rate_limit: RateLimit {
+            requests,
+            window_secs: window.as_secs(),
+        },
+        slow_request_ms: state.limits.slow_threshold().as_millis() as u64,
+        rejected: Rejected {
+            body_size: state.limits.too_large.load(Ordering::Relaxed),
+            timeout: state.limits.timed_out.load(Ordering::Relaxed),
+            rate_limit: state.limiter.rejected(),
+        },
+        slow_requests: state.limits.slow.load(Ordering::Relaxed),
+    }
+}
+
+/// The limits in effect, with the requests each turned away since startup.
+#[utoipa::path(
+    get,
+    path = "/limits",
+    tag = "admin",
+    summary = "Report the request limits",
+    responses(
+        (status = OK, description = "The limits in effect and what they turned away", body = LimitsReport),
+    )
+)]
+async fn limits_report(State(state): State<AppState>) -> Json<LimitsReport> {
+    Json(limits(&state))
+}
+
+/// Changes the rate limit and the slow-request threshold until the server
+/// stops; the configuration keeps its values for the next start. A new rate
+/// limit gives every client a full allowance.
+#[utoipa::path(
+    patch,
+    path = "/limits",
+    tag = "admin",
+    summary = "Change the soft request limits",
+    request_body = LimitsPatch,
+    responses(
+        (status = OK, description = "The limits now in effect", body = LimitsReport),
+        (status = UNPROCESSABLE_ENTITY, description = "A zero rate limit window; nothing was changed", body = ErrorBody),
+    )
+)]
+async fn adjust_limits(
+    State(state): State<AppState>,
+    JsonBody(patch): JsonBody<LimitsPatch>,
+) -> Result<Json<LimitsReport>, ApiError> {
+    if patch.rate_limit_window_secs == Some(0) {
+        return Err(ApiError::validation(
+            "rate_limit_window_secs",
+            "must be at least 1",
+        ));
+    }
+
+    if patch.rate_limit_requests.is_some() || patch.rate_limit_window_secs.is_some() {
+        let (requests, window) = state.limiter.allowance();
+        state.limiter.set_allowance(
+            patch.rate_limit_requests.unwrap_or(requests),
+            patch
+                .rate_limit_window_secs
+                .map_or(window, Duration::from_secs),
+        );
+    }
+    if let Some(ms) = patch.slow_request_ms {
+        state.limits.set_slow_threshold(Duration::from_millis(ms));
+    }
+    tracing::info!(event = "limits.changed", ?patch, "request limits changed");
+
+    Ok(Json(limits(&state)))
+}
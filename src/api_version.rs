@@ -0,0 +1,167 @@
+//! Dated versions of the response shapes, picked per request with an
+//! `X-Api-Version` header and the latest without one. Handlers always answer
+//! in the latest shape; this middleware reshapes JSON bodies for an older
+//! version and marks them with `Deprecation` and `Sunset`, so the version
+//! shaping lives in one place instead of every handler. Streamed bodies are
+//! passed through as they are, since reshaping them would hold them whole.
+
+use axum::{
+    body::{Body, HttpBody, to_bytes},
+    extract::Request,
+    http::{HeaderName, HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::Value;
+
+use crate::case::is_json;
+use crate::errors::ApiError;
+
+pub const X_API_VERSION: HeaderName = HeaderName::from_static("x-api-version");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApiVersion {
+    pub name: &'static str,
+    /// When the version stops being served; `None` while it is current.
+    pub sunset: Option<&'static str>,
+}
+
+/// Oldest first; the last one is served by default.
+pub const VERSIONS: [ApiVersion; 2] = [
+    // `GET /movie` answered a bare array of every movie, and movies had no
+    // timestamps.
+    ApiVersion {
+        name: "2024-01",
+        sunset: Some("Fri, 01 Jan 2027 00:00:00 GMT"),
+    },
+    ApiVersion {
+        name: "2025-01",
+        sunset: None,
+    },
+];
+
+/// Marks a response whose body is a page of movies, which `2024-01` answers
+/// with the bare array of its items. Set by the handlers as a response
+/// extension.
+#[derive(Debug, Clone, Copy)]
+pub struct MoviePage;
+
+impl ApiVersion {
+    pub fn latest() -> Self {
+        VERSIONS[VERSIONS.len() - 1]
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        VERSIONS
+            .into_iter()
+            .find(|version| version.name == value.trim())
+    }
+}
+
+pub async fn negotiate(request: Request, next: Next) -> Response {
+    let version = match request.headers().get(X_API_VERSION) {
+        None => ApiVersion::latest(),
+        Some(value) => match value.to_str().ok().and_then(ApiVersion::parse) {
+            Some(version) => version,
+            None => {
+                let supported: Vec<&str> = VERSIONS.iter().map(|version| version.name).collect();
+                return ApiError::BadRequest(format!(
+                    "unsupported API version, expected one of {}",
+                    supported.join(", ")
+                ))
+                .into_response();
+            }
+        },
+    };
+    let mut response = next.run(request).await;
+    response
+        .headers_mut()
+        .append(header::VARY, HeaderValue::from_static("x-api-version"));
+    let Some(sunset) = version.sunset else {
+        return response;
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(
+        HeaderName::from_static("deprecation"),
+        HeaderValue::from_static("true"),
+    );
+    // Over the date of the unversioned paths, which outlive old versions.
+    headers.insert(
+        HeaderName::from_static("sunset"),
+        HeaderValue::from_static(sunset),
+    );
+    let size = response.body().size_hint().exact();
+    let Some(size) = size.filter(|_| is_json(response.headers())) else {
+        return response;
+    };
+
+    let lists_movies = response.extensions().get::<MoviePage>().is_some();
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = to_bytes(body, size as usize).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let Ok(value) = serde_json::from_slice::<Value>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+
+    let bytes = serde_json::to_vec(&shape_2024_01(value, lists_movies))
+        .expect("json values always serialize");
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+/// A body in the shape of `2024-01`: a page of movies becomes its items, and
+/// movies, recognized by their `was_good`, lose their timestamps.
+fn shape_2024_01(value: Value, lists_movies: bool) -> Value {
+    let value = match value {
+        Value::Object(mut page) if lists_movies && page.contains_key("per_page") => {
+            page.remove("items").unwrap_or(Value::Array(Vec::new()))
+        }
+        value => value,
+    };
+
+    without_timestamps(value)
+}
+
+fn without_timestamps(value: Value) -> Value {
+    match value {
+        Value::Object(mut map) => {
+            if map.contains_key("was_good") {
+                map.remove("created_at");
+                map.remove("updated_at");
+            }
+            Value::Object(
+                map.into_iter()
+                    .map(|(key, value)| (key, without_timestamps(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(without_timestamps).collect()),
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn pages_of_movies_become_bare_arrays_without_timestamps() {
+        let movie = json!({"id": "1", "was_good": true, "created_at": "2024-01-01T00:00:00Z"});
+        let page = json!({"items": [movie], "total": 1, "page": 1, "per_page": 20});
+        assert_eq!(
+            shape_2024_01(page.clone(), true),
+            json!([{"id": "1", "was_good": true}])
+        );
+        // Only the listing is unwrapped, and only movies lose timestamps.
+        assert_eq!(
+            shape_2024_01(page, false),
+            json!({"items": [{"id": "1", "was_good": true}], "total": 1, "page": 1, "per_page": 20})
+        );
+        let rating = json!({"id": 1, "created_at": "2024-01-01T00:00:00Z"});
+        assert_eq!(shape_2024_01(rating.clone(), false), rating);
+    }
+}
@@ -0,0 +1,154 @@
+//! Cache-Control for the whole API. Routes are sorted into a few classes and
+//! each class gets one configurable policy, so handlers never set the header
+//! themselves.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderValue, Method, header},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::openapi;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteClass {
+    /// Operator routes under `/admin`, stored by nobody.
+    Admin,
+    /// Reads of a single movie.
+    Movie,
+    /// Reads returning several movies.
+    List,
+    /// The OpenAPI document and Swagger UI, which only change with the
+    /// server.
+    Docs,
+}
+
+impl RouteClass {
+    /// Classifies a request, or `None` for requests that get no policy, such
+    /// as writes to movie routes.
+    fn of(method: &Method, path: &str) -> Option<Self> {
+        if path == "/admin" || path.starts_with("/admin/") {
+            return Some(RouteClass::Admin);
+        }
+        if method != Method::GET && method != Method::HEAD {
+            return None;
+        }
+        if [openapi::DOCUMENT_PATH, "/swagger-ui"]
+            .into_iter()
+            .any(|docs| path == docs || path.starts_with(&format!("{docs}/")))
+        {
+            return Some(RouteClass::Docs);
+        }
+
+        // Versioned paths are classified like the unversioned ones.
+        let path = path
+            .strip_prefix("/v1")
+            .filter(|rest| rest.starts_with('/'))
+            .unwrap_or(path);
+        match path.trim_end_matches('/') {
+            // Streams keep the `no-cache` they are sent with.
+            "/movie/events" => None,
+            "/movie" | "/movie/popular" | "/movie/search" | "/movie/export" | "/movie/trash"
+            | "/movie/stats" | "/genre" | "/genre/tree" => Some(RouteClass::List),
+            path if path.starts_with("/movie/") && path.ends_with("/rating") => {
+                Some(RouteClass::List)
+            }
+            path if path.starts_with("/movie/") => Some(RouteClass::Movie),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachePolicies {
+    pub admin: String,
+    pub movie: String,
+    pub list: String,
+    pub docs: String,
+}
+
+impl Default for CachePolicies {
+    fn default() -> Self {
+        Self {
+            admin: "no-store".to_string(),
+            movie: "private, max-age=0, must-revalidate".to_string(),
+            list: "no-cache".to_string(),
+            docs: "public, max-age=300".to_string(),
+        }
+    }
+}
+
+impl CachePolicies {
+    fn get(&self, class: RouteClass) -> &str {
+        match class {
+            RouteClass::Admin => &self.admin,
+            RouteClass::Movie => &self.movie,
+            RouteClass::List => &self.list,
+            RouteClass::Docs => &self.docs,
+        }
+    }
+}
+
+pub async fn cache_control(
+    State(policies): State<Arc<CachePolicies>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let class = RouteClass::of(request.method(), request.uri().path());
+    let mut response = next.run(request).await;
+
+    let policy = class
+        .map(|class| policies.get(class))
+        .filter(|policy| !policy.is_empty())
+        .and_then(|policy| HeaderValue::from_str(policy).ok());
+    if let Some(policy) = policy {
+        response.headers_mut().insert(header::CACHE_CONTROL, policy);
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_routes() {
+        let cases = [
+            (Method::GET, "/admin/chaos", Some(RouteClass::Admin)),
+            (Method::POST, "/admin/chaos", Some(RouteClass::Admin)),
+            (Method::GET, "/movie", Some(RouteClass::List)),
+            (Method::GET, "/movie/popular", Some(RouteClass::List)),
+            (Method::GET, "/movie/search", Some(RouteClass::List)),
+            (Method::GET, "/genre", Some(RouteClass::List)),
+            (Method::GET, "/movie/trash", Some(RouteClass::List)),
+            (Method::GET, "/movie/1", Some(RouteClass::Movie)),
+            (Method::HEAD, "/movie/1", Some(RouteClass::Movie)),
+            (Method::GET, "/movie/by-name/heat", Some(RouteClass::Movie)),
+            (Method::GET, "/movie/1/rating", Some(RouteClass::List)),
+            (Method::GET, "/movie/events", None),
+            (Method::GET, "/v1/movie", Some(RouteClass::List)),
+            (Method::GET, "/v1/movie/1", Some(RouteClass::Movie)),
+            (Method::GET, "/v1/movie/events", None),
+            (Method::GET, "/v1/movie/stats", Some(RouteClass::List)),
+            (Method::GET, "/v1movie/1", None),
+            (Method::PUT, "/movie/1", None),
+            (Method::GET, "/administrator", None),
+            (
+                Method::GET,
+                "/api-docs/openapi.json",
+                Some(RouteClass::Docs),
+            ),
+            (Method::GET, "/swagger-ui", Some(RouteClass::Docs)),
+            (Method::GET, "/swagger-ui/index.css", Some(RouteClass::Docs)),
+            (Method::GET, "/swagger-uix", None),
+        ];
+
+        for (method, path, class) in cases {
+            assert_eq!(RouteClass::of(&method, path), class, "{method} {path}");
+        }
+    }
+}
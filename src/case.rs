@@ -0,0 +1,216 @@
+//! Response key casing. Handlers always serialize snake_case; this middleware
+//! re-maps JSON keys to camelCase when the client asks for it with
+//! `?case=camel` or an `X-Response-Case: camel` header, and accepts request
+//! bodies in either casing by mapping camelCase keys back to snake_case.
+//! Only bodies of a known size are renamed: streams, such as `?stream=true`
+//! listings, are passed on in snake_case as they are produced rather than
+//! collected first.
+
+use std::sync::Arc;
+
+use axum::{
+    body::{Body, HttpBody, to_bytes},
+    extract::{Query, Request, State},
+    http::{HeaderMap, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::errors::ApiError;
+use crate::limits;
+
+/// Keys under this field are user-defined and kept verbatim in both
+/// directions.
+const VERBATIM_FIELD: &str = "custom";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Case {
+    Snake,
+    Camel,
+}
+
+impl Case {
+    fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "snake" => Some(Case::Snake),
+            "camel" => Some(Case::Camel),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct CaseParams {
+    case: Option<String>,
+}
+
+pub async fn response_case(
+    State(config): State<Arc<Config>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let requested = Query::<CaseParams>::try_from_uri(request.uri())
+        .ok()
+        .and_then(|Query(params)| params.case)
+        .or_else(|| {
+            request
+                .headers()
+                .get("x-response-case")
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        });
+
+    let case = match requested.as_deref().map(Case::parse) {
+        None => Case::Snake,
+        Some(Some(case)) => case,
+        Some(None) => {
+            return ApiError::BadRequest("unsupported case, expected snake or camel".to_string())
+                .into_response();
+        }
+    };
+
+    let request = match snake_case_body(request, config.upload_body_limit).await {
+        Ok(request) => request,
+        Err(response) => return response,
+    };
+
+    let response = next.run(request).await;
+
+    let size = response.body().size_hint().exact();
+    let Some(size) = size.filter(|_| case == Case::Camel && is_json(response.headers())) else {
+        return response;
+    };
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = to_bytes(body, size as usize).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let Ok(value) = serde_json::from_slice::<Value>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+
+    let bytes = serde_json::to_vec(&rename_keys(value, to_camel_case))
+        .expect("json values always serialize");
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+/// Reads at most `limit` bytes of a JSON body, which the extractor would
+/// read whole anyway.
+async fn snake_case_body(request: Request, limit: usize) -> Result<Request, Response> {
+    if !is_json(request.headers()) {
+        return Ok(request);
+    }
+
+    let (mut parts, body) = request.into_parts();
+    let bytes = to_bytes(body, limit)
+        .await
+        .map_err(|error| limits::body_error(error).into_response())?;
+
+    // Malformed bodies are passed through untouched so the extractor reports
+    // them the same way it would without this middleware.
+    let Ok(value) = serde_json::from_slice::<Value>(&bytes) else {
+        return Ok(Request::from_parts(parts, Body::from(bytes)));
+    };
+
+    let bytes = serde_json::to_vec(&rename_keys(value, to_snake_case))
+        .expect("json values always serialize");
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    Ok(Request::from_parts(parts, Body::from(bytes)))
+}
+
+pub fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"))
+}
+
+fn rename_keys(value: Value, rename: fn(&str) -> String) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| {
+                    let value = if key == VERBATIM_FIELD {
+                        value
+                    } else {
+                        rename_keys(value, rename)
+                    };
+                    (rename(&key), value)
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(|value| rename_keys(value, rename))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+fn to_camel_case(key: &str) -> String {
+    let mut out = String::with_capacity(key.len());
+    let mut upper = false;
+
+    for c in key.chars() {
+        if c == '_' && !out.is_empty() {
+            upper = true;
+        } else if upper {
+            out.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+fn to_snake_case(key: &str) -> String {
+    let mut out = String::with_capacity(key.len() + 4);
+
+    for c in key.chars() {
+        if c.is_uppercase() {
+            if !out.is_empty() {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn converts_between_cases() {
+        assert_eq!(to_camel_case("was_good"), "wasGood");
+        assert_eq!(to_camel_case("id"), "id");
+        assert_eq!(to_camel_case("locked_fields"), "lockedFields");
+        assert_eq!(to_snake_case("wasGood"), "was_good");
+        assert_eq!(to_snake_case("was_good"), "was_good");
+        assert_eq!(to_snake_case("newId"), "new_id");
+    }
+
+    #[test]
+    fn keeps_custom_keys_verbatim() {
+        let value = json!({"sort_name": "x", "custom": {"my_key": {"inner_key": 1}}});
+        assert_eq!(
+            rename_keys(value, to_camel_case),
+            json!({"sortName": "x", "custom": {"my_key": {"inner_key": 1}}})
+        );
+    }
+}
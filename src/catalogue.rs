@@ -0,0 +1,267 @@
+//! The catalogue as CSV, for keeping it in a spreadsheet: `GET /movie/export`
+//! writes the `id,name,year,was_good` columns of every movie and
+//! `POST /movie/import` creates movies from the same columns. Locked fields,
+//! custom fields, genres and versions are not part of the file. The
+//! `movies export` and `movies import` commands share this code, see `cli`.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::header,
+    response::{IntoResponse, Json, Response},
+};
+use futures_util::stream;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::errors::{ApiError, ErrorBody, FieldError};
+use crate::extract::QueryParams;
+use crate::import::{self, MAX_REPORTED_ERRORS, OnConflict};
+use crate::{AppState, Movie, validate_new_movie};
+
+const COLUMNS: [&str; 4] = ["id", "name", "year", "was_good"];
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Row {
+    id: String,
+    name: String,
+    year: u16,
+    was_good: bool,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+}
+
+#[derive(Deserialize, Debug, Default, IntoParams)]
+#[serde(default)]
+#[into_params(parameter_in = Query)]
+pub struct ExportParams {
+    #[param(inline)]
+    pub format: ExportFormat,
+}
+
+#[derive(Deserialize, Debug, Default, IntoParams)]
+#[serde(default)]
+#[into_params(parameter_in = Query)]
+pub struct ImportParams {
+    /// `skip` when left out.
+    #[param(inline)]
+    pub on_conflict: Option<OnConflict>,
+}
+
+#[derive(Serialize, Debug, Default, ToSchema)]
+pub struct CsvImportReport {
+    pub imported: usize,
+    /// Rows that replaced a stored movie, with `on_conflict=overwrite`.
+    pub updated: usize,
+    /// Rows whose id is already stored or appeared on an earlier row, with
+    /// `on_conflict=skip`.
+    pub skipped: usize,
+    pub invalid: usize,
+    /// Why rows were invalid, keyed by `line <n>` (1-based, the header is
+    /// line 1).
+    pub errors: Vec<FieldError>,
+}
+
+/// Streams the movies ordered by id, one row at a time.
+#[utoipa::path(
+    get,
+    path = "/movie/export",
+    tag = "movies",
+    summary = "Export movies as CSV",
+    operation_id = "export_csv",
+    params(ExportParams),
+    responses(
+        (status = OK, description = "A header row and a movie per row, ordered by id", content_type = "text/csv", body = String),
+    )
+)]
+pub async fn export(
+    QueryParams(params): QueryParams<ExportParams>,
+    State(state): State<AppState>,
+) -> Result<Response, ApiError> {
+    let ExportFormat::Csv = params.format;
+
+    let mut movies = state.live_movies().await?;
+    movies.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let body = stream::iter(lines(movies).map(Ok::<_, Infallible>));
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"movies.csv\"",
+            ),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
+
+/// `movies` in the export's columns, for `GET /movie` with `Accept: text/csv`.
+pub fn csv(movies: Vec<Movie>) -> Vec<u8> {
+    lines(movies).flatten().collect()
+}
+
+/// The header row followed by a row per movie.
+fn lines(movies: Vec<Movie>) -> impl Iterator<Item = Bytes> {
+    let rows = movies.into_iter().map(|movie| {
+        encode(Row {
+            id: movie.id,
+            name: movie.name,
+            year: movie.year,
+            was_good: movie.was_good,
+        })
+    });
+    std::iter::once(encode(COLUMNS)).chain(rows)
+}
+
+/// Writes one record as a CSV line, quoting fields that need it.
+fn encode(record: impl Serialize) -> Bytes {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    writer
+        .serialize(record)
+        .expect("rows always serialize to memory");
+
+    Bytes::from(writer.into_inner().expect("writing to memory never fails"))
+}
+
+/// Creates a movie per row, checked like on `POST /movie`. Rows whose id is
+/// taken are skipped rather than overwriting anything, unless
+/// `?on_conflict=overwrite` asks to replace the stored movie; the rows are
+/// stored in one repository batch.
+#[utoipa::path(
+    post,
+    path = "/movie/import",
+    tag = "movies",
+    summary = "Import movies from CSV",
+    operation_id = "import_csv",
+    params(ImportParams),
+    request_body(content = String, content_type = "text/csv", description = "A header row naming id, name, year and was_good, then a movie per row"),
+    responses(
+        (status = OK, description = "What was imported, skipped and invalid", body = CsvImportReport),
+        (status = BAD_REQUEST, description = "An unreadable header row", body = ErrorBody),
+    )
+)]
+pub async fn import(
+    QueryParams(params): QueryParams<ImportParams>,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Result<Json<CsvImportReport>, ApiError> {
+    let on_conflict = params.on_conflict.unwrap_or(OnConflict::Skip);
+    import_csv(&state, &body, on_conflict).await.map(Json)
+}
+
+/// Reads and stores the rows of `body`, for `POST /movie/import` and
+/// `movies import`.
+pub async fn import_csv(
+    state: &AppState,
+    body: &[u8],
+    on_conflict: OnConflict,
+) -> Result<CsvImportReport, ApiError> {
+    let _bulk = state.bulk.write().await;
+    let mut reader = csv::Reader::from_reader(body);
+    let headers = reader
+        .headers()
+        .map_err(|error| ApiError::BadRequest(format!("unreadable header row: {error}")))?
+        .clone();
+    let missing: Vec<&str> = COLUMNS
+        .into_iter()
+        .filter(|column| !headers.iter().any(|header| header == *column))
+        .collect();
+    if !missing.is_empty() {
+        return Err(ApiError::BadRequest(format!(
+            "header row lacks the {} column(s)",
+            missing.join(", ")
+        )));
+    }
+
+    let mut report = CsvImportReport::default();
+    let mut movies = Vec::new();
+    let mut record = csv::StringRecord::new();
+    loop {
+        let result = match reader.read_record(&mut record) {
+            Ok(false) => break,
+            Ok(true) => record.deserialize::<Row>(Some(&headers)),
+            Err(error) => Err(error),
+        };
+        // The position of a failed read is in the error, that of a read
+        // record in the record.
+        let line = match &result {
+            Err(error) => error.position(),
+            Ok(_) => record.position(),
+        }
+        .map_or(0, |position| position.line());
+
+        let errors = match result {
+            Ok(row) => {
+                let movie = Movie {
+                    id: row.id,
+                    name: row.name,
+                    year: row.year,
+                    was_good: row.was_good,
+                    locked_fields: Vec::new(),
+                    custom: HashMap::new(),
+                    genres: Vec::new(),
+                    sort_name: None,
+                    director_id: None,
+                    cast: Vec::new(),
+                    slug: String::new(),
+                    version: 0,
+                    created_at: Default::default(),
+                    updated_at: Default::default(),
+                    deleted_at: None,
+                    has_poster: false,
+                };
+                let errors = validate_new_movie(&movie, &state.config, &format!("line {line}."));
+                if errors.is_empty() {
+                    movies.push(movie);
+                    continue;
+                }
+                errors
+            }
+            Err(error) => vec![FieldError::new(
+                format!("line {line}"),
+                describe(&error, &headers),
+            )],
+        };
+
+        report.invalid += 1;
+        let room = MAX_REPORTED_ERRORS.saturating_sub(report.errors.len());
+        report.errors.extend(errors.into_iter().take(room));
+    }
+
+    let stored = import::store(state, movies, on_conflict).await?;
+    report.imported = stored.created;
+    report.updated = stored.updated;
+    report.skipped = stored.skipped;
+
+    Ok(report)
+}
+
+/// The part of a CSV error that is about the row, without the position the
+/// report already carries.
+fn describe(error: &csv::Error, headers: &csv::StringRecord) -> String {
+    match error.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => {
+            match err.field().and_then(|index| headers.get(index as usize)) {
+                Some(column) => format!("{column}: {}", err.kind()),
+                None => err.kind().to_string(),
+            }
+        }
+        csv::ErrorKind::UnequalLengths {
+            expected_len, len, ..
+        } => format!("expected {expected_len} columns, got {len}"),
+        _ => error.to_string(),
+    }
+}
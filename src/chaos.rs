@@ -0,0 +1,137 @@
+//! Fault injection for testing clients against a misbehaving server. Only
+//! compiled with the `chaos` feature and only installed when the runtime
+//! config enables it; `POST /admin/chaos` then adjusts how movie routes are
+//! delayed and failed. Admin routes are never affected.
+
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use axum::{
+    Router,
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{IntoResponse, Json, Response},
+    routing::get,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::errors::{ApiError, FieldError};
+use crate::extract::JsonBody;
+use crate::sync::LockExt;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ChaosSettings {
+    /// Fixed delay added before every response.
+    pub latency_ms: u64,
+    /// Upper bound of an extra random delay on top of `latency_ms`.
+    pub jitter_ms: u64,
+    /// Fraction of requests, in `0.0..=1.0`, answered with `error_status`.
+    pub error_rate: f64,
+    pub error_status: u16,
+}
+
+impl Default for ChaosSettings {
+    fn default() -> Self {
+        Self {
+            latency_ms: 0,
+            jitter_ms: 0,
+            error_rate: 0.0,
+            error_status: 500,
+        }
+    }
+}
+
+impl ChaosSettings {
+    fn validate(&self) -> Result<(), ApiError> {
+        let mut errors = Vec::new();
+
+        if !(0.0..=1.0).contains(&self.error_rate) {
+            errors.push(FieldError::new("error_rate", "must be between 0 and 1"));
+        }
+        if !(400..=599).contains(&self.error_status) {
+            errors.push(FieldError::new(
+                "error_status",
+                "must be an error status between 400 and 599",
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ApiError::Validation(errors))
+        }
+    }
+}
+
+type Settings = Arc<RwLock<ChaosSettings>>;
+
+/// Wraps `routes` with the fault-injection layer and mounts `/admin/chaos`
+/// next to them, outside the layer.
+pub fn install(routes: Router) -> Router {
+    let settings = Settings::default();
+
+    routes
+        .layer(middleware::from_fn_with_state(settings.clone(), inject))
+        .merge(
+            Router::new()
+                .route("/admin/chaos", get(get_chaos).post(set_chaos))
+                .with_state(settings),
+        )
+}
+
+async fn inject(State(settings): State<Settings>, request: Request, next: Next) -> Response {
+    let settings = settings.read_or_recover().clone();
+
+    let (delay, fail) = {
+        let mut rng = rand::rng();
+        let jitter = match settings.jitter_ms {
+            0 => 0,
+            jitter => rng.random_range(0..=jitter),
+        };
+        (
+            Duration::from_millis(settings.latency_ms + jitter),
+            rng.random_bool(settings.error_rate),
+        )
+    };
+
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+
+    // The handler is skipped entirely, so an injected failure never has side
+    // effects the client could not know about.
+    if fail {
+        let status = StatusCode::from_u16(settings.error_status)
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        return (
+            status,
+            Json(json!({
+                "code": "INJECTED_FAULT",
+                "message": "failure injected by chaos mode",
+            })),
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+async fn get_chaos(State(settings): State<Settings>) -> Json<ChaosSettings> {
+    Json(settings.read_or_recover().clone())
+}
+
+/// Replaces the settings; omitted fields fall back to their defaults, so an
+/// empty body switches all faults off.
+async fn set_chaos(
+    State(settings): State<Settings>,
+    JsonBody(payload): JsonBody<ChaosSettings>,
+) -> Result<Json<ChaosSettings>, ApiError> {
+    payload.validate()?;
+    *settings.write_or_recover() = payload.clone();
+
+    Ok(Json(payload))
+}
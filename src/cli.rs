@@ -0,0 +1,613 @@
+//! One-shot commands run against the store without starting the server:
+//! `movies export` writes the catalogue to a file and `movies import` reads
+//! one into it, through the same code as `GET /movie/export`,
+//! `POST /movie/import` and `POST /movie/import/stream`. The store is opened
+//! as the server opens it, from the same settings, with `--store` standing
+//! in for `MOVIES_DB_PATH`, or `DATABASE_URL` when it starts with `sqlite:`.
+
+use std::fmt;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use axum::body::Body;
+use serde::Serialize;
+
+use crate::AppState;
+use crate::catalogue::{self, CsvImportReport};
+use crate::config::Config;
+use crate::errors::FieldError;
+use crate::import::{self, ImportReport, OnConflict};
+
+pub const USAGE: &str = "\
+usage:
+  movies
+      serve the API, configured from the environment
+  movies export --store <path> [--format csv|json|ndjson] [--out <file>|-]
+      write every movie not in the trash, ordered by id, to standard output
+      or a file
+  movies import --store <path> --in <file>|- [--format csv|json|ndjson]
+                [--on-conflict skip|overwrite] [--json]
+      read movies into the store and print what was imported, as text or
+      with --json as JSON";
+
+/// Nothing could be imported, or the command could not run at all.
+pub const EXIT_FAILED: i32 = 1;
+/// The arguments were not understood.
+pub const EXIT_USAGE: i32 = 2;
+/// Some movies were imported and others failed.
+pub const EXIT_PARTIAL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    /// The `id,name,year,was_good` columns of `GET /movie/export`.
+    Csv,
+    /// One array of movies.
+    Json,
+    /// A movie per line, as `POST /movie/import/stream` takes them.
+    Ndjson,
+}
+
+impl FileFormat {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "csv" => Ok(FileFormat::Csv),
+            "json" => Ok(FileFormat::Json),
+            "ndjson" | "jsonl" => Ok(FileFormat::Ndjson),
+            _ => Err(format!(
+                "--format must be csv, json or ndjson, got {value:?}"
+            )),
+        }
+    }
+
+    /// The format a file's extension names.
+    fn of(path: &Path) -> Option<Self> {
+        Self::parse(path.extension()?.to_str()?).ok()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Help,
+    Export(Export),
+    Import(Import),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Export {
+    pub store: String,
+    pub format: FileFormat,
+    /// `None` for standard output.
+    pub out: Option<PathBuf>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Import {
+    pub store: String,
+    /// `None` for standard input.
+    pub input: Option<PathBuf>,
+    pub format: FileFormat,
+    pub on_conflict: OnConflict,
+    /// Print the report as JSON instead of text.
+    pub json: bool,
+}
+
+/// The command `args` name, without the program name, or `None` for none,
+/// which serves the API.
+pub fn parse(args: &[String]) -> Result<Option<Command>, String> {
+    let Some((command, rest)) = args.split_first() else {
+        return Ok(None);
+    };
+    if matches!(command.as_str(), "-h" | "--help" | "help") {
+        return Ok(Some(Command::Help));
+    }
+
+    let mut options = Options::parse(rest)?;
+    let command = match command.as_str() {
+        "export" => {
+            let store = options.required("--store")?;
+            let format = match options.take("--format") {
+                Some(format) => FileFormat::parse(&format)?,
+                None => FileFormat::Csv,
+            };
+            let out = options.take("--out").filter(|out| out != "-");
+            Command::Export(Export {
+                store,
+                format,
+                out: out.map(PathBuf::from),
+            })
+        }
+        "import" => {
+            let store = options.required("--store")?;
+            let input = Some(options.required("--in")?)
+                .filter(|input| input != "-")
+                .map(PathBuf::from);
+            let format = match options.take("--format") {
+                Some(format) => FileFormat::parse(&format)?,
+                None => input
+                    .as_deref()
+                    .and_then(FileFormat::of)
+                    .ok_or("--format is needed when the extension of --in does not name one")?,
+            };
+            let on_conflict = match options.take("--on-conflict").as_deref() {
+                None | Some("skip") => OnConflict::Skip,
+                Some("overwrite") => OnConflict::Overwrite,
+                Some(value) => {
+                    return Err(format!(
+                        "--on-conflict must be skip or overwrite, got {value:?}"
+                    ));
+                }
+            };
+            Command::Import(Import {
+                store,
+                input,
+                format,
+                on_conflict,
+                json: options.flag("--json"),
+            })
+        }
+        _ => return Err(format!("unknown command {command:?}")),
+    };
+    options.finish()?;
+
+    Ok(Some(command))
+}
+
+/// `--name value`, `--name=value` and bare `--flag` options, in any order.
+struct Options(Vec<(String, Option<String>)>);
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Vec::new();
+        let mut args = args.iter().peekable();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                return Err(format!("unexpected argument {arg:?}"));
+            }
+            let option = match arg.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => {
+                    let value = args.next_if(|next| !next.starts_with("--") || *next == "-");
+                    (arg.clone(), value.cloned())
+                }
+            };
+            if options.iter().any(|(name, _)| *name == option.0) {
+                return Err(format!("{} is given twice", option.0));
+            }
+            options.push(option);
+        }
+        Ok(Options(options))
+    }
+
+    fn take(&mut self, name: &str) -> Option<String> {
+        let index = self.0.iter().position(|(option, _)| option == name)?;
+        self.0.remove(index).1
+    }
+
+    fn required(&mut self, name: &str) -> Result<String, String> {
+        self.take(name)
+            .ok_or_else(|| format!("{name} <value> is required"))
+    }
+
+    fn flag(&mut self, name: &str) -> bool {
+        let index = self
+            .0
+            .iter()
+            .position(|(option, value)| option == name && value.is_none());
+        index.map(|index| self.0.remove(index)).is_some()
+    }
+
+    /// Fails on any option no command took.
+    fn finish(self) -> Result<(), String> {
+        match self.0.first() {
+            Some((name, _)) => Err(format!("unknown option {name}")),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Runs `command` and returns the process's exit code.
+pub async fn run(config: Config, command: Command) -> i32 {
+    let result = match command {
+        Command::Help => {
+            println!("{USAGE}");
+            return 0;
+        }
+        Command::Export(command) => export(config, &command).await.map(|_| 0),
+        Command::Import(command) => import(config, &command).await.map(|report| {
+            if command.json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&report).expect("reports always serialize")
+                );
+            } else {
+                print!("{report}");
+            }
+            report.exit_code()
+        }),
+    };
+    result.unwrap_or_else(|error| {
+        eprintln!("error: {error}");
+        EXIT_FAILED
+    })
+}
+
+/// `config` with `store` in place of the store it names.
+async fn open(mut config: Config, store: &str) -> Result<AppState, String> {
+    if store.starts_with("sqlite:") {
+        config.database_url = Some(store.to_string());
+        config.db_path = None;
+    } else {
+        config.db_path = Some(PathBuf::from(store));
+        config.database_url = None;
+    }
+    AppState::open(config)
+        .await
+        .map_err(|error| format!("cannot open the store {store}: {error}"))
+}
+
+/// Writes the movies out as `command` asks, returning how many it wrote.
+pub async fn export(config: Config, command: &Export) -> Result<usize, String> {
+    // Opening a file store that is not there would create it.
+    if !command.store.starts_with("sqlite:") && !Path::new(&command.store).exists() {
+        return Err(format!("no store at {}", command.store));
+    }
+    let state = open(config, &command.store).await?;
+    let mut movies = state
+        .live_movies()
+        .await
+        .map_err(|error| error.to_string())?;
+    movies.sort_by(|a, b| a.id.cmp(&b.id));
+    let count = movies.len();
+
+    let bytes = match command.format {
+        FileFormat::Csv => catalogue::csv(movies),
+        FileFormat::Json => {
+            let mut json = serde_json::to_vec_pretty(&movies).expect("movies always serialize");
+            json.push(b'\n');
+            json
+        }
+        FileFormat::Ndjson => movies
+            .iter()
+            .flat_map(|movie| {
+                let mut line = serde_json::to_vec(movie).expect("movies always serialize");
+                line.push(b'\n');
+                line
+            })
+            .collect(),
+    };
+    let written = match &command.out {
+        Some(path) => std::fs::write(path, bytes),
+        None => std::io::stdout().lock().write_all(&bytes),
+    };
+    written.map_err(|error| format!("cannot write the export: {error}"))?;
+
+    Ok(count)
+}
+
+/// Reads the movies in as `command` asks and flushes the store.
+pub async fn import(config: Config, command: &Import) -> Result<Report, String> {
+    let mut bytes = Vec::new();
+    let read = match &command.input {
+        Some(path) => std::fs::File::open(path).and_then(|mut file| file.read_to_end(&mut bytes)),
+        None => std::io::stdin().lock().read_to_end(&mut bytes),
+    };
+    read.map_err(|error| format!("cannot read the import: {error}"))?;
+    // A JSON array is imported as NDJSON, one movie per line.
+    if command.format == FileFormat::Json {
+        let movies: Vec<serde_json::Value> = serde_json::from_slice(&bytes)
+            .map_err(|error| format!("expected an array of movies: {error}"))?;
+        bytes = movies
+            .iter()
+            .flat_map(|movie| {
+                let mut line = serde_json::to_vec(movie).expect("values always serialize");
+                line.push(b'\n');
+                line
+            })
+            .collect();
+    }
+
+    let state = open(config, &command.store).await?;
+    let report = match command.format {
+        FileFormat::Csv => Report::Csv(
+            catalogue::import_csv(&state, &bytes, command.on_conflict)
+                .await
+                .map_err(|error| error.message())?,
+        ),
+        FileFormat::Json | FileFormat::Ndjson => {
+            // A body read from memory never breaks off.
+            let (Ok(report) | Err(report)) =
+                import::run(&state, Body::from(bytes), command.on_conflict, |_| {}).await;
+            Report::Movies(report)
+        }
+    };
+    state
+        .shutdown()
+        .await
+        .map_err(|error| format!("cannot flush the store: {error}"))?;
+
+    Ok(report)
+}
+
+/// What `movies import` did: the report `POST /movie/import` answers for
+/// CSV, that of `POST /movie/import/stream` for JSON and NDJSON.
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum Report {
+    Csv(CsvImportReport),
+    Movies(ImportReport),
+}
+
+impl Report {
+    /// Movies stored, or left as they were stored.
+    fn handled(&self) -> usize {
+        match self {
+            Report::Csv(report) => report.imported + report.updated + report.skipped,
+            Report::Movies(report) => {
+                let progress = &report.progress;
+                progress.created + progress.updated + progress.skipped
+            }
+        }
+    }
+
+    fn failed(&self) -> usize {
+        match self {
+            Report::Csv(report) => report.invalid,
+            Report::Movies(report) => report.progress.failed,
+        }
+    }
+
+    fn errors(&self) -> &[FieldError] {
+        match self {
+            Report::Csv(report) => &report.errors,
+            Report::Movies(report) => &report.errors,
+        }
+    }
+
+    /// 0 when nothing failed, `EXIT_PARTIAL` when some movies failed and
+    /// others did not, and `EXIT_FAILED` when all of them failed.
+    pub fn exit_code(&self) -> i32 {
+        match (self.failed(), self.handled()) {
+            (0, _) => 0,
+            (_, 0) => EXIT_FAILED,
+            _ => EXIT_PARTIAL,
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Report::Csv(report) => writeln!(
+                f,
+                "imported {}, updated {}, skipped {}, invalid {}",
+                report.imported, report.updated, report.skipped, report.invalid
+            )?,
+            Report::Movies(report) => {
+                let progress = &report.progress;
+                writeln!(
+                    f,
+                    "read {} lines: created {}, updated {}, skipped {}, failed {}",
+                    progress.lines,
+                    progress.created,
+                    progress.updated,
+                    progress.skipped,
+                    progress.failed
+                )?
+            }
+        }
+        for error in self.errors() {
+            writeln!(f, "  {}: {}", error.field, error.message)?;
+        }
+        let unreported = self.failed().saturating_sub(self.errors().len());
+        if self.errors().len() == import::MAX_REPORTED_ERRORS && unreported > 0 {
+            writeln!(f, "  ...and more")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Movie;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    fn heat() -> Movie {
+        serde_json::from_str(r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#).unwrap()
+    }
+
+    #[test]
+    fn commands_are_parsed_from_their_options() {
+        assert_eq!(parse(&[]), Ok(None));
+        assert_eq!(
+            parse(&args("export --store movies.json --format=ndjson --out -")),
+            Ok(Some(Command::Export(Export {
+                store: "movies.json".to_string(),
+                format: FileFormat::Ndjson,
+                out: None,
+            })))
+        );
+        assert_eq!(
+            parse(&args(
+                "import --json --in new.CSV --format csv --store movies.json"
+            )),
+            Ok(Some(Command::Import(Import {
+                store: "movies.json".to_string(),
+                input: Some(PathBuf::from("new.CSV")),
+                format: FileFormat::Csv,
+                on_conflict: OnConflict::Skip,
+                json: true,
+            })))
+        );
+
+        for (line, error) in [
+            ("serve", "unknown command \"serve\""),
+            ("export", "--store <value> is required"),
+            ("export --store a --store b", "--store is given twice"),
+            ("export --store a --verbose", "unknown option --verbose"),
+            (
+                "export --store a --format xml",
+                "--format must be csv, json or ndjson, got \"xml\"",
+            ),
+            (
+                "import --store a --in -",
+                "--format is needed when the extension of --in does not name one",
+            ),
+            (
+                "import --store a --in a.csv --on-conflict merge",
+                "--on-conflict must be skip or overwrite, got \"merge\"",
+            ),
+        ] {
+            assert_eq!(parse(&args(line)), Err(error.to_string()), "{line}");
+        }
+    }
+
+    #[tokio::test]
+    async fn exports_are_written_in_each_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = dir.path().join("movies.json");
+        let state = open(Config::default(), store.to_str().unwrap())
+            .await
+            .unwrap();
+        let mut ronin = heat();
+        ronin.id = "2".to_string();
+        ronin.name = "Ronin, the \"Director's Cut\"".to_string();
+        import::store(&state, vec![ronin, heat()], OnConflict::Skip)
+            .await
+            .unwrap();
+        state.shutdown().await.unwrap();
+
+        let out = dir.path().join("out");
+        let command = |format| Export {
+            store: store.to_str().unwrap().to_string(),
+            format,
+            out: Some(out.clone()),
+        };
+
+        assert_eq!(export_to(&command(FileFormat::Csv)).await, 2);
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "id,name,year,was_good\n1,Heat,1995,true\n2,\"Ronin, the \"\"Director's Cut\"\"\",1995,true\n"
+        );
+
+        export_to(&command(FileFormat::Ndjson)).await;
+        let lines: Vec<Movie> = std::fs::read_to_string(&out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines.iter().map(|movie| &movie.id).collect::<Vec<_>>(),
+            ["1", "2"]
+        );
+
+        export_to(&command(FileFormat::Json)).await;
+        let movies: Vec<Movie> = serde_json::from_slice(&std::fs::read(&out).unwrap()).unwrap();
+        assert_eq!(movies, lines);
+
+        let missing = Export {
+            store: dir
+                .path()
+                .join("nothing.json")
+                .to_str()
+                .unwrap()
+                .to_string(),
+            ..command(FileFormat::Csv)
+        };
+        assert!(export(Config::default(), &missing).await.is_err());
+        assert!(!dir.path().join("nothing.json").exists());
+    }
+
+    async fn export_to(command: &Export) -> usize {
+        export(Config::default(), command).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn imports_report_what_they_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = dir.path().join("movies.json");
+        let input = dir.path().join("movies.csv");
+        std::fs::write(
+            &input,
+            "id,name,year,was_good\n1,Heat,1995,true\n2,Ronin,nineteen,true\n3,Thief,1981,false\n",
+        )
+        .unwrap();
+        let command = Import {
+            store: store.to_str().unwrap().to_string(),
+            input: Some(input.clone()),
+            format: FileFormat::Csv,
+            on_conflict: OnConflict::Skip,
+            json: false,
+        };
+
+        let report = import(Config::default(), &command).await.unwrap();
+        assert_eq!(
+            report.to_string(),
+            "imported 2, updated 0, skipped 0, invalid 1\n  line 3: year: invalid digit found in string\n"
+        );
+        assert_eq!(report.exit_code(), EXIT_PARTIAL);
+
+        // The store was flushed, and the same rows now conflict.
+        std::fs::write(&input, "id,name,year,was_good\n1,Heat (1995),1995,true\n").unwrap();
+        let report = import(Config::default(), &command).await.unwrap();
+        assert_eq!(report.exit_code(), 0);
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            serde_json::json!({"imported": 0, "updated": 0, "skipped": 1, "invalid": 0, "errors": []})
+        );
+        let overwrite = Import {
+            on_conflict: OnConflict::Overwrite,
+            ..command
+        };
+        let report = import(Config::default(), &overwrite).await.unwrap();
+        assert_eq!(
+            report.to_string(),
+            "imported 0, updated 1, skipped 0, invalid 0\n"
+        );
+
+        let state = open(Config::default(), store.to_str().unwrap())
+            .await
+            .unwrap();
+        let stored = state.repo.get("1").await.unwrap().unwrap();
+        assert_eq!((stored.name.as_str(), stored.version), ("Heat (1995)", 2));
+    }
+
+    #[tokio::test]
+    async fn imports_that_store_nothing_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("movies.ndjson");
+        std::fs::write(&input, "{\"id\":\"1\"}\nnot json\n").unwrap();
+        let command = Import {
+            store: dir.path().join("movies.json").to_str().unwrap().to_string(),
+            input: Some(input.clone()),
+            format: FileFormat::Ndjson,
+            on_conflict: OnConflict::Skip,
+            json: true,
+        };
+
+        let report = import(Config::default(), &command).await.unwrap();
+        assert!(matches!(&report, Report::Movies(report) if report.progress.failed == 2));
+        assert_eq!(report.exit_code(), EXIT_FAILED);
+
+        // A JSON array goes through the same path, a movie per line.
+        let input = dir.path().join("movies.json.in");
+        std::fs::write(&input, serde_json::to_vec(&[heat()]).unwrap()).unwrap();
+        let report = import(
+            Config::default(),
+            &Import {
+                input: Some(input),
+                format: FileFormat::Json,
+                ..command
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            report.to_string(),
+            "read 1 lines: created 1, updated 0, skipped 0, failed 0\n"
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! A typed client for the API, built on the same [`model`](crate::model)
+//! types the server uses. Only compiled with the `client` feature.
+
+use std::fmt;
+
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::model::{FieldError, Movie, MoviePatch, Page};
+
+/// Why a call failed: the server's error envelope mapped to a variant, or
+/// the request not getting an answer at all.
+#[derive(Debug)]
+pub enum ClientError {
+    NotFound {
+        resource: String,
+        id: String,
+    },
+    Validation(Vec<FieldError>),
+    Conflict {
+        conflict_type: String,
+        message: String,
+        existing: Option<Value>,
+        candidates: Option<Value>,
+    },
+    /// Any other error status; `code` is `None` when the body was not an
+    /// error envelope.
+    Api {
+        status: StatusCode,
+        code: Option<String>,
+        message: String,
+    },
+    Http(reqwest::Error),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::NotFound { resource, id } => write!(f, "{resource} {id} does not exist"),
+            ClientError::Validation(errors) => {
+                write!(f, "validation failed:")?;
+                for error in errors {
+                    write!(f, " {}: {};", error.field, error.message)?;
+                }
+                Ok(())
+            }
+            ClientError::Conflict { message, .. } => write!(f, "conflict: {message}"),
+            ClientError::Api {
+                status, message, ..
+            } => write!(f, "{status}: {message}"),
+            ClientError::Http(error) => write!(f, "request failed: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Http(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(error: reqwest::Error) -> Self {
+        ClientError::Http(error)
+    }
+}
+
+/// The error envelope every failing endpoint answers with.
+#[derive(Deserialize)]
+struct Envelope {
+    code: String,
+    message: String,
+    resource: Option<String>,
+    id: Option<String>,
+    #[serde(default)]
+    errors: Vec<FieldError>,
+    conflict_type: Option<String>,
+    existing: Option<Value>,
+    candidates: Option<Value>,
+}
+
+impl ClientError {
+    async fn from_response(response: Response) -> Self {
+        let status = response.status();
+        let body = match response.text().await {
+            Ok(body) => body,
+            Err(error) => return ClientError::Http(error),
+        };
+
+        let Ok(envelope) = serde_json::from_str::<Envelope>(&body) else {
+            return ClientError::Api {
+                status,
+                code: None,
+                message: body,
+            };
+        };
+
+        match (status, envelope) {
+            (
+                StatusCode::NOT_FOUND,
+                Envelope {
+                    resource: Some(resource),
+                    id: Some(id),
+                    ..
+                },
+            ) => ClientError::NotFound { resource, id },
+            (StatusCode::UNPROCESSABLE_ENTITY, envelope) if !envelope.errors.is_empty() => {
+                ClientError::Validation(envelope.errors)
+            }
+            (
+                StatusCode::CONFLICT,
+                Envelope {
+                    conflict_type: Some(conflict_type),
+                    message,
+                    existing,
+                    candidates,
+                    ..
+                },
+            ) => ClientError::Conflict {
+                conflict_type,
+                message,
+                existing,
+                candidates,
+            },
+            (status, envelope) => ClientError::Api {
+                status,
+                code: Some(envelope.code),
+                message: envelope.message,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MoviesClient {
+    base_url: String,
+    http: reqwest::Client,
+}
+
+impl MoviesClient {
+    /// `base_url` is where the server is reachable, e.g.
+    /// `http://127.0.0.1:3000`.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_http_client(base_url, reqwest::Client::new())
+    }
+
+    /// Like [`MoviesClient::new`], reusing a configured `reqwest` client,
+    /// e.g. for timeouts, proxies or default headers.
+    pub fn with_http_client(base_url: impl Into<String>, http: reqwest::Client) -> Self {
+        MoviesClient {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            http,
+        }
+    }
+
+    /// `GET /movie`, with `query` passed as query parameters, e.g.
+    /// `[("custom.rewatches", "2"), ("page", "2")]`.
+    pub async fn list(&self, query: &[(&str, &str)]) -> Result<Page<Movie>, ClientError> {
+        self.send(self.request(Method::GET, "/movie").query(query))
+            .await
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Movie, ClientError> {
+        self.send(self.request(Method::GET, &format!("/movie/{id}")))
+            .await
+    }
+
+    pub async fn create(&self, movie: &Movie) -> Result<Movie, ClientError> {
+        self.send(self.request(Method::POST, "/movie").json(movie))
+            .await
+    }
+
+    pub async fn update(&self, id: &str, movie: &Movie) -> Result<Movie, ClientError> {
+        self.send(
+            self.request(Method::PUT, &format!("/movie/{id}"))
+                .json(movie),
+        )
+        .await
+    }
+
+    pub async fn patch(&self, id: &str, patch: &MoviePatch) -> Result<Movie, ClientError> {
+        self.send(
+            self.request(Method::PATCH, &format!("/movie/{id}"))
+                .json(patch),
+        )
+        .await
+    }
+
+    pub async fn delete(&self, id: &str) -> Result<(), ClientError> {
+        let response = self
+            .request(Method::DELETE, &format!("/movie/{id}"))
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(ClientError::from_response(response).await)
+        }
+    }
+
+    /// `path` is relative to `/v1`, the version the client speaks.
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http
+            .request(method, format!("{}/v1{path}", self.base_url))
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, ClientError> {
+        let response = request.send().await?;
+
+        if response.status().is_success() {
+            Ok(response.json().await?)
+        } else {
+            Err(ClientError::from_response(response).await)
+        }
+    }
+}
@@ -0,0 +1,44 @@
+//! Which responses the compression layer in `router` encodes. Only bodies
+//! of a known size of at least `MIN_SIZE` bytes are: tiny ones do not pay
+//! for the encoding, and bodies of unknown size are streams (the change
+//! feed, SSE imports, the CSV export) whose chunks must reach the client as
+//! they are produced instead of collecting in an encoder's buffer.
+
+use axum::{body::HttpBody, http::Response};
+use tower_http::compression::Predicate;
+
+pub const MIN_SIZE: u64 = 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub struct LargeBodies;
+
+impl Predicate for LargeBodies {
+    fn should_compress<B>(&self, response: &Response<B>) -> bool
+    where
+        B: HttpBody,
+    {
+        response
+            .body()
+            .size_hint()
+            .exact()
+            .is_some_and(|size| size >= MIN_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use futures_util::stream;
+
+    use super::*;
+
+    #[test]
+    fn only_large_bodies_of_known_size_are_compressed() {
+        let response = |body| Response::new(body);
+        assert!(LargeBodies.should_compress(&response(Body::from(vec![b'a'; 1024]))));
+        assert!(!LargeBodies.should_compress(&response(Body::from(vec![b'a'; 1023]))));
+
+        let chunks = stream::iter([Ok::<_, std::io::Error>(vec![b'a'; 4096])]);
+        assert!(!LargeBodies.should_compress(&response(Body::from_stream(chunks))));
+    }
+}
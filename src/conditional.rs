@@ -0,0 +1,116 @@
+//! Entity tags and the conditional request headers compared against them. A
+//! movie's tag is its quoted `version`, so it changes with every write; a
+//! list's tag is a hash of its serialized body.
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use axum::{
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+
+pub fn etag(version: u64) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{version}\"")).expect("digits are a valid header value")
+}
+
+/// Tags a body by its content, for responses made of many movies where no
+/// single version covers them all.
+pub fn content_etag(body: &[u8]) -> HeaderValue {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    HeaderValue::from_str(&format!("\"{:016x}\"", hasher.finish()))
+        .expect("hex digits are a valid header value")
+}
+
+/// `304 Not Modified`, repeating the tag the client already holds.
+pub fn not_modified(etag: HeaderValue) -> Response {
+    (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response()
+}
+
+/// The `If-Match` header of a request that sent one.
+#[derive(Debug)]
+pub struct IfMatch(String);
+
+impl IfMatch {
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        // A value that is not ASCII names no tag, so it matches nothing.
+        headers
+            .get(header::IF_MATCH)
+            .map(|value| IfMatch(value.to_str().unwrap_or_default().to_string()))
+    }
+
+    /// Whether the header lists the tag of `version` or is `*`. `If-Match`
+    /// compares strongly, so weak `W/` tags never match.
+    pub fn matches(&self, version: u64) -> bool {
+        let etag = format!("\"{version}\"");
+        self.0
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag == etag)
+    }
+}
+
+/// The `If-None-Match` header of a request that sent one.
+#[derive(Debug)]
+pub struct IfNoneMatch(String);
+
+impl IfNoneMatch {
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        headers
+            .get(header::IF_NONE_MATCH)
+            .map(|value| IfNoneMatch(value.to_str().unwrap_or_default().to_string()))
+    }
+
+    /// Tags a client sent some other way than in the header, written as the
+    /// header would hold them.
+    pub fn new(tags: &str) -> Self {
+        IfNoneMatch(tags.to_string())
+    }
+
+    /// Whether the header lists `etag` or is `*`. `If-None-Match` compares
+    /// weakly, so a `W/` prefix is ignored.
+    pub fn matches(&self, etag: &HeaderValue) -> bool {
+        self.0
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/").as_bytes() == etag.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn if_match(value: &str) -> IfMatch {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_MATCH, HeaderValue::from_str(value).unwrap());
+        IfMatch::from_headers(&headers).unwrap()
+    }
+
+    #[test]
+    fn if_match_compares_listed_tags() {
+        assert!(if_match("\"3\"").matches(3));
+        assert!(if_match("\"1\", \"3\"").matches(3));
+        assert!(if_match("*").matches(3));
+        assert!(!if_match("\"2\"").matches(3));
+        assert!(!if_match("3").matches(3));
+        assert!(!if_match("W/\"3\"").matches(3));
+        assert!(IfMatch::from_headers(&HeaderMap::new()).is_none());
+    }
+
+    #[test]
+    fn if_none_match_compares_weakly() {
+        let if_none_match = |value| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static(value));
+            IfNoneMatch::from_headers(&headers).unwrap()
+        };
+
+        assert!(if_none_match("\"3\"").matches(&etag(3)));
+        assert!(if_none_match("W/\"3\"").matches(&etag(3)));
+        assert!(if_none_match("\"1\", \"3\"").matches(&etag(3)));
+        assert!(if_none_match("*").matches(&etag(3)));
+        assert!(!if_none_match("\"2\"").matches(&etag(3)));
+        assert_ne!(content_etag(b"[]"), content_etag(b"[{}]"));
+    }
+}
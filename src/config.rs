@@ -0,0 +1,1154 @@
+//! Server configuration. Every setting has a default, can be given in a TOML
+//! file named by `MOVIES_CONFIG`, and is overridden by its environment
+//! variable. Values are checked as they are loaded, so a bad one stops the
+//! server at startup with an error naming its variable rather than being
+//! ignored.
+//!
+//! The file takes the variables' names in lower case, optionally under a
+//! table named after their prefix: `port = 8080` sets `PORT`, and
+//! `requests = 50` under `[rate_limit]` or `rate_limit.requests = 50` sets
+//! `RATE_LIMIT_REQUESTS`. Arrays stand for the comma-separated lists the
+//! variables take.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use tracing_subscriber::EnvFilter;
+
+use crate::ConflictDetail;
+use crate::cache::CachePolicies;
+use crate::cors::CorsPolicy;
+use crate::durability::Durability;
+use crate::encryption::Key;
+
+/// Names the TOML file settings are read from before the environment.
+const CONFIG_FILE_VAR: &str = "MOVIES_CONFIG";
+
+/// Variables setting the same thing in different ways, of which at most one
+/// may be given.
+const ALTERNATIVES: &[&[&str]] = &[&["MOVIES_DB_PATH", "DATABASE_URL", "MOVIES_SPILL_DIR"]];
+
+/// Every variable a setting is read from; the file may set only these.
+const VARIABLES: &[&str] = &[
+    "BIND_ADDRESS",
+    "PORT",
+    "LOG_LEVEL",
+    "LOG_FORMAT",
+    "SORT_NAMES",
+    "SORT_ARTICLES",
+    "ID_REDIRECT_GRACE_SECS",
+    "CONFLICT_DETAIL",
+    "POPULARITY_CAPACITY",
+    "POPULARITY_DECAY_SECS",
+    "IMPORT_CHUNK_SIZE",
+    "BULK_WRITE_WAIT_MS",
+    "WRITE_DURABILITY",
+    "MOVIES_DB_PATH",
+    "SNAPSHOT_INTERVAL_SECS",
+    "ENCRYPTION_KEY",
+    "MEDIA_DIR",
+    "DATABASE_URL",
+    "MOVIES_SPILL_DIR",
+    "RESIDENT_MOVIES",
+    "RATE_LIMIT_REQUESTS",
+    "RATE_LIMIT_WINDOW_SECS",
+    "TRUST_FORWARDED_FOR",
+    "BASE_PATH",
+    "SHUTDOWN_TIMEOUT_SECS",
+    "BODY_LIMIT_BYTES",
+    "REQUEST_TIMEOUT_SECS",
+    "UPLOAD_BODY_LIMIT_BYTES",
+    "UPLOAD_TIMEOUT_SECS",
+    "SLOW_REQUEST_MS",
+    "MOVIE_MAX_BYTES",
+    "MOVIE_MAX_LIST_ITEMS",
+    "CORS_ALLOWED_ORIGINS",
+    "CORS_MAX_AGE_SECS",
+    "CACHE_CONTROL_ADMIN",
+    "CACHE_CONTROL_MOVIE",
+    "CACHE_CONTROL_LIST",
+    "CACHE_CONTROL_DOCS",
+    "CHAOS_ENABLED",
+    "OMDB_API_KEY",
+    "OMDB_BASE_URL",
+    "OUTBOUND_CONNECT_TIMEOUT_SECS",
+    "OUTBOUND_READ_TIMEOUT_SECS",
+    "OUTBOUND_RETRIES",
+    "OUTBOUND_BACKOFF_MS",
+    "OUTBOUND_MAX_PER_HOST",
+    "OUTBOUND_USER_AGENT",
+    "NATS_URL",
+    "NATS_SUBJECT",
+    "PUBLISH_BUFFER",
+];
+
+#[derive(Clone)]
+pub struct Config {
+    /// Address the server listens on.
+    pub bind_address: IpAddr,
+    /// Port the server listens on; 0 picks a free one.
+    pub port: u16,
+    /// What is logged, as `tracing` filter directives such as
+    /// `movies=debug,tower_http=warn`. `RUST_LOG` still wins when set.
+    pub log_level: String,
+    /// Log one JSON object per line instead of human-readable lines.
+    pub log_json: bool,
+    /// Derive a `sort_name` with leading articles moved to the end.
+    pub sort_names: bool,
+    /// Leading articles recognized when deriving `sort_name`.
+    pub articles: Vec<String>,
+    /// How long an old id keeps redirecting after `change-id`.
+    pub id_redirect_grace: Duration,
+    /// How much of the existing movie 409 responses embed.
+    pub conflict_detail: ConflictDetail,
+    /// How many movies the popularity counters track at most.
+    pub popularity_capacity: usize,
+    /// How often all popularity counts are halved.
+    pub popularity_decay: Duration,
+    /// Cache-Control sent for each class of route.
+    pub cache: CachePolicies,
+    /// Which browser origins may call the API.
+    pub cors: CorsPolicy,
+    /// How many lines a streaming import applies per write lock.
+    pub import_chunk_size: usize,
+    /// How long a write waits for a restore, import or transaction in
+    /// progress before it is refused; zero refuses it at once.
+    pub bulk_write_wait: Duration,
+    /// When writes are answered unless they ask with `?durability=`: once
+    /// on disk, or once logged.
+    pub write_durability: Durability,
+    /// JSON file the store is loaded from and saved to, with its write-ahead
+    /// log next to it; in memory only when unset.
+    pub db_path: Option<PathBuf>,
+    /// How often the write-ahead log is folded into the snapshot; zero only
+    /// does it at shutdown.
+    pub snapshot_interval: Duration,
+    /// Key the snapshot and its log are encrypted with, 32 bytes in base64;
+    /// plaintext when unset.
+    pub encryption_key: Option<Key>,
+    /// Directory the posters are kept in, created on the first upload.
+    pub media_dir: PathBuf,
+    /// Database holding the movies instead, e.g. `sqlite://movies.db`.
+    /// Exclusive with `db_path`.
+    pub database_url: Option<String>,
+    /// Directory holding one file per movie instead, with only the
+    /// `resident_movies` most recently used kept in memory. Exclusive with
+    /// `db_path` and `database_url`.
+    pub spill_dir: Option<PathBuf>,
+    /// How many movies stay in memory in front of `spill_dir`.
+    pub resident_movies: usize,
+    /// Requests a client may burst; 0 turns rate limiting off.
+    pub rate_limit_requests: u32,
+    /// How long an emptied allowance takes to refill completely.
+    pub rate_limit_window: Duration,
+    /// Count requests against `X-Forwarded-For` instead of the peer address,
+    /// and put links under `X-Forwarded-Prefix` instead of `base_path`.
+    pub trust_forwarded_for: bool,
+    /// Path a proxy serves the API under, e.g. `/api`, put in front of the
+    /// links responses carry; requests arrive without it.
+    pub base_path: String,
+    /// How long requests in flight at shutdown get to finish.
+    pub shutdown_timeout: Duration,
+    /// Largest request body a route without uploads reads.
+    pub body_limit: usize,
+    /// How long a route without uploads may take to answer.
+    pub request_timeout: Duration,
+    /// Largest body the imports, posters and restores read, and any request
+    /// carries at all.
+    pub upload_body_limit: usize,
+    /// How long the imports, posters and restores may take to answer.
+    pub upload_timeout: Duration,
+    /// Requests taking longer are logged as slow; zero logs none.
+    pub slow_request: Duration,
+    /// Largest a single movie may be, serialized as JSON.
+    pub movie_max_bytes: usize,
+    /// Most items a movie's `genres` or `cast` may hold.
+    pub movie_max_list_items: usize,
+    /// Install the fault-injection layer and `/admin/chaos`.
+    #[cfg(feature = "chaos")]
+    pub chaos: bool,
+    /// Key for the OMDb API; external imports are off without one.
+    #[cfg(feature = "metadata")]
+    pub omdb_api_key: Option<String>,
+    /// Where the OMDb API is reached.
+    #[cfg(feature = "metadata")]
+    pub omdb_base_url: String,
+    /// How long a request to another service may take to connect.
+    #[cfg(feature = "metadata")]
+    pub outbound_connect_timeout: Duration,
+    /// How long another service may leave a request without a byte.
+    #[cfg(feature = "metadata")]
+    pub outbound_read_timeout: Duration,
+    /// How many times a failed idempotent request to another service is
+    /// tried again.
+    #[cfg(feature = "metadata")]
+    pub outbound_retries: u32,
+    /// Wait before the first retry, doubled for each one after.
+    #[cfg(feature = "metadata")]
+    pub outbound_backoff: Duration,
+    /// Most requests in flight to one host; more wait their turn.
+    #[cfg(feature = "metadata")]
+    pub outbound_max_per_host: usize,
+    /// `User-Agent` of requests to other services.
+    #[cfg(feature = "metadata")]
+    pub outbound_user_agent: String,
+    /// `host:port` of the NATS server movie changes are published to; none
+    /// are published when unset.
+    #[cfg(feature = "nats")]
+    pub nats_url: Option<String>,
+    /// Subject the changes are published under.
+    #[cfg(feature = "nats")]
+    pub nats_subject: String,
+    /// Most changes held while the server cannot be reached; more are
+    /// dropped.
+    #[cfg(feature = "nats")]
+    pub publish_buffer: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 3000,
+            log_level: "info".to_string(),
+            log_json: false,
+            sort_names: false,
+            articles: ["The", "A", "An"].map(String::from).to_vec(),
+            id_redirect_grace: Duration::from_secs(7 * 24 * 60 * 60),
+            conflict_detail: ConflictDetail::Full,
+            popularity_capacity: 1000,
+            popularity_decay: Duration::from_secs(60 * 60),
+            cache: CachePolicies::default(),
+            cors: CorsPolicy::default(),
+            import_chunk_size: 500,
+            bulk_write_wait: Duration::from_secs(2),
+            write_durability: Durability::Flush,
+            db_path: None,
+            snapshot_interval: Duration::from_secs(60),
+            encryption_key: None,
+            media_dir: PathBuf::from("media"),
+            database_url: None,
+            spill_dir: None,
+            resident_movies: 1000,
+            rate_limit_requests: 100,
+            rate_limit_window: Duration::from_secs(10),
+            trust_forwarded_for: false,
+            base_path: String::new(),
+            shutdown_timeout: Duration::from_secs(10),
+            body_limit: 1024 * 1024,
+            request_timeout: Duration::from_secs(30),
+            upload_body_limit: 64 * 1024 * 1024,
+            upload_timeout: Duration::from_secs(5 * 60),
+            slow_request: Duration::from_secs(1),
+            movie_max_bytes: 64 * 1024,
+            movie_max_list_items: 500,
+            #[cfg(feature = "chaos")]
+            chaos: false,
+            #[cfg(feature = "metadata")]
+            omdb_api_key: None,
+            #[cfg(feature = "metadata")]
+            omdb_base_url: "http://www.omdbapi.com/".to_string(),
+            #[cfg(feature = "metadata")]
+            outbound_connect_timeout: Duration::from_secs(5),
+            #[cfg(feature = "metadata")]
+            outbound_read_timeout: Duration::from_secs(10),
+            #[cfg(feature = "metadata")]
+            outbound_retries: 2,
+            #[cfg(feature = "metadata")]
+            outbound_backoff: Duration::from_millis(200),
+            #[cfg(feature = "metadata")]
+            outbound_max_per_host: 4,
+            #[cfg(feature = "metadata")]
+            outbound_user_agent: concat!("movies/", env!("CARGO_PKG_VERSION")).to_string(),
+            #[cfg(feature = "nats")]
+            nats_url: None,
+            #[cfg(feature = "nats")]
+            nats_subject: "movies.events".to_string(),
+            #[cfg(feature = "nats")]
+            publish_buffer: 10_000,
+        }
+    }
+}
+
+/// Written to the startup log, so secrets are left out.
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut config = f.debug_struct("Config");
+        config
+            .field("bind_address", &self.bind_address)
+            .field("port", &self.port)
+            .field("log_level", &self.log_level)
+            .field("log_json", &self.log_json)
+            .field("sort_names", &self.sort_names)
+            .field("articles", &self.articles)
+            .field("id_redirect_grace", &self.id_redirect_grace)
+            .field("conflict_detail", &self.conflict_detail)
+            .field("popularity_capacity", &self.popularity_capacity)
+            .field("popularity_decay", &self.popularity_decay)
+            .field("cache", &self.cache)
+            .field("cors", &self.cors)
+            .field("import_chunk_size", &self.import_chunk_size)
+            .field("bulk_write_wait", &self.bulk_write_wait)
+            .field("write_durability", &self.write_durability)
+            .field("db_path", &self.db_path)
+            .field("snapshot_interval", &self.snapshot_interval)
+            .field(
+                "encryption_key",
+                &self.encryption_key.as_ref().map(|_| "<redacted>"),
+            )
+            .field("media_dir", &self.media_dir)
+            .field("database_url", &self.database_url)
+            .field("spill_dir", &self.spill_dir)
+            .field("resident_movies", &self.resident_movies)
+            .field("rate_limit_requests", &self.rate_limit_requests)
+            .field("rate_limit_window", &self.rate_limit_window)
+            .field("trust_forwarded_for", &self.trust_forwarded_for)
+            .field("base_path", &self.base_path)
+            .field("shutdown_timeout", &self.shutdown_timeout)
+            .field("body_limit", &self.body_limit)
+            .field("request_timeout", &self.request_timeout)
+            .field("upload_body_limit", &self.upload_body_limit)
+            .field("upload_timeout", &self.upload_timeout)
+            .field("slow_request", &self.slow_request)
+            .field("movie_max_bytes", &self.movie_max_bytes)
+            .field("movie_max_list_items", &self.movie_max_list_items);
+        #[cfg(feature = "chaos")]
+        config.field("chaos", &self.chaos);
+        #[cfg(feature = "metadata")]
+        config
+            .field(
+                "omdb_api_key",
+                &self.omdb_api_key.as_ref().map(|_| "<redacted>"),
+            )
+            .field("omdb_base_url", &self.omdb_base_url)
+            .field("outbound_connect_timeout", &self.outbound_connect_timeout)
+            .field("outbound_read_timeout", &self.outbound_read_timeout)
+            .field("outbound_retries", &self.outbound_retries)
+            .field("outbound_backoff", &self.outbound_backoff)
+            .field("outbound_max_per_host", &self.outbound_max_per_host)
+            .field("outbound_user_agent", &self.outbound_user_agent);
+        #[cfg(feature = "nats")]
+        config
+            .field("nats_url", &self.nats_url)
+            .field("nats_subject", &self.nats_subject)
+            .field("publish_buffer", &self.publish_buffer);
+        config.finish()
+    }
+}
+
+/// A setting that could not be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    /// The environment variable of the setting, or `MOVIES_CONFIG` for a
+    /// file that cannot be read.
+    pub variable: String,
+    pub message: String,
+}
+
+impl ConfigError {
+    fn new(variable: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            variable: variable.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.variable, self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+struct Setting {
+    value: String,
+    /// The file the value was read from, if not the environment.
+    file: Option<String>,
+}
+
+/// Raw setting values by variable, the environment's over the file's.
+#[derive(Default)]
+struct Settings {
+    values: HashMap<String, Setting>,
+}
+
+impl Settings {
+    fn get(&self, variable: &str) -> Option<&str> {
+        self.values
+            .get(variable)
+            .map(|setting| setting.value.as_str())
+    }
+
+    fn invalid(&self, variable: &str, message: String) -> ConfigError {
+        match self.values.get(variable).and_then(|s| s.file.as_deref()) {
+            Some(file) => ConfigError::new(variable, format!("{message} (set in {file})")),
+            None => ConfigError::new(variable, message),
+        }
+    }
+
+    fn parse<T: FromStr>(&self, variable: &str, expected: &str) -> Result<Option<T>, ConfigError> {
+        self.get(variable)
+            .map(|value| {
+                value.trim().parse().map_err(|_| {
+                    self.invalid(variable, format!("expected {expected}, got {value:?}"))
+                })
+            })
+            .transpose()
+    }
+
+    fn secs(&self, variable: &str) -> Result<Option<Duration>, ConfigError> {
+        Ok(self
+            .parse(variable, "a number of seconds")?
+            .map(Duration::from_secs))
+    }
+
+    fn flag(&self, variable: &str) -> Result<Option<bool>, ConfigError> {
+        self.get(variable)
+            .map(|value| match value.trim().to_ascii_lowercase().as_str() {
+                "true" | "1" => Ok(true),
+                "false" | "0" => Ok(false),
+                _ => Err(self.invalid(variable, format!("expected true or false, got {value:?}"))),
+            })
+            .transpose()
+    }
+
+    fn read_env(&mut self, env: &HashMap<String, String>) {
+        for variable in VARIABLES {
+            if let Some(value) = env.get(*variable) {
+                self.values.insert(
+                    variable.to_string(),
+                    Setting {
+                        value: value.clone(),
+                        file: None,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Reads the settings of a TOML file at `path` holding `text`.
+    fn read_file(&mut self, path: &str, text: &str) -> Result<(), ConfigError> {
+        let table: toml::Table = toml::from_str(text).map_err(|error| {
+            let line = error
+                .span()
+                .map_or(1, |span| text[..span.start].matches('\n').count() + 1);
+            ConfigError::new(
+                CONFIG_FILE_VAR,
+                format!("{path}, line {line}: {}", error.message().trim_end()),
+            )
+        })?;
+
+        let mut values = Vec::new();
+        flatten(String::new(), table, &mut values);
+        for (key, value) in values {
+            let unreadable =
+                |message: &str| ConfigError::new(CONFIG_FILE_VAR, format!("{path}: {message}"));
+            let variable = key.replace('.', "_").to_ascii_uppercase();
+            if !VARIABLES.contains(&variable.as_str()) {
+                return Err(unreadable(&format!("unknown setting `{key}`")));
+            }
+            let value = setting_value(value).ok_or_else(|| {
+                unreadable(&format!(
+                    "`{key}` must be a string, number, boolean or list of them"
+                ))
+            })?;
+
+            let setting = Setting {
+                value,
+                file: Some(path.to_string()),
+            };
+            // A dotted key and a table can name the same variable.
+            if self.values.insert(variable, setting).is_some() {
+                return Err(unreadable(&format!("`{key}` is set twice")));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops what the file says about a choice the environment makes, such
+    /// as the store, so the environment wins even by setting another
+    /// variable of it.
+    fn prefer_env(&mut self) {
+        for alternatives in ALTERNATIVES {
+            let from_env = alternatives.iter().any(|variable| {
+                self.values
+                    .get(*variable)
+                    .is_some_and(|setting| setting.file.is_none())
+            });
+            if from_env {
+                self.values.retain(|variable, setting| {
+                    setting.file.is_none() || !alternatives.contains(&variable.as_str())
+                });
+            }
+        }
+    }
+}
+
+/// The values of `table` with their dotted keys, tables nested in it
+/// included.
+fn flatten(prefix: String, table: toml::Table, values: &mut Vec<(String, toml::Value)>) {
+    for (key, value) in table {
+        let key = format!("{prefix}{key}");
+        match value {
+            toml::Value::Table(table) => flatten(format!("{key}."), table, values),
+            value => values.push((key, value)),
+        }
+    }
+}
+
+/// A TOML value as a variable would hold it, with arrays as comma-separated
+/// lists.
+fn setting_value(value: toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(value) => Some(value),
+        toml::Value::Integer(value) => Some(value.to_string()),
+        toml::Value::Float(value) => Some(value.to_string()),
+        toml::Value::Boolean(value) => Some(value.to_string()),
+        toml::Value::Datetime(value) => Some(value.to_string()),
+        toml::Value::Array(items) => items
+            .into_iter()
+            .map(|item| match item {
+                toml::Value::Array(_) | toml::Value::Table(_) => None,
+                item => setting_value(item),
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(|items| items.join(",")),
+        toml::Value::Table(_) => None,
+    }
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+impl Config {
+    /// Loads the configuration from the process's environment.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::load(&std::env::vars().collect())
+    }
+
+    /// Loads the configuration from `env`, reading the file its
+    /// `MOVIES_CONFIG` names first.
+    pub fn load(env: &HashMap<String, String>) -> Result<Self, ConfigError> {
+        let mut settings = Settings::default();
+        if let Some(path) = env.get(CONFIG_FILE_VAR) {
+            let text = std::fs::read_to_string(path).map_err(|error| {
+                ConfigError::new(CONFIG_FILE_VAR, format!("failed to read {path}: {error}"))
+            })?;
+            settings.read_file(path, &text)?;
+        }
+        settings.read_env(env);
+        settings.prefer_env();
+
+        Self::from_settings(&settings)
+    }
+
+    fn from_settings(settings: &Settings) -> Result<Self, ConfigError> {
+        let mut config = Config::default();
+
+        if let Some(address) = settings.parse("BIND_ADDRESS", "an IP address")? {
+            config.bind_address = address;
+        }
+        if let Some(port) = settings.parse("PORT", "a port number")? {
+            config.port = port;
+        }
+        if let Some(level) = settings.get("LOG_LEVEL") {
+            EnvFilter::try_new(level).map_err(|error| {
+                settings.invalid("LOG_LEVEL", format!("invalid filter {level:?}: {error}"))
+            })?;
+            config.log_level = level.to_string();
+        }
+        if let Some(format) = settings.get("LOG_FORMAT") {
+            config.log_json = match format.trim().to_ascii_lowercase().as_str() {
+                "json" => true,
+                "text" => false,
+                _ => {
+                    return Err(settings.invalid(
+                        "LOG_FORMAT",
+                        format!("expected text or json, got {format:?}"),
+                    ));
+                }
+            };
+        }
+        if let Some(sort_names) = settings.flag("SORT_NAMES")? {
+            config.sort_names = sort_names;
+        }
+        if let Some(articles) = settings.get("SORT_ARTICLES") {
+            config.articles = split_list(articles);
+        }
+        if let Some(grace) = settings.secs("ID_REDIRECT_GRACE_SECS")? {
+            config.id_redirect_grace = grace;
+        }
+        if let Some(detail) = settings.get("CONFLICT_DETAIL") {
+            config.conflict_detail = match detail.trim().to_ascii_lowercase().as_str() {
+                "full" => ConflictDetail::Full,
+                "minimal" => ConflictDetail::Minimal,
+                _ => {
+                    return Err(settings.invalid(
+                        "CONFLICT_DETAIL",
+                        format!("expected full or minimal, got {detail:?}"),
+                    ));
+                }
+            };
+        }
+        if let Some(capacity) = settings.parse("POPULARITY_CAPACITY", "a number")? {
+            config.popularity_capacity = capacity;
+        }
+        if let Some(decay) = settings.secs("POPULARITY_DECAY_SECS")? {
+            config.popularity_decay = decay;
+        }
+        if let Some(size) = settings.parse("IMPORT_CHUNK_SIZE", "a number")? {
+            if size == 0 {
+                return Err(settings.invalid("IMPORT_CHUNK_SIZE", "must be at least 1".to_string()));
+            }
+            config.import_chunk_size = size;
+        }
+        if let Some(ms) = settings.parse("BULK_WRITE_WAIT_MS", "a number of milliseconds")? {
+            config.bulk_write_wait = Duration::from_millis(ms);
+        }
+        if let Some(durability) = settings.get("WRITE_DURABILITY") {
+            config.write_durability = match durability.trim().to_ascii_lowercase().as_str() {
+                "flush" => Durability::Flush,
+                "buffered" => Durability::Buffered,
+                _ => {
+                    return Err(settings.invalid(
+                        "WRITE_DURABILITY",
+                        format!("expected flush or buffered, got {durability:?}"),
+                    ));
+                }
+            };
+        }
+        if let Some(path) = settings.get("MOVIES_DB_PATH") {
+            config.db_path = Some(PathBuf::from(path));
+        }
+        if let Some(interval) = settings.secs("SNAPSHOT_INTERVAL_SECS")? {
+            config.snapshot_interval = interval;
+        }
+        if let Some(path) = settings.get("MEDIA_DIR") {
+            config.media_dir = PathBuf::from(path);
+        }
+        if let Some(url) = settings.get("DATABASE_URL") {
+            if config.db_path.is_some() {
+                return Err(settings.invalid(
+                    "DATABASE_URL",
+                    "cannot be combined with MOVIES_DB_PATH".to_string(),
+                ));
+            }
+            config.database_url = Some(url.to_string());
+        }
+        if let Some(dir) = settings.get("MOVIES_SPILL_DIR") {
+            if config.db_path.is_some() || config.database_url.is_some() {
+                return Err(settings.invalid(
+                    "MOVIES_SPILL_DIR",
+                    "cannot be combined with MOVIES_DB_PATH or DATABASE_URL".to_string(),
+                ));
+            }
+            config.spill_dir = Some(PathBuf::from(dir));
+        }
+        if let Some(resident) = settings.parse("RESIDENT_MOVIES", "a number of movies")? {
+            if resident == 0 {
+                return Err(settings.invalid("RESIDENT_MOVIES", "must be at least 1".to_string()));
+            }
+            config.resident_movies = resident;
+        }
+        if let Some(key) = settings.get("ENCRYPTION_KEY") {
+            if config.db_path.is_none() {
+                return Err(settings.invalid(
+                    "ENCRYPTION_KEY",
+                    "needs MOVIES_DB_PATH, the only store written to files".to_string(),
+                ));
+            }
+            let key = Key::from_base64(key)
+                .map_err(|message| settings.invalid("ENCRYPTION_KEY", message))?;
+            config.encryption_key = Some(key);
+        }
+        if let Some(requests) = settings.parse("RATE_LIMIT_REQUESTS", "a number")? {
+            config.rate_limit_requests = requests;
+        }
+        if let Some(window) = settings.secs("RATE_LIMIT_WINDOW_SECS")? {
+            config.rate_limit_window = window;
+        }
+        if let Some(trust) = settings.flag("TRUST_FORWARDED_FOR")? {
+            config.trust_forwarded_for = trust;
+        }
+        if let Some(path) = settings.get("BASE_PATH") {
+            let path = path.trim().trim_end_matches('/');
+            if !path.is_empty() && !path.starts_with('/') {
+                return Err(
+                    settings.invalid("BASE_PATH", format!("must start with /, got {path:?}"))
+                );
+            }
+            config.base_path = path.to_string();
+        }
+        if let Some(timeout) = settings.secs("SHUTDOWN_TIMEOUT_SECS")? {
+            config.shutdown_timeout = timeout;
+        }
+        for (variable, limit) in [
+            ("BODY_LIMIT_BYTES", &mut config.body_limit),
+            ("UPLOAD_BODY_LIMIT_BYTES", &mut config.upload_body_limit),
+        ] {
+            if let Some(bytes) = settings.parse(variable, "a number of bytes")? {
+                *limit = bytes;
+            }
+        }
+        for (variable, timeout) in [
+            ("REQUEST_TIMEOUT_SECS", &mut config.request_timeout),
+            ("UPLOAD_TIMEOUT_SECS", &mut config.upload_timeout),
+        ] {
+            if let Some(secs) = settings.secs(variable)? {
+                if secs.is_zero() {
+                    return Err(settings.invalid(variable, "must be at least 1".to_string()));
+                }
+                *timeout = secs;
+            }
+        }
+        if let Some(ms) = settings.parse("SLOW_REQUEST_MS", "a number of milliseconds")? {
+            config.slow_request = Duration::from_millis(ms);
+        }
+        for (variable, budget) in [
+            ("MOVIE_MAX_BYTES", &mut config.movie_max_bytes),
+            ("MOVIE_MAX_LIST_ITEMS", &mut config.movie_max_list_items),
+        ] {
+            if let Some(value) = settings.parse(variable, "a number")? {
+                if value == 0 {
+                    return Err(settings.invalid(variable, "must be at least 1".to_string()));
+                }
+                *budget = value;
+            }
+        }
+        if config.upload_body_limit < config.body_limit {
+            return Err(settings.invalid(
+                "UPLOAD_BODY_LIMIT_BYTES",
+                "must be at least BODY_LIMIT_BYTES, which it bounds".to_string(),
+            ));
+        }
+        if let Some(origins) = settings.get("CORS_ALLOWED_ORIGINS") {
+            config.cors.allowed_origins = Some(CorsPolicy::parse_origins(origins));
+        }
+        if let Some(max_age) = settings.secs("CORS_MAX_AGE_SECS")? {
+            config.cors.max_age = max_age;
+        }
+        for (variable, policy) in [
+            ("CACHE_CONTROL_ADMIN", &mut config.cache.admin),
+            ("CACHE_CONTROL_MOVIE", &mut config.cache.movie),
+            ("CACHE_CONTROL_LIST", &mut config.cache.list),
+            ("CACHE_CONTROL_DOCS", &mut config.cache.docs),
+        ] {
+            if let Some(value) = settings.get(variable) {
+                *policy = value.to_string();
+            }
+        }
+        #[cfg(feature = "chaos")]
+        if let Some(chaos) = settings.flag("CHAOS_ENABLED")? {
+            config.chaos = chaos;
+        }
+        #[cfg(feature = "metadata")]
+        if let Some(key) = settings.get("OMDB_API_KEY") {
+            config.omdb_api_key = Some(key.to_string());
+        }
+        #[cfg(feature = "metadata")]
+        if let Some(url) = settings.get("OMDB_BASE_URL") {
+            config.omdb_base_url = url.to_string();
+        }
+        #[cfg(feature = "metadata")]
+        for (variable, timeout) in [
+            (
+                "OUTBOUND_CONNECT_TIMEOUT_SECS",
+                &mut config.outbound_connect_timeout,
+            ),
+            (
+                "OUTBOUND_READ_TIMEOUT_SECS",
+                &mut config.outbound_read_timeout,
+            ),
+        ] {
+            if let Some(secs) = settings.secs(variable)? {
+                if secs.is_zero() {
+                    return Err(settings.invalid(variable, "must be at least 1".to_string()));
+                }
+                *timeout = secs;
+            }
+        }
+        #[cfg(feature = "metadata")]
+        if let Some(retries) = settings.parse("OUTBOUND_RETRIES", "a number")? {
+            config.outbound_retries = retries;
+        }
+        #[cfg(feature = "metadata")]
+        if let Some(ms) = settings.parse("OUTBOUND_BACKOFF_MS", "a number of milliseconds")? {
+            config.outbound_backoff = Duration::from_millis(ms);
+        }
+        #[cfg(feature = "metadata")]
+        if let Some(max) = settings.parse("OUTBOUND_MAX_PER_HOST", "a number")? {
+            if max == 0 {
+                return Err(
+                    settings.invalid("OUTBOUND_MAX_PER_HOST", "must be at least 1".to_string())
+                );
+            }
+            config.outbound_max_per_host = max;
+        }
+        #[cfg(feature = "metadata")]
+        if let Some(agent) = settings.get("OUTBOUND_USER_AGENT") {
+            if reqwest::header::HeaderValue::from_str(agent).is_err() {
+                return Err(settings.invalid(
+                    "OUTBOUND_USER_AGENT",
+                    format!("not a valid header value: {agent:?}"),
+                ));
+            }
+            config.outbound_user_agent = agent.to_string();
+        }
+        #[cfg(feature = "nats")]
+        if let Some(url) = settings.get("NATS_URL") {
+            let address = url
+                .trim()
+                .trim_start_matches("nats://")
+                .trim_end_matches('/');
+            if address.is_empty() || address.contains(['/', ' ']) {
+                return Err(settings.invalid(
+                    "NATS_URL",
+                    format!("expected nats://host:port, got {url:?}"),
+                ));
+            }
+            config.nats_url = Some(if address.contains(':') {
+                address.to_string()
+            } else {
+                format!("{address}:4222")
+            });
+        }
+        #[cfg(feature = "nats")]
+        if let Some(subject) = settings.get("NATS_SUBJECT") {
+            let subject = subject.trim();
+            if subject.is_empty() || subject.contains(char::is_whitespace) {
+                return Err(settings.invalid(
+                    "NATS_SUBJECT",
+                    format!("expected a subject without spaces, got {subject:?}"),
+                ));
+            }
+            config.nats_subject = subject.to_string();
+        }
+        #[cfg(feature = "nats")]
+        if let Some(buffer) = settings.parse("PUBLISH_BUFFER", "a number")? {
+            if buffer == 0 {
+                return Err(settings.invalid("PUBLISH_BUFFER", "must be at least 1".to_string()));
+            }
+            config.publish_buffer = buffer;
+        }
+
+        Ok(config)
+    }
+
+    /// "The Matrix" becomes "Matrix, The"; names without a leading article
+    /// (or with sort names disabled) have no separate sort name.
+    pub fn sort_name(&self, name: &str) -> Option<String> {
+        if !self.sort_names {
+            return None;
+        }
+
+        let (first, rest) = name.split_once(' ')?;
+        self.articles
+            .iter()
+            .any(|article| article.eq_ignore_ascii_case(first))
+            .then(|| format!("{rest}, {first}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> HashMap<String, String> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// Loads `env` on top of a config file holding `toml`.
+    fn load_with_file(toml: &str, vars: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movies.toml");
+        std::fs::write(&path, toml).unwrap();
+
+        let mut env = env(vars);
+        env.insert(CONFIG_FILE_VAR.to_string(), path.display().to_string());
+        Config::load(&env)
+    }
+
+    #[test]
+    fn unset_settings_keep_their_defaults() {
+        let config = Config::load(&env(&[("HOME", "/root"), ("PORT_", "1")])).unwrap();
+        assert_eq!(config.bind_address, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        assert_eq!(config.port, 3000);
+        assert_eq!(config.log_level, "info");
+        assert!(!config.log_json);
+        assert_eq!(config.rate_limit_requests, 100);
+        assert_eq!(config.db_path, None);
+        assert_eq!(config.cors, CorsPolicy::default());
+
+        let config = Config::load(&env(&[
+            ("BIND_ADDRESS", "127.0.0.1"),
+            ("PORT", "8080"),
+            ("SORT_ARTICLES", "The, Der,,"),
+            ("CACHE_CONTROL_LIST", ""),
+        ]))
+        .unwrap();
+        assert_eq!(config.bind_address, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.articles, ["The", "Der"]);
+        assert_eq!(config.cache.list, "");
+    }
+
+    #[test]
+    fn the_environment_overrides_the_file() {
+        let toml = r##"
+            # Settings shared by every instance.
+            port = 8080
+            log_level = "movies=debug" # inline comments are fine
+            sort_articles = [
+                'The',
+                "#1",
+            ]
+            import.chunk_size = 10
+
+            [rate_limit]
+            requests = 5
+            window_secs = 1_000
+
+            [cors]
+            allowed_origins = ["https://a.example", "https://b.example/"]
+        "##;
+        let config =
+            load_with_file(toml, &[("PORT", "9090"), ("RATE_LIMIT_REQUESTS", "0")]).unwrap();
+
+        assert_eq!(config.port, 9090);
+        assert_eq!(config.rate_limit_requests, 0);
+        assert_eq!(config.rate_limit_window, Duration::from_secs(1000));
+        assert_eq!(config.log_level, "movies=debug");
+        assert_eq!(config.articles, ["The", "#1"]);
+        assert_eq!(config.import_chunk_size, 10);
+        assert_eq!(
+            config.cors.allowed_origins,
+            Some(vec![
+                "https://a.example".to_string(),
+                "https://b.example".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn cache_policies_are_overridden_per_class() {
+        let toml = r#"
+            [cache_control]
+            docs = "public, max-age=86400"
+            movie = "no-store"
+        "#;
+        let config = load_with_file(toml, &[("CACHE_CONTROL_MOVIE", "no-cache")]).unwrap();
+        assert_eq!(config.cache.docs, "public, max-age=86400");
+        assert_eq!(config.cache.movie, "no-cache");
+        assert_eq!(config.cache.admin, CachePolicies::default().admin);
+
+        let config = Config::load(&env(&[("CACHE_CONTROL_DOCS", "")])).unwrap();
+        assert_eq!(config.cache.docs, "");
+    }
+
+    #[test]
+    fn the_environment_picks_the_store_over_the_file() {
+        let config = load_with_file(
+            "movies_db_path = \"movies.json\"",
+            &[("DATABASE_URL", "sqlite::memory:")],
+        )
+        .unwrap();
+        assert_eq!(config.db_path, None);
+        assert_eq!(config.database_url.as_deref(), Some("sqlite::memory:"));
+
+        let config = load_with_file(
+            "database_url = \"sqlite::memory:\"",
+            &[("MOVIES_DB_PATH", "movies.json")],
+        )
+        .unwrap();
+        assert_eq!(config.db_path, Some(PathBuf::from("movies.json")));
+        assert_eq!(config.database_url, None);
+
+        let config = load_with_file(
+            "movies_db_path = \"movies.json\"\nresident_movies = 50",
+            &[("MOVIES_SPILL_DIR", "movies")],
+        )
+        .unwrap();
+        assert_eq!(config.db_path, None);
+        assert_eq!(config.spill_dir, Some(PathBuf::from("movies")));
+        assert_eq!(config.resident_movies, 50);
+
+        // Given both ways in one place, the choice is the user's to make.
+        let toml = "movies_db_path = \"movies.json\"\ndatabase_url = \"sqlite::memory:\"";
+        let error = load_with_file(toml, &[]).unwrap_err();
+        assert_eq!(error.variable, "DATABASE_URL");
+        assert!(error.message.ends_with("movies.toml)"), "{error}");
+    }
+
+    #[test]
+    fn invalid_settings_name_their_variable() {
+        let invalid = |vars: &[(&str, &str)]| Config::load(&env(vars)).unwrap_err();
+
+        let error = invalid(&[("PORT", "http")]);
+        assert_eq!(error.variable, "PORT");
+        assert_eq!(
+            error.to_string(),
+            r#"PORT: expected a port number, got "http""#
+        );
+        assert_eq!(invalid(&[("PORT", "65536")]).variable, "PORT");
+        assert_eq!(
+            invalid(&[("RESIDENT_MOVIES", "0")]).variable,
+            "RESIDENT_MOVIES"
+        );
+        assert_eq!(
+            invalid(&[("BIND_ADDRESS", "localhost")]).variable,
+            "BIND_ADDRESS"
+        );
+        assert_eq!(invalid(&[("SORT_NAMES", "yes")]).variable, "SORT_NAMES");
+        assert_eq!(
+            invalid(&[("CONFLICT_DETAIL", "some")]).variable,
+            "CONFLICT_DETAIL"
+        );
+        assert_eq!(
+            invalid(&[("WRITE_DURABILITY", "eventually")]).variable,
+            "WRITE_DURABILITY"
+        );
+        assert_eq!(
+            invalid(&[("LOG_LEVEL", "movies=loud")]).variable,
+            "LOG_LEVEL"
+        );
+        assert_eq!(invalid(&[("LOG_FORMAT", "yaml")]).variable, "LOG_FORMAT");
+        assert_eq!(invalid(&[("BASE_PATH", "api")]).variable, "BASE_PATH");
+        assert_eq!(
+            invalid(&[("IMPORT_CHUNK_SIZE", "0")]).variable,
+            "IMPORT_CHUNK_SIZE"
+        );
+        assert_eq!(
+            invalid(&[("REQUEST_TIMEOUT_SECS", "0")]).variable,
+            "REQUEST_TIMEOUT_SECS"
+        );
+        assert_eq!(
+            invalid(&[
+                ("BODY_LIMIT_BYTES", "2048"),
+                ("UPLOAD_BODY_LIMIT_BYTES", "1024")
+            ])
+            .variable,
+            "UPLOAD_BODY_LIMIT_BYTES"
+        );
+        assert_eq!(
+            invalid(&[("RATE_LIMIT_WINDOW_SECS", "-1")]).variable,
+            "RATE_LIMIT_WINDOW_SECS"
+        );
+        assert_eq!(
+            invalid(&[
+                ("DATABASE_URL", "sqlite::memory:"),
+                ("MOVIES_DB_PATH", "movies.json")
+            ])
+            .variable,
+            "DATABASE_URL"
+        );
+
+        // Values from the file are checked alike, and say where they are.
+        for toml in ["port = \"http\"", "port = 80.80"] {
+            let error = load_with_file(toml, &[]).unwrap_err();
+            assert_eq!(error.variable, "PORT");
+            assert!(error.message.ends_with("movies.toml)"), "{error}");
+        }
+    }
+
+    #[test]
+    fn unreadable_files_are_refused() {
+        for toml in [
+            "port = 8080\nport = 8081",
+            "port 8080",
+            "log_level = \"info",
+            "sort_articles = [\"The\",",
+            "port = 8080 8081",
+        ] {
+            let error = load_with_file(toml, &[]).unwrap_err();
+            assert_eq!(error.variable, CONFIG_FILE_VAR, "{toml}");
+            assert!(error.message.contains("movies.toml, line "), "{error}");
+        }
+        for toml in [
+            "prot = 8080",
+            "[rate_limit.window]\nminutes = 1",
+            "[[movies]]",
+            "sort_articles = [[\"The\"]]",
+            "rate_limit_requests = 1\n[rate_limit]\nrequests = 2",
+        ] {
+            let error = load_with_file(toml, &[]).unwrap_err();
+            assert_eq!(error.variable, CONFIG_FILE_VAR, "{toml}");
+            assert!(error.message.contains("movies.toml: "), "{error}");
+        }
+
+        let error =
+            Config::load(&env(&[(CONFIG_FILE_VAR, "/nonexistent/movies.toml")])).unwrap_err();
+        assert_eq!(error.variable, CONFIG_FILE_VAR);
+    }
+
+    #[cfg(feature = "metadata")]
+    #[test]
+    fn secrets_are_redacted_when_logged() {
+        let config = Config::load(&env(&[("OMDB_API_KEY", "s3cret")])).unwrap();
+        assert_eq!(config.omdb_api_key.as_deref(), Some("s3cret"));
+
+        let logged = format!("{config:?}");
+        assert!(!logged.contains("s3cret"), "{logged}");
+        assert!(logged.contains("<redacted>"), "{logged}");
+    }
+
+    #[cfg(feature = "nats")]
+    #[test]
+    fn nats_urls_default_their_port() {
+        let config = Config::load(&env(&[("NATS_URL", "nats://broker")])).unwrap();
+        assert_eq!(config.nats_url.as_deref(), Some("broker:4222"));
+        let config = Config::load(&env(&[("NATS_URL", "broker:4333")])).unwrap();
+        assert_eq!(config.nats_url.as_deref(), Some("broker:4333"));
+
+        let error = Config::load(&env(&[("NATS_URL", "nats://broker/events")])).unwrap_err();
+        assert_eq!(error.variable, "NATS_URL");
+        let error = Config::load(&env(&[("PUBLISH_BUFFER", "0")])).unwrap_err();
+        assert_eq!(error.variable, "PUBLISH_BUFFER");
+    }
+
+    #[test]
+    fn encryption_keys_are_checked_and_never_shown() {
+        // 32 bytes, `a` to `F`.
+        let key = "YWJjZGVmZ2hpamtsbW5vcHFyc3R1dnd4eXpBQkNERUY=";
+        let config = Config::load(&env(&[
+            ("MOVIES_DB_PATH", "movies.json"),
+            ("ENCRYPTION_KEY", key),
+        ]))
+        .unwrap();
+        assert!(config.encryption_key.is_some());
+        let logged = format!("{config:?}");
+        assert!(!logged.contains(key), "{logged}");
+        assert!(
+            logged.contains("encryption_key: Some(\"<redacted>\")"),
+            "{logged}"
+        );
+
+        for short in ["c2hvcnQ=", "not base64!"] {
+            let error = Config::load(&env(&[
+                ("MOVIES_DB_PATH", "movies.json"),
+                ("ENCRYPTION_KEY", short),
+            ]))
+            .unwrap_err();
+            assert_eq!(error.variable, "ENCRYPTION_KEY");
+            assert!(!error.message.contains(short), "{error}");
+        }
+
+        // Nothing would be encrypted.
+        let error = Config::load(&env(&[("ENCRYPTION_KEY", key)])).unwrap_err();
+        assert_eq!(error.variable, "ENCRYPTION_KEY");
+        assert!(error.message.contains("MOVIES_DB_PATH"), "{error}");
+    }
+}
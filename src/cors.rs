@@ -0,0 +1,89 @@
+//! Cross-origin access for browser clients. Without a configured list every
+//! origin is allowed, which suits development; with `CORS_ALLOWED_ORIGINS`
+//! only the listed origins get `Access-Control-Allow-Origin`, so browsers
+//! refuse the responses everywhere else.
+
+use std::time::Duration;
+
+use axum::http::{HeaderName, HeaderValue, Method, header};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// Request headers besides the CORS-safelisted ones that clients may send:
+/// bodies, credentials, the conditional requests the API supports and the
+/// API version.
+const ALLOWED_HEADERS: [HeaderName; 5] = [
+    header::CONTENT_TYPE,
+    header::AUTHORIZATION,
+    header::IF_MATCH,
+    header::IF_NONE_MATCH,
+    crate::api_version::X_API_VERSION,
+];
+
+/// Response headers scripts need to read, which browsers hide otherwise.
+const EXPOSED_HEADERS: [&str; 7] = [
+    "etag",
+    "link",
+    "retry-after",
+    "x-durable",
+    "x-ratelimit-remaining",
+    "x-request-id",
+    "x-skipped-fields",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorsPolicy {
+    /// Origins such as `https://app.example.com`, or `*` for any; `None`
+    /// allows everything.
+    pub allowed_origins: Option<Vec<String>>,
+    /// How long browsers may cache a preflight answer.
+    pub max_age: Duration,
+}
+
+impl Default for CorsPolicy {
+    fn default() -> Self {
+        Self {
+            allowed_origins: None,
+            max_age: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl CorsPolicy {
+    /// Reads the comma-separated `CORS_ALLOWED_ORIGINS` value.
+    pub fn parse_origins(value: &str) -> Vec<String> {
+        value
+            .split(',')
+            .map(|origin| origin.trim().trim_end_matches('/').to_string())
+            .filter(|origin| !origin.is_empty())
+            .collect()
+    }
+
+    pub fn layer(&self) -> CorsLayer {
+        let Some(origins) = &self.allowed_origins else {
+            return CorsLayer::permissive().max_age(self.max_age);
+        };
+
+        let allow_origin = if origins.iter().any(|origin| origin == "*") {
+            AllowOrigin::any()
+        } else {
+            AllowOrigin::list(origins.iter().filter_map(|origin| {
+                HeaderValue::from_str(origin)
+                    .inspect_err(|_| tracing::warn!(%origin, "ignoring invalid CORS origin"))
+                    .ok()
+            }))
+        };
+
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([
+                Method::GET,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ])
+            .allow_headers(ALLOWED_HEADERS)
+            .expose_headers(EXPOSED_HEADERS.map(HeaderName::from_static))
+            .max_age(self.max_age)
+    }
+}
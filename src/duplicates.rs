@@ -0,0 +1,227 @@
+//! Near-duplicate movies, as imports tend to leave behind:
+//! `GET /movie/duplicates` groups movies whose names only differ in casing,
+//! punctuation or a leading article and that share a year, and
+//! `POST /movie/merge` folds such a group into one of its movies.
+
+use std::collections::{BTreeMap, HashSet};
+
+use axum::{extract::State, response::Json};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::errors::{ApiError, ErrorBody, FieldError};
+use crate::events::EventKind;
+use crate::extract::JsonBody;
+use crate::ratings::RatedMovie;
+use crate::repo::{RepoError, Write};
+use crate::{AppState, Movie, hand_over, normalize_name};
+
+/// What movies are grouped by: the name lowercased, without punctuation and
+/// with a leading article moved to the end, so "The Matrix" and
+/// "Matrix, The" both become "matrix the".
+pub fn duplicate_key(name: &str, articles: &[String]) -> String {
+    let stripped: String = normalize_name(name)
+        .chars()
+        .filter(|c| c.is_alphanumeric() || c.is_whitespace())
+        .collect();
+    let mut words: Vec<&str> = stripped.split_whitespace().collect();
+    if words.len() > 1
+        && articles
+            .iter()
+            .any(|article| article.to_lowercase() == words[0])
+    {
+        words.rotate_left(1);
+    }
+
+    words.join(" ")
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct DuplicateGroup {
+    /// The normalized name the movies share.
+    pub key: String,
+    pub year: u16,
+    /// At least two, by id.
+    pub movies: Vec<Movie>,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct MergeRequest {
+    /// The movie that stays.
+    pub keep: String,
+    /// The movies folded into `keep` and deleted.
+    pub remove: Vec<String>,
+}
+
+/// Groups of live movies sharing a year and a normalized name, by name and
+/// year.
+#[utoipa::path(
+    get,
+    path = "/movie/duplicates",
+    tag = "movies",
+    summary = "List groups of likely duplicate movies",
+    responses(
+        (status = OK, description = "Every group of more than one movie", body = Vec<DuplicateGroup>),
+    )
+)]
+pub async fn find(State(state): State<AppState>) -> Result<Json<Vec<DuplicateGroup>>, ApiError> {
+    let mut groups: BTreeMap<(String, u16), Vec<Movie>> = BTreeMap::new();
+    for movie in state.live_movies().await? {
+        groups
+            .entry((
+                duplicate_key(&movie.name, &state.config.articles),
+                movie.year,
+            ))
+            .or_default()
+            .push(movie);
+    }
+
+    Ok(Json(
+        groups
+            .into_iter()
+            .filter(|(_, movies)| movies.len() > 1)
+            .map(|((key, year), mut movies)| {
+                movies.sort_by(|a, b| a.id.cmp(&b.id));
+                DuplicateGroup { key, year, movies }
+            })
+            .collect(),
+    ))
+}
+
+/// Checks a merge against the live movies, reporting every problem at once.
+async fn validate(state: &AppState, request: &MergeRequest) -> Result<Movie, ApiError> {
+    let mut errors = Vec::new();
+    let keep = state.live_movie(&request.keep).await?;
+    if keep.is_none() {
+        errors.push(FieldError::new(
+            "keep",
+            format!("no movie with id {}", request.keep),
+        ));
+    }
+    if request.remove.is_empty() {
+        errors.push(FieldError::new("remove", "must name at least one movie"));
+    }
+
+    let mut seen = HashSet::new();
+    for (index, id) in request.remove.iter().enumerate() {
+        let field = format!("remove[{index}]");
+        if *id == request.keep {
+            errors.push(FieldError::new(field, "is the movie kept"));
+        } else if !seen.insert(id) {
+            errors.push(FieldError::new(
+                field,
+                format!("{id} is given more than once"),
+            ));
+        } else if state.live_movie(id).await?.is_none() {
+            errors.push(FieldError::new(field, format!("no movie with id {id}")));
+        }
+    }
+
+    match keep {
+        Some(keep) if errors.is_empty() => Ok(keep),
+        _ => Err(ApiError::Validation(errors)),
+    }
+}
+
+/// Deletes the `remove` movies for good and gives their ratings, watchlist
+/// entries, slugs and ids to `keep`, which moves to its next version; an old
+/// id redirects to `keep` like after a `change-id`. The movies change in one
+/// repository batch and the indexes follow, all while the re-keying lock is
+/// held for writing, so a read of a movie with its ratings, watchlists or
+/// redirects sees the movies before the merge or after it, never halfway.
+/// Nothing changes unless every movie named exists and `keep` is not also
+/// removed.
+#[utoipa::path(
+    post,
+    path = "/movie/merge",
+    tag = "movies",
+    summary = "Merge duplicate movies into one",
+    request_body = MergeRequest,
+    responses(
+        (status = OK, description = "The movie kept, with the ratings of the merged ones", body = RatedMovie),
+        (status = UNPROCESSABLE_ENTITY, description = "Unknown or repeated ids, or `keep` among `remove`; nothing was changed", body = ErrorBody),
+    )
+)]
+pub async fn merge(
+    State(state): State<AppState>,
+    JsonBody(request): JsonBody<MergeRequest>,
+) -> Result<Json<RatedMovie>, ApiError> {
+    let _fence = state.fence().await?;
+    let _rekeying = state.rekeying.write().await;
+    let (before, keep, removed) = loop {
+        let before = validate(&state, &request).await?;
+        let mut keep = before.clone();
+        let mut removed = Vec::with_capacity(request.remove.len());
+        for id in &request.remove {
+            removed.extend(state.live_movie(id).await?);
+        }
+        if removed.len() < request.remove.len() {
+            // Trashed since it was checked; check again.
+            continue;
+        }
+
+        keep.version += 1;
+        let mut writes = vec![Write::Update(keep.clone())];
+        // At the versions read, so the movies announced as deleted are the
+        // ones deleted.
+        writes.extend(
+            removed
+                .iter()
+                .map(|movie| Write::Delete(movie.id.clone(), Some(movie.version))),
+        );
+        match state.repo.apply(writes).await {
+            // Changed or deleted since it was checked; check again.
+            Err(RepoError::Stale(_) | RepoError::NotFound(_)) => continue,
+            result => result?,
+        }
+        break (before, keep, removed);
+    };
+
+    state.ratings.merge(&request.remove, &keep.id);
+    state.watchlists.merge_movies(&request.remove, &keep.id);
+    for movie in removed {
+        state.popularity.remove(&movie.id);
+        state.posters.remove(&movie.id).await;
+        state.events.publish(EventKind::Deleted, movie);
+    }
+    for id in &request.remove {
+        hand_over(&state, id.clone(), &keep.id);
+    }
+    state.events.publish_update(&before, keep.clone());
+    tracing::info!(
+        event = "movie.merged",
+        id = %keep.id,
+        removed = ?request.remove,
+        "movies merged"
+    );
+
+    Ok(Json(RatedMovie {
+        ratings: state.ratings.summary(&keep.id),
+        movie: keep,
+        expanded: None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_differing_in_case_punctuation_and_articles_match() {
+        let articles = ["The", "A", "An"].map(String::from);
+        let key = |name| duplicate_key(name, &articles);
+
+        assert_eq!(key("The Matrix"), "matrix the");
+        assert_eq!(key("Matrix, The"), key("The Matrix"));
+        assert_eq!(key("  the MATRIX!"), key("The Matrix"));
+        assert_eq!(key("Spider-Man"), key("Spiderman"));
+        assert_eq!(key("A Beautiful Mind"), key("Beautiful Mind, A"));
+        assert_eq!(key("Amélie"), "amélie");
+
+        // Only a leading article moves, and not a name of nothing else.
+        assert_eq!(key("Theory of Everything"), "theory of everything");
+        assert_eq!(key("Escape from the Planet"), "escape from the planet");
+        assert_eq!(key("The"), "the");
+        assert_ne!(key("The Matrix"), key("Matrix"));
+    }
+}
@@ -0,0 +1,68 @@
+//! Write concern. The write-ahead log takes every write before it is
+//! answered, but only reaches the disk when synced: a write made with
+//! `?durability=flush`, or with `WRITE_DURABILITY=flush` (the default) and
+//! no parameter, is answered once the repository has synced it, while
+//! `?durability=buffered` answers as soon as the write is in the log and
+//! leaves the sync to the operating system. Either way, `X-Durable` says
+//! whether the write is known to be on disk.
+
+use axum::{
+    body::HttpBody,
+    extract::{Query, Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+use serde::Deserialize;
+
+use crate::AppState;
+use crate::errors::ApiError;
+
+pub const X_DURABLE: &str = "x-durable";
+
+/// When a write is answered.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Durability {
+    /// Once it is on disk.
+    Flush,
+    /// Once the log has it, before it is synced.
+    Buffered,
+}
+
+#[derive(Deserialize, Debug)]
+struct DurabilityParams {
+    durability: Option<Durability>,
+}
+
+/// Syncs the repository after every request that may write, when its
+/// durability asks for it. Streamed responses, like a streamed import, go
+/// on writing after they are answered, so they are never reported durable.
+pub async fn confirm(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if request.method().is_safe() {
+        return Ok(next.run(request).await);
+    }
+    let Query(params) = Query::<DurabilityParams>::try_from_uri(request.uri())
+        .map_err(|rejection| ApiError::BadRequest(rejection.body_text()))?;
+    let durability = params.durability.unwrap_or(state.config.write_durability);
+
+    let mut response = next.run(request).await;
+    let durable = durability == Durability::Flush
+        && response.body().size_hint().exact().is_some()
+        && match state.repo.sync().await {
+            Ok(durable) => durable,
+            Err(error) => {
+                tracing::error!(%error, "failed to sync the store");
+                false
+            }
+        };
+    response.headers_mut().insert(
+        X_DURABLE,
+        HeaderValue::from_static(if durable { "true" } else { "false" }),
+    );
+    Ok(response)
+}
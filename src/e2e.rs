@@ -0,0 +1,420 @@
+//! End-to-end tests against the real server over TCP. Unlike the `oneshot`
+//! tests in `main.rs` these go through connection handling, so they cover
+//! concurrent sockets, streaming bodies and shutdown. Every test starts its
+//! own server on an ephemeral port and can run in parallel with the rest.
+
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{Router, routing::get};
+use futures_util::StreamExt;
+use reqwest::{Body, Client, StatusCode};
+use serde_json::{Value, json};
+use tokio::net::TcpListener;
+use tokio::sync::{Notify, mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+use crate::{Config, run, serve};
+
+/// A running server. Dropping it, e.g. while a failing test unwinds, stops
+/// the server so no task outlives its test.
+struct TestServer {
+    base_url: String,
+    shutdown: Option<oneshot::Sender<()>>,
+    server: Option<JoinHandle<std::io::Result<()>>>,
+}
+
+impl TestServer {
+    async fn start(config: Config) -> Self {
+        let (shutdown, signal) = oneshot::channel();
+        let config = Config {
+            bind_address: Ipv4Addr::LOCALHOST.into(),
+            port: 0,
+            ..config
+        };
+        let (addr, server) = serve(config, async {
+            let _ = signal.await;
+        })
+        .await
+        .expect("failed to start test server");
+
+        TestServer {
+            base_url: format!("http://{addr}"),
+            shutdown: Some(shutdown),
+            server: Some(server),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+
+    /// Shuts down gracefully and waits until in-flight requests are done.
+    async fn shutdown(mut self) -> std::io::Result<()> {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+
+        let server = self.server.take().expect("server is running");
+        server.await.expect("server task panicked")
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(server) = self.server.take() {
+            server.abort();
+        }
+    }
+}
+
+/// A request body fed line by line from the test.
+fn streamed_body() -> (mpsc::Sender<String>, Body) {
+    let (lines, rx) = mpsc::channel::<String>(8);
+    let body = futures_util::stream::unfold(rx, |mut rx| async move {
+        let line = rx.recv().await?;
+        Some((Ok::<_, std::io::Error>(line), rx))
+    });
+
+    (lines, Body::wrap_stream(body))
+}
+
+fn movie_line(id: &str, name: &str) -> String {
+    format!(
+        "{}\n",
+        json!({"id": id, "name": name, "year": 2000, "was_good": true})
+    )
+}
+
+/// Polls `GET path` until it answers 200.
+async fn wait_for(client: &Client, url: &str) {
+    for _ in 0..200 {
+        let response = client.get(url).send().await.unwrap();
+        if response.status() == StatusCode::OK {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    panic!("{url} never became available");
+}
+
+/// Polls `GET url` until the connection is refused.
+async fn wait_until_refused(url: &str) {
+    for _ in 0..200 {
+        if Client::new().get(url).send().await.is_err() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    panic!("server kept accepting connections");
+}
+
+/// A bare app whose `GET /slow` notifies `started` and answers only once
+/// `release` is notified, plus the server running it under `run`.
+struct SlowServer {
+    url: String,
+    started: Arc<Notify>,
+    release: Arc<Notify>,
+    shutdown: oneshot::Sender<()>,
+    server: JoinHandle<std::io::Result<()>>,
+}
+
+impl SlowServer {
+    async fn start(drain_timeout: Duration) -> Self {
+        let started = Arc::new(Notify::new());
+        let release = Arc::new(Notify::new());
+        let app = Router::new().route(
+            "/slow",
+            get({
+                let (started, release) = (started.clone(), release.clone());
+                move || async move {
+                    started.notify_one();
+                    release.notified().await;
+                    "done"
+                }
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/slow", listener.local_addr().unwrap());
+        let (shutdown, signal) = oneshot::channel();
+        let server = tokio::spawn(run(
+            listener,
+            app,
+            async {
+                let _ = signal.await;
+            },
+            drain_timeout,
+        ));
+
+        SlowServer {
+            url,
+            started,
+            release,
+            shutdown,
+            server,
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_clients_interleave_crud() {
+    let server = TestServer::start(Config::default()).await;
+    let client = Client::new();
+
+    let clients: Vec<_> = (0..8)
+        .map(|n| {
+            let client = client.clone();
+            let url = server.url("/movie");
+            tokio::spawn(async move {
+                let id = n.to_string();
+                let mut movie = json!({
+                    "id": id,
+                    "name": format!("Movie {n}"),
+                    "year": 2000,
+                    "was_good": false,
+                });
+
+                let response = client.post(&url).json(&movie).send().await.unwrap();
+                assert_eq!(response.status(), StatusCode::CREATED);
+
+                movie["was_good"] = json!(true);
+                let response = client
+                    .put(format!("{url}/{id}"))
+                    .json(&movie)
+                    .send()
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+
+                let response = client.get(format!("{url}/{id}")).send().await.unwrap();
+                let movie: Value = response.json().await.unwrap();
+                assert_eq!(movie["was_good"], true);
+
+                if n % 2 == 0 {
+                    let response = client.delete(format!("{url}/{id}")).send().await.unwrap();
+                    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+                }
+            })
+        })
+        .collect();
+
+    for client in clients {
+        client.await.unwrap();
+    }
+
+    let page: Value = client
+        .get(server.url("/movie"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let movies = page["items"].as_array().unwrap();
+    let ids: Vec<&str> = movies.iter().map(|m| m["id"].as_str().unwrap()).collect();
+    assert_eq!(ids, ["1", "3", "5", "7"]);
+    assert!(movies.iter().all(|m| m["was_good"] == true));
+
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn import_progress_streams_while_uploading() {
+    let server = TestServer::start(Config {
+        import_chunk_size: 1,
+        ..Config::default()
+    })
+    .await;
+    let (lines, body) = streamed_body();
+
+    lines.send(movie_line("1", "Heat")).await.unwrap();
+    let response = Client::new()
+        .post(server.url("/movie/import/stream"))
+        .header("content-type", "application/x-ndjson")
+        .header("accept", "text/event-stream")
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Each progress event arrives before the next line is even sent.
+    let mut events = response.bytes_stream();
+    let mut received = String::new();
+    for (n, next) in [(1, Some("2")), (2, Some("3")), (3, None)] {
+        while !received.contains("\n\n") {
+            let chunk = events.next().await.unwrap().unwrap();
+            received.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        let (event, rest) = received.split_once("\n\n").unwrap();
+        assert!(event.starts_with("event: progress"), "{event}");
+        assert!(event.contains(&format!(r#""lines":{n}"#)), "{event}");
+        received = rest.to_string();
+
+        if let Some(id) = next {
+            lines.send(movie_line(id, "Heat")).await.unwrap();
+        }
+    }
+
+    drop(lines);
+    while let Some(chunk) = events.next().await {
+        received.push_str(std::str::from_utf8(&chunk.unwrap()).unwrap());
+    }
+    assert!(received.starts_with("event: summary"), "{received}");
+    assert!(received.contains(r#""created":3"#), "{received}");
+
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn shutdown_finishes_in_flight_requests() {
+    let server = TestServer::start(Config {
+        import_chunk_size: 1,
+        ..Config::default()
+    })
+    .await;
+    let client = Client::new();
+    let (lines, body) = streamed_body();
+
+    let import = tokio::spawn(
+        client
+            .post(server.url("/movie/import/stream"))
+            .header("content-type", "application/x-ndjson")
+            .body(body)
+            .send(),
+    );
+    lines.send(movie_line("1", "Heat")).await.unwrap();
+    wait_for(&client, &server.url("/movie/1")).await;
+
+    let url = server.url("/movie");
+    let stopping = tokio::spawn(server.shutdown());
+
+    // The listener is closed right away, while the import keeps going.
+    wait_until_refused(&url).await;
+    assert!(!stopping.is_finished());
+
+    lines.send(movie_line("2", "Dune")).await.unwrap();
+    drop(lines);
+
+    let response = import.await.unwrap().unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let report: Value = response.json().await.unwrap();
+    assert_eq!(report["created"], 2);
+
+    stopping.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn run_drains_requests_in_flight() {
+    let server = SlowServer::start(Duration::from_secs(10)).await;
+    let slow = tokio::spawn(Client::new().get(&server.url).send());
+    server.started.notified().await;
+
+    server.shutdown.send(()).unwrap();
+    wait_until_refused(&server.url).await;
+    assert!(!server.server.is_finished());
+
+    server.release.notify_one();
+    let response = slow.await.unwrap().unwrap();
+    assert_eq!(response.text().await.unwrap(), "done");
+    server.server.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn run_gives_up_on_requests_past_the_timeout() {
+    let server = SlowServer::start(Duration::from_millis(50)).await;
+    let slow = tokio::spawn(Client::new().get(&server.url).send());
+    server.started.notified().await;
+
+    // The handler is never released, so only the timeout ends the server.
+    server.shutdown.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(5), server.server)
+        .await
+        .expect("server outlived its shutdown timeout")
+        .unwrap()
+        .unwrap();
+    assert!(!slow.is_finished());
+}
+
+#[cfg(feature = "client")]
+#[tokio::test]
+async fn typed_client_maps_responses_and_errors() {
+    use movies::client::{ClientError, MoviesClient};
+    use movies::model::{Movie, MoviePatch};
+
+    let server = TestServer::start(Config::default()).await;
+    let client = MoviesClient::new(server.url("/"));
+
+    let movie: Movie = serde_json::from_value(json!({
+        "id": "1",
+        "name": "The Matrix",
+        "year": 1999,
+        "was_good": true,
+        "custom": {"rewatches": 2},
+    }))
+    .unwrap();
+
+    let created = client.create(&movie).await.unwrap();
+    assert_eq!(created.slug, "the-matrix-1999");
+    assert_eq!(client.get("1").await.unwrap(), created);
+
+    let patched = client
+        .patch(
+            "1",
+            &MoviePatch {
+                was_good: Some(false),
+                ..MoviePatch::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert!(!patched.was_good);
+    assert_eq!(patched.name, "The Matrix");
+
+    let listed = client.list(&[("custom.rewatches", "2")]).await.unwrap();
+    assert_eq!(listed.items, [patched]);
+    assert_eq!(listed.total, 1);
+    assert!(
+        client
+            .list(&[("custom.rewatches", "3")])
+            .await
+            .unwrap()
+            .items
+            .is_empty()
+    );
+
+    match client.create(&movie).await {
+        Err(ClientError::Conflict {
+            conflict_type,
+            existing,
+            ..
+        }) => {
+            assert_eq!(conflict_type, "duplicate_id");
+            assert_eq!(existing.unwrap()["name"], "The Matrix");
+        }
+        other => panic!("expected a conflict, got {other:?}"),
+    }
+
+    let invalid = Movie {
+        id: String::new(),
+        ..movie.clone()
+    };
+    match client.create(&invalid).await {
+        Err(ClientError::Validation(errors)) => assert_eq!(errors[0].field, "id"),
+        other => panic!("expected a validation error, got {other:?}"),
+    }
+
+    client.delete("1").await.unwrap();
+    match client.get("1").await {
+        Err(ClientError::NotFound { resource, id }) => {
+            assert_eq!((resource.as_str(), id.as_str()), ("movie", "1"));
+        }
+        other => panic!("expected not found, got {other:?}"),
+    }
+
+    server.shutdown().await.unwrap();
+}
@@ -0,0 +1,153 @@
+//! Encryption at rest for the snapshot and its write-ahead log, on when
+//! `ENCRYPTION_KEY` is set. Everything written is sealed with
+//! ChaCha20-Poly1305 under a fresh random nonce: snapshot files whole, the
+//! log line by line so appending stays appending. Sealed data is marked, so
+//! plaintext written before a key was set is still read, for `storage` to
+//! rewrite sealed.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chacha20poly1305::aead::{Aead, Generate, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+
+/// Starts a sealed snapshot file; plaintext ones start with JSON.
+const SEALED_FILE: &[u8] = b"movies-sealed-v1\n";
+
+/// Starts a sealed line of the log, followed by the sealed bytes in base64.
+const SEALED_LINE: &[u8] = b"sealed:";
+
+const NONCE_LEN: usize = 12;
+
+/// The key files are sealed with. Never printed, not even in `Debug`.
+#[derive(Clone)]
+pub struct Key(ChaCha20Poly1305);
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl Key {
+    /// Reads a key from 32 bytes in base64. Errors leave the value out.
+    pub fn from_base64(value: &str) -> Result<Self, String> {
+        let bytes = STANDARD
+            .decode(value.trim())
+            .map_err(|_| "expected 32 bytes in base64, got something else".to_string())?;
+        ChaCha20Poly1305::new_from_slice(&bytes)
+            .map(Key)
+            .map_err(|_| format!("expected 32 bytes in base64, got {} bytes", bytes.len()))
+    }
+
+    /// `plaintext` under a fresh nonce, which it is prefixed with.
+    fn seal(&self, plaintext: &[u8]) -> Vec<u8> {
+        let nonce = Nonce::generate();
+        let ciphertext = self
+            .0
+            .encrypt(&nonce, plaintext)
+            .expect("sealing into a Vec cannot run out of room");
+        [nonce.as_slice(), &ciphertext].concat()
+    }
+
+    fn open(&self, sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_from(nonce).ok()?;
+        self.0.decrypt(&nonce, ciphertext).ok()
+    }
+}
+
+/// The bytes of a snapshot file holding `json`.
+pub fn seal_file(key: Option<&Key>, json: Vec<u8>) -> Vec<u8> {
+    match key {
+        Some(key) => [SEALED_FILE, &key.seal(&json)].concat(),
+        None => json,
+    }
+}
+
+/// The line of the log holding `json`, with its newline.
+pub fn seal_line(key: Option<&Key>, mut json: Vec<u8>) -> Vec<u8> {
+    match key {
+        Some(key) => {
+            let mut line = SEALED_LINE.to_vec();
+            line.extend(STANDARD.encode(key.seal(&json)).into_bytes());
+            line.push(b'\n');
+            line
+        }
+        None => {
+            json.push(b'\n');
+            json
+        }
+    }
+}
+
+/// What a file or line read back holds.
+pub struct Unsealed<'a> {
+    pub json: std::borrow::Cow<'a, [u8]>,
+    /// It was stored as plaintext.
+    pub plaintext: bool,
+}
+
+/// The JSON in the snapshot file at `path` holding `bytes`.
+pub fn open_file<'a>(key: Option<&Key>, path: &Path, bytes: &'a [u8]) -> io::Result<Unsealed<'a>> {
+    match bytes.strip_prefix(SEALED_FILE) {
+        Some(sealed) => Ok(Unsealed {
+            json: unseal(key, path, sealed)?.into(),
+            plaintext: false,
+        }),
+        None => Ok(Unsealed {
+            json: bytes.into(),
+            plaintext: true,
+        }),
+    }
+}
+
+/// The JSON in a whole `line` of the log at `path`. A line that is not
+/// sealed, torn ones included, is returned as it is.
+pub fn open_line<'a>(key: Option<&Key>, path: &Path, line: &'a [u8]) -> io::Result<Unsealed<'a>> {
+    let sealed = line
+        .strip_prefix(SEALED_LINE)
+        .and_then(|sealed| sealed.strip_suffix(b"\n"));
+    match sealed {
+        Some(sealed) => {
+            let sealed = STANDARD.decode(sealed).map_err(|_| undecryptable(path))?;
+            Ok(Unsealed {
+                json: unseal(key, path, &sealed)?.into(),
+                plaintext: false,
+            })
+        }
+        None => Ok(Unsealed {
+            json: line.into(),
+            plaintext: !line.starts_with(SEALED_LINE),
+        }),
+    }
+}
+
+fn unseal(key: Option<&Key>, path: &Path, sealed: &[u8]) -> io::Result<Vec<u8>> {
+    let Some(key) = key else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} is encrypted; set ENCRYPTION_KEY to the key it was written with",
+                path.display()
+            ),
+        ));
+    };
+    key.open(sealed).ok_or_else(|| undecryptable(path))
+}
+
+fn undecryptable(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "cannot decrypt {}: ENCRYPTION_KEY is not the key it was written with, or the file is damaged",
+            path.display()
+        ),
+    )
+}
@@ -0,0 +1,454 @@
+//! The error type every handler returns. Each variant maps to a status code
+//! and renders the same envelope: a machine-readable `code`, a human-readable
+//! `message`, and the variant's own fields next to them.
+
+use std::time::Duration;
+
+use axum::{
+    http::{HeaderValue, StatusCode, header},
+    response::{IntoResponse, Json, Response},
+};
+use serde::Serialize;
+use serde_json::{Value, json};
+use utoipa::ToSchema;
+
+pub use movies::model::FieldError;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictType {
+    DuplicateId,
+    AmbiguousName,
+    /// The id belongs to a movie in the trash, which can be restored.
+    Trashed,
+    /// A restore of a movie that is not in the trash.
+    NotTrashed,
+    /// A delete of a person movies still refer to, or of a genre with
+    /// child genres or movies in it.
+    Referenced,
+}
+
+/// The envelope of every error response, for the OpenAPI document only:
+/// responses are rendered from `ApiError`, and each kind of error adds just
+/// its own fields to `code` and `message`.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct ErrorBody {
+    /// Machine-readable, e.g. `MOVIE_NOT_FOUND`.
+    code: String,
+    message: String,
+    /// The kind of resource that was not found.
+    resource: Option<String>,
+    /// The resource that was not found or has changed.
+    id: Option<String>,
+    /// Every failing field of a validation error.
+    errors: Option<Vec<FieldError>>,
+    conflict_type: Option<ConflictType>,
+    /// What a conflicting request collided with.
+    existing: Option<Value>,
+    /// The movies an ambiguous name could mean.
+    candidates: Option<Vec<Value>>,
+    /// The current version of a movie that has changed.
+    version: Option<u64>,
+    /// The component that is not ready.
+    component: Option<String>,
+    /// Seconds until a rate-limited or fenced client may retry.
+    retry_after: Option<u64>,
+    /// The media types an endpoint can answer with.
+    supported: Option<Vec<String>>,
+}
+
+#[derive(Debug)]
+pub enum ApiError {
+    NotFound {
+        resource: &'static str,
+        id: String,
+    },
+    BadRequest(String),
+    /// A request body that is not JSON or does not fit the expected shape.
+    /// Keeps the status axum chose: 400 for syntax errors, 415 for a missing
+    /// content type and 422 for mismatched data.
+    InvalidBody {
+        status: StatusCode,
+        message: String,
+    },
+    Validation(Vec<FieldError>),
+    /// Carries what the request collided with, so clients need no follow-up
+    /// GET: the `existing` resource, or the `candidates` to pick from.
+    Conflict {
+        conflict_type: ConflictType,
+        message: String,
+        existing: Option<Value>,
+        candidates: Option<Value>,
+    },
+    /// An `If-Match` naming another version than the stored one; carries the
+    /// current `version` so clients know what they are behind.
+    PreconditionFailed {
+        id: String,
+        version: u64,
+    },
+    /// An `Accept` header no format the endpoint offers satisfies; lists
+    /// the media types it does.
+    NotAcceptable {
+        supported: Vec<&'static str>,
+    },
+    /// A request carrying more than an endpoint takes at once.
+    PayloadTooLarge(String),
+    /// A component the service needs is not ready; reported by `/readyz`.
+    Unavailable {
+        component: &'static str,
+    },
+    /// A feature the server was not set up for; the message says what it
+    /// needs.
+    NotConfigured(String),
+    /// A service the request depends on failed.
+    BadGateway(String),
+    /// The request was not handled within its timeout.
+    TimedOut,
+    /// The storage backend failed. Details are logged, not sent, since they
+    /// may describe the backend's internals.
+    Storage,
+    // Not emitted by any handler yet; reserved for authentication so its
+    // responses follow the same envelope when it lands.
+    #[allow(dead_code)]
+    Unauthorized,
+    RateLimited {
+        retry_after: Duration,
+    },
+    /// A write that waited too long for a restore, import or transaction
+    /// to finish.
+    BulkInProgress {
+        retry_after: Duration,
+    },
+    #[allow(dead_code)]
+    Internal {
+        request_id: String,
+    },
+}
+
+impl ApiError {
+    pub fn not_found(resource: &'static str, id: impl Into<String>) -> Self {
+        ApiError::NotFound {
+            resource,
+            id: id.into(),
+        }
+    }
+
+    pub fn validation(field: impl Into<String>, message: impl Into<String>) -> Self {
+        ApiError::Validation(vec![FieldError::new(field, message)])
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound { .. } => StatusCode::NOT_FOUND,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::InvalidBody { status, .. } => *status,
+            ApiError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Conflict { .. } => StatusCode::CONFLICT,
+            ApiError::PreconditionFailed { .. } => StatusCode::PRECONDITION_FAILED,
+            ApiError::NotAcceptable { .. } => StatusCode::NOT_ACCEPTABLE,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::Unavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::NotConfigured(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::BadGateway(_) => StatusCode::BAD_GATEWAY,
+            ApiError::TimedOut => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::Storage => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::BulkInProgress { .. } => StatusCode::CONFLICT,
+            ApiError::Internal { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> String {
+        match self {
+            ApiError::NotFound { resource, .. } => {
+                format!("{}_NOT_FOUND", resource.to_uppercase())
+            }
+            ApiError::BadRequest(_) => "BAD_REQUEST".to_string(),
+            ApiError::InvalidBody { .. } => "INVALID_BODY".to_string(),
+            ApiError::Validation(_) => "VALIDATION_FAILED".to_string(),
+            ApiError::Conflict { .. } => "CONFLICT".to_string(),
+            ApiError::PreconditionFailed { .. } => "PRECONDITION_FAILED".to_string(),
+            ApiError::NotAcceptable { .. } => "NOT_ACCEPTABLE".to_string(),
+            ApiError::PayloadTooLarge(_) => "PAYLOAD_TOO_LARGE".to_string(),
+            ApiError::Unavailable { .. } => "UNAVAILABLE".to_string(),
+            ApiError::NotConfigured(_) => "NOT_CONFIGURED".to_string(),
+            ApiError::BadGateway(_) => "BAD_GATEWAY".to_string(),
+            ApiError::TimedOut => "TIMED_OUT".to_string(),
+            ApiError::Storage => "STORAGE_FAILED".to_string(),
+            ApiError::Unauthorized => "UNAUTHORIZED".to_string(),
+            ApiError::RateLimited { .. } => "RATE_LIMITED".to_string(),
+            ApiError::BulkInProgress { .. } => "BULK_IN_PROGRESS".to_string(),
+            ApiError::Internal { .. } => "INTERNAL_ERROR".to_string(),
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            ApiError::NotFound { resource, id } => format!("{resource} {id} does not exist"),
+            ApiError::BadRequest(message) => message.clone(),
+            ApiError::InvalidBody { message, .. } => message.clone(),
+            ApiError::Validation(errors) => match errors.as_slice() {
+                [error] => format!("{}: {}", error.field, error.message),
+                errors => format!("{} fields failed validation", errors.len()),
+            },
+            ApiError::Conflict { message, .. } => message.clone(),
+            ApiError::PreconditionFailed { id, version } => {
+                format!("movie {id} has changed, it is now at version {version}")
+            }
+            ApiError::NotAcceptable { supported } => {
+                format!(
+                    "cannot answer in any accepted type, expected one of {}",
+                    supported.join(", ")
+                )
+            }
+            ApiError::PayloadTooLarge(message) => message.clone(),
+            ApiError::Unavailable { component } => format!("{component} is not ready"),
+            ApiError::NotConfigured(message) | ApiError::BadGateway(message) => message.clone(),
+            ApiError::TimedOut => "request took too long to handle".to_string(),
+            ApiError::Storage => "storage backend failed".to_string(),
+            ApiError::Unauthorized => "missing or invalid credentials".to_string(),
+            ApiError::RateLimited { retry_after } => {
+                format!("too many requests, retry in {}s", retry_after.as_secs())
+            }
+            ApiError::BulkInProgress { retry_after } => format!(
+                "a restore, import or transaction is in progress, retry in {}s",
+                retry_after.as_secs()
+            ),
+            ApiError::Internal { .. } => "internal server error".to_string(),
+        }
+    }
+
+    fn body(&self) -> Value {
+        let mut body = json!({
+            "code": self.code(),
+            "message": self.message(),
+        });
+
+        match self {
+            ApiError::NotFound { resource, id } => {
+                body["resource"] = json!(resource);
+                body["id"] = json!(id);
+            }
+            ApiError::Validation(errors) => body["errors"] = json!(errors),
+            ApiError::Conflict {
+                conflict_type,
+                existing,
+                candidates,
+                ..
+            } => {
+                body["conflict_type"] = json!(conflict_type);
+                if let Some(existing) = existing {
+                    body["existing"] = existing.clone();
+                }
+                if let Some(candidates) = candidates {
+                    body["candidates"] = candidates.clone();
+                }
+            }
+            ApiError::PreconditionFailed { id, version } => {
+                body["id"] = json!(id);
+                body["version"] = json!(version);
+            }
+            ApiError::NotAcceptable { supported } => body["supported"] = json!(supported),
+            ApiError::Unavailable { component } => body["component"] = json!(component),
+            ApiError::RateLimited { retry_after } | ApiError::BulkInProgress { retry_after } => {
+                body["retry_after"] = json!(retry_after.as_secs());
+            }
+            ApiError::Internal { request_id } => body["request_id"] = json!(request_id),
+            ApiError::BadRequest(_)
+            | ApiError::InvalidBody { .. }
+            | ApiError::PayloadTooLarge(_)
+            | ApiError::NotConfigured(_)
+            | ApiError::BadGateway(_)
+            | ApiError::TimedOut
+            | ApiError::Storage
+            | ApiError::Unauthorized => {}
+        }
+
+        body
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = (self.status(), Json(self.body())).into_response();
+
+        if let ApiError::RateLimited { retry_after } | ApiError::BulkInProgress { retry_after } =
+            self
+        {
+            response.headers_mut().insert(
+                header::RETRY_AFTER,
+                HeaderValue::from(retry_after.as_secs()),
+            );
+        }
+
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn variants_render_status_and_envelope() {
+        let cases = [
+            (
+                ApiError::not_found("movie", "999"),
+                StatusCode::NOT_FOUND,
+                json!({
+                    "code": "MOVIE_NOT_FOUND",
+                    "message": "movie 999 does not exist",
+                    "resource": "movie",
+                    "id": "999",
+                }),
+            ),
+            (
+                ApiError::BadRequest("unsupported case".to_string()),
+                StatusCode::BAD_REQUEST,
+                json!({"code": "BAD_REQUEST", "message": "unsupported case"}),
+            ),
+            (
+                ApiError::InvalidBody {
+                    status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    message: "expected application/json".to_string(),
+                },
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                json!({"code": "INVALID_BODY", "message": "expected application/json"}),
+            ),
+            (
+                ApiError::Validation(vec![
+                    FieldError::new("name", "must not be empty"),
+                    FieldError::new("year", "must be after 1878"),
+                ]),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                json!({
+                    "code": "VALIDATION_FAILED",
+                    "message": "2 fields failed validation",
+                    "errors": [
+                        {"field": "name", "message": "must not be empty"},
+                        {"field": "year", "message": "must be after 1878"},
+                    ],
+                }),
+            ),
+            (
+                ApiError::Conflict {
+                    conflict_type: ConflictType::DuplicateId,
+                    message: "movie 1 already exists".to_string(),
+                    existing: Some(json!({"id": "1"})),
+                    candidates: None,
+                },
+                StatusCode::CONFLICT,
+                json!({
+                    "code": "CONFLICT",
+                    "message": "movie 1 already exists",
+                    "conflict_type": "duplicate_id",
+                    "existing": {"id": "1"},
+                }),
+            ),
+            (
+                ApiError::PreconditionFailed {
+                    id: "1".to_string(),
+                    version: 4,
+                },
+                StatusCode::PRECONDITION_FAILED,
+                json!({
+                    "code": "PRECONDITION_FAILED",
+                    "message": "movie 1 has changed, it is now at version 4",
+                    "id": "1",
+                    "version": 4,
+                }),
+            ),
+            (
+                ApiError::NotAcceptable {
+                    supported: vec!["application/json", "application/xml"],
+                },
+                StatusCode::NOT_ACCEPTABLE,
+                json!({
+                    "code": "NOT_ACCEPTABLE",
+                    "message": "cannot answer in any accepted type, expected one of application/json, application/xml",
+                    "supported": ["application/json", "application/xml"],
+                }),
+            ),
+            (
+                ApiError::PayloadTooLarge("too many movies".to_string()),
+                StatusCode::PAYLOAD_TOO_LARGE,
+                json!({"code": "PAYLOAD_TOO_LARGE", "message": "too many movies"}),
+            ),
+            (
+                ApiError::Unavailable {
+                    component: "storage",
+                },
+                StatusCode::SERVICE_UNAVAILABLE,
+                json!({
+                    "code": "UNAVAILABLE",
+                    "message": "storage is not ready",
+                    "component": "storage",
+                }),
+            ),
+            (
+                ApiError::NotConfigured("imports need a key".to_string()),
+                StatusCode::SERVICE_UNAVAILABLE,
+                json!({"code": "NOT_CONFIGURED", "message": "imports need a key"}),
+            ),
+            (
+                ApiError::BadGateway("the provider failed".to_string()),
+                StatusCode::BAD_GATEWAY,
+                json!({"code": "BAD_GATEWAY", "message": "the provider failed"}),
+            ),
+            (
+                ApiError::TimedOut,
+                StatusCode::GATEWAY_TIMEOUT,
+                json!({"code": "TIMED_OUT", "message": "request took too long to handle"}),
+            ),
+            (
+                ApiError::Storage,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"code": "STORAGE_FAILED", "message": "storage backend failed"}),
+            ),
+            (
+                ApiError::Unauthorized,
+                StatusCode::UNAUTHORIZED,
+                json!({"code": "UNAUTHORIZED", "message": "missing or invalid credentials"}),
+            ),
+            (
+                ApiError::RateLimited {
+                    retry_after: Duration::from_secs(7),
+                },
+                StatusCode::TOO_MANY_REQUESTS,
+                json!({
+                    "code": "RATE_LIMITED",
+                    "message": "too many requests, retry in 7s",
+                    "retry_after": 7,
+                }),
+            ),
+            (
+                ApiError::Internal {
+                    request_id: "abc".to_string(),
+                },
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({
+                    "code": "INTERNAL_ERROR",
+                    "message": "internal server error",
+                    "request_id": "abc",
+                }),
+            ),
+        ];
+
+        for (error, status, body) in cases {
+            let code = error.code();
+            let response = error.into_response();
+            assert_eq!(response.status(), status, "{code}");
+
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                assert_eq!(response.headers()[header::RETRY_AFTER], "7");
+            }
+
+            let bytes = response.into_body().collect().await.unwrap().to_bytes();
+            let actual: Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(actual, body, "{code}");
+        }
+    }
+}
@@ -0,0 +1,332 @@
+//! `GET /movie/events`: a Server-Sent Events stream of movie changes. Every
+//! handler that writes movies publishes to one broadcast channel after the
+//! write is stored, and each open stream relays the channel to its client.
+//! Nothing is replayed, so a client only sees changes made while connected.
+//! Updates carry the fields they changed, worked out by `diff`, so consumers
+//! need not compare movies themselves.
+
+use std::collections::{BTreeSet, HashMap};
+use std::convert::Infallible;
+use std::time::Duration;
+
+use axum::{
+    extract::State,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures_util::{Stream, stream};
+use movies::model::Movie;
+use serde::Serialize;
+use serde_json::{Value, json};
+use tokio::sync::broadcast::{self, error::RecvError};
+use utoipa::ToSchema;
+
+use crate::AppState;
+
+/// Events a stream may fall behind by before it skips ahead.
+const CAPACITY: usize = 1024;
+/// Comments sent on an idle stream so proxies do not time it out.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Created,
+    Updated,
+    /// Moved to the trash or removed for good.
+    Deleted,
+}
+
+impl EventKind {
+    fn name(self) -> &'static str {
+        match self {
+            EventKind::Created => "created",
+            EventKind::Updated => "updated",
+            EventKind::Deleted => "deleted",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct MovieEvent {
+    pub kind: EventKind,
+    /// The movie as stored after the change, or as it was last stored for a
+    /// permanent delete.
+    pub movie: Movie,
+    /// For `updated` only: the fields that changed, in field order. Empty
+    /// when only the version moved on, e.g. after a rating.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changes: Option<Vec<FieldChange>>,
+}
+
+impl MovieEvent {
+    pub fn new(kind: EventKind, movie: Movie) -> Self {
+        MovieEvent {
+            kind,
+            movie,
+            changes: None,
+        }
+    }
+
+    /// `movie` stored over `before`.
+    pub fn updated(before: &Movie, movie: Movie) -> Self {
+        MovieEvent {
+            kind: EventKind::Updated,
+            changes: Some(diff(before, &movie)),
+            movie,
+        }
+    }
+}
+
+/// One field an update changed.
+#[derive(Serialize, Debug, Clone, PartialEq, ToSchema)]
+#[serde(untagged)]
+pub enum FieldChange {
+    /// A value replaced; `null` stands for one unset. Each key of `custom`
+    /// is a field of its own, `custom.<key>`.
+    Value {
+        field: String,
+        old: Value,
+        new: Value,
+    },
+    /// Items added to or taken from a list such as `genres`, each sorted.
+    /// A list only put in another order, like a recast billing, is a `Value`
+    /// change instead.
+    Items {
+        field: String,
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+}
+
+/// The fields a client sees change from `before` to `after`. What the server
+/// manages, such as `version`, the timestamps and what is derived from the
+/// name, is left out.
+pub fn diff(before: &Movie, after: &Movie) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    value(&mut changes, "name", json!(before.name), json!(after.name));
+    value(&mut changes, "year", json!(before.year), json!(after.year));
+    value(
+        &mut changes,
+        "was_good",
+        json!(before.was_good),
+        json!(after.was_good),
+    );
+    items(
+        &mut changes,
+        "locked_fields",
+        &before.locked_fields,
+        &after.locked_fields,
+    );
+    let keys: BTreeSet<&String> = before.custom.keys().chain(after.custom.keys()).collect();
+    for key in keys {
+        let entry = |custom: &HashMap<String, Value>| custom.get(key).cloned().unwrap_or_default();
+        value(
+            &mut changes,
+            &format!("custom.{key}"),
+            entry(&before.custom),
+            entry(&after.custom),
+        );
+    }
+    items(&mut changes, "genres", &before.genres, &after.genres);
+    value(
+        &mut changes,
+        "director_id",
+        json!(before.director_id),
+        json!(after.director_id),
+    );
+    items(&mut changes, "cast", &before.cast, &after.cast);
+    value(
+        &mut changes,
+        "has_poster",
+        json!(before.has_poster),
+        json!(after.has_poster),
+    );
+
+    changes
+}
+
+fn value(changes: &mut Vec<FieldChange>, field: &str, old: Value, new: Value) {
+    if old != new {
+        changes.push(FieldChange::Value {
+            field: field.to_string(),
+            old,
+            new,
+        });
+    }
+}
+
+fn items(changes: &mut Vec<FieldChange>, field: &str, old: &[String], new: &[String]) {
+    let old_items: BTreeSet<&String> = old.iter().collect();
+    let new_items: BTreeSet<&String> = new.iter().collect();
+    if old_items == new_items {
+        // Only the order changed, which matters for a billing.
+        value(changes, field, json!(old), json!(new));
+        return;
+    }
+    changes.push(FieldChange::Items {
+        field: field.to_string(),
+        added: new_items
+            .difference(&old_items)
+            .map(|item| item.to_string())
+            .collect(),
+        removed: old_items
+            .difference(&new_items)
+            .map(|item| item.to_string())
+            .collect(),
+    });
+}
+
+#[derive(Debug)]
+pub struct Events {
+    sender: broadcast::Sender<MovieEvent>,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self::new(CAPACITY)
+    }
+}
+
+impl Events {
+    pub fn new(capacity: usize) -> Self {
+        Events {
+            sender: broadcast::channel(capacity).0,
+        }
+    }
+
+    /// Announces a stored change to every open stream; with none open the
+    /// event is simply dropped.
+    pub fn send(&self, event: MovieEvent) {
+        let _ = self.sender.send(event);
+    }
+
+    /// A movie created or deleted; updates go through `publish_update`.
+    pub fn publish(&self, kind: EventKind, movie: Movie) {
+        self.send(MovieEvent::new(kind, movie));
+    }
+
+    /// `movie` stored over `before`, with the fields that changed.
+    pub fn publish_update(&self, before: &Movie, movie: Movie) {
+        self.send(MovieEvent::updated(before, movie));
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<MovieEvent> {
+        self.sender.subscribe()
+    }
+}
+
+/// Each change arrives as an event named after its kind with the
+/// `MovieEvent` as JSON data. A client too slow to keep up gets a `lagged`
+/// event with the number of changes it `missed` and carries on from the
+/// oldest one still buffered.
+#[utoipa::path(
+    get,
+    path = "/movie/events",
+    tag = "movies",
+    summary = "Stream movie changes",
+    responses(
+        (status = OK, description = "`created`, `updated` and `deleted` events carrying a `MovieEvent`, and `lagged` events after changes were skipped", content_type = "text/event-stream", body = MovieEvent),
+    )
+)]
+pub async fn stream_events(
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    Sse::new(relay(state.events.subscribe())).keep_alive(KeepAlive::new().interval(KEEP_ALIVE))
+}
+
+/// Ends once the channel closes, which only happens when the state is gone.
+fn relay(
+    receiver: broadcast::Receiver<MovieEvent>,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    stream::unfold(receiver, |mut receiver| async move {
+        let event = match receiver.recv().await {
+            Ok(event) => Event::default()
+                .event(event.kind.name())
+                .json_data(&event)
+                .expect("movie events always serialize"),
+            Err(RecvError::Lagged(missed)) => Event::default()
+                .event("lagged")
+                .json_data(json!({ "missed": missed }))
+                .expect("lag notices always serialize"),
+            Err(RecvError::Closed) => return None,
+        };
+        Some((Ok(event), receiver))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::response::IntoResponse;
+    use http_body_util::BodyExt;
+
+    use super::*;
+
+    fn movie(id: &str) -> Movie {
+        serde_json::from_value(json!({ "id": id, "name": "Heat", "year": 1995, "was_good": true }))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn lagging_streams_skip_ahead() {
+        let events = Events::new(2);
+        let receiver = events.subscribe();
+        for id in ["1", "2", "3", "4", "5"] {
+            events.publish(EventKind::Created, movie(id));
+        }
+        events.publish(EventKind::Deleted, movie("5"));
+        drop(events);
+
+        let body = Sse::new(relay(receiver))
+            .into_response()
+            .into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes();
+        let body = std::str::from_utf8(&body).unwrap();
+
+        let names: Vec<&str> = body
+            .lines()
+            .filter_map(|line| line.strip_prefix("event: "))
+            .collect();
+        assert_eq!(names, ["lagged", "created", "deleted"]);
+        assert!(body.contains(r#"data: {"missed":4}"#), "{body}");
+        assert!(body.contains(r#""kind":"deleted""#), "{body}");
+    }
+
+    #[test]
+    fn updates_carry_the_fields_they_changed() {
+        let before = Movie {
+            genres: vec!["crime".into(), "drama".into()],
+            cast: vec!["pacino".into(), "de-niro".into()],
+            director_id: Some("mann".into()),
+            ..movie("1")
+        };
+        let after = Movie {
+            name: "Heat (1995)".into(),
+            version: before.version + 1,
+            genres: vec!["crime".into(), "thriller".into()],
+            cast: vec!["de-niro".into(), "pacino".into()],
+            director_id: None,
+            ..before.clone()
+        };
+
+        let event = serde_json::to_value(MovieEvent::updated(&before, after)).unwrap();
+        assert_eq!(
+            event["changes"],
+            json!([
+                {"field": "name", "old": "Heat", "new": "Heat (1995)"},
+                {"field": "genres", "added": ["thriller"], "removed": ["drama"]},
+                {"field": "director_id", "old": "mann", "new": null},
+                // Recast in another order only.
+                {"field": "cast", "old": ["pacino", "de-niro"], "new": ["de-niro", "pacino"]},
+            ])
+        );
+
+        // Only the version moved on, as after a rating.
+        let event = serde_json::to_value(MovieEvent::updated(&before, before.clone())).unwrap();
+        assert_eq!(event["changes"], json!([]));
+        let event = serde_json::to_value(MovieEvent::new(EventKind::Created, before)).unwrap();
+        assert!(event.get("changes").is_none());
+    }
+}
@@ -0,0 +1,93 @@
+//! Wrappers around axum's body and query extractors whose rejections render
+//! as [`ApiError`]s, so malformed requests get the same envelope as every
+//! other failure instead of axum's plain-text bodies.
+
+use axum::{
+    Json,
+    extract::{
+        FromRequest, FromRequestParts, Multipart, Query, Request, multipart::MultipartError,
+    },
+    http::{StatusCode, request::Parts},
+};
+use serde::de::DeserializeOwned;
+
+use crate::errors::ApiError;
+use crate::limits;
+
+/// A JSON request body; rejections answer `INVALID_BODY`, or
+/// `PAYLOAD_TOO_LARGE` past the route's body limit.
+#[derive(Debug)]
+pub struct JsonBody<T>(pub T);
+
+impl<T, S> FromRequest<S> for JsonBody<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::from_request(request, state)
+            .await
+            .map_err(|rejection| match rejection.status() {
+                StatusCode::PAYLOAD_TOO_LARGE => limits::too_large(),
+                status => ApiError::InvalidBody {
+                    status,
+                    message: rejection.body_text(),
+                },
+            })?;
+
+        Ok(JsonBody(value))
+    }
+}
+
+/// A `multipart/form-data` body, read field by field as it arrives; anything
+/// else answers `UNSUPPORTED_MEDIA_TYPE`.
+pub struct MultipartBody(pub Multipart);
+
+impl<S: Send + Sync> FromRequest<S> for MultipartBody {
+    type Rejection = ApiError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let multipart =
+            Multipart::from_request(request, state)
+                .await
+                .map_err(|_| ApiError::InvalidBody {
+                    status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    message: "expected a multipart/form-data body with a boundary".to_string(),
+                })?;
+
+        Ok(MultipartBody(multipart))
+    }
+}
+
+/// A multipart body that broke off or is malformed answers `BAD_REQUEST`,
+/// one past the route's body limit `PAYLOAD_TOO_LARGE`.
+impl From<MultipartError> for ApiError {
+    fn from(error: MultipartError) -> Self {
+        match error.status() {
+            StatusCode::PAYLOAD_TOO_LARGE => limits::too_large(),
+            _ => ApiError::BadRequest(format!("malformed multipart body: {}", error.body_text())),
+        }
+    }
+}
+
+/// Query parameters; rejections answer `BAD_REQUEST`.
+#[derive(Debug)]
+pub struct QueryParams<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for QueryParams<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(value) = Query::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| ApiError::BadRequest(rejection.body_text()))?;
+
+        Ok(QueryParams(value))
+    }
+}
@@ -0,0 +1,364 @@
+//! A taxonomy of genres under `/genre`: each genre may sit under a parent,
+//! `?genre=` on `GET /movie` matches the genres under the one asked for too,
+//! and `GET /genre/tree` rolls movie counts up the tree. The taxonomy is kept
+//! in memory, like watchlists. Movies still name genres freely; one named by
+//! movies but not in the taxonomy shows up as a top-level genre of its own.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::RwLock;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Json},
+};
+use chrono::Utc;
+use movies::model::{Genre, GenreParent};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::errors::{ApiError, ConflictType, ErrorBody};
+use crate::extract::{JsonBody, QueryParams};
+use crate::repo::RepoError;
+use crate::sync::LockExt;
+use crate::{AppState, normalize_genre, validate_name};
+
+#[derive(Debug, Default)]
+pub struct Genres {
+    /// Every genre with the one it is under, by name.
+    parent_of: RwLock<BTreeMap<String, Option<String>>>,
+}
+
+impl Genres {
+    /// `genre` normalized, with every genre under it.
+    pub fn descendants(&self, genre: &str) -> BTreeSet<String> {
+        let parent_of = self.parent_of.read_or_recover();
+        let mut found = BTreeSet::from([normalize_genre(genre)]);
+        loop {
+            let before = found.len();
+            for (name, parent) in parent_of.iter() {
+                if parent.as_ref().is_some_and(|parent| found.contains(parent)) {
+                    found.insert(name.clone());
+                }
+            }
+            if found.len() == before {
+                return found;
+            }
+        }
+    }
+}
+
+/// Whether `ancestor` is `genre` or one of the genres above it.
+fn is_within(parent_of: &BTreeMap<String, Option<String>>, genre: &str, ancestor: &str) -> bool {
+    let mut at = Some(genre);
+    while let Some(genre) = at {
+        if genre == ancestor {
+            return true;
+        }
+        at = parent_of.get(genre).and_then(Option::as_deref);
+    }
+    false
+}
+
+fn children_of(parent_of: &BTreeMap<String, Option<String>>, genre: &str) -> Vec<String> {
+    parent_of
+        .iter()
+        .filter(|(_, parent)| parent.as_deref() == Some(genre))
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+fn genre_not_found(name: &str) -> ApiError {
+    ApiError::not_found("genre", name)
+}
+
+/// `parent` normalized, which must name a genre already there.
+fn check_parent(
+    parent_of: &BTreeMap<String, Option<String>>,
+    parent: Option<&str>,
+) -> Result<Option<String>, ApiError> {
+    let Some(parent) = parent.map(normalize_genre) else {
+        return Ok(None);
+    };
+    if !parent_of.contains_key(&parent) {
+        return Err(ApiError::validation(
+            "parent",
+            format!("no genre {parent:?}"),
+        ));
+    }
+    Ok(Some(parent))
+}
+
+/// One genre of `GET /genre/tree`, with the genres under it.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct GenreNode {
+    pub name: String,
+    /// Movies naming this genre.
+    pub count: usize,
+    /// Movies naming this genre or any under it, each counted once.
+    pub total: usize,
+    /// By name.
+    #[schema(no_recursion)]
+    pub children: Vec<GenreNode>,
+}
+
+#[derive(Deserialize, Debug, Default, IntoParams)]
+#[serde(default)]
+#[into_params(parameter_in = Query)]
+pub struct DeleteGenreParams {
+    /// Delete the genre even while genres sit under it or movies name it,
+    /// moving those genres up to its parent and taking it off those movies.
+    force: bool,
+}
+
+#[utoipa::path(
+    post,
+    path = "/genre",
+    tag = "movies",
+    summary = "Add a genre to the taxonomy",
+    operation_id = "create_genre",
+    request_body = Genre,
+    responses(
+        (status = CREATED, description = "The new genre, its name normalized", body = Genre),
+        (status = CONFLICT, description = "The genre is already in the taxonomy", body = ErrorBody),
+        (status = UNPROCESSABLE_ENTITY, description = "A blank or too long name, or a parent that is not a genre", body = ErrorBody),
+    )
+)]
+pub async fn create(
+    State(state): State<AppState>,
+    JsonBody(payload): JsonBody<Genre>,
+) -> Result<impl IntoResponse, ApiError> {
+    if let Some(error) = validate_name(&payload.name, "name".to_string()) {
+        return Err(ApiError::Validation(vec![error]));
+    }
+
+    let name = normalize_genre(&payload.name);
+    let mut parent_of = state.genres.parent_of.write_or_recover();
+    if let Some(parent) = parent_of.get(&name) {
+        return Err(ApiError::Conflict {
+            conflict_type: ConflictType::DuplicateId,
+            message: format!("genre {name} already exists"),
+            existing: Some(json!(Genre {
+                name: name.clone(),
+                parent: parent.clone(),
+            })),
+            candidates: None,
+        });
+    }
+    let parent = check_parent(&parent_of, payload.parent.as_deref())?;
+    if parent.as_deref() == Some(name.as_str()) {
+        return Err(ApiError::validation(
+            "parent",
+            "a genre cannot be its own parent",
+        ));
+    }
+    parent_of.insert(name.clone(), parent.clone());
+
+    Ok((StatusCode::CREATED, Json(Genre { name, parent })))
+}
+
+/// Moves the genre under another one, or to the top with `"parent": null`.
+/// A move that would put the genre under itself is refused.
+#[utoipa::path(
+    put,
+    path = "/genre/{name}",
+    tag = "movies",
+    summary = "Move a genre in the taxonomy",
+    operation_id = "move_genre",
+    params(("name" = String, Path, description = "The genre, in any casing")),
+    request_body = GenreParent,
+    responses(
+        (status = OK, description = "The genre with its new parent", body = Genre),
+        (status = NOT_FOUND, description = "No such genre", body = ErrorBody),
+        (status = UNPROCESSABLE_ENTITY, description = "A parent that is not a genre, or is under the genre", body = ErrorBody),
+    )
+)]
+pub async fn update(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+    JsonBody(payload): JsonBody<GenreParent>,
+) -> Result<Json<Genre>, ApiError> {
+    let name = normalize_genre(&name);
+    let mut parent_of = state.genres.parent_of.write_or_recover();
+    if !parent_of.contains_key(&name) {
+        return Err(genre_not_found(&name));
+    }
+    let parent = check_parent(&parent_of, payload.parent.as_deref())?;
+    if let Some(parent) = &parent
+        && is_within(&parent_of, parent, &name)
+    {
+        return Err(ApiError::validation(
+            "parent",
+            format!("{parent} is {name} or under it, which would make a cycle"),
+        ));
+    }
+    parent_of.insert(name.clone(), parent.clone());
+
+    Ok(Json(Genre { name, parent }))
+}
+
+/// Every genre as a tree of top-level genres, each with the number of live
+/// movies naming it and the number naming it or any genre under it.
+#[utoipa::path(
+    get,
+    path = "/genre/tree",
+    tag = "movies",
+    summary = "Get the genre taxonomy with movie counts",
+    operation_id = "genre_tree",
+    responses(
+        (status = OK, description = "The top-level genres with the genres under them, by name", body = Vec<GenreNode>),
+    )
+)]
+pub async fn tree(State(state): State<AppState>) -> Result<Json<Vec<GenreNode>>, ApiError> {
+    let movies = state.live_movies().await?;
+    let parent_of = state.genres.parent_of.read_or_recover().clone();
+
+    // Own and rolled-up counts, by genre.
+    let mut counts: HashMap<String, (usize, usize)> = HashMap::new();
+    for movie in movies {
+        let mut within = BTreeSet::new();
+        for genre in movie.genres {
+            counts.entry(genre.clone()).or_default().0 += 1;
+            let mut at = Some(genre);
+            // A genre seen before had its ancestors added along with it.
+            while let Some(genre) = at.filter(|genre| within.insert(genre.clone())) {
+                at = parent_of.get(&genre).cloned().flatten();
+            }
+        }
+        for genre in within {
+            counts.entry(genre).or_default().1 += 1;
+        }
+    }
+
+    let names: BTreeSet<&String> = parent_of.keys().chain(counts.keys()).collect();
+    let mut children: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut roots = Vec::new();
+    for name in names {
+        match parent_of.get(name).and_then(Option::as_deref) {
+            Some(parent) => children.entry(parent).or_default().push(name),
+            None => roots.push(name.as_str()),
+        }
+    }
+
+    fn node(
+        name: &str,
+        children: &HashMap<&str, Vec<&str>>,
+        counts: &HashMap<String, (usize, usize)>,
+    ) -> GenreNode {
+        let (count, total) = counts.get(name).copied().unwrap_or_default();
+        GenreNode {
+            name: name.to_string(),
+            count,
+            total,
+            children: children
+                .get(name)
+                .into_iter()
+                .flatten()
+                .map(|child| node(child, children, counts))
+                .collect(),
+        }
+    }
+
+    Ok(Json(
+        roots
+            .into_iter()
+            .map(|root| node(root, &children, &counts))
+            .collect(),
+    ))
+}
+
+/// Refused with 409 while genres sit under the genre or movies, in the
+/// trash or not, name it, unless `?force=true` asks to move those genres up
+/// to its parent and take it off those movies; each such movie moves to its
+/// next version.
+#[utoipa::path(
+    delete,
+    path = "/genre/{name}",
+    tag = "movies",
+    summary = "Delete a genre from the taxonomy",
+    operation_id = "delete_genre",
+    params(
+        ("name" = String, Path, description = "The genre, in any casing"),
+        DeleteGenreParams,
+    ),
+    responses(
+        (status = NO_CONTENT, description = "The genre was deleted"),
+        (status = NOT_FOUND, description = "No such genre", body = ErrorBody),
+        (status = CONFLICT, description = "Genres sit under it or movies name it; `existing` lists their names and ids", body = ErrorBody),
+    )
+)]
+pub async fn delete(
+    Path(name): Path<String>,
+    QueryParams(params): QueryParams<DeleteGenreParams>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, ApiError> {
+    let name = normalize_genre(&name);
+    let _fence = state.fence().await?;
+    if !state.genres.parent_of.read_or_recover().contains_key(&name) {
+        return Err(genre_not_found(&name));
+    }
+
+    let mut naming: Vec<String> = state
+        .repo
+        .list()
+        .await?
+        .into_iter()
+        .filter(|movie| movie.genres.contains(&name))
+        .map(|movie| movie.id)
+        .collect();
+    naming.sort();
+    {
+        let mut parent_of = state.genres.parent_of.write_or_recover();
+        let children = children_of(&parent_of, &name);
+        if (!children.is_empty() || !naming.is_empty()) && !params.force {
+            return Err(ApiError::Conflict {
+                conflict_type: ConflictType::Referenced,
+                message: format!(
+                    "genre {name} has {} genres under it and {} movies in it, delete with ?force=true to move those up and take it off the movies",
+                    children.len(),
+                    naming.len()
+                ),
+                existing: Some(json!({"genres": children, "movies": naming})),
+                candidates: None,
+            });
+        }
+        let Some(parent) = parent_of.remove(&name) else {
+            return Err(genre_not_found(&name));
+        };
+        for child in children {
+            parent_of.insert(child, parent.clone());
+        }
+    }
+    for movie_id in naming {
+        detach(&state, &movie_id, &name).await?;
+    }
+    tracing::info!(event = "genre.deleted", name = %name, "genre deleted");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Takes the genre `name` off the movie `movie_id`, at its next version.
+async fn detach(state: &AppState, movie_id: &str, name: &str) -> Result<(), ApiError> {
+    loop {
+        let Some(stored) = state.repo.get(movie_id).await? else {
+            return Ok(());
+        };
+        if !stored.genres.iter().any(|genre| genre == name) {
+            return Ok(());
+        }
+
+        let mut movie = stored.clone();
+        movie.genres.retain(|genre| genre != name);
+        movie.version += 1;
+        movie.updated_at = Utc::now();
+        match state.repo.update(movie.clone()).await {
+            Err(RepoError::Stale(_)) => continue,
+            // Purged meanwhile, which took the genre along.
+            Err(RepoError::NotFound(_)) => return Ok(()),
+            result => result?,
+        }
+        state.events.publish_update(&stored, movie);
+        return Ok(());
+    }
+}
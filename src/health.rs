@@ -0,0 +1,72 @@
+//! Probes for orchestrators: `/healthz` answers as long as the process
+//! serves HTTP, `/readyz` only while the storage backend responds. Both are
+//! mounted outside every other layer that could fail or refuse a request.
+//! Readiness still holds while the message queue publisher fails, which it
+//! lists under `warnings`.
+
+use std::time::Duration;
+
+use axum::{extract::State, response::Json};
+use serde_json::{Value, json};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::AppState;
+use crate::errors::{ApiError, ErrorBody};
+
+/// How long the backend gets to answer a readiness ping.
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn routes(state: AppState) -> OpenApiRouter {
+    OpenApiRouter::new()
+        .routes(routes!(healthz))
+        .routes(routes!(readyz))
+        .with_state(state)
+}
+
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    summary = "Check that the process is alive",
+    responses((status = OK, description = "The process serves HTTP")),
+)]
+async fn healthz() -> Json<Value> {
+    Json(json!({"status": "ok"}))
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    summary = "Check that the service can take traffic",
+    responses(
+        (status = OK, description = "The storage backend responds; `warnings` lists components failing without taking the service down, such as the `publisher`"),
+        (status = SERVICE_UNAVAILABLE, description = "The storage backend does not respond", body = ErrorBody),
+    ),
+)]
+async fn readyz(State(state): State<AppState>) -> Result<Json<Value>, ApiError> {
+    match tokio::time::timeout(PING_TIMEOUT, state.repo.ping()).await {
+        Ok(Ok(())) => {
+            // The API works without the message queue, so it only warns.
+            match state.outbox.as_ref().and_then(|outbox| outbox.warning()) {
+                Some(warning) => Ok(Json(json!({
+                    "status": "ok",
+                    "warnings": [{"component": "publisher", "message": warning}],
+                }))),
+                None => Ok(Json(json!({"status": "ok"}))),
+            }
+        }
+        Ok(Err(error)) => {
+            tracing::warn!(%error, "storage failed its readiness ping");
+            Err(ApiError::Unavailable {
+                component: "storage",
+            })
+        }
+        Err(_) => {
+            tracing::warn!(timeout = ?PING_TIMEOUT, "storage did not answer its readiness ping");
+            Err(ApiError::Unavailable {
+                component: "storage",
+            })
+        }
+    }
+}
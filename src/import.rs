@@ -0,0 +1,341 @@
+//! `POST /movie/import/stream`: NDJSON imports that never buffer the whole
+//! body. Lines are parsed as they arrive and applied in chunks of
+//! `Config::import_chunk_size`, each as a single repository batch, so an
+//! upload that breaks off leaves exactly the chunks that were completed.
+//! `?on_conflict=` picks what becomes of a movie whose id is stored: it is
+//! overwritten, by default, or skipped.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{HeaderMap, header},
+    response::{
+        IntoResponse, Json, Response,
+        sse::{Event, Sse},
+    },
+};
+use futures_util::{StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::errors::{ApiError, ErrorBody, FieldError};
+use crate::events::{EventKind, MovieEvent};
+use crate::extract::QueryParams;
+use crate::repo::{RepoError, Write};
+use crate::sync::LockExt;
+use crate::{AppState, Movie, new_movie, refresh_slug, validate_new_movie};
+
+/// Only the first errors are reported line by line; the rest are counted.
+pub const MAX_REPORTED_ERRORS: usize = 100;
+
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, ToSchema)]
+pub struct ImportProgress {
+    /// Lines read so far, blank ones included.
+    pub lines: usize,
+    pub created: usize,
+    pub updated: usize,
+    /// Movies left as they were stored, with `on_conflict=skip`.
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+/// What an import does with a movie whose id is already stored, or came
+/// earlier in the same import.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OnConflict {
+    /// Keeps the stored movie.
+    Skip,
+    /// Replaces it, at its next version.
+    Overwrite,
+}
+
+#[derive(Deserialize, Debug, Default, IntoParams)]
+#[serde(default)]
+#[into_params(parameter_in = Query)]
+pub struct ImportParams {
+    /// `overwrite` when left out.
+    #[param(inline)]
+    on_conflict: Option<OnConflict>,
+}
+
+#[derive(Serialize, Debug, Default, ToSchema)]
+pub struct ImportReport {
+    #[serde(flatten)]
+    pub progress: ImportProgress,
+    /// Why lines failed, keyed by `line <n>` (1-based).
+    pub errors: Vec<FieldError>,
+}
+
+/// Answers with the final report as JSON, or with `Accept: text/event-stream`
+/// streams a `progress` event after every applied chunk and ends with a
+/// `summary` event carrying the report.
+#[utoipa::path(
+    post,
+    path = "/movie/import/stream",
+    tag = "movies",
+    summary = "Import movies from an NDJSON stream",
+    params(ImportParams),
+    request_body(content = String, content_type = "application/x-ndjson", description = "A movie per line"),
+    responses(
+        (status = OK, description = "The final report, or with `Accept: text/event-stream` a `progress` event per chunk and a closing `summary` event", content(
+            (ImportReport = "application/json"),
+            (String = "text/event-stream"),
+        )),
+        (status = BAD_REQUEST, description = "The body ended unexpectedly", body = ErrorBody),
+    )
+)]
+pub async fn import_stream(
+    QueryParams(params): QueryParams<ImportParams>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    let on_conflict = params.on_conflict.unwrap_or(OnConflict::Overwrite);
+    let sse = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("text/event-stream"));
+    // Held until the last chunk is applied, in the task when streaming.
+    let bulk = state.bulk.clone().write_owned().await;
+
+    if !sse {
+        return match run(&state, body, on_conflict, |_| {}).await {
+            Ok(report) => Json(report).into_response(),
+            Err(_) => {
+                ApiError::BadRequest("request body ended unexpectedly".to_string()).into_response()
+            }
+        };
+    }
+
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(async move {
+        let _bulk = bulk;
+        // Progress is cumulative, so a client that reads slowly only misses
+        // intermediate snapshots; the summary is always delivered.
+        let progress = tx.clone();
+        let result = run(&state, body, on_conflict, |snapshot| {
+            let _ = progress.try_send(event("progress", snapshot));
+        })
+        .await;
+
+        if let Ok(report) = result {
+            let _ = tx.send(event("summary", &report)).await;
+        }
+    });
+
+    let events = stream::unfold(rx, |mut rx| async move {
+        let event = rx.recv().await?;
+        Some((Ok::<_, Infallible>(event), rx))
+    });
+
+    Sse::new(events).into_response()
+}
+
+fn event(name: &str, data: &impl Serialize) -> Event {
+    Event::default()
+        .event(name)
+        .json_data(data)
+        .expect("import reports always serialize")
+}
+
+/// Reads `body` line by line, applying every full chunk as soon as it is
+/// complete. If the body breaks off, the partial chunk is discarded and the
+/// report of what was applied comes back as the error.
+pub async fn run(
+    state: &AppState,
+    body: Body,
+    on_conflict: OnConflict,
+    mut on_progress: impl FnMut(&ImportProgress),
+) -> Result<ImportReport, ImportReport> {
+    let chunk_size = state.config.import_chunk_size.max(1);
+    let mut report = ImportReport::default();
+    let mut chunk = Vec::with_capacity(chunk_size);
+    let mut pending = Vec::new();
+    let mut body = body.into_data_stream();
+
+    while let Some(frame) = body.next().await {
+        let bytes = match frame {
+            Ok(bytes) => bytes,
+            Err(error) => {
+                tracing::warn!(
+                    %error,
+                    lines = report.progress.lines,
+                    created = report.progress.created,
+                    updated = report.progress.updated,
+                    discarded = chunk.len(),
+                    "import stream interrupted"
+                );
+                return Err(report);
+            }
+        };
+
+        pending.extend_from_slice(&bytes);
+        while let Some(end) = pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            parse_line(state, &mut report, &mut chunk, &line);
+
+            if chunk.len() >= chunk_size {
+                apply(state, &mut report, &mut chunk, on_conflict).await;
+                on_progress(&report.progress);
+            }
+        }
+    }
+
+    if !pending.is_empty() {
+        parse_line(state, &mut report, &mut chunk, &pending);
+    }
+    apply(state, &mut report, &mut chunk, on_conflict).await;
+
+    Ok(report)
+}
+
+fn parse_line(state: &AppState, report: &mut ImportReport, chunk: &mut Vec<Movie>, line: &[u8]) {
+    report.progress.lines += 1;
+    let field = format!("line {}", report.progress.lines);
+
+    if line.trim_ascii().is_empty() {
+        return;
+    }
+
+    let errors = match serde_json::from_slice::<Movie>(line) {
+        Ok(movie) => {
+            let mut errors = validate_new_movie(&movie, &state.config, &format!("{field}."));
+            errors.extend(state.people.missing_from(&movie, &format!("{field}.")));
+            if errors.is_empty() {
+                chunk.push(movie);
+                return;
+            }
+            errors
+        }
+        Err(error) => vec![FieldError::new(field, error.to_string())],
+    };
+
+    report.progress.failed += 1;
+    for error in errors {
+        push_error(report, error);
+    }
+}
+
+fn push_error(report: &mut ImportReport, error: FieldError) {
+    if report.errors.len() < MAX_REPORTED_ERRORS {
+        report.errors.push(error);
+    }
+}
+
+/// Stores `chunk` as one repository batch. Movies naming a person deleted
+/// since their line was read fail, and if the repository fails, every movie
+/// in the chunk counts as failed.
+async fn apply(
+    state: &AppState,
+    report: &mut ImportReport,
+    chunk: &mut Vec<Movie>,
+    on_conflict: OnConflict,
+) {
+    let _checking = state.people.checking().await;
+    chunk.retain(|movie| {
+        let errors = state
+            .people
+            .missing_from(movie, &format!("movie {}.", movie.id));
+        if errors.is_empty() {
+            return true;
+        }
+
+        report.progress.failed += 1;
+        for error in errors {
+            push_error(report, error);
+        }
+        false
+    });
+    if chunk.is_empty() {
+        return;
+    }
+
+    let count = chunk.len();
+    match store(state, std::mem::take(chunk), on_conflict).await {
+        Ok(stored) => {
+            report.progress.created += stored.created;
+            report.progress.updated += stored.updated;
+            report.progress.skipped += stored.skipped;
+        }
+        Err(error) => {
+            tracing::error!(%error, "failed to store import chunk");
+            report.progress.failed += count;
+            push_error(
+                report,
+                FieldError::new("chunk", format!("{count} movies were not stored")),
+            );
+        }
+    }
+}
+
+/// How many movies one `store` created, updated and skipped.
+#[derive(Debug, Default)]
+pub struct Stored {
+    pub created: usize,
+    pub updated: usize,
+    pub skipped: usize,
+}
+
+/// Stores `movies` as one repository batch, creating new ones and treating
+/// the rest as `on_conflict` says.
+pub async fn store(
+    state: &AppState,
+    movies: Vec<Movie>,
+    on_conflict: OnConflict,
+) -> Result<Stored, RepoError> {
+    let mut stored = HashMap::new();
+    for movie in &movies {
+        if let Some(previous) = state.repo.get(&movie.id).await? {
+            stored.insert(previous.id.clone(), previous);
+        }
+    }
+
+    let mut changes = Vec::with_capacity(movies.len());
+    let mut skipped = 0;
+    let writes: Vec<Write> = {
+        let mut slugs = state.slugs.write_or_recover();
+        movies
+            .into_iter()
+            .filter_map(|movie| {
+                if on_conflict == OnConflict::Skip && stored.contains_key(&movie.id) {
+                    skipped += 1;
+                    return None;
+                }
+                let mut movie = new_movie(&state.config, movie);
+                let previous = stored.get(&movie.id);
+                if let Some(previous) = previous {
+                    movie.version = previous.version + 1;
+                    movie.created_at = previous.created_at;
+                }
+                refresh_slug(&mut slugs, previous, &mut movie);
+
+                changes.push(match stored.insert(movie.id.clone(), movie.clone()) {
+                    Some(before) => MovieEvent::updated(&before, movie.clone()),
+                    None => MovieEvent::new(EventKind::Created, movie.clone()),
+                });
+                Some(Write::Upsert(movie))
+            })
+            .collect()
+    };
+    state.repo.apply(writes).await?;
+
+    let created = changes
+        .iter()
+        .filter(|event| event.kind == EventKind::Created)
+        .count();
+    let updated = changes.len() - created;
+    for event in changes {
+        state.events.send(event);
+    }
+
+    Ok(Stored {
+        created,
+        updated,
+        skipped,
+    })
+}
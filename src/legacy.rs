@@ -0,0 +1,35 @@
+//! The unversioned paths that predate `/v1`, kept for the scripts already
+//! calling them. They answer exactly like their `/v1` counterparts, plus
+//! headers announcing their retirement: `Deprecation`, the `Sunset` date
+//! and a `Link` to the successor path, next to any the response already
+//! carries.
+
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+
+/// When the unversioned paths go away.
+pub const SUNSET: &str = "Thu, 01 Jul 2027 00:00:00 GMT";
+
+pub async fn deprecated(request: Request, next: Next) -> Response {
+    let successor = format!("</v1{}>; rel=\"successor-version\"", request.uri().path());
+    let mut response = next.run(request).await;
+
+    let headers = response.headers_mut();
+    headers.insert(
+        HeaderName::from_static("deprecation"),
+        HeaderValue::from_static("true"),
+    );
+    headers.insert(
+        HeaderName::from_static("sunset"),
+        HeaderValue::from_static(SUNSET),
+    );
+    if let Ok(link) = HeaderValue::from_str(&successor) {
+        headers.append(axum::http::header::LINK, link);
+    }
+
+    response
+}
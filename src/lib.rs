@@ -0,0 +1,7 @@
+//! Types for talking to the movies API. The server binary and the optional
+//! typed client (`client` feature) share them, so their wire formats cannot
+//! drift apart.
+
+#[cfg(feature = "client")]
+pub mod client;
+pub mod model;
@@ -0,0 +1,150 @@
+//! Bounds on how much a request may send and how long it may take, so a
+//! client cannot tie the server up with an endless body or a request that
+//! never finishes. Routes taking uploads (the imports, posters and restores)
+//! get `UPLOAD_BODY_LIMIT_BYTES` and `UPLOAD_TIMEOUT_SECS`, which bound every
+//! request before anything buffers its body; every other route is narrowed
+//! to `BODY_LIMIT_BYTES` and `REQUEST_TIMEOUT_SECS`.
+//!
+//! tower-http refuses a body with plain text and times out with an empty
+//! body; `error_body` renders both as the usual error envelope and counts
+//! them. Requests slower than `SLOW_REQUEST_MS` are logged and counted by
+//! `watch`; `GET /admin/limits` reports the counts.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tower_http::limit::RequestBodyLimitLayer;
+use tower_http::timeout::TimeoutLayer;
+
+use crate::Config;
+use crate::errors::ApiError;
+
+/// What the limits turned away since startup, and the slow-request
+/// threshold, which unlike the other limits can change while the server
+/// runs.
+#[derive(Debug)]
+pub struct Limits {
+    /// Requests answered 413 for a body past their route's limit.
+    pub too_large: AtomicU64,
+    /// Requests answered 504 for running past their route's timeout.
+    pub timed_out: AtomicU64,
+    /// Requests that took longer than `slow_threshold` to answer.
+    pub slow: AtomicU64,
+    /// In milliseconds; 0 logs no request as slow.
+    slow_threshold: AtomicU64,
+}
+
+impl Limits {
+    pub fn new(config: &Config) -> Self {
+        Limits {
+            too_large: AtomicU64::new(0),
+            timed_out: AtomicU64::new(0),
+            slow: AtomicU64::new(0),
+            slow_threshold: AtomicU64::new(config.slow_request.as_millis() as u64),
+        }
+    }
+
+    pub fn slow_threshold(&self) -> Duration {
+        Duration::from_millis(self.slow_threshold.load(Ordering::Relaxed))
+    }
+
+    pub fn set_slow_threshold(&self, threshold: Duration) {
+        self.slow_threshold
+            .store(threshold.as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+/// The limits of routes that take no uploads.
+pub fn requests(config: &Config) -> (RequestBodyLimitLayer, TimeoutLayer) {
+    (
+        RequestBodyLimitLayer::new(config.body_limit),
+        TimeoutLayer::with_status_code(StatusCode::GATEWAY_TIMEOUT, config.request_timeout),
+    )
+}
+
+/// The limits of routes taking uploads, and so of every request.
+pub fn uploads(config: &Config) -> (RequestBodyLimitLayer, TimeoutLayer) {
+    (
+        RequestBodyLimitLayer::new(config.upload_body_limit),
+        TimeoutLayer::with_status_code(StatusCode::GATEWAY_TIMEOUT, config.upload_timeout),
+    )
+}
+
+/// The error for a body that failed to be read, e.g. by passing its limit
+/// midway.
+pub fn body_error(error: axum::Error) -> ApiError {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(&error);
+    while let Some(error) = source {
+        if error.is::<http_body_util::LengthLimitError>() {
+            return too_large();
+        }
+        source = error.source();
+    }
+
+    ApiError::BadRequest(format!("failed to read the body: {error}"))
+}
+
+pub fn too_large() -> ApiError {
+    ApiError::PayloadTooLarge("request body is larger than this route accepts".to_string())
+}
+
+/// Counts refusals and timeouts and replaces the bodies of those from
+/// outside the handlers; errors a handler returned are JSON already and left
+/// alone.
+pub async fn error_body(
+    State(limits): State<Arc<Limits>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let response = next.run(request).await;
+    let error = match response.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => {
+            limits.too_large.fetch_add(1, Ordering::Relaxed);
+            too_large()
+        }
+        StatusCode::GATEWAY_TIMEOUT => {
+            limits.timed_out.fetch_add(1, Ordering::Relaxed);
+            ApiError::TimedOut
+        }
+        _ => return response,
+    };
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+
+    if is_json {
+        response
+    } else {
+        error.into_response()
+    }
+}
+
+/// Logs and counts requests answered later than the slow threshold, timed
+/// until the response starts.
+pub async fn watch(State(limits): State<Arc<Limits>>, request: Request, next: Next) -> Response {
+    let threshold = limits.slow_threshold();
+    let started = Instant::now();
+    let response = next.run(request).await;
+
+    let elapsed = started.elapsed();
+    if !threshold.is_zero() && elapsed > threshold {
+        limits.slow.fetch_add(1, Ordering::Relaxed);
+        tracing::warn!(
+            event = "request.slow",
+            elapsed_ms = elapsed.as_millis() as u64,
+            threshold_ms = threshold.as_millis() as u64,
+            "slow request"
+        );
+    }
+
+    response
+}
@@ -0,0 +1,58 @@
+//! `Link` headers (RFC 8288) of a paged `GET /movie`, for clients whose
+//! pagination libraries follow them instead of reading the page's totals.
+//! Each link repeats the request's query with only `page` changed, so
+//! filters, sorting and `per_page` carry over.
+
+use axum::http::{HeaderMap, HeaderValue, Uri};
+
+use crate::config::Config;
+
+const X_FORWARDED_PREFIX: &str = "x-forwarded-prefix";
+
+/// What links are put under: the proxy's `X-Forwarded-Prefix` when the
+/// server trusts its forwarded headers, `BASE_PATH` otherwise.
+pub fn prefix(config: &Config, headers: &HeaderMap) -> String {
+    let forwarded = headers
+        .get(X_FORWARDED_PREFIX)
+        .and_then(|value| value.to_str().ok())
+        .filter(|_| config.trust_forwarded_for);
+
+    match forwarded {
+        Some(prefix) => prefix.trim().trim_end_matches('/').to_string(),
+        None => config.base_path.clone(),
+    }
+}
+
+/// Links to the first and `last` page, and to the ones before and after
+/// `page` where there are such pages, for a request to `uri`.
+pub fn header(prefix: &str, uri: &Uri, page: usize, last: usize) -> HeaderValue {
+    let kept: Vec<&str> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| {
+            let key = pair.split('=').next().unwrap_or_default();
+            !pair.is_empty() && key != "page" && key != "cursor"
+        })
+        .collect();
+    let link = |page: usize, rel: &str| {
+        let query = kept
+            .iter()
+            .copied()
+            .chain([format!("page={page}").as_str()])
+            .collect::<Vec<_>>()
+            .join("&");
+        format!("<{prefix}{}?{query}>; rel=\"{rel}\"", uri.path())
+    };
+
+    let mut links = vec![link(1, "first")];
+    if page > 1 {
+        links.push(link((page - 1).min(last), "prev"));
+    }
+    if page < last {
+        links.push(link(page + 1, "next"));
+    }
+    links.push(link(last, "last"));
+
+    HeaderValue::from_str(&links.join(", ")).expect("paths and queries are valid header values")
+}
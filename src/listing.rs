@@ -0,0 +1,100 @@
+//! `GET /movie?stream=true`: every matching movie as one JSON array, sent
+//! while the store is still being read. The repository is walked in id
+//! order `CHUNK` movies at a time, so neither the whole catalogue nor its
+//! serialized form is ever held at once, and writers only wait for one
+//! chunk's read. Movies written during the walk show up if their id comes
+//! after the chunk being read.
+
+use axum::{
+    body::{Body, Bytes},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use futures_util::stream;
+
+use crate::repo::RepoError;
+use crate::{AppState, MovieFilter};
+
+/// Movies read from the repository per step.
+const CHUNK: usize = 1000;
+
+struct Walk {
+    state: AppState,
+    filter: MovieFilter,
+    /// The last id read, `None` before the first chunk.
+    after: Option<String>,
+    /// Whether a movie was written yet, so the next needs a comma.
+    wrote_any: bool,
+    done: bool,
+}
+
+/// The response, headed like a JSON page but without an `ETag`, since the
+/// content is not known up front. A failing read ends the body early, which
+/// leaves the array unterminated so clients notice.
+pub fn stream(state: AppState, filter: MovieFilter) -> Response {
+    let walk = Walk {
+        state,
+        filter,
+        after: None,
+        wrote_any: false,
+        done: false,
+    };
+    let body = stream::unfold(walk, |mut walk| async move {
+        if walk.done {
+            return None;
+        }
+        match walk.next_chunk().await {
+            Ok(bytes) => Some((Ok(bytes), walk)),
+            Err(error) => {
+                tracing::error!(%error, "streamed listing failed");
+                walk.done = true;
+                Some((Err(error), walk))
+            }
+        }
+    });
+
+    (
+        [(header::CONTENT_TYPE, "application/json")],
+        Body::from_stream(body),
+    )
+        .into_response()
+}
+
+impl Walk {
+    /// The next piece of the array: the matches of one chunk, opened with
+    /// `[` on the first and closed with `]` once the store is exhausted.
+    async fn next_chunk(&mut self) -> Result<Bytes, RepoError> {
+        let mut out = Vec::new();
+        if self.after.is_none() {
+            out.push(b'[');
+        }
+
+        let movies = self
+            .state
+            .repo
+            .list_after(self.after.as_deref(), CHUNK)
+            .await?;
+        if let Some(last) = movies.last() {
+            self.after = Some(last.id.clone());
+        }
+        if movies.len() < CHUNK {
+            self.done = true;
+        }
+
+        for movie in movies
+            .iter()
+            .filter(|movie| movie.deleted_at.is_none() && self.filter.matches(movie))
+        {
+            if self.wrote_any {
+                out.push(b',');
+            }
+            serde_json::to_writer(&mut out, movie).expect("movies always serialize");
+            self.wrote_any = true;
+        }
+
+        if self.done {
+            out.push(b']');
+        }
+        Ok(Bytes::from(out))
+    }
+}
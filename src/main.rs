@@ -1,368 +1,9565 @@
-use std::collections::HashMap;
+mod admin;
+mod api_version;
+mod cache;
+mod case;
+mod catalogue;
+#[cfg(feature = "chaos")]
+mod chaos;
+mod cli;
+mod compression;
+mod conditional;
+mod config;
+mod cors;
+mod duplicates;
+mod durability;
+#[cfg(test)]
+mod e2e;
+mod encryption;
+mod errors;
+mod events;
+mod extract;
+mod genres;
+mod health;
+mod import;
+mod legacy;
+mod limits;
+mod links;
+mod listing;
+mod metadata;
+mod negotiate;
+mod openapi;
+#[cfg(feature = "metadata")]
+mod outbound;
+mod paging;
+mod people;
+mod popularity;
+mod poster;
+mod publisher;
+mod rate_limit;
+mod ratings;
+mod repo;
+mod revalidate;
+mod searches;
+mod snapshot;
+mod stats;
+mod storage;
+mod sync;
+mod watchlist;
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
 use axum::{
-    Router,
-    extract::{Json as EJson, Path, State},
-    http::StatusCode,
-    response::{IntoResponse, Json},
-    routing::get,
+    Extension, Router,
+    extract::{DefaultBodyLimit, OriginalUri, Path, State},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, header},
+    middleware,
+    response::{IntoResponse, Json, Redirect, Response},
 };
 
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{Value, json};
+use tower_http::compression::CompressionLayer;
+use tower_http::decompression::RequestDecompressionLayer;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tracing::Level;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::MakeWriter;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use api_version::MoviePage;
+use conditional::{IfMatch, IfNoneMatch};
+use config::Config;
+use errors::{ApiError, ConflictType, ErrorBody, FieldError};
+use events::{EventKind, Events, MovieEvent};
+use extract::{JsonBody, QueryParams};
+use genres::Genres;
+use limits::Limits;
+use metadata::MetadataProvider;
+use movies::model::{Movie, MoviePatch, Page};
+use negotiate::{Format, VARY_ACCEPT};
+use paging::{Collation, Position, Sort, SortKey, SortOrder};
+use people::People;
+use popularity::Popularity;
+use rate_limit::RateLimiter;
+use ratings::{RatedMovie, Ratings};
+use repo::{InMemoryRepository, MovieRepository, RepoError, Write};
+use searches::Searches;
+use sync::LockExt;
+use tokio::task::JoinHandle;
+use unicode_normalization::UnicodeNormalization;
+use watchlist::Watchlists;
+
+/// Limits on the free-form `custom` map so it stays an escape hatch rather
+/// than a second document store.
+const MAX_CUSTOM_KEYS: usize = 20;
+const MAX_CUSTOM_VALUE_BYTES: usize = 1024;
+
+const MAX_GENRES: usize = 10;
+
+/// Taken from the request when the client sends one, generated otherwise, and
+/// echoed on the response.
+const X_REQUEST_ID: &str = "x-request-id";
+
+/// Puts a field's stored value back into an updated movie, telling whether
+/// the update tried to change it.
+type Restore = fn(&mut Movie, &Movie) -> bool;
+
+/// Every field an update takes from the client, which are the fields that
+/// can be protected from overwrites with `POST /movie/{id}/lock`.
+const UPDATABLE_FIELDS: &[(&str, Restore)] = &[
+    ("name", |movie, stored| keep(&mut movie.name, &stored.name)),
+    ("year", |movie, stored| keep(&mut movie.year, &stored.year)),
+    ("was_good", |movie, stored| {
+        keep(&mut movie.was_good, &stored.was_good)
+    }),
+    ("custom", |movie, stored| {
+        keep(&mut movie.custom, &stored.custom)
+    }),
+    ("genres", |movie, stored| {
+        keep(&mut movie.genres, &stored.genres)
+    }),
+    ("director_id", |movie, stored| {
+        keep(&mut movie.director_id, &stored.director_id)
+    }),
+    ("cast", |movie, stored| keep(&mut movie.cast, &stored.cast)),
+];
+
+/// Puts `stored` back into `value`, telling whether they differed.
+fn keep<T: Clone + PartialEq>(value: &mut T, stored: &T) -> bool {
+    std::mem::replace(value, stored.clone()) != *stored
+}
+
+/// Merges an incoming full update into the stored movie, as the version
+/// after it, updated now. Locked fields keep their stored values unless `force` is set;
+/// the names of locked fields whose incoming value was discarded are returned
+/// alongside.
+fn apply_update(stored: &Movie, payload: Movie, force: bool) -> (Movie, Vec<String>) {
+    let mut movie = Movie {
+        id: stored.id.clone(),
+        locked_fields: stored.locked_fields.clone(),
+        genres: normalize_genres(&payload.genres),
+        version: stored.version + 1,
+        created_at: stored.created_at,
+        updated_at: Utc::now(),
+        deleted_at: stored.deleted_at,
+        has_poster: stored.has_poster,
+        ..payload
+    };
+    let mut skipped = Vec::new();
+
+    if force {
+        return (movie, skipped);
+    }
+
+    // Put the stored value back for every locked field, remembering the ones
+    // where the payload actually tried to change it.
+    for (field, restore) in UPDATABLE_FIELDS {
+        if stored.locked_fields.iter().any(|locked| locked == field) && restore(&mut movie, stored)
+        {
+            skipped.push(field.to_string());
+        }
+    }
+
+    (movie, skipped)
+}
+
+/// Minimum similarity for a fuzzy name match to be returned without asking
+/// the caller to disambiguate.
+const CONFIDENT_MATCH: f64 = 0.8;
+/// Minimum similarity for a movie to be offered as a candidate at all.
+const CANDIDATE_MATCH: f64 = 0.5;
+const MAX_CANDIDATES: usize = 5;
+
+#[derive(Serialize, Debug)]
+struct NameCandidate {
+    id: String,
+    name: String,
+    year: u16,
+    score: f64,
+}
+
+/// Cleans up a display name as it arrives: NFC-normalizes it, folds smart
+/// quotes to their ASCII form, trims it and collapses internal whitespace.
+fn clean_name(name: &str) -> String {
+    name.nfc()
+        .map(|c| match c {
+            '\u{2018}' | '\u{2019}' => '\'',
+            '\u{201C}' | '\u{201D}' => '"',
+            c => c,
+        })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Lowercases a cleaned-up name so lookups ignore differences in casing,
+/// spacing and unicode composition.
+fn normalize_name(name: &str) -> String {
+    clean_name(name).to_lowercase()
+}
+
+/// Levenshtein similarity in `0.0..=1.0`, where `1.0` means identical.
+fn similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let longest = a.len().max(b.len());
+
+    if longest == 0 {
+        return 1.0;
+    }
+
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    1.0 - row[b.len()] as f64 / longest as f64
+}
+
+/// Normalizes a movie arriving through any of the create paths: cleans up
+/// its name, derives the sort name and starts it at version 1, created now,
+/// without locked fields.
+fn new_movie(config: &Config, payload: Movie) -> Movie {
+    let name = clean_name(&payload.name);
+    let now = Utc::now();
+    Movie {
+        sort_name: config.sort_name(&name),
+        name,
+        locked_fields: Vec::new(),
+        genres: normalize_genres(&payload.genres),
+        version: 1,
+        created_at: now,
+        updated_at: now,
+        deleted_at: None,
+        has_poster: false,
+        ..payload
+    }
+}
+
+/// Lowercase ASCII words joined by dashes and followed by the year, e.g.
+/// "the-matrix-1999". Accents are stripped; other non-ASCII letters dropped.
+fn slugify(name: &str, year: u16) -> String {
+    let ascii: String = name.nfd().filter(char::is_ascii).collect();
+
+    ascii
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .chain([year.to_string()])
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Gives `movie` its slug, keeping the `previous` one while name and year are
+/// unchanged. New slugs get the first free `-2`, `-3`, ... suffix on
+/// collision; replaced slugs stay in the index as aliases of the movie.
+fn refresh_slug(slugs: &mut HashMap<String, String>, previous: Option<&Movie>, movie: &mut Movie) {
+    if let Some(previous) = previous
+        && previous.name == movie.name
+        && previous.year == movie.year
+        && !previous.slug.is_empty()
+    {
+        movie.slug = previous.slug.clone();
+        return;
+    }
+
+    let base = slugify(&movie.name, movie.year);
+    let slug = std::iter::once(base.clone())
+        .chain((2..).map(|n| format!("{base}-{n}")))
+        .find(|slug| slugs.get(slug).is_none_or(|id| *id == movie.id))
+        .expect("suffixes are unbounded");
+
+    slugs.insert(slug.clone(), movie.id.clone());
+    movie.slug = slug;
+}
+
+/// Checks the `custom` map against its limits, reporting every offending
+/// key under `field` (e.g. `custom.my_key`).
+fn validate_custom(custom: &HashMap<String, Value>, field: &str) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+
+    if custom.len() > MAX_CUSTOM_KEYS {
+        errors.push(FieldError::new(
+            field,
+            format!(
+                "may hold at most {MAX_CUSTOM_KEYS} keys, got {}",
+                custom.len()
+            ),
+        ));
+    }
+
+    for (key, value) in custom {
+        let mut chars = key.chars();
+        let valid = chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            errors.push(FieldError::new(
+                format!("{field}.{key}"),
+                "key is not a valid identifier",
+            ));
+        }
+
+        let size = serde_json::to_vec(value)
+            .expect("json values always serialize")
+            .len();
+        if size > MAX_CUSTOM_VALUE_BYTES {
+            errors.push(FieldError::new(
+                format!("{field}.{key}"),
+                format!("value is {size} bytes, the limit is {MAX_CUSTOM_VALUE_BYTES}"),
+            ));
+        }
+    }
+
+    errors
+}
+
+/// Genres compare by their trimmed, lowercased form, so "Sci-Fi" and
+/// "sci-fi" are the same genre.
+fn normalize_genre(genre: &str) -> String {
+    genre.trim().to_lowercase()
+}
+
+/// `genres` normalized, keeping the first of any duplicates.
+fn normalize_genres(genres: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::with_capacity(genres.len());
+    for genre in genres.iter().map(|genre| normalize_genre(genre)) {
+        if !normalized.contains(&genre) {
+            normalized.push(genre);
+        }
+    }
+
+    normalized
+}
+
+/// Duplicates and blank entries, which are reported on their own, do not
+/// count against `MAX_GENRES`.
+fn validate_genres(genres: &[String], field: &str) -> Vec<FieldError> {
+    let mut errors: Vec<FieldError> = genres
+        .iter()
+        .enumerate()
+        .filter(|(_, genre)| genre.trim().is_empty())
+        .map(|(index, _)| FieldError::new(format!("{field}[{index}]"), "must not be blank"))
+        .collect();
+
+    let count = normalize_genres(genres)
+        .iter()
+        .filter(|genre| !genre.is_empty())
+        .count();
+    if count > MAX_GENRES {
+        errors.push(FieldError::new(
+            field,
+            format!("may hold at most {MAX_GENRES} genres, got {count}"),
+        ));
+    }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct Movie {
+    errors
+}
+
+/// The size of `movie` as it is stored and sent.
+fn serialized_size(movie: &Movie) -> usize {
+    serde_json::to_vec(movie)
+        .expect("movies always serialize")
+        .len()
+}
+
+/// Checks `movie` against the configured budgets, so a single huge movie
+/// cannot slow every listing and snapshot down. List fields are reported
+/// under their names, the movie as a whole under `prefix` alone (`movie` at
+/// the top level).
+fn validate_budget(movie: &Movie, config: &Config, prefix: &str) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+
+    for (field, len) in [("genres", movie.genres.len()), ("cast", movie.cast.len())] {
+        if len > config.movie_max_list_items {
+            errors.push(FieldError::new(
+                format!("{prefix}{field}"),
+                format!(
+                    "holds {len} items, over the MOVIE_MAX_LIST_ITEMS budget of {}",
+                    config.movie_max_list_items
+                ),
+            ));
+        }
+    }
+
+    let bytes = serialized_size(movie);
+    if bytes > config.movie_max_bytes {
+        let field = match prefix.trim_end_matches('.') {
+            "" => "movie",
+            field => field,
+        };
+        errors.push(FieldError::new(
+            field,
+            format!(
+                "is {bytes} bytes serialized, over the MOVIE_MAX_BYTES budget of {}",
+                config.movie_max_bytes
+            ),
+        ));
+    }
+
+    errors
+}
+
+/// Ids of the `movies` over their budgets, by id. Only movies stored before
+/// the budgets were lowered can be, since every write is checked.
+fn over_budget(movies: &[Movie], config: &Config) -> Vec<String> {
+    let mut ids: Vec<String> = movies
+        .iter()
+        .filter(|movie| !validate_budget(movie, config, "").is_empty())
+        .map(|movie| movie.id.clone())
+        .collect();
+    ids.sort();
+    ids
+}
+
+/// Equality used by the `?custom.<key>=<value>` list filter. Strings compare
+/// verbatim, numbers numerically and booleans by their literal; nested
+/// values never match.
+fn custom_matches(value: &Value, expected: &str) -> bool {
+    match value {
+        Value::String(s) => s == expected,
+        Value::Number(n) => expected.parse::<f64>().ok() == n.as_f64(),
+        Value::Bool(b) => expected.parse::<bool>().ok() == Some(*b),
+        _ => false,
+    }
+}
+
+const DEFAULT_PER_PAGE: usize = 20;
+const MAX_PER_PAGE: usize = 100;
+
+/// Filters, paging and ordering of `GET /movie`. Unknown keys are ignored
+/// here, the `custom.<key>` filters are read separately.
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ListParams {
+    year: Option<u16>,
+    /// Inclusive, like `year_to`. Either may be given alone but not together
+    /// with `year`.
+    year_from: Option<u16>,
+    year_to: Option<u16>,
+    /// `true` or `false` in any casing.
+    was_good: Option<String>,
+    /// Matched like stored genres, ignoring casing, along with every genre
+    /// under it in the taxonomy.
+    genre: Option<String>,
+    /// 1-based.
+    #[serde(default = "default_page")]
+    page: usize,
+    /// 20 by default; values above `MAX_PER_PAGE` (100) are capped.
+    #[serde(default = "default_per_page")]
+    per_page: usize,
+    /// Comma-separated fields out of `id`, `name`, `year` and `created_at`,
+    /// each descending with a leading `-`, e.g. `-year,name`. The id breaks
+    /// ties.
+    #[serde(default)]
+    sort: String,
+    /// The direction of fields without a `-` and of the id breaking ties.
+    #[serde(default)]
+    #[param(inline)]
+    order: SortOrder,
+    /// How `name` compares: `natural` ignores accents and casing and
+    /// compares numbers by value, `binary` compares code points.
+    #[serde(default)]
+    #[param(inline)]
+    collation: Collation,
+    /// The `next_cursor` of the previous page, to continue right after its
+    /// last movie however the catalogue changed since; not with `page`.
+    cursor: Option<String>,
+    /// Send every matching movie, ordered by id, as a JSON array streamed
+    /// while the store is read instead of one page.
+    #[serde(default)]
+    stream: bool,
+}
+
+impl ListParams {
+    /// Checks paging, filters and ordering against each other, with the
+    /// `custom.<key>` filters in `query`; `genre` widens by `genres`.
+    fn check(&self, query: &HashMap<String, String>, genres: &Genres) -> Result<Listing, ApiError> {
+        if self.page == 0 {
+            return Err(ApiError::BadRequest("page must be at least 1".to_string()));
+        }
+        if self.per_page == 0 {
+            return Err(ApiError::BadRequest(
+                "per_page must be at least 1".to_string(),
+            ));
+        }
+        if self.cursor.is_some() && self.page != 1 {
+            return Err(ApiError::BadRequest(
+                "cursor cannot be combined with page".to_string(),
+            ));
+        }
+        let filter = self.filter(query, genres)?;
+        let sort = Sort::parse(&self.sort, self.order, self.collation)?;
+        let after = self
+            .cursor
+            .as_deref()
+            .map(|cursor| sort.after(cursor))
+            .transpose()?;
+
+        Ok(Listing {
+            filter,
+            sort,
+            after,
+            per_page: self.per_page.min(MAX_PER_PAGE),
+        })
+    }
+
+    /// Checks the filters against each other and gathers them with the
+    /// `custom.<key>` ones from `query`.
+    fn filter(
+        &self,
+        query: &HashMap<String, String>,
+        genres: &Genres,
+    ) -> Result<MovieFilter, ApiError> {
+        if self.year.is_some() && (self.year_from.is_some() || self.year_to.is_some()) {
+            return Err(ApiError::BadRequest(
+                "year cannot be combined with year_from or year_to".to_string(),
+            ));
+        }
+
+        let was_good = self
+            .was_good
+            .as_deref()
+            .map(|value| match value.to_ascii_lowercase().as_str() {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                _ => Err(ApiError::BadRequest(format!(
+                    "was_good must be true or false, got {value:?}"
+                ))),
+            })
+            .transpose()?;
+
+        Ok(MovieFilter {
+            year: self.year,
+            year_from: self.year_from,
+            year_to: self.year_to,
+            was_good,
+            genre: self.genre.as_deref().map(|genre| genres.descendants(genre)),
+            custom: query
+                .iter()
+                .filter_map(|(k, v)| Some((k.strip_prefix("custom.")?.to_string(), v.clone())))
+                .collect(),
+        })
+    }
+}
+
+/// A checked `GET /movie`, short of the page number.
+#[derive(Debug)]
+struct Listing {
+    filter: MovieFilter,
+    sort: Sort,
+    after: Option<Position>,
+    /// Capped at `MAX_PER_PAGE`.
+    per_page: usize,
+}
+
+/// The filters of `GET /movie`, owned so a streamed listing can keep them.
+#[derive(Debug, Clone)]
+struct MovieFilter {
+    year: Option<u16>,
+    year_from: Option<u16>,
+    year_to: Option<u16>,
+    was_good: Option<bool>,
+    /// The genre asked for and every genre under it, normalized like stored
+    /// genres.
+    genre: Option<BTreeSet<String>>,
+    custom: Vec<(String, String)>,
+}
+
+impl MovieFilter {
+    fn matches(&self, movie: &Movie) -> bool {
+        self.year.is_none_or(|y| movie.year == y)
+            && self.year_from.is_none_or(|from| movie.year >= from)
+            && self.year_to.is_none_or(|to| movie.year <= to)
+            && self
+                .was_good
+                .is_none_or(|was_good| movie.was_good == was_good)
+            && self
+                .genre
+                .as_ref()
+                .is_none_or(|genres| movie.genres.iter().any(|genre| genres.contains(genre)))
+            && self.custom.iter().all(|(key, expected)| {
+                movie
+                    .custom
+                    .get(key)
+                    .is_some_and(|value| custom_matches(value, expected))
+            })
+    }
+}
+
+fn default_page() -> usize {
+    1
+}
+
+fn default_per_page() -> usize {
+    DEFAULT_PER_PAGE
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SearchParams {
+    /// Matched against names ignoring casing, spacing and unicode
+    /// composition.
+    #[serde(default)]
+    q: String,
+}
+
+/// Whether `movie` is a hit for a search already run through
+/// `normalize_name`. Only the name is searched for now.
+fn matches(movie: &Movie, q: &str) -> bool {
+    normalize_name(&movie.name).contains(q)
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct UpdateParams {
+    /// Overwrite locked fields too.
+    #[serde(default)]
+    force: bool,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct GetParams {
+    /// Answer like `HEAD`, without a body.
+    #[serde(default)]
+    existence_only: bool,
+    /// References to embed under `expanded`, comma-separated: `director`,
+    /// `cast`.
+    expand: Option<String>,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PopularParams {
+    /// How many movies to list, 10 by default.
+    #[serde(default = "default_popular_limit")]
+    limit: usize,
+}
+
+fn default_popular_limit() -> usize {
+    10
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+struct PopularMovie {
     id: String,
     name: String,
     year: u16,
-    was_good: bool,
+    count: u64,
+}
+
+#[derive(Deserialize, Debug, Default, IntoParams)]
+#[serde(default)]
+#[into_params(parameter_in = Query)]
+struct DeleteParams {
+    /// Remove the movie for good instead of moving it to the trash.
+    permanent: bool,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+struct GenreCount {
+    genre: String,
+    count: usize,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct LockRequest {
+    fields: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct ChangeIdRequest {
+    new_id: String,
+}
+
+/// Left behind by `POST /movie/{id}/change-id` so the old id keeps resolving
+/// for a while.
+#[derive(Debug, Clone)]
+struct IdRedirect {
+    new_id: String,
+    expires_at: Instant,
+}
+
+const INVALID_ID: &str = "must be non-empty and only contain URL-safe characters";
+
+/// Ids end up in URLs, so they are limited to RFC 3986 unreserved characters.
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '~'))
+}
+
+/// Longest name accepted, in characters after cleaning it up.
+const MAX_NAME_CHARS: usize = 256;
+/// From the first motion picture to a reasonable horizon for announcements.
+const YEARS: std::ops::RangeInclusive<u16> = 1878..=2100;
+
+fn validate_name(name: &str, field: String) -> Option<FieldError> {
+    let length = clean_name(name).chars().count();
+    if length == 0 {
+        Some(FieldError::new(field, "must not be blank"))
+    } else if length > MAX_NAME_CHARS {
+        Some(FieldError::new(
+            field,
+            format!("may be at most {MAX_NAME_CHARS} characters, got {length}"),
+        ))
+    } else {
+        None
+    }
+}
+
+fn validate_year(year: u16, field: String) -> Option<FieldError> {
+    (!YEARS.contains(&year)).then(|| {
+        FieldError::new(
+            field,
+            format!(
+                "must be between {} and {}, got {year}",
+                YEARS.start(),
+                YEARS.end()
+            ),
+        )
+    })
+}
+
+/// Checks the fields a movie's creator or editor chooses, and the movie
+/// against its budgets, reporting every failing one under `prefix`.
+fn validate_movie(movie: &Movie, config: &Config, prefix: &str) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+
+    errors.extend(validate_name(&movie.name, format!("{prefix}name")));
+    errors.extend(validate_year(movie.year, format!("{prefix}year")));
+    errors.extend(validate_custom(&movie.custom, &format!("{prefix}custom")));
+    errors.extend(validate_genres(&movie.genres, &format!("{prefix}genres")));
+    errors.extend(validate_budget(movie, config, prefix));
+
+    errors
+}
+
+/// Checks a movie about to be created, reporting errors under `prefix`
+/// (e.g. `[2].movie.` inside a transaction).
+fn validate_new_movie(movie: &Movie, config: &Config, prefix: &str) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+
+    if !is_valid_id(&movie.id) {
+        errors.push(FieldError::new(format!("{prefix}id"), INVALID_ID));
+    }
+    errors.extend(validate_movie(movie, config, prefix));
+
+    errors
+}
+
+/// A single step of a `POST /movie/transaction` batch, carrying the same
+/// payload the matching standalone endpoint would take.
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Operation {
+    Create { movie: Movie },
+    Update { id: String, movie: Movie },
+    Delete { id: String },
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+struct OperationResult {
+    index: usize,
+    op: &'static str,
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    movie: Option<Movie>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    skipped: Vec<String>,
+}
+
+/// Most movies a single `POST /movie/batch` may create.
+const MAX_BATCH: usize = 1000;
+
+/// When a write refused during a restore, import or transaction is told to
+/// come back.
+const BULK_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize, Debug, Default, IntoParams)]
+#[serde(default)]
+#[into_params(parameter_in = Query)]
+struct BatchParams {
+    /// Create nothing unless every movie can be created.
+    atomic: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum BatchStatus {
+    Created,
+    DuplicateId,
+    Invalid,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+struct BatchResult {
+    index: usize,
+    id: String,
+    status: BatchStatus,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FieldError>,
+}
+
+/// How much of an existing movie a conflict response reveals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConflictDetail {
+    /// The whole stored record.
+    Full,
+    /// Only `id`, `name` and `year`, for setups where the caller may not be
+    /// allowed to read the record it collided with.
+    Minimal,
+}
+
+impl ApiError {
+    fn movie_not_found(id: impl Into<String>) -> Self {
+        ApiError::not_found("movie", id)
+    }
+
+    fn duplicate_id(existing: &Movie, detail: ConflictDetail) -> Self {
+        let projection = match detail {
+            ConflictDetail::Full => json!(existing),
+            ConflictDetail::Minimal => json!({
+                "id": existing.id,
+                "name": existing.name,
+                "year": existing.year,
+            }),
+        };
+
+        if existing.deleted_at.is_some() {
+            return ApiError::Conflict {
+                conflict_type: ConflictType::Trashed,
+                message: format!(
+                    "movie {id} is in the trash, restore it with POST /movie/{id}/restore",
+                    id = existing.id
+                ),
+                existing: Some(projection),
+                candidates: None,
+            };
+        }
+
+        ApiError::Conflict {
+            conflict_type: ConflictType::DuplicateId,
+            message: format!("movie {} already exists", existing.id),
+            existing: Some(projection),
+            candidates: None,
+        }
+    }
+
+    fn ambiguous_name(candidates: Vec<NameCandidate>) -> Self {
+        ApiError::Conflict {
+            conflict_type: ConflictType::AmbiguousName,
+            message: "movie name is ambiguous".to_string(),
+            existing: None,
+            candidates: Some(json!(candidates)),
+        }
+    }
+}
+
+/// For repository errors a handler does not handle itself. Conflicts embed
+/// the full existing movie; handlers that honor `CONFLICT_DETAIL` check for
+/// them first.
+impl From<RepoError> for ApiError {
+    fn from(error: RepoError) -> Self {
+        match error {
+            RepoError::Conflict(existing) => {
+                ApiError::duplicate_id(&existing, ConflictDetail::Full)
+            }
+            RepoError::NotFound(id) => ApiError::movie_not_found(id),
+            RepoError::Stale(current) => ApiError::PreconditionFailed {
+                id: current.id,
+                version: current.version,
+            },
+            RepoError::Backend(message) => {
+                tracing::error!(%message, "storage backend failed");
+                ApiError::Storage
+            }
+        }
+    }
 }
 
 #[derive(Clone)]
 struct AppState {
-    data: Arc<RwLock<HashMap<String, Movie>>>,
+    repo: Arc<dyn MovieRepository>,
+    redirects: Arc<RwLock<HashMap<String, IdRedirect>>>,
+    /// Every slug, current or replaced, to the id of its movie. Never held
+    /// across a repository call.
+    slugs: Arc<RwLock<HashMap<String, String>>>,
+    config: Arc<Config>,
+    popularity: Arc<Popularity>,
+    limiter: Arc<RateLimiter>,
+    limits: Arc<Limits>,
+    ratings: Arc<Ratings>,
+    watchlists: Arc<Watchlists>,
+    genres: Arc<Genres>,
+    searches: Arc<Searches>,
+    people: Arc<People>,
+    events: Arc<Events>,
+    /// Held for writing while a movie changes its id or movies merge, from
+    /// the repository batch until ratings, watchlist entries, posters, slugs
+    /// and redirects follow. Handlers that read a movie together with any of
+    /// those hold it for reading, so they see the ids before or after, never
+    /// halfway.
+    rekeying: Arc<tokio::sync::RwLock<()>>,
+    /// Held for writing through a restore, an import or a transaction, and
+    /// for reading by every other request that writes movies, people,
+    /// ratings, posters or watchlist entries, so none lands halfway through
+    /// one. Taken before any other lock; see `fence`.
+    bulk: Arc<tokio::sync::RwLock<()>>,
+    posters: Arc<poster::Posters>,
+    /// Where `POST /movie/import/external` looks titles up, if anywhere.
+    metadata: Option<Arc<dyn MetadataProvider>>,
+    /// Every request to another service goes through this one client.
+    #[cfg(feature = "metadata")]
+    outbound: Arc<outbound::HttpClient>,
+    /// Movie changes on their way to a message queue, if they go to one.
+    outbox: Option<Arc<publisher::Outbox>>,
 }
 
+#[cfg(test)]
 fn app() -> Router {
-    let data: HashMap<String, Movie> = HashMap::new();
-    let state = AppState {
-        data: Arc::new(RwLock::new(data)),
+    app_with_config(Config::default())
+}
+
+impl AppState {
+    /// `MOVIES_TEST_REPO=vec cargo test` runs the whole suite against the
+    /// toy `VecRepository` instead of the in-memory map, and `sqlite` (with
+    /// the `sqlite` feature) against an in-memory SQLite database.
+    #[cfg(test)]
+    fn new(config: Config) -> Self {
+        let repo: Arc<dyn MovieRepository> = match std::env::var("MOVIES_TEST_REPO").as_deref() {
+            Ok("vec") => Arc::new(repo::VecRepository::default()),
+            #[cfg(feature = "sqlite")]
+            Ok("sqlite") => Arc::new(repo::SqliteRepository::open("sqlite::memory:").unwrap()),
+            _ => Arc::new(InMemoryRepository::new()),
+        };
+        Self::with_repository(config, repo)
+    }
+
+    /// State around `repo`, with empty slug and redirect indexes; use `open`
+    /// to index the slugs of a repository that already holds movies.
+    fn with_repository(config: Config, repo: Arc<dyn MovieRepository>) -> Self {
+        #[cfg(feature = "metadata")]
+        let outbound = Arc::new(outbound::HttpClient::new(&config));
+        AppState {
+            repo,
+            redirects: Arc::new(RwLock::new(HashMap::new())),
+            slugs: Arc::new(RwLock::new(HashMap::new())),
+            popularity: Arc::new(Popularity::new(
+                config.popularity_capacity,
+                config.popularity_decay,
+                Instant::now(),
+            )),
+            limiter: Arc::new(RateLimiter::new(
+                config.rate_limit_requests,
+                config.rate_limit_window,
+                config.trust_forwarded_for,
+                Instant::now(),
+            )),
+            limits: Arc::new(Limits::new(&config)),
+            ratings: Arc::default(),
+            watchlists: Arc::default(),
+            genres: Arc::default(),
+            searches: Arc::default(),
+            people: Arc::default(),
+            events: Arc::default(),
+            rekeying: Arc::default(),
+            bulk: Arc::default(),
+            posters: Arc::new(poster::Posters::new(config.media_dir.clone())),
+            #[cfg(feature = "metadata")]
+            metadata: metadata::provider(&config, outbound.clone()),
+            #[cfg(not(feature = "metadata"))]
+            metadata: None,
+            #[cfg(feature = "metadata")]
+            outbound,
+            outbox: None,
+            config: Arc::new(config),
+        }
+    }
+
+    /// Like `new`, but on the repository the config asks for: the database
+    /// at `database_url`, the map saved to `db_path`, the files in
+    /// `spill_dir` with the most recently used movies in memory, or a plain
+    /// map.
+    async fn open(config: Config) -> std::io::Result<Self> {
+        let stores = [
+            config.database_url.is_some(),
+            config.db_path.is_some(),
+            config.spill_dir.is_some(),
+        ];
+        if stores.into_iter().filter(|set| *set).count() > 1 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "only one of DATABASE_URL, MOVIES_DB_PATH and MOVIES_SPILL_DIR can be set",
+            ));
+        }
+        let repo: Arc<dyn MovieRepository> =
+            match (&config.database_url, &config.db_path, &config.spill_dir) {
+                #[cfg(feature = "sqlite")]
+                (Some(url), _, _) => Arc::new(repo::SqliteRepository::open(url)?),
+                #[cfg(not(feature = "sqlite"))]
+                (Some(_), _, _) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::Unsupported,
+                        "DATABASE_URL needs a build with the sqlite feature",
+                    ));
+                }
+                (None, Some(path), _) => Arc::new(InMemoryRepository::open(
+                    path.clone(),
+                    config.encryption_key.clone(),
+                )?),
+                (None, None, Some(dir)) => Arc::new(repo::TieredRepository::new(
+                    repo::FileRepository::open(dir)?,
+                    config.resident_movies,
+                )),
+                (None, None, None) => Arc::new(InMemoryRepository::new()),
+            };
+        let mut state = Self::with_repository(config, repo);
+
+        state.people = Arc::new(People::load(state.repo.people().await?));
+        let movies = state.repo.list().await?;
+        let oversized = over_budget(&movies, &state.config);
+        if !oversized.is_empty() {
+            tracing::warn!(
+                event = "store.over_budget",
+                ids = ?oversized,
+                "stored movies are over MOVIE_MAX_BYTES or MOVIE_MAX_LIST_ITEMS; they are served as they are, and updates must bring them within budget"
+            );
+        }
+        *state.slugs.write_or_recover() = movies
+            .into_iter()
+            .map(|movie| (movie.slug, movie.id))
+            .collect();
+
+        Ok(state)
+    }
+
+    /// Waits for a restore, import or transaction in progress to finish, for
+    /// `bulk_write_wait` at most, and keeps the next from starting until the
+    /// guard is dropped.
+    async fn fence(&self) -> Result<tokio::sync::RwLockReadGuard<'_, ()>, ApiError> {
+        tokio::time::timeout(self.config.bulk_write_wait, self.bulk.read())
+            .await
+            .map_err(|_| ApiError::BulkInProgress {
+                retry_after: BULK_RETRY_AFTER,
+            })
+    }
+
+    /// The movie with `id` unless it is in the trash. Handlers look movies up
+    /// through here; only the trash endpoints and id checks see trashed ones.
+    async fn live_movie(&self, id: &str) -> Result<Option<Movie>, RepoError> {
+        Ok(self
+            .repo
+            .get(id)
+            .await?
+            .filter(|movie| movie.deleted_at.is_none()))
+    }
+
+    /// Every movie not in the trash, in no particular order.
+    async fn live_movies(&self) -> Result<Vec<Movie>, RepoError> {
+        let mut movies = self.repo.list().await?;
+        movies.retain(|movie| movie.deleted_at.is_none());
+        Ok(movies)
+    }
+
+    /// Runs once the server has stopped serving: flushes the repository so
+    /// nothing written is lost with the process.
+    async fn shutdown(&self) -> std::io::Result<()> {
+        self.repo.flush().await?;
+        tracing::info!(event = "flushed", "store flushed");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn app_with_config(config: Config) -> Router {
+    router(AppState::new(config))
+}
+
+#[cfg(test)]
+fn app_with_repository(repo: Arc<dyn MovieRepository>) -> Router {
+    router(AppState::with_repository(Config::default(), repo))
+}
+
+#[cfg(test)]
+async fn app_with_store(path: std::path::PathBuf) -> Router {
+    let config = Config {
+        db_path: Some(path),
+        ..Config::default()
     };
+    router(AppState::open(config).await.expect("failed to open store"))
+}
 
-    Router::new()
-        .route("/movie", get(list_movies).post(create_movie))
-        .route(
-            "/movie/{id}",
-            get(get_movie).put(update_movie).delete(delete_movie),
-        )
+/// The routes that predate versioning. Every route is mounted from its
+/// `#[utoipa::path]`, which keeps the OpenAPI document in step with what is
+/// actually served.
+fn unversioned_routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(list_movies, create_movie))
+        .routes(routes!(create_movies))
+        .routes(routes!(movie_transaction))
+        .routes(routes!(catalogue::export))
+        .routes(routes!(popular_movies))
+        .routes(routes!(events::stream_events))
+        .routes(routes!(trashed_movies))
+        .routes(routes!(search_movies))
+        .routes(routes!(get_movie_by_name))
+        .routes(routes!(get_movie_by_slug))
+        .routes(routes!(get_movie, update_movie, patch_movie, delete_movie))
+        .routes(routes!(lock_fields))
+        .routes(routes!(unlock_fields))
+        .routes(routes!(change_movie_id))
+        .routes(routes!(restore_movie))
+        .routes(routes!(list_genres))
+        .routes(routes!(ratings::list, ratings::create))
+        .routes(routes!(ratings::delete))
+        .routes(routes!(watchlist::list, watchlist::create))
+        .routes(routes!(watchlist::get, watchlist::delete))
+        .routes(routes!(watchlist::add_movie, watchlist::remove_movie))
+}
+
+/// The unversioned routes taking uploads, kept apart from the rest so they
+/// are not narrowed to its body limit and timeout.
+fn unversioned_uploads() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(catalogue::import))
+        .routes(routes!(import::import_stream))
+}
+
+/// Everything served under `/v1`, relative to it. Routes added after
+/// versioning go here only, so the unversioned aliases stay as they were; a
+/// `/v2` starts as a copy of this and diverges.
+fn v1_routes(state: AppState) -> OpenApiRouter {
+    let uploads = unversioned_uploads().routes(routes!(poster::upload));
+
+    unversioned_routes()
+        .routes(routes!(stats::movie_stats))
+        .routes(routes!(metadata::import_external))
+        .routes(routes!(poster::get, poster::delete))
+        .routes(routes!(people::list, people::create))
+        .routes(routes!(people::get, people::update, people::delete))
+        .routes(routes!(people::movies))
+        .routes(routes!(duplicates::find))
+        .routes(routes!(duplicates::merge))
+        .routes(routes!(revalidate::revalidate))
+        .routes(routes!(searches::list, searches::create))
+        .routes(routes!(searches::results))
+        .routes(routes!(genres::create))
+        .routes(routes!(genres::tree))
+        .routes(routes!(genres::update, genres::delete))
+        .layer(limits::requests(&state.config))
+        .merge(uploads)
         .with_state(state)
 }
 
+fn router(state: AppState) -> Router {
+    #[cfg(feature = "chaos")]
+    let chaos = state.config.chaos;
+    let cache = Arc::new(state.config.cache.clone());
+    let cors = state.config.cors.layer();
+    let config = state.config.clone();
+    let limiter = state.limiter.clone();
+    let limits = state.limits.clone();
+    let (probes, probes_document) = health::routes(state.clone()).split_for_parts();
+    let (legacy, _) = unversioned_routes()
+        .layer(limits::requests(&config))
+        .merge(unversioned_uploads())
+        .with_state(state.clone())
+        .split_for_parts();
+    let (admin, admin_document) = admin::routes(state.clone()).split_for_parts();
+    let durable_state = state.clone();
+    let (v1, v1_document) = v1_routes(state).split_for_parts();
+
+    // Only `/v1` is documented; the aliases are on their way out.
+    let mut document = openapi::ApiDoc::openapi().nest("/v1", v1_document);
+    document.merge(admin_document);
+    document.merge(probes_document);
+
+    let router = Router::new()
+        .nest("/v1", v1)
+        .merge(legacy.layer(middleware::from_fn(legacy::deprecated)));
+
+    #[cfg(feature = "chaos")]
+    let router = if chaos {
+        chaos::install(router)
+    } else {
+        router
+    };
+    // Unversioned like `/admin/chaos`, and out of reach of fault injection.
+    let router = router.merge(admin).layer(middleware::from_fn_with_state(
+        durable_state,
+        durability::confirm,
+    ));
+
+    // Probes are merged after the limiter so a busy client cannot make an
+    // orchestrator restart the process. It is installed even with no
+    // allowance, which `PATCH /admin/limits` may give later.
+    let router = router.layer(middleware::from_fn_with_state(limiter, rate_limit::limit));
+
+    // The last layer runs first: a request is given an id, then traced, and
+    // the id is copied to the response on the way out.
+    let x_request_id = HeaderName::from_static(X_REQUEST_ID);
+    router
+        .merge(probes)
+        .merge(openapi::routes(document))
+        .layer(middleware::from_fn_with_state(cache, cache::cache_control))
+        // Inside the casing, so versions are shaped in snake_case.
+        .layer(middleware::from_fn(api_version::negotiate))
+        .layer(middleware::from_fn_with_state(
+            config.clone(),
+            case::response_case,
+        ))
+        // Every request is bound before the casing buffers its body, and
+        // routes without uploads narrow the bounds further. axum's own cap
+        // on extracted bodies would undercut the upload limit.
+        .layer(limits::uploads(&config))
+        .layer(DefaultBodyLimit::disable())
+        .layer(middleware::from_fn_with_state(
+            limits.clone(),
+            limits::error_body,
+        ))
+        // Outside the casing, which reads and rewrites bodies in the clear.
+        .layer(RequestDecompressionLayer::new())
+        .layer(CompressionLayer::new().compress_when(compression::LargeBodies))
+        // Preflights are answered here, before they reach any route.
+        .layer(cors)
+        .layer(middleware::from_fn_with_state(limits, limits::watch))
+        .layer(PropagateRequestIdLayer::new(x_request_id.clone()))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(request_span)
+                .on_request(())
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        )
+        .layer(SetRequestIdLayer::new(x_request_id, MakeRequestUuid))
+}
+
+/// Binds the configured address, logs the startup sequence and serves in a
+/// background task. The returned address is the one actually bound, so
+/// binding port 0 picks an ephemeral port the caller can discover. The task
+/// ends once `run` returns and the store has been flushed.
+async fn serve(
+    config: Config,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> std::io::Result<(SocketAddr, JoinHandle<std::io::Result<()>>)> {
+    tracing::info!(config = ?config, "configuration loaded");
+    let addr = SocketAddr::new(config.bind_address, config.port);
+
+    let backend = if config.database_url.is_some() {
+        "sqlite"
+    } else if config.db_path.is_some() {
+        "file"
+    } else {
+        "memory"
+    };
+    let state = AppState::open(config).await?;
+    #[cfg(feature = "nats")]
+    let state = AppState {
+        outbox: publisher::from_config(&state.config, &state.events),
+        ..state
+    };
+    tracing::info!(
+        backend,
+        movies = state.repo.list().await?.len(),
+        "store ready"
+    );
+
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    tracing::info!(addr = %local_addr, "listening");
+
+    let drain_timeout = state.config.shutdown_timeout;
+    let interval = state.config.snapshot_interval;
+    let compaction = (!interval.is_zero())
+        .then(|| tokio::spawn(storage::compact_periodically(state.repo.clone(), interval)));
+    let server = tokio::spawn(async move {
+        let served = run(listener, router(state.clone()), shutdown, drain_timeout).await;
+        if let Some(compaction) = compaction {
+            compaction.abort();
+        }
+        let flushed = state.shutdown().await;
+        served.and(flushed)
+    });
+    tracing::info!(addr = %local_addr, event = "ready", "ready");
+
+    Ok((local_addr, server))
+}
+
+/// Serves `app` on `listener` until `shutdown` resolves, then stops accepting
+/// connections and waits up to `drain_timeout` for requests in flight.
+/// Requests still running after that are no longer waited for; they end with
+/// the runtime when the process exits.
+async fn run(
+    listener: tokio::net::TcpListener,
+    app: Router,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+    drain_timeout: Duration,
+) -> std::io::Result<()> {
+    let (draining, drain_started) = tokio::sync::oneshot::channel();
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(async move {
+        shutdown.await;
+        tracing::info!(event = "shutdown", "shutting down");
+        let _ = draining.send(());
+    })
+    .into_future();
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => return result,
+        Ok(()) = drain_started => {}
+    }
+
+    match tokio::time::timeout(drain_timeout, server).await {
+        Ok(result) => result,
+        Err(_) => {
+            tracing::warn!(
+                timeout = ?drain_timeout,
+                "requests still in flight after the shutdown timeout were abandoned"
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM where there is one, as sent by
+/// `docker stop` and orchestrators.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for ctrl-c");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+}
+
+/// Logs human-readable lines by default, or one JSON object per line with
+/// `LOG_FORMAT=json` for log-parsing orchestration. `LOG_LEVEL` picks what is
+/// logged, unless `RUST_LOG` is set as well.
+/// Logs to `writer`: standard output while serving, standard error for
+/// commands, which may write their results to standard output.
+fn init_tracing<W>(config: &Config, writer: W)
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&config.log_level));
+
+    if config.log_json {
+        tracing_subscriber::fmt()
+            .json()
+            .with_env_filter(filter)
+            .with_writer(writer)
+            .init();
+    } else {
+        tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_writer(writer)
+            .init();
+    }
+}
+
+/// The span every request is handled in, so all events it causes carry its
+/// method, path and request id.
+fn request_span(request: &axum::extract::Request) -> tracing::Span {
+    let request_id = request
+        .headers()
+        .get(X_REQUEST_ID)
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = request.uri().path(),
+        request_id,
+    )
+}
+
 #[tokio::main]
 async fn main() {
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app()).await.unwrap();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = cli::parse(&args).unwrap_or_else(|error| {
+        eprintln!("{error}\n\n{}", cli::USAGE);
+        std::process::exit(cli::EXIT_USAGE);
+    });
+    let config = Config::from_env().unwrap_or_else(|error| {
+        eprintln!("invalid configuration: {error}");
+        std::process::exit(1);
+    });
+    if let Some(command) = command {
+        init_tracing(&config, std::io::stderr);
+        std::process::exit(cli::run(config, command).await);
+    }
+    init_tracing(&config, std::io::stdout);
+
+    let (_, server) = serve(config, shutdown_signal())
+        .await
+        .expect("failed to start server");
+
+    server
+        .await
+        .expect("server task panicked")
+        .expect("server failed");
 }
 
-async fn list_movies(State(state): State<AppState>) -> impl IntoResponse {
-    let movies: Vec<Movie> = state
-        .data
-        .read()
-        .expect("lock was poisoned")
-        .values()
-        .cloned()
-        .collect();
+/// One page of the matching movies in `?sort=` order, as JSON, XML or CSV.
+/// Paging is in `paging`, `Link` headers in `links` and `?stream=true` in
+/// `listing`; the ETag lets polling clients get a 304.
+#[utoipa::path(
+    get,
+    path = "/movie",
+    tag = "movies",
+    summary = "List movies, a page at a time",
+    params(
+        ListParams,
+        ("Accept" = Option<String>, Header, description = "`application/json` (the default), `application/xml` or `text/csv`"),
+        ("If-None-Match" = Option<String>, Header, description = "The ETag of a copy already held"),
+    ),
+    responses(
+        (status = OK, description = "A page of movies, or with `stream` a JSON array of every matching movie", content(
+            (Page<Movie> = "application/json"),
+            (Page<Movie> = "application/xml"),
+            (String = "text/csv"),
+        ), headers(("ETag" = String, description = "The quoted version"), ("Link" = String, description = "The first, previous, next and last page, given `page` or `per_page`"))),
+        (status = NOT_MODIFIED, description = "The page has not changed"),
+        (status = BAD_REQUEST, description = "Conflicting filters, an unknown sort key, an invalid cursor, a zero page or a sorted stream", body = ErrorBody),
+        (status = NOT_ACCEPTABLE, description = "None of the accepted types is offered", body = ErrorBody),
+    )
+)]
+async fn list_movies(
+    OriginalUri(uri): OriginalUri,
+    QueryParams(params): QueryParams<ListParams>,
+    QueryParams(query): QueryParams<HashMap<String, String>>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let format = Format::negotiate(&headers, &[Format::Json, Format::Xml, Format::Csv])?;
+    let checked = params.check(&query, &state.genres)?;
+
+    if params.stream {
+        if format != Format::Json {
+            return Err(ApiError::NotAcceptable {
+                supported: vec![Format::Json.content_type()],
+            });
+        }
+        if !checked.sort.is_by_id() || checked.after.is_some() {
+            return Err(ApiError::BadRequest(
+                "a streamed listing is ordered by id and has no cursor, sort and order cannot be changed"
+                    .to_string(),
+            ));
+        }
+        return Ok(listing::stream(state, checked.filter));
+    }
+
+    let page = movie_page(&state, params.page, &checked).await?;
+    let total = page.total;
+
+    // Only for clients paging by number, which a cursor's pages have none.
+    let link = (checked.after.is_none()
+        && (query.contains_key("page") || query.contains_key("per_page")))
+    .then(|| {
+        links::header(
+            &links::prefix(&state.config, &headers),
+            &uri,
+            page.page,
+            total.div_ceil(page.per_page).max(1),
+        )
+    });
+
+    // Serialized once for the tag and, unless the client has it, the body.
+    // CSV has no room for the paging fields, so it only holds the rows.
+    let body = match format {
+        Format::Json => serde_json::to_vec(&page).expect("movies always serialize"),
+        Format::Xml => negotiate::xml("page", &page).into_bytes(),
+        Format::Csv => catalogue::csv(page.items),
+    };
+    let etag = conditional::content_etag(&body);
+    if IfNoneMatch::from_headers(&headers).is_some_and(|tags| tags.matches(&etag)) {
+        let mut response = conditional::not_modified(etag);
+        response.headers_mut().extend([VARY_ACCEPT]);
+        return Ok(response);
+    }
+
+    let mut response = (
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(format.content_type()),
+            ),
+            (header::ETAG, etag),
+            VARY_ACCEPT,
+        ],
+        Extension(MoviePage),
+        body,
+    )
+        .into_response();
+    if let Some(link) = link {
+        response.headers_mut().insert(header::LINK, link);
+    }
+
+    Ok(response)
+}
+
+/// The page of live movies `listing` picks: the `page`th, or the one right
+/// after its cursor.
+async fn movie_page(
+    state: &AppState,
+    page: usize,
+    listing: &Listing,
+) -> Result<Page<Movie>, ApiError> {
+    let mut movies: Vec<Movie> = state
+        .live_movies()
+        .await?
+        .into_iter()
+        .filter(|movie| listing.filter.matches(movie))
+        .collect();
+    let total = movies.len();
+    let sort = &listing.sort;
+    sort.order(&mut movies);
+
+    // A cursor picks up after its position, a page number after the pages
+    // before it.
+    let skip = match &listing.after {
+        Some(after) => {
+            movies.partition_point(|movie| sort.compare(&sort.position(movie), after).is_le())
+        }
+        None => (page - 1).saturating_mul(listing.per_page),
+    };
+    let mut rest = movies.into_iter().skip(skip);
+    let items: Vec<Movie> = rest.by_ref().take(listing.per_page).collect();
+    let next_cursor = match (items.last(), rest.next()) {
+        (Some(last), Some(_)) => Some(sort.cursor(last)),
+        _ => None,
+    };
+
+    Ok(Page {
+        items,
+        total,
+        page,
+        per_page: listing.per_page,
+        next_cursor,
+    })
+}
+
+/// Movies whose name contains `?q=`, ignoring casing, spacing and unicode
+/// composition, ordered by name.
+#[utoipa::path(
+    get,
+    path = "/movie/search",
+    tag = "movies",
+    summary = "Search movies by name",
+    params(
+        SearchParams,
+    ),
+    responses(
+        (status = OK, description = "Matching movies by name", body = Vec<Movie>),
+        (status = BAD_REQUEST, description = "A missing or empty `q`", body = ErrorBody),
+    )
+)]
+async fn search_movies(
+    QueryParams(params): QueryParams<SearchParams>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<Movie>>, ApiError> {
+    let q = normalize_name(&params.q);
+    if q.is_empty() {
+        return Err(ApiError::BadRequest("q must not be empty".to_string()));
+    }
+
+    let mut movies: Vec<Movie> = state
+        .live_movies()
+        .await?
+        .into_iter()
+        .filter(|movie| matches(movie, &q))
+        .collect();
+    Sort::by(SortKey::Name).order(&mut movies);
+
+    Ok(Json(movies))
+}
+
+/// Every genre in use with the number of movies in it, by genre.
+#[utoipa::path(
+    get,
+    path = "/genre",
+    tag = "movies",
+    summary = "List genres with their movie counts",
+    responses(
+        (status = OK, description = "Every genre in use, by genre", body = Vec<GenreCount>),
+    )
+)]
+async fn list_genres(State(state): State<AppState>) -> Result<Json<Vec<GenreCount>>, ApiError> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for movie in state.live_movies().await? {
+        for genre in movie.genres {
+            *counts.entry(genre).or_default() += 1;
+        }
+    }
+
+    let mut genres: Vec<GenreCount> = counts
+        .into_iter()
+        .map(|(genre, count)| GenreCount { genre, count })
+        .collect();
+    genres.sort_by(|a, b| a.genre.cmp(&b.genre));
+
+    Ok(Json(genres))
+}
+
+/// The most fetched movies, most popular first. Counts decay over time and
+/// only cover full `GET /movie/{id}` reads.
+#[utoipa::path(
+    get,
+    path = "/movie/popular",
+    tag = "movies",
+    summary = "List the most fetched movies",
+    params(
+        PopularParams,
+    ),
+    responses(
+        (status = OK, description = "The most fetched movies, most popular first", body = Vec<PopularMovie>),
+    )
+)]
+async fn popular_movies(
+    QueryParams(params): QueryParams<PopularParams>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<PopularMovie>>, ApiError> {
+    let mut movies = Vec::new();
+
+    for (id, count) in state.popularity.top(params.limit, Instant::now()) {
+        if let Some(movie) = state.live_movie(&id).await? {
+            movies.push(PopularMovie {
+                id,
+                name: movie.name,
+                year: movie.year,
+                count,
+            });
+        }
+    }
+
+    Ok(Json(movies))
+}
+
+/// HEAD requests and `?existence_only=true` only check whether the movie
+/// exists and answer with the same status and headers a full GET would,
+/// without serializing the movie. Answers JSON or XML, as `Accept` asks.
+#[utoipa::path(
+    get,
+    path = "/movie/{id}",
+    tag = "movies",
+    summary = "Get a movie by ID",
+    params(
+        ("id" = String, Path, description = "The id of the movie"),
+        GetParams,
+        ("Accept" = Option<String>, Header, description = "`application/json` (the default) or `application/xml`"),
+        ("If-None-Match" = Option<String>, Header, description = "The ETag of a copy already held"),
+    ),
+    responses(
+        (status = OK, description = "The movie with a summary of its ratings", content(
+            (RatedMovie = "application/json"),
+            (RatedMovie = "application/xml"),
+        ), headers(("ETag" = String, description = "The quoted version"))),
+        (status = NOT_MODIFIED, description = "The movie has not changed"),
+        (status = PERMANENT_REDIRECT, description = "The movie has a new id, named by `Location`"),
+        (status = BAD_REQUEST, description = "An unknown or repeated `expand` target", body = ErrorBody),
+        (status = NOT_FOUND, description = "No such movie", body = ErrorBody),
+        (status = NOT_ACCEPTABLE, description = "None of the accepted types is offered", body = ErrorBody),
+    )
+)]
+async fn get_movie(
+    method: Method,
+    OriginalUri(uri): OriginalUri,
+    Path(id): Path<String>,
+    QueryParams(params): QueryParams<GetParams>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let existence_only = method == Method::HEAD || params.existence_only;
+    let format = Format::negotiate(&headers, &[Format::Json, Format::Xml])?;
+    let expand = params
+        .expand
+        .as_deref()
+        .map(people::Expand::parse)
+        .transpose()?;
+
+    // Expanding, people do not change between reading the movie and them.
+    let _checking = match expand {
+        Some(_) => Some(state.people.checking().await),
+        None => None,
+    };
+    let _rekeying = state.rekeying.read().await;
+
+    // A client that already holds this version gets no body and is not
+    // counted as a read.
+    let unchanged = |version| {
+        let etag = conditional::etag(version);
+        IfNoneMatch::from_headers(&headers)
+            .is_some_and(|tags| tags.matches(&etag))
+            .then(|| {
+                let mut response = conditional::not_modified(etag);
+                response.headers_mut().extend([VARY_ACCEPT]);
+                response
+            })
+    };
+
+    if existence_only {
+        if let Some(version) = state.repo.exists(&id).await? {
+            if let Some(response) = unchanged(version) {
+                return Ok(response);
+            }
+            return Ok((
+                StatusCode::OK,
+                [
+                    (
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static(format.content_type()),
+                    ),
+                    (header::ETAG, conditional::etag(version)),
+                    VARY_ACCEPT,
+                ],
+            )
+                .into_response());
+        }
+    } else if let Some(movie) = state.live_movie(&id).await? {
+        if let Some(response) = unchanged(movie.version) {
+            return Ok(response);
+        }
+
+        state.popularity.record(&id, Instant::now());
+        let etag = conditional::etag(movie.version);
+        let movie = RatedMovie {
+            ratings: state.ratings.summary(&id),
+            expanded: expand.map(|targets| state.people.expand(&movie, &targets)),
+            movie,
+        };
+        let headers = [(header::ETAG, etag), VARY_ACCEPT];
+        return Ok(match format {
+            Format::Xml => (
+                headers,
+                [(header::CONTENT_TYPE, format.content_type())],
+                negotiate::xml("movie", &movie),
+            )
+                .into_response(),
+            _ => (headers, Json(movie)).into_response(),
+        });
+    }
+
+    let redirects = state.redirects.read_or_recover();
+    match redirects.get(&id) {
+        Some(redirect) if redirect.expires_at > Instant::now() => {
+            // Next to the path asked for, so `/v1` clients stay on `/v1`.
+            let parent = uri.path().rsplit_once('/').map_or("", |(parent, _)| parent);
+            Ok(Redirect::permanent(&format!("{parent}/{}", redirect.new_id)).into_response())
+        }
+        _ if existence_only => Ok((
+            StatusCode::NOT_FOUND,
+            [(header::CONTENT_TYPE, "application/json")],
+        )
+            .into_response()),
+        _ => Err(ApiError::movie_not_found(id)),
+    }
+}
+
+/// Resolves a movie by name: a single exact (normalized) match or a single
+/// confident fuzzy match is returned directly, anything ambiguous answers 409
+/// with the best candidates so the caller can pick one by id.
+#[utoipa::path(
+    get,
+    path = "/movie/by-name/{name}",
+    tag = "movies",
+    summary = "Get a movie by (fuzzy) name",
+    params(
+        ("name" = String, Path, description = "The name to look for, matched loosely"),
+    ),
+    responses(
+        (status = OK, description = "The one movie matching the name", body = Movie),
+        (status = NOT_FOUND, description = "No movie matches", body = ErrorBody),
+        (status = CONFLICT, description = "Several movies match; `candidates` lists them", body = ErrorBody),
+    )
+)]
+async fn get_movie_by_name(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<Movie>, ApiError> {
+    let query = normalize_name(&name);
+    let s: HashMap<String, Movie> = state
+        .live_movies()
+        .await?
+        .into_iter()
+        .map(|movie| (movie.id.clone(), movie))
+        .collect();
+
+    let mut candidates: Vec<NameCandidate> = s
+        .values()
+        .map(|movie| NameCandidate {
+            id: movie.id.clone(),
+            name: movie.name.clone(),
+            year: movie.year,
+            score: similarity(&query, &normalize_name(&movie.name)),
+        })
+        .filter(|candidate| candidate.score >= CANDIDATE_MATCH)
+        .collect();
+
+    candidates.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.year.cmp(&b.year))
+            .then_with(|| a.id.cmp(&b.id))
+    });
+
+    let exact = candidates.iter().take_while(|c| c.score == 1.0).count();
+    let resolved = match candidates.as_slice() {
+        [] => return Err(ApiError::movie_not_found(name)),
+        [best, ..] if exact == 1 => Some(best),
+        [best] if best.score >= CONFIDENT_MATCH => Some(best),
+        [best, second, ..]
+            if exact == 0 && best.score >= CONFIDENT_MATCH && second.score < CONFIDENT_MATCH =>
+        {
+            Some(best)
+        }
+        _ => None,
+    };
+
+    if let Some(best) = resolved {
+        return Ok(Json(s[&best.id].clone()));
+    }
+
+    if exact > 1 {
+        candidates.truncate(exact);
+    }
+    candidates.truncate(MAX_CANDIDATES);
+    for candidate in &mut candidates {
+        candidate.score = (candidate.score * 100.0).round() / 100.0;
+    }
+
+    Err(ApiError::ambiguous_name(candidates))
+}
+
+/// Resolves current and replaced slugs alike, answering with the movie
+/// itself; its `slug` field names the current one.
+#[utoipa::path(
+    get,
+    path = "/movie/slug/{slug}",
+    tag = "movies",
+    summary = "Get a movie by its URL slug",
+    params(
+        ("slug" = String, Path, description = "A current or replaced slug"),
+    ),
+    responses(
+        (status = OK, description = "The movie", body = Movie),
+        (status = NOT_FOUND, description = "No movie has this slug", body = ErrorBody),
+    )
+)]
+async fn get_movie_by_slug(
+    Path(slug): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<Movie>, ApiError> {
+    let _rekeying = state.rekeying.read().await;
+    let id = state.slugs.read_or_recover().get(&slug).cloned();
+    let movie = match id {
+        Some(id) => state.live_movie(&id).await?,
+        None => None,
+    };
+
+    movie
+        .map(Json)
+        .ok_or_else(|| ApiError::movie_not_found(slug))
+}
+
+#[utoipa::path(
+    put,
+    path = "/movie/{id}",
+    tag = "movies",
+    summary = "Update a movie",
+    params(
+        ("id" = String, Path, description = "The id of the movie"),
+        UpdateParams,
+        ("If-Match" = Option<String>, Header, description = "Only go ahead while the movie is at one of these versions"),
+    ),
+    request_body = Movie,
+    responses(
+        (status = OK, description = "The updated movie", body = Movie, headers(("ETag" = String, description = "The quoted version"), ("X-Skipped-Fields" = String, description = "Locked fields whose new value was discarded"))),
+        (status = NOT_FOUND, description = "No such movie", body = ErrorBody),
+        (status = PRECONDITION_FAILED, description = "The movie is at another version than `If-Match` names", body = ErrorBody),
+        (status = UNPROCESSABLE_ENTITY, description = "Invalid fields", body = ErrorBody),
+    )
+)]
+async fn update_movie(
+    Path(id): Path<String>,
+    QueryParams(params): QueryParams<UpdateParams>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    JsonBody(payload): JsonBody<Movie>,
+) -> Result<impl IntoResponse, ApiError> {
+    let _fence = state.fence().await?;
+    let _checking = state.people.checking().await;
+    let mut errors = validate_movie(&payload, &state.config, "");
+    errors.extend(state.people.missing_from(&payload, ""));
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+
+    let if_match = IfMatch::from_headers(&headers);
+    store_update(&state, id, params.force, if_match, |_| payload.clone()).await
+}
+
+/// Only the fields present are changed; the rest keep their stored values.
+/// A present `custom` replaces the whole map.
+#[utoipa::path(
+    patch,
+    path = "/movie/{id}",
+    tag = "movies",
+    summary = "Update some fields of a movie",
+    params(
+        ("id" = String, Path, description = "The id of the movie"),
+        UpdateParams,
+        ("If-Match" = Option<String>, Header, description = "Only go ahead while the movie is at one of these versions"),
+    ),
+    request_body = MoviePatch,
+    responses(
+        (status = OK, description = "The updated movie", body = Movie, headers(("ETag" = String, description = "The quoted version"), ("X-Skipped-Fields" = String, description = "Locked fields whose new value was discarded"))),
+        (status = NOT_FOUND, description = "No such movie", body = ErrorBody),
+        (status = PRECONDITION_FAILED, description = "The movie is at another version than `If-Match` names", body = ErrorBody),
+        (status = UNPROCESSABLE_ENTITY, description = "Invalid fields", body = ErrorBody),
+    )
+)]
+async fn patch_movie(
+    Path(id): Path<String>,
+    QueryParams(params): QueryParams<UpdateParams>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    JsonBody(patch): JsonBody<MoviePatch>,
+) -> Result<impl IntoResponse, ApiError> {
+    let _fence = state.fence().await?;
+    let _checking = state.people.checking().await;
+    let mut errors = Vec::new();
+    if patch.id.is_some() {
+        errors.push(FieldError::new(
+            "id",
+            "cannot be patched, use POST /movie/{id}/change-id",
+        ));
+    }
+    if let Some(name) = &patch.name {
+        errors.extend(validate_name(name, "name".to_string()));
+    }
+    if let Some(year) = patch.year {
+        errors.extend(validate_year(year, "year".to_string()));
+    }
+    if let Some(custom) = &patch.custom {
+        errors.extend(validate_custom(custom, "custom"));
+    }
+    if let Some(genres) = &patch.genres {
+        errors.extend(validate_genres(genres, "genres"));
+    }
+    errors.extend(state.people.missing(
+        patch.director_id.as_ref().and_then(Option::as_deref),
+        patch.cast.as_deref().unwrap_or_default(),
+        "",
+    ));
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+
+    let if_match = IfMatch::from_headers(&headers);
+    store_update(&state, id, params.force, if_match, |stored| Movie {
+        name: patch.name.clone().unwrap_or_else(|| stored.name.clone()),
+        year: patch.year.unwrap_or(stored.year),
+        was_good: patch.was_good.unwrap_or(stored.was_good),
+        custom: patch
+            .custom
+            .clone()
+            .unwrap_or_else(|| stored.custom.clone()),
+        genres: patch
+            .genres
+            .clone()
+            .unwrap_or_else(|| stored.genres.clone()),
+        director_id: patch
+            .director_id
+            .clone()
+            .unwrap_or_else(|| stored.director_id.clone()),
+        cast: patch.cast.clone().unwrap_or_else(|| stored.cast.clone()),
+        ..stored.clone()
+    })
+    .await
+}
+
+/// Merges the full movie `build` derives from the stored one into the store,
+/// honoring locked fields, and answers like `PUT /movie/{id}`. With
+/// `If-Match` the stored movie must be at a listed version, otherwise 412;
+/// without it the last write wins, so a movie that changed between reading
+/// and writing it is simply merged again. An update changing nothing is not
+/// stored or announced, and answers the movie at its current version.
+async fn store_update(
+    state: &AppState,
+    id: String,
+    force: bool,
+    if_match: Option<IfMatch>,
+    build: impl Fn(&Movie) -> Movie,
+) -> Result<(HeaderMap, Json<Movie>), ApiError> {
+    let (before, movie, skipped) = loop {
+        let Some(stored) = state.live_movie(&id).await? else {
+            return Err(ApiError::movie_not_found(id));
+        };
+        if let Some(if_match) = &if_match
+            && !if_match.matches(stored.version)
+        {
+            return Err(ApiError::PreconditionFailed {
+                id,
+                version: stored.version,
+            });
+        }
+
+        let payload = build(&stored);
+        let payload = Movie {
+            name: clean_name(&payload.name),
+            ..payload
+        };
+        let (mut movie, skipped) = apply_update(&stored, payload, force);
+        if events::diff(&stored, &movie).is_empty() {
+            break (None, stored, skipped);
+        }
+        movie.sort_name = state.config.sort_name(&movie.name);
+        // A patch is only checked field by field, so the movie it makes is
+        // checked against the budgets as a whole.
+        let errors = validate_budget(&movie, &state.config, "");
+        if !errors.is_empty() {
+            return Err(ApiError::Validation(errors));
+        }
+        refresh_slug(
+            &mut state.slugs.write_or_recover(),
+            Some(&stored),
+            &mut movie,
+        );
+        match state.repo.update(movie.clone()).await {
+            Err(RepoError::Stale(_)) if if_match.is_none() => continue,
+            result => result?,
+        }
+
+        break (Some(stored), movie, skipped);
+    };
+    if let Some(before) = &before {
+        state.events.publish_update(before, movie.clone());
+    }
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::ETAG, conditional::etag(movie.version));
+    // Locked fields that were left untouched are reported in a header so the
+    // body keeps the same shape as every other movie response.
+    if !skipped.is_empty() {
+        headers.insert(
+            "x-skipped-fields",
+            HeaderValue::from_str(&skipped.join(", "))
+                .expect("field names are valid header values"),
+        );
+    }
+
+    Ok((headers, Json(movie)))
+}
+
+/// Moves the movie to the trash, or with `?permanent=true` removes it for
+/// good, trashed or not. With `If-Match` only deletes the movie while it is
+/// at a listed version.
+#[utoipa::path(
+    delete,
+    path = "/movie/{id}",
+    tag = "movies",
+    summary = "Move a movie to the trash",
+    params(
+        ("id" = String, Path, description = "The id of the movie"),
+        DeleteParams,
+        ("If-Match" = Option<String>, Header, description = "Only go ahead while the movie is at one of these versions"),
+    ),
+    responses(
+        (status = NO_CONTENT, description = "The movie was deleted"),
+        (status = NOT_FOUND, description = "No such movie", body = ErrorBody),
+        (status = PRECONDITION_FAILED, description = "The movie is at another version than `If-Match` names", body = ErrorBody),
+    )
+)]
+async fn delete_movie(
+    Path(id): Path<String>,
+    QueryParams(params): QueryParams<DeleteParams>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ApiError> {
+    let _fence = state.fence().await?;
+    let if_match = IfMatch::from_headers(&headers);
+    if params.permanent {
+        return purge_movie(&state, id, if_match).await;
+    }
+
+    loop {
+        let Some(mut movie) = state.live_movie(&id).await? else {
+            return Err(ApiError::movie_not_found(id));
+        };
+        if let Some(if_match) = &if_match
+            && !if_match.matches(movie.version)
+        {
+            return Err(ApiError::PreconditionFailed {
+                id,
+                version: movie.version,
+            });
+        }
+
+        movie.deleted_at = Some(Utc::now());
+        movie.version += 1;
+        match state.repo.update(movie.clone()).await {
+            Err(RepoError::Stale(_)) if if_match.is_none() => continue,
+            result => result?,
+        }
+        state.events.publish(EventKind::Deleted, movie);
+        break;
+    }
+
+    // Ratings and slugs stay for a restore; reads start over.
+    state.popularity.remove(&id);
+    tracing::info!(event = "movie.deleted", id = %id, "movie moved to the trash");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn purge_movie(
+    state: &AppState,
+    id: String,
+    if_match: Option<IfMatch>,
+) -> Result<StatusCode, ApiError> {
+    let version = match if_match {
+        Some(if_match) => {
+            let Some(stored) = state.repo.get(&id).await? else {
+                return Err(ApiError::movie_not_found(id));
+            };
+            if !if_match.matches(stored.version) {
+                return Err(ApiError::PreconditionFailed {
+                    id,
+                    version: stored.version,
+                });
+            }
+            Some(stored.version)
+        }
+        None => None,
+    };
+    let movie = state.repo.delete(&id, version).await?;
+    state.events.publish(EventKind::Deleted, movie);
+
+    state
+        .slugs
+        .write_or_recover()
+        .retain(|_, slug_id| *slug_id != id);
+    state.popularity.remove(&id);
+    state.ratings.remove_movie(&id);
+    state.posters.remove(&id).await;
+    tracing::info!(event = "movie.deleted", id = %id, permanent = true, "movie deleted");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Takes a movie out of the trash as it was, at its next version.
+#[utoipa::path(
+    post,
+    path = "/movie/{id}/restore",
+    tag = "movies",
+    summary = "Restore a movie from the trash",
+    params(
+        ("id" = String, Path, description = "The id of the movie"),
+    ),
+    responses(
+        (status = OK, description = "The restored movie", body = Movie, headers(("ETag" = String, description = "The quoted version"))),
+        (status = NOT_FOUND, description = "No such movie", body = ErrorBody),
+        (status = CONFLICT, description = "The movie is not in the trash", body = ErrorBody),
+    )
+)]
+async fn restore_movie(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let _fence = state.fence().await?;
+    let movie = loop {
+        let Some(mut movie) = state.repo.get(&id).await? else {
+            return Err(ApiError::movie_not_found(id));
+        };
+        if movie.deleted_at.is_none() {
+            return Err(ApiError::Conflict {
+                conflict_type: ConflictType::NotTrashed,
+                message: format!("movie {id} is not in the trash"),
+                existing: None,
+                candidates: None,
+            });
+        }
+
+        movie.deleted_at = None;
+        movie.version += 1;
+        match state.repo.update(movie.clone()).await {
+            Err(RepoError::Stale(_)) => continue,
+            result => result?,
+        }
+        break movie;
+    };
+    // Back in listings, so announced like a new movie.
+    state.events.publish(EventKind::Created, movie.clone());
+    tracing::info!(event = "movie.restored", id = %id, "movie restored");
+
+    Ok((
+        [(header::ETAG, conditional::etag(movie.version))],
+        Json(movie),
+    ))
+}
+
+/// The movies in the trash, most recently deleted first.
+#[utoipa::path(
+    get,
+    path = "/movie/trash",
+    tag = "movies",
+    summary = "List movies in the trash",
+    responses(
+        (status = OK, description = "Trashed movies, most recently deleted first", body = Vec<Movie>),
+    )
+)]
+async fn trashed_movies(State(state): State<AppState>) -> Result<Json<Vec<Movie>>, ApiError> {
+    let mut movies: Vec<Movie> = state
+        .repo
+        .list()
+        .await?
+        .into_iter()
+        .filter(|movie| movie.deleted_at.is_some())
+        .collect();
+    movies.sort_by(|a, b| {
+        b.deleted_at
+            .cmp(&a.deleted_at)
+            .then_with(|| a.id.cmp(&b.id))
+    });
+
+    Ok(Json(movies))
+}
+
+#[utoipa::path(
+    post,
+    path = "/movie",
+    tag = "movies",
+    summary = "Create a movie",
+    request_body = Movie,
+    responses(
+        (status = CREATED, description = "The created movie", body = Movie),
+        (status = CONFLICT, description = "The id is taken, possibly by a movie in the trash", body = ErrorBody),
+        (status = UNPROCESSABLE_ENTITY, description = "Invalid fields", body = ErrorBody),
+    )
+)]
+async fn create_movie(
+    State(state): State<AppState>,
+    JsonBody(payload): JsonBody<Movie>,
+) -> Result<impl IntoResponse, ApiError> {
+    let _fence = state.fence().await?;
+    let movie = store_new_movie(&state, payload).await?;
+
+    Ok((StatusCode::CREATED, Json(movie)))
+}
+
+/// Validates `payload`, stores it as a new movie and announces it; the
+/// common part of `POST /movie` and the imports of single movies.
+async fn store_new_movie(state: &AppState, payload: Movie) -> Result<Movie, ApiError> {
+    let _checking = state.people.checking().await;
+    let mut errors = validate_new_movie(&payload, &state.config, "");
+    errors.extend(state.people.missing_from(&payload, ""));
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+
+    // Checked before a slug is reserved, so a taken id does not leave an
+    // alias to the existing movie behind.
+    if let Some(existing) = state.repo.get(&payload.id).await? {
+        return Err(ApiError::duplicate_id(
+            &existing,
+            state.config.conflict_detail,
+        ));
+    }
+
+    let mut movie = new_movie(&state.config, payload);
+    refresh_slug(&mut state.slugs.write_or_recover(), None, &mut movie);
+    match state.repo.insert(movie.clone()).await {
+        Err(RepoError::Conflict(existing)) => {
+            return Err(ApiError::duplicate_id(
+                &existing,
+                state.config.conflict_detail,
+            ));
+        }
+        result => result?,
+    }
+
+    state.events.publish(EventKind::Created, movie.clone());
+    tracing::info!(event = "movie.created", id = %movie.id, "movie created");
+
+    Ok(movie)
+}
+
+/// Creates many movies with one repository write. Each movie is checked like
+/// on `POST /movie`, and ids already stored or earlier in the batch count as
+/// duplicates. By default the valid movies are created and the rest reported;
+/// with `?atomic=true` any failing movie fails the whole batch with 422.
+#[utoipa::path(
+    post,
+    path = "/movie/batch",
+    tag = "movies",
+    summary = "Create many movies at once",
+    params(
+        BatchParams,
+    ),
+    request_body = Vec<Movie>,
+    responses(
+        (status = OK, description = "The outcome for each movie", body = Vec<BatchResult>),
+        (status = PAYLOAD_TOO_LARGE, description = "More movies than one batch takes", body = ErrorBody),
+        (status = UNPROCESSABLE_ENTITY, description = "With `?atomic=true`, some movie could not be created", body = ErrorBody),
+    )
+)]
+async fn create_movies(
+    QueryParams(params): QueryParams<BatchParams>,
+    State(state): State<AppState>,
+    JsonBody(movies): JsonBody<Vec<Movie>>,
+) -> Result<Json<Vec<BatchResult>>, ApiError> {
+    if movies.len() > MAX_BATCH {
+        return Err(ApiError::PayloadTooLarge(format!(
+            "a batch holds at most {MAX_BATCH} movies, got {}",
+            movies.len()
+        )));
+    }
+
+    let _fence = state.fence().await?;
+    let _checking = state.people.checking().await;
+    let mut taken = HashSet::new();
+    for movie in &movies {
+        if state.repo.get(&movie.id).await?.is_some() {
+            taken.insert(movie.id.clone());
+        }
+    }
+
+    let mut results = Vec::with_capacity(movies.len());
+    let mut failures = Vec::new();
+    for (index, movie) in movies.iter().enumerate() {
+        let mut errors = validate_new_movie(movie, &state.config, &format!("[{index}]."));
+        errors.extend(state.people.missing_from(movie, &format!("[{index}].")));
+        let status = if !errors.is_empty() {
+            failures.extend(errors.iter().cloned());
+            BatchStatus::Invalid
+        } else if !taken.insert(movie.id.clone()) {
+            failures.push(FieldError::new(
+                format!("[{index}].id"),
+                format!("movie {} already exists", movie.id),
+            ));
+            BatchStatus::DuplicateId
+        } else {
+            BatchStatus::Created
+        };
+
+        results.push(BatchResult {
+            index,
+            id: movie.id.clone(),
+            status,
+            errors,
+        });
+    }
+
+    if params.atomic && !failures.is_empty() {
+        return Err(ApiError::Validation(failures));
+    }
+
+    let created: Vec<Movie> = {
+        let mut slugs = state.slugs.write_or_recover();
+        movies
+            .into_iter()
+            .zip(&results)
+            .filter(|(_, result)| result.status == BatchStatus::Created)
+            .map(|(movie, _)| {
+                let mut movie = new_movie(&state.config, movie);
+                refresh_slug(&mut slugs, None, &mut movie);
+                movie
+            })
+            .collect()
+    };
+    state
+        .repo
+        .apply(created.iter().cloned().map(Write::Insert).collect())
+        .await?;
+    for movie in created {
+        tracing::info!(event = "movie.created", id = %movie.id, "movie created");
+        state.events.publish(EventKind::Created, movie);
+    }
+
+    Ok(Json(results))
+}
+
+fn validate_lockable(fields: &[String]) -> Result<(), ApiError> {
+    let errors: Vec<FieldError> = fields
+        .iter()
+        .enumerate()
+        .filter(|(_, f)| !UPDATABLE_FIELDS.iter().any(|(field, _)| field == f))
+        .map(|(i, f)| FieldError::new(format!("fields[{i}]"), format!("unknown field {f}")))
+        .collect();
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ApiError::Validation(errors))
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/movie/{id}/lock",
+    tag = "movies",
+    summary = "Lock fields against overwrites",
+    params(
+        ("id" = String, Path, description = "The id of the movie"),
+    ),
+    request_body = LockRequest,
+    responses(
+        (status = OK, description = "The movie with its locked fields", body = Movie),
+        (status = NOT_FOUND, description = "No such movie", body = ErrorBody),
+        (status = UNPROCESSABLE_ENTITY, description = "A field that cannot be locked", body = ErrorBody),
+    )
+)]
+async fn lock_fields(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    JsonBody(payload): JsonBody<LockRequest>,
+) -> Result<Json<Movie>, ApiError> {
+    validate_lockable(&payload.fields)?;
+    let _fence = state.fence().await?;
+
+    store_locks(&state, id, |locked| {
+        *locked = UPDATABLE_FIELDS
+            .iter()
+            .map(|(f, _)| *f)
+            .filter(|f| locked.iter().any(|l| l == f) || payload.fields.iter().any(|l| l == f))
+            .map(|f| f.to_string())
+            .collect();
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/movie/{id}/unlock",
+    tag = "movies",
+    summary = "Unlock previously locked fields",
+    params(
+        ("id" = String, Path, description = "The id of the movie"),
+    ),
+    request_body = LockRequest,
+    responses(
+        (status = OK, description = "The movie with its locked fields", body = Movie),
+        (status = NOT_FOUND, description = "No such movie", body = ErrorBody),
+        (status = UNPROCESSABLE_ENTITY, description = "A field that cannot be locked", body = ErrorBody),
+    )
+)]
+async fn unlock_fields(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    JsonBody(payload): JsonBody<LockRequest>,
+) -> Result<Json<Movie>, ApiError> {
+    validate_lockable(&payload.fields)?;
+    let _fence = state.fence().await?;
+
+    store_locks(&state, id, |locked| {
+        locked.retain(|f| !payload.fields.contains(f))
+    })
+    .await
+}
+
+/// Stores the movie with its locked fields changed by `change`, at its next
+/// version. Lock requests carry no `If-Match`, so a movie that changed
+/// between reading and writing it is read and changed again.
+async fn store_locks(
+    state: &AppState,
+    id: String,
+    change: impl Fn(&mut Vec<String>),
+) -> Result<Json<Movie>, ApiError> {
+    loop {
+        let Some(stored) = state.live_movie(&id).await? else {
+            return Err(ApiError::movie_not_found(id));
+        };
+
+        let mut movie = stored.clone();
+        change(&mut movie.locked_fields);
+        movie.version += 1;
+        movie.updated_at = Utc::now();
+        match state.repo.update(movie.clone()).await {
+            Err(RepoError::Stale(_)) => continue,
+            result => result?,
+        }
+        state.events.publish_update(&stored, movie.clone());
+
+        return Ok(Json(movie));
+    }
+}
+
+/// Re-keys a movie under a new id. The old id answers GETs with a permanent
+/// redirect to the new one for the configured grace period. A change made to
+/// the movie meanwhile moves along with it, and no read sees the movie under
+/// its new id before its ratings, watchlist entries and poster are there.
+#[utoipa::path(
+    post,
+    path = "/movie/{id}/change-id",
+    tag = "movies",
+    summary = "Move a movie to a new ID",
+    params(
+        ("id" = String, Path, description = "The id of the movie"),
+    ),
+    request_body = ChangeIdRequest,
+    responses(
+        (status = OK, description = "The movie under its new id", body = Movie),
+        (status = NOT_FOUND, description = "No such movie", body = ErrorBody),
+        (status = CONFLICT, description = "The new id is taken", body = ErrorBody),
+        (status = UNPROCESSABLE_ENTITY, description = "An invalid new id", body = ErrorBody),
+    )
+)]
+async fn change_movie_id(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    JsonBody(payload): JsonBody<ChangeIdRequest>,
+) -> Result<Json<Movie>, ApiError> {
+    let _fence = state.fence().await?;
+    let new_id = payload.new_id;
+
+    if !is_valid_id(&new_id) {
+        return Err(ApiError::validation("new_id", INVALID_ID));
+    }
+
+    if let Some(existing) = state.repo.get(&new_id).await? {
+        return Err(ApiError::duplicate_id(
+            &existing,
+            state.config.conflict_detail,
+        ));
+    }
+
+    // The references move to the new id in one batch, which a person's
+    // delete must not look for halfway.
+    let _checking = state.people.checking().await;
+    let _rekeying = state.rekeying.write().await;
+    let (previous, movie) = loop {
+        let Some(previous) = state.live_movie(&id).await? else {
+            return Err(ApiError::movie_not_found(id));
+        };
+        let movie = Movie {
+            id: new_id.clone(),
+            version: previous.version + 1,
+            updated_at: Utc::now(),
+            ..previous.clone()
+        };
+        match state
+            .repo
+            .apply(vec![
+                Write::Delete(id.clone(), Some(previous.version)),
+                Write::Insert(movie.clone()),
+            ])
+            .await
+        {
+            // Changed since it was read; move it as it is now.
+            Err(RepoError::Stale(_)) => continue,
+            Err(RepoError::Conflict(existing)) => {
+                return Err(ApiError::duplicate_id(
+                    &existing,
+                    state.config.conflict_detail,
+                ));
+            }
+            result => result?,
+        }
+        break (previous, movie);
+    };
+    // To clients keyed on ids the old one is gone and the new one appeared.
+    state.events.publish(EventKind::Deleted, previous);
+    state.events.publish(EventKind::Created, movie.clone());
+    state.popularity.remove(&id);
+    state.ratings.rename(&id, &new_id);
+    state.watchlists.rename_movie(&id, &new_id);
+    if movie.has_poster {
+        state.posters.rename(&id, &new_id).await;
+    }
+
+    hand_over(&state, id, &new_id);
+
+    Ok(Json(movie))
+}
+
+/// Points what led to the movie `id` at `new_id` instead: its slugs, which
+/// stay as aliases, redirects to it, and a new redirect from `id` itself
+/// that expires after `ID_REDIRECT_GRACE`.
+fn hand_over(state: &AppState, id: String, new_id: &str) {
+    for slug_id in state
+        .slugs
+        .write_or_recover()
+        .values_mut()
+        .filter(|slug_id| **slug_id == id)
+    {
+        *slug_id = new_id.to_string();
+    }
+
+    let mut redirects = state.redirects.write_or_recover();
+    let now = Instant::now();
+    redirects.retain(|_, redirect| redirect.expires_at > now);
+    for redirect in redirects.values_mut() {
+        if redirect.new_id == id {
+            redirect.new_id = new_id.to_string();
+        }
+    }
+    redirects.insert(
+        id,
+        IdRedirect {
+            new_id: new_id.to_string(),
+            expires_at: now + state.config.id_redirect_grace,
+        },
+    );
+}
+
+/// Applies an ordered list of operations atomically. Every operation is
+/// validated against the state the preceding ones would leave behind before
+/// anything is written, and the whole batch goes to the repository as one
+/// `apply`, so readers never observe a partially applied transaction.
+#[utoipa::path(
+    post,
+    path = "/movie/transaction",
+    tag = "movies",
+    summary = "Apply several operations atomically",
+    request_body = Vec<Operation>,
+    responses(
+        (status = OK, description = "The result of each operation", body = Vec<OperationResult>),
+        (status = UNPROCESSABLE_ENTITY, description = "The failing operations; nothing was applied", body = ErrorBody),
+    )
+)]
+async fn movie_transaction(
+    State(state): State<AppState>,
+    JsonBody(operations): JsonBody<Vec<Operation>>,
+) -> Result<Json<Vec<OperationResult>>, ApiError> {
+    let _bulk = state.bulk.write().await;
+    let _checking = state.people.checking().await;
+    let Transaction {
+        results, changes, ..
+    } = loop {
+        let transaction = plan_transaction(&state, &operations).await?;
+        // A movie written since it was read fails the batch as a whole and
+        // nothing is applied, so it is planned again against the movie as
+        // it is now.
+        match state.repo.apply(transaction.writes.clone()).await {
+            Err(RepoError::Stale(_)) => continue,
+            result => result?,
+        }
+        break transaction;
+    };
+
+    for event in changes {
+        state.events.send(event);
+    }
+    for result in &results {
+        match result.op {
+            "create" => tracing::info!(event = "movie.created", id = %result.id, "movie created"),
+            "delete" => {
+                state.popularity.remove(&result.id);
+                tracing::info!(event = "movie.deleted", id = %result.id, "movie moved to the trash");
+            }
+            _ => {}
+        }
+    }
+
+    Ok(Json(results))
+}
+
+/// A checked `POST /movie/transaction`, ready to be applied.
+struct Transaction {
+    writes: Vec<Write>,
+    results: Vec<OperationResult>,
+    /// Published once the writes are applied.
+    changes: Vec<MovieEvent>,
+}
+
+/// Checks `operations` against the stored movies they touch and builds the
+/// writes applying them all.
+async fn plan_transaction(
+    state: &AppState,
+    operations: &[Operation],
+) -> Result<Transaction, ApiError> {
+    // The stored movies the batch touches, updated as the batch is applied.
+    let mut stored: HashMap<String, Movie> = HashMap::new();
+    for operation in operations {
+        let id = match operation {
+            Operation::Create { movie } => &movie.id,
+            Operation::Update { id, .. } | Operation::Delete { id } => id,
+        };
+        if !stored.contains_key(id)
+            && let Some(movie) = state.repo.get(id).await?
+        {
+            stored.insert(id.clone(), movie);
+        }
+    }
+
+    // Tracks ids created or deleted by earlier operations in the batch,
+    // falling back to the store for ids the batch has not touched yet. A
+    // deleted movie goes to the trash and keeps its id taken.
+    let mut pending: HashMap<&str, bool> = HashMap::new();
+    let mut errors = Vec::new();
+
+    for (index, operation) in operations.iter().enumerate() {
+        if let Operation::Update { movie, .. } = operation {
+            errors.extend(validate_movie(
+                movie,
+                &state.config,
+                &format!("[{index}].movie."),
+            ));
+        }
+
+        match operation {
+            Operation::Create { movie } => {
+                errors.extend(validate_new_movie(
+                    movie,
+                    &state.config,
+                    &format!("[{index}].movie."),
+                ));
+
+                let taken =
+                    pending.get(movie.id.as_str()) == Some(&true) || stored.contains_key(&movie.id);
+
+                if taken {
+                    errors.push(FieldError::new(
+                        format!("[{index}].movie.id"),
+                        format!("movie {} already exists", movie.id),
+                    ));
+                } else {
+                    pending.insert(&movie.id, true);
+                }
+            }
+            Operation::Update { id, .. } | Operation::Delete { id } => {
+                let exists = pending.get(id.as_str()).copied().unwrap_or_else(|| {
+                    stored
+                        .get(id)
+                        .is_some_and(|movie| movie.deleted_at.is_none())
+                });
+
+                if !exists {
+                    errors.push(FieldError::new(
+                        format!("[{index}].id"),
+                        format!("movie {id} does not exist"),
+                    ));
+                } else if matches!(operation, Operation::Delete { .. }) {
+                    pending.insert(id, false);
+                }
+            }
+        }
+        if let Operation::Create { movie } | Operation::Update { movie, .. } = operation {
+            errors.extend(
+                state
+                    .people
+                    .missing_from(movie, &format!("[{index}].movie.")),
+            );
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+
+    let mut writes = Vec::with_capacity(operations.len());
+    let mut changes = Vec::with_capacity(operations.len());
+    let results: Vec<OperationResult> = {
+        let mut slugs = state.slugs.write_or_recover();
+        operations
+            .iter()
+            .enumerate()
+            .map(|(index, operation)| match operation {
+                Operation::Create { movie } => {
+                    let mut movie = new_movie(&state.config, movie.clone());
+                    refresh_slug(&mut slugs, None, &mut movie);
+                    stored.insert(movie.id.clone(), movie.clone());
+                    writes.push(Write::Insert(movie.clone()));
+                    changes.push(MovieEvent::new(EventKind::Created, movie.clone()));
+                    OperationResult {
+                        index,
+                        op: "create",
+                        id: movie.id.clone(),
+                        movie: Some(movie),
+                        skipped: Vec::new(),
+                    }
+                }
+                Operation::Update { id, movie } => {
+                    let movie = Movie {
+                        name: clean_name(&movie.name),
+                        ..movie.clone()
+                    };
+                    let (mut movie, skipped) = apply_update(&stored[id], movie, false);
+                    // Changing nothing, it is neither written nor announced.
+                    if events::diff(&stored[id], &movie).is_empty() {
+                        movie = stored[id].clone();
+                    } else {
+                        movie.sort_name = state.config.sort_name(&movie.name);
+                        refresh_slug(&mut slugs, stored.get(id), &mut movie);
+                        let before = stored.insert(movie.id.clone(), movie.clone());
+                        writes.push(Write::Update(movie.clone()));
+                        changes.push(MovieEvent::updated(
+                            &before.expect("updated movies are stored"),
+                            movie.clone(),
+                        ));
+                    }
+                    OperationResult {
+                        index,
+                        op: "update",
+                        id: movie.id.clone(),
+                        movie: Some(movie),
+                        skipped,
+                    }
+                }
+                Operation::Delete { id } => {
+                    let mut movie = stored[id].clone();
+                    movie.deleted_at = Some(Utc::now());
+                    movie.version += 1;
+                    stored.insert(id.clone(), movie.clone());
+                    writes.push(Write::Update(movie.clone()));
+                    changes.push(MovieEvent::new(EventKind::Deleted, movie));
+                    OperationResult {
+                        index,
+                        op: "delete",
+                        id: id.clone(),
+                        movie: None,
+                        skipped: Vec::new(),
+                    }
+                }
+            })
+            .collect()
+    };
+
+    Ok(Transaction {
+        writes,
+        results,
+        changes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::{Body, HttpBody},
+        extract::ConnectInfo,
+        http::Request,
+    };
+    use cache::CachePolicies;
+    use cors::CorsPolicy;
+    use http_body_util::BodyExt;
+    use std::path::PathBuf;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn create_movie_returns_created() {
+        let response = app()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/movie")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"{"id":"1","name":"Test Movie","year":2024,"was_good":true}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let movie: Movie = serde_json::from_slice(&body).unwrap();
+        assert_eq!(movie.id, "1");
+        assert_eq!(movie.name, "Test Movie");
+        assert_eq!(movie.year, 2024);
+        assert!(movie.was_good);
+    }
+
+    #[tokio::test]
+    async fn create_movie_rejects_taken_id() {
+        let app = app();
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"The Matrix","year":1999,"was_good":true}"#],
+        )
+        .await;
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/movie")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["conflict_type"], "duplicate_id");
+        assert_eq!(body["existing"]["id"], "1");
+        assert_eq!(body["existing"]["name"], "The Matrix");
+
+        let (status, movie) = get_by_name(&app, "the%20matrix").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(movie["id"], "1");
+    }
+
+    #[tokio::test]
+    async fn create_movie_rejects_empty_id() {
+        let response = app()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/movie")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"{"id":"","name":"Heat","year":1995,"was_good":true}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["errors"][0]["field"], "id");
+    }
+
+    async fn send_json(app: &Router, method: &str, uri: &str, body: Value) -> (StatusCode, Value) {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn failing_fields(body: &Value) -> Vec<&str> {
+        body["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|error| error["field"].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn create_movie_validates_each_field() {
+        let app = app();
+        let heat = json!({"id": "1", "name": "Heat", "year": 1995, "was_good": true});
+        let with = |field: &str, value: Value| {
+            let mut movie = heat.clone();
+            movie[field] = value;
+            movie
+        };
+
+        for (movie, field) in [
+            (with("name", json!("")), "name"),
+            (with("name", json!(" \t\n ")), "name"),
+            (with("name", json!("x".repeat(MAX_NAME_CHARS + 1))), "name"),
+            (with("year", json!(0)), "year"),
+            (with("year", json!(1877)), "year"),
+            (with("year", json!(2101)), "year"),
+            (with("year", json!(65535)), "year"),
+            (with("id", json!("")), "id"),
+            (with("id", json!("the matrix")), "id"),
+            (with("id", json!("a/b")), "id"),
+        ] {
+            let (status, body) = send_json(&app, "POST", "/movie", movie.clone()).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{movie}");
+            assert_eq!(failing_fields(&body), [field], "{movie}");
+        }
+
+        for (id, movie) in [
+            ("2", with("year", json!(1878))),
+            ("3", with("year", json!(2100))),
+            (
+                "4",
+                with("name", json!(format!("  {}  ", "x".repeat(MAX_NAME_CHARS)))),
+            ),
+        ] {
+            let mut movie = movie;
+            movie["id"] = json!(id);
+            let (status, _) = send_json(&app, "POST", "/movie", movie.clone()).await;
+            assert_eq!(status, StatusCode::CREATED, "{movie}");
+        }
+    }
+
+    #[tokio::test]
+    async fn validation_reports_every_failing_field() {
+        let app = app();
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#],
+        )
+        .await;
+
+        let (status, body) = send_json(
+            &app,
+            "POST",
+            "/movie",
+            json!({"id": "", "name": "   ", "year": 0, "was_good": true}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "VALIDATION_FAILED");
+        assert_eq!(failing_fields(&body), ["id", "name", "year"]);
+
+        let (status, body) = send_json(
+            &app,
+            "PUT",
+            "/movie/1",
+            json!({"id": "1", "name": "", "year": 3000, "was_good": true}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(failing_fields(&body), ["name", "year"]);
+
+        let (status, body) = send_json(
+            &app,
+            "PATCH",
+            "/movie/1",
+            json!({"name": " ", "year": 1800}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(failing_fields(&body), ["name", "year"]);
+
+        let (_, movie) = get_by_slug(&app, "heat-1995").await;
+        assert_eq!(movie["name"], "Heat");
+    }
+
+    #[tokio::test]
+    async fn get_movie_not_found() {
+        let response = app()
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/movie/999")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "MOVIE_NOT_FOUND");
+        assert_eq!(body["message"], "movie 999 does not exist");
+    }
+
+    #[tokio::test]
+    async fn create_and_get_movie() {
+        let app = app();
+
+        // Create a movie
+        let create_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/movie")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"{"id":"1","name":"The Matrix","year":1999,"was_good":true}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(create_response.status(), StatusCode::CREATED);
+
+        // Get the movie
+        let get_response = app
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/movie/1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(get_response.status(), StatusCode::OK);
+
+        let body = get_response.into_body().collect().await.unwrap().to_bytes();
+        let movie: Movie = serde_json::from_slice(&body).unwrap();
+        assert_eq!(movie.id, "1");
+        assert_eq!(movie.name, "The Matrix");
+        assert_eq!(movie.year, 1999);
+        assert!(movie.was_good);
+    }
+
+    #[tokio::test]
+    async fn list_movies_empty() {
+        let response = app()
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/movie")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let movies = serde_json::from_slice::<Page<Movie>>(&body).unwrap().items;
+        assert!(movies.is_empty());
+    }
+
+    #[tokio::test]
+    async fn list_movies_with_data() {
+        let app = app();
+
+        // Create a movie first
+        app.clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/movie")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"{"id":"1","name":"Test Movie","year":2024,"was_good":true}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // List movies
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/movie")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let movies = serde_json::from_slice::<Page<Movie>>(&body).unwrap().items;
+        assert_eq!(movies.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn update_movie_success() {
+        let app = app();
+
+        // Create a movie
+        app.clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/movie")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"{"id":"1","name":"Old Name","year":2020,"was_good":false}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Update the movie
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("PUT")
+                    .uri("/movie/1")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"{"id":"1","name":"New Name","year":2024,"was_good":true}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let movie: Movie = serde_json::from_slice(&body).unwrap();
+        assert_eq!(movie.name, "New Name");
+        assert_eq!(movie.year, 2024);
+        assert!(movie.was_good);
+    }
+
+    #[tokio::test]
+    async fn update_movie_not_found() {
+        let response = app()
+            .oneshot(
+                Request::builder()
+                    .method("PUT")
+                    .uri("/movie/999")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"{"id":"999","name":"Test","year":2024,"was_good":true}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn patch_movie_changes_only_given_fields() {
+        let app = app();
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"The Matrix","year":1999,"was_good":false}"#],
+        )
+        .await;
+
+        let patch = |id: &str, body: &'static str| {
+            Request::builder()
+                .method("PATCH")
+                .uri(format!("/movie/{id}"))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(patch("1", r#"{"was_good":true}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/movie/1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let movie: Movie = serde_json::from_slice(&body).unwrap();
+        assert_eq!(movie.name, "The Matrix");
+        assert_eq!(movie.year, 1999);
+        assert!(movie.was_good);
+
+        let response = app
+            .clone()
+            .oneshot(patch("1", r#"{"id":"2"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let response = app
+            .oneshot(patch("999", r#"{"was_good":true}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn delete_movie_success() {
+        let app = app();
+
+        // Create a movie
+        app.clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/movie")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"{"id":"1","name":"Test","year":2024,"was_good":true}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Delete the movie
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/movie/1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn delete_movie_not_found() {
+        let response = app()
+            .oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/movie/999")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    async fn put_if_match(app: &Router, if_match: Option<&str>, body: &str) -> Response {
+        let mut request = Request::builder()
+            .method("PUT")
+            .uri("/movie/1")
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(if_match) = if_match {
+            request = request.header(header::IF_MATCH, if_match);
+        }
+
+        app.clone()
+            .oneshot(request.body(Body::from(body.to_string())).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn if_match_guards_updates() {
+        let app = app();
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#],
+        )
+        .await;
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/movie/1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::ETAG], "\"1\"");
+
+        // The version in the body is ignored in favor of the stored one.
+        let response = put_if_match(
+            &app,
+            Some("\"1\""),
+            r#"{"id":"1","name":"Heat","year":1995,"was_good":false,"version":40}"#,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::ETAG], "\"2\"");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let movie: Movie = serde_json::from_slice(&body).unwrap();
+        assert_eq!(movie.version, 2);
+        assert!(!movie.was_good);
+
+        // A client still holding version 1 is told about version 2.
+        let response = put_if_match(
+            &app,
+            Some("\"1\""),
+            r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "PRECONDITION_FAILED");
+        assert_eq!(body["version"], 2);
+
+        let (_, movie) = get_by_slug(&app, "heat-1995").await;
+        assert_eq!(movie["was_good"], false);
+
+        // Without If-Match the last write wins.
+        let response = put_if_match(
+            &app,
+            None,
+            r#"{"id":"1","name":"Heat","year":1995,"was_good":true,"version":1}"#,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::ETAG], "\"3\"");
+    }
+
+    #[tokio::test]
+    async fn if_match_guards_deletes() {
+        let app = app();
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#],
+        )
+        .await;
+
+        for (if_match, status) in [
+            ("\"2\"", StatusCode::PRECONDITION_FAILED),
+            ("\"1\"", StatusCode::NO_CONTENT),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("DELETE")
+                        .uri("/movie/1")
+                        .header(header::IF_MATCH, if_match)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{if_match}");
+        }
+    }
+
+    async fn get_if_none_match(app: &Router, uri: &str, etag: &HeaderValue) -> Response {
+        app.clone()
+            .oneshot(
+                Request::get(uri)
+                    .header(header::IF_NONE_MATCH, etag)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn if_none_match_answers_not_modified() {
+        let app = app();
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#],
+        )
+        .await;
+
+        for uri in ["/movie", "/movie/1"] {
+            let response = app
+                .clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let etag = response.headers()[header::ETAG].clone();
+
+            let response = get_if_none_match(&app, uri, &etag).await;
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED, "{uri}");
+            assert_eq!(response.headers()[header::ETAG], etag, "{uri}");
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert!(body.is_empty(), "{uri}");
+        }
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/movie").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let etag = response.headers()[header::ETAG].clone();
+        seed(
+            &app,
+            &[r#"{"id":"2","name":"Ronin","year":1998,"was_good":true}"#],
+        )
+        .await;
+
+        let response = get_if_none_match(&app, "/movie", &etag).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(response.headers()[header::ETAG], etag);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let page: Page<Movie> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(page.total, 2);
+    }
+
+    #[tokio::test]
+    async fn cached_movies_are_revalidated_in_one_request() {
+        let app = app();
+        seed(
+            &app,
+            &[
+                r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#,
+                r#"{"id":"2","name":"Ronin","year":1998,"was_good":true}"#,
+                r#"{"id":"3","name":"Thief","year":1981,"was_good":true}"#,
+                r#"{"id":"4","name":"Alien","year":1979,"was_good":true}"#,
+            ],
+        )
+        .await;
+        let (status, _) = send_json(&app, "PATCH", "/movie/2", json!({"year": 1999})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(delete(&app, "/movie/3").await, StatusCode::NO_CONTENT);
+
+        let (status, body) = send_json(
+            &app,
+            "POST",
+            "/v1/movie/revalidate",
+            json!({"1": "\"1\"", "2": "\"1\"", "3": "\"1\"", "4": "W/\"1\"", "9": "\"1\""}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["1"], json!({"status": "unchanged"}));
+        assert_eq!(body["2"]["status"], "changed");
+        assert_eq!(body["2"]["movie"]["year"], 1999);
+        assert_eq!(body["2"]["movie"]["version"], 2);
+        assert_eq!(body["2"]["movie"]["rating_count"], 0);
+        assert_eq!(body["3"], json!({"status": "gone"}));
+        assert_eq!(body["4"], json!({"status": "unchanged"}));
+        assert_eq!(body["9"], json!({"status": "gone"}));
+
+        let held: serde_json::Map<String, Value> = (0..=revalidate::MAX_REVALIDATE)
+            .map(|id| (id.to_string(), json!("\"1\"")))
+            .collect();
+        let (status, body) =
+            send_json(&app, "POST", "/v1/movie/revalidate", Value::Object(held)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["code"], "PAYLOAD_TOO_LARGE");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn revalidation_sees_transactions_whole() {
+        let app = app();
+        let ids: Vec<String> = (1..=16).map(|id| id.to_string()).collect();
+        for id in &ids {
+            add_movie(&app, id, "Heat", 1995).await;
+        }
+        let held: serde_json::Map<String, Value> =
+            ids.iter().map(|id| (id.clone(), json!("\"1\""))).collect();
+
+        // Every round moves all the movies to their next version at once.
+        let writer = tokio::spawn({
+            let (app, ids) = (app.clone(), ids.clone());
+            async move {
+                for round in 0..50 {
+                    let operations: Vec<Value> = ids
+                        .iter()
+                        .map(|id| {
+                            json!({"op": "update", "id": id, "movie": {
+                                "id": id, "name": format!("Heat {round}"), "year": 1995, "was_good": true,
+                            }})
+                        })
+                        .collect();
+                    let (status, _) =
+                        send_json(&app, "POST", "/movie/transaction", json!(operations)).await;
+                    assert_eq!(status, StatusCode::OK);
+                }
+            }
+        });
+        while !writer.is_finished() {
+            let (status, body) = send_json(
+                &app,
+                "POST",
+                "/v1/movie/revalidate",
+                Value::Object(held.clone()),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            let versions: HashSet<u64> = body
+                .as_object()
+                .unwrap()
+                .values()
+                .map(|revalidated| revalidated["movie"]["version"].as_u64().unwrap_or(1))
+                .collect();
+            assert_eq!(versions.len(), 1, "{body}");
+        }
+        writer.await.unwrap();
+    }
+
+    async fn batch(app: &Router, query: &str, body: String) -> (StatusCode, Value) {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/movie/batch?{query}"))
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    const MIXED_BATCH: &str = r#"[
+        {"id":"2","name":"Ronin","year":1998,"was_good":true},
+        {"id":"1","name":"Heat","year":1995,"was_good":true},
+        {"id":"","name":"Thief","year":1981,"was_good":true},
+        {"id":"2","name":"Ronin","year":1998,"was_good":true},
+        {"id":"3","name":"Collateral","year":2004,"was_good":true}
+    ]"#;
+
+    #[tokio::test]
+    async fn batch_creates_what_it_can() {
+        let app = app();
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#],
+        )
+        .await;
+
+        let (status, results) = batch(&app, "", MIXED_BATCH.to_string()).await;
+        assert_eq!(status, StatusCode::OK);
+        let statuses: Vec<_> = results
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| {
+                (
+                    result["id"].as_str().unwrap(),
+                    result["status"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("2", "created"),
+                ("1", "duplicate_id"),
+                ("", "invalid"),
+                ("2", "duplicate_id"),
+                ("3", "created"),
+            ]
+        );
+        assert_eq!(results[2]["errors"][0]["field"], "[2].id");
+
+        let (_, page) = list(&app, "").await;
+        assert_eq!(ids(&page), ["1", "2", "3"]);
+        let (status, _) = get_by_slug(&app, "collateral-2004").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn atomic_batch_creates_all_or_nothing() {
+        let app = app();
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#],
+        )
+        .await;
+
+        let (status, body) = batch(&app, "atomic=true", MIXED_BATCH.to_string()).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let fields: Vec<_> = body["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|error| error["field"].as_str().unwrap())
+            .collect();
+        assert_eq!(fields, ["[1].id", "[2].id", "[3].id"]);
+        let (_, page) = list(&app, "").await;
+        assert_eq!(ids(&page), ["1"]);
+
+        let (status, _) = batch(
+            &app,
+            "atomic=true",
+            r#"[{"id":"2","name":"Ronin","year":1998,"was_good":true}]"#.to_string(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, page) = list(&app, "").await;
+        assert_eq!(ids(&page), ["1", "2"]);
+    }
+
+    #[tokio::test]
+    async fn oversized_batch_is_rejected() {
+        let app = app();
+        let movies: Vec<Value> = (0..=MAX_BATCH)
+            .map(|i| json!({"id": i.to_string(), "name": "Heat", "year": 1995, "was_good": true}))
+            .collect();
+
+        let (status, body) = batch(&app, "", json!(movies).to_string()).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["code"], "PAYLOAD_TOO_LARGE");
+        let (_, page) = list(&app, "").await;
+        assert_eq!(page["total"], 0);
+    }
+
+    async fn import_csv(app: &Router, body: impl Into<Body>) -> (StatusCode, Value) {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/movie/import")
+                    .header(header::CONTENT_TYPE, "text/csv")
+                    .body(body.into())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn csv_export_round_trips() {
+        let app = app();
+        seed(
+            &app,
+            &[
+                r#"{"id":"1","name":"Crouching Tiger, Hidden Dragon","year":2000,"was_good":true}"#,
+                r#"{"id":"2","name":"The \"Burbs","year":1989,"was_good":false}"#,
+                r#"{"id":"3","name":"Heat","year":1995,"was_good":true}"#,
+            ],
+        )
+        .await;
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/movie/export?format=csv")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/csv; charset=utf-8"
+        );
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"movies.csv\""
+        );
+        let csv = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            std::str::from_utf8(&csv).unwrap(),
+            "id,name,year,was_good\n\
+             1,\"Crouching Tiger, Hidden Dragon\",2000,true\n\
+             2,\"The \"\"Burbs\",1989,false\n\
+             3,Heat,1995,true\n"
+        );
+
+        let fresh = app_with_repository(Arc::new(InMemoryRepository::new()));
+        let (status, report) = import_csv(&fresh, csv).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["imported"], 3);
+
+        // CSV carries no timestamps, so imported movies are created anew.
+        let without_timestamps = |(status, mut page): (StatusCode, Value)| {
+            for movie in page["items"].as_array_mut().unwrap() {
+                let movie = movie.as_object_mut().unwrap();
+                movie.remove("created_at");
+                movie.remove("updated_at");
+            }
+            (status, page)
+        };
+        assert_eq!(
+            without_timestamps(list(&fresh, "").await),
+            without_timestamps(list(&app, "").await)
+        );
+    }
+
+    #[tokio::test]
+    async fn csv_import_reports_skipped_and_invalid_rows() {
+        let app = app();
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#],
+        )
+        .await;
+
+        let csv = "name,id,year,was_good\n\
+                   Heat,1,1995,true\n\
+                   Ronin,2,1998,true\n\
+                   Thief,3,nineteen,true\n\
+                   Collateral,4,2004\n\
+                   Ronin,2,1998,true\n\
+                   Alien,,1979,true\n\
+                   Aliens,5,1986,false\n";
+        let (status, report) = import_csv(&app, csv).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["imported"], 2);
+        assert_eq!(report["skipped"], 2);
+        assert_eq!(report["invalid"], 3);
+        let fields: Vec<_> = report["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|error| error["field"].as_str().unwrap())
+            .collect();
+        assert_eq!(fields, ["line 4", "line 5", "line 7.id"]);
+        assert!(
+            report["errors"][0]["message"]
+                .as_str()
+                .unwrap()
+                .starts_with("year: "),
+            "{report}"
+        );
+
+        let (_, page) = list(&app, "").await;
+        assert_eq!(ids(&page), ["1", "2", "5"]);
+
+        let (status, body) = import_csv(&app, "id,name,year\n6,Heat,1995\n").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["message"], "header row lacks the was_good column(s)");
+    }
+
+    #[tokio::test]
+    async fn imports_skip_or_overwrite_stored_movies_on_request() {
+        let app = app();
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#],
+        )
+        .await;
+        let import = |uri: &'static str, body: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(Request::post(uri).body(Body::from(body)).unwrap())
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                serde_json::from_slice::<Value>(&body).unwrap()
+            }
+        };
+
+        let report = import(
+            "/movie/import?on_conflict=overwrite",
+            "id,name,year,was_good\n1,Heat (1995),1995,true\n2,Ronin,1998,true\n",
+        )
+        .await;
+        assert_eq!(
+            (&report["imported"], &report["updated"], &report["skipped"]),
+            (&json!(1), &json!(1), &json!(0))
+        );
+        let (_, movie) = probe(&app, "/movie/1").await;
+        assert_eq!(
+            (&movie["name"], &movie["version"]),
+            (&json!("Heat (1995)"), &json!(2))
+        );
+
+        let report = import(
+            "/movie/import/stream?on_conflict=skip",
+            "{\"id\":\"1\",\"name\":\"Thief\",\"year\":1981,\"was_good\":true}\n",
+        )
+        .await;
+        assert_eq!(
+            (&report["created"], &report["skipped"]),
+            (&json!(0), &json!(1))
+        );
+        let (_, movie) = probe(&app, "/movie/1").await;
+        assert_eq!(movie["name"], "Heat (1995)");
+    }
+
+    #[tokio::test]
+    async fn transaction_applies_mixed_operations() {
+        let app = app();
+
+        for body in [
+            r#"{"id":"1","name":"Alien","year":1979,"was_good":true}"#,
+            r#"{"id":"2","name":"Aliens","year":1986,"was_good":true}"#,
+        ] {
+            app.clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/movie")
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .body(Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+        }
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/movie/transaction")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"[
+                            {"op":"delete","id":"1"},
+                            {"op":"update","id":"2","movie":{"id":"2","name":"Aliens (Special Edition)","year":1986,"was_good":true}},
+                            {"op":"create","movie":{"id":"3","name":"Alien 3","year":1992,"was_good":false}}
+                        ]"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let results: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let ops: Vec<&str> = results
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["op"].as_str().unwrap())
+            .collect();
+        assert_eq!(ops, ["delete", "update", "create"]);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/movie")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let mut movies = serde_json::from_slice::<Page<Movie>>(&body).unwrap().items;
+        movies.sort_by(|a, b| a.id.cmp(&b.id));
+        assert_eq!(movies.len(), 2);
+        assert_eq!(movies[0].name, "Aliens (Special Edition)");
+        assert_eq!(movies[1].id, "3");
+    }
+
+    #[tokio::test]
+    async fn transaction_rejects_whole_batch_on_failure() {
+        let app = app();
+
+        app.clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/movie")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"{"id":"1","name":"Alien","year":1979,"was_good":true}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // The second delete targets a movie the first one already removed.
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/movie/transaction")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"[
+                            {"op":"create","movie":{"id":"2","name":"Aliens","year":1986,"was_good":true}},
+                            {"op":"delete","id":"1"},
+                            {"op":"delete","id":"1"},
+                            {"op":"update","id":"999","movie":{"id":"999","name":"Nope","year":2000,"was_good":false}}
+                        ]"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let failed: Vec<&str> = body["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["field"].as_str().unwrap())
+            .collect();
+        assert_eq!(failed, ["[2].id", "[3].id"]);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/movie")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let movies = serde_json::from_slice::<Page<Movie>>(&body).unwrap().items;
+        assert_eq!(movies.len(), 1);
+        assert_eq!(movies[0].id, "1");
+    }
+
+    #[tokio::test]
+    async fn locked_field_survives_update() {
+        let app = app();
+
+        app.clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/movie")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"{"id":"1","name":"Blade Runner","year":1982,"was_good":true}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/movie/1/lock")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"fields":["name"]}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("PUT")
+                    .uri("/movie/1")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"{"id":"1","name":"Blade Runner (Final Cut)","year":2007,"was_good":true}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-skipped-fields"], "name");
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let movie: Movie = serde_json::from_slice(&body).unwrap();
+        assert_eq!(movie.name, "Blade Runner");
+        assert_eq!(movie.year, 2007);
+        assert_eq!(movie.locked_fields, ["name"]);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("PUT")
+                    .uri("/movie/1?force=true")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"{"id":"1","name":"Blade Runner (Final Cut)","year":2007,"was_good":true}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert!(!response.headers().contains_key("x-skipped-fields"));
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let movie: Movie = serde_json::from_slice(&body).unwrap();
+        assert_eq!(movie.name, "Blade Runner (Final Cut)");
+    }
+
+    #[tokio::test]
+    async fn every_updatable_field_can_be_locked() {
+        let app = app();
+        let heat = json!({
+            "id": "1", "name": "Heat", "year": 1995, "was_good": true,
+            "genres": ["crime", "drama"], "custom": {"studio": "Warner"},
+        });
+        assert_eq!(
+            send_json(&app, "POST", "/movie", heat).await.0,
+            StatusCode::CREATED
+        );
+        let (status, movie) = send_json(
+            &app,
+            "POST",
+            "/movie/1/lock",
+            json!({"fields": ["cast", "genres", "custom", "director_id"]}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            movie["locked_fields"],
+            json!(["custom", "genres", "director_id", "cast"])
+        );
+
+        // The same genres in another casing are no attempt to change them.
+        let patch = |body: Value| {
+            app.clone().oneshot(
+                Request::builder()
+                    .method("PATCH")
+                    .uri("/movie/1")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+        };
+        let response = patch(json!({"genres": ["Crime", "Drama"]})).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key("x-skipped-fields"));
+
+        let response = patch(json!({"genres": ["thriller"], "year": 1996}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-skipped-fields"], "genres");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let movie: Movie = serde_json::from_slice(&body).unwrap();
+        assert_eq!(movie.genres, ["crime", "drama"]);
+        assert_eq!(movie.year, 1996);
+    }
+
+    #[tokio::test]
+    async fn lock_unknown_field_is_rejected() {
+        let app = app();
+
+        app.clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/movie")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"{"id":"1","name":"Blade Runner","year":1982,"was_good":true}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/movie/1/lock")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"fields":["director"]}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn concurrent_locks_both_apply() {
+        let repo = repo::SlowRepository {
+            inner: InMemoryRepository::new(),
+            delay: Duration::from_millis(20),
+        };
+        let app = router(AppState::with_repository(Config::default(), Arc::new(repo)));
+        let movie = json!({"id": "1", "name": "Heat", "year": 1995, "was_good": true});
+        let (status, _) = send_json(&app, "POST", "/v1/movie", movie).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        // Both read the movie at the same version, so one write goes stale
+        // and has to read it again instead of answering 412.
+        let lock = |field| send_json(&app, "POST", "/v1/movie/1/lock", json!({"fields": [field]}));
+        let ((name, _), (year, _)) = tokio::join!(lock("name"), lock("year"));
+        assert_eq!(name, StatusCode::OK);
+        assert_eq!(year, StatusCode::OK);
+
+        let (_, movie) = probe(&app, "/v1/movie/1").await;
+        assert_eq!(movie["locked_fields"], json!(["name", "year"]));
+        assert_eq!(movie["version"], 3);
+    }
+
+    async fn seed(app: &Router, movies: &[&'static str]) {
+        for movie in movies {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/movie")
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .body(Body::from(*movie))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::CREATED);
+        }
+    }
+
+    async fn get_by_name(app: &Router, name: &str) -> (StatusCode, serde_json::Value) {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri(format!("/movie/by-name/{name}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn get_by_name_resolves_exact_and_fuzzy_matches() {
+        let app = app();
+        seed(
+            &app,
+            &[
+                r#"{"id":"1","name":"The Matrix","year":1999,"was_good":true}"#,
+                r#"{"id":"2","name":"Heat","year":1995,"was_good":true}"#,
+            ],
+        )
+        .await;
+
+        let (status, movie) = get_by_name(&app, "the%20%20MATRIX%20").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(movie["id"], "1");
+
+        let (status, movie) = get_by_name(&app, "the%20matrx").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(movie["id"], "1");
+    }
+
+    #[tokio::test]
+    async fn get_by_name_lists_candidates_when_ambiguous() {
+        let app = app();
+        seed(
+            &app,
+            &[
+                r#"{"id":"1","name":"Dune","year":1984,"was_good":false}"#,
+                r#"{"id":"2","name":"Dune","year":2021,"was_good":true}"#,
+                r#"{"id":"3","name":"Alien","year":1979,"was_good":true}"#,
+                r#"{"id":"4","name":"Aliens","year":1986,"was_good":true}"#,
+            ],
+        )
+        .await;
+
+        let (status, body) = get_by_name(&app, "dune").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let ids: Vec<&str> = body["candidates"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| c["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids, ["1", "2"]);
+
+        let (status, body) = get_by_name(&app, "alienz").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["candidates"].as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn get_by_name_not_found() {
+        let app = app();
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"The Matrix","year":1999,"was_good":true}"#],
+        )
+        .await;
+
+        let (status, _) = get_by_name(&app, "zzzzzz").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn custom_fields_round_trip() {
+        let app = app();
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Heat","year":1995,"was_good":true,
+                 "custom":{"seen_at":{"venue":"cinema","friends":["a","b"]},"rewatches":2}}"#],
+        )
+        .await;
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/movie/1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let movie: Movie = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            movie.custom["seen_at"],
+            json!({"venue": "cinema", "friends": ["a", "b"]})
+        );
+        assert_eq!(movie.custom["rewatches"], json!(2));
+    }
+
+    #[tokio::test]
+    async fn custom_fields_enforce_limits() {
+        let too_many: HashMap<String, Value> = (0..=MAX_CUSTOM_KEYS)
+            .map(|i| (format!("k{i}"), json!(i)))
+            .collect();
+        let too_large = HashMap::from([("notes".to_string(), json!("x".repeat(1100)))]);
+        let bad_key = HashMap::from([("not-an-ident".to_string(), json!(true))]);
+
+        for custom in [too_many, too_large, bad_key] {
+            let body = json!({"id": "1", "name": "Heat", "year": 1995, "was_good": true, "custom": custom});
+            let response = app()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/movie")
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .body(Body::from(body.to_string()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
+
+    #[tokio::test]
+    async fn list_movies_filters_on_custom_fields() {
+        let app = app();
+        seed(
+            &app,
+            &[
+                r#"{"id":"1","name":"Heat","year":1995,"was_good":true,"custom":{"rewatches":2,"format":"bluray"}}"#,
+                r#"{"id":"2","name":"Ronin","year":1998,"was_good":true,"custom":{"rewatches":2.0,"format":"dvd"}}"#,
+                r#"{"id":"3","name":"Collateral","year":2004,"was_good":true,"custom":{"rewatches":"2"}}"#,
+                r#"{"id":"4","name":"Thief","year":1981,"was_good":true}"#,
+            ],
+        )
+        .await;
+
+        for (query, expected) in [
+            ("custom.rewatches=2", vec!["1", "2", "3"]),
+            ("custom.rewatches=2&custom.format=dvd", vec!["2"]),
+            ("custom.format=vhs", vec![]),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("GET")
+                        .uri(format!("/movie?{query}"))
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let movies = serde_json::from_slice::<Page<Movie>>(&body).unwrap().items;
+            let mut ids: Vec<String> = movies.into_iter().map(|m| m.id).collect();
+            ids.sort();
+            assert_eq!(ids, expected, "query {query}");
+        }
+    }
+
+    async fn list(app: &Router, query: &str) -> (StatusCode, serde_json::Value) {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri(format!("/movie?{query}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn ids(page: &serde_json::Value) -> Vec<&str> {
+        page["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["id"].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn list_movies_paginates_and_sorts() {
+        let app = app();
+        seed(
+            &app,
+            &[
+                r#"{"id":"a","name":"Heat","year":1995,"was_good":true}"#,
+                r#"{"id":"b","name":"Ronin","year":1998,"was_good":true}"#,
+                r#"{"id":"c","name":"Collateral","year":2004,"was_good":true}"#,
+                r#"{"id":"d","name":"Thief","year":1981,"was_good":true}"#,
+                r#"{"id":"e","name":"Manhunter","year":1986,"was_good":true}"#,
+            ],
+        )
+        .await;
+
+        let (status, page) = list(&app, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids(&page), ["a", "b", "c", "d", "e"]);
+        assert_eq!(page["total"], 5);
+        assert_eq!(page["page"], 1);
+        assert_eq!(page["per_page"], DEFAULT_PER_PAGE);
+
+        let (_, page) = list(&app, "page=3&per_page=2").await;
+        assert_eq!(ids(&page), ["e"]);
+        assert_eq!(page["total"], 5);
+
+        let (status, page) = list(&app, "page=4&per_page=2").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids(&page), Vec::<&str>::new());
+        assert_eq!(page["total"], 5);
+
+        let (_, page) = list(&app, "sort=year&order=desc").await;
+        assert_eq!(ids(&page), ["c", "b", "a", "e", "d"]);
+
+        let (_, page) = list(&app, "sort=name&per_page=2&page=2").await;
+        assert_eq!(ids(&page), ["e", "b"]);
+
+        let (_, page) = list(&app, "per_page=1000").await;
+        assert_eq!(page["per_page"], MAX_PER_PAGE);
+    }
+
+    #[tokio::test]
+    async fn list_movies_rejects_invalid_paging() {
+        let app = app();
+
+        for query in ["sort=rating", "order=up", "per_page=0", "page=0", "page=x"] {
+            let (status, body) = list(&app, query).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "query {query}");
+            assert_eq!(body["code"], "BAD_REQUEST", "query {query}");
+        }
+
+        let (_, body) = list(&app, "sort=rating").await;
+        assert!(
+            body["message"]
+                .as_str()
+                .unwrap()
+                .contains("unknown variant `rating`"),
+            "{body}"
+        );
+    }
+
+    #[tokio::test]
+    async fn list_movies_filters_on_year_and_was_good() {
+        let app = app();
+        seed(
+            &app,
+            &[
+                r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#,
+                r#"{"id":"2","name":"Waterworld","year":1995,"was_good":false}"#,
+                r#"{"id":"3","name":"Ronin","year":1998,"was_good":true}"#,
+                r#"{"id":"4","name":"Batman & Robin","year":1997,"was_good":false}"#,
+                r#"{"id":"5","name":"Collateral","year":2004,"was_good":true}"#,
+                r#"{"id":"6","name":"Thief","year":1981,"was_good":true}"#,
+            ],
+        )
+        .await;
+
+        for (query, expected) in [
+            ("year=1995", vec!["1", "2"]),
+            ("year_from=1990", vec!["1", "2", "3", "4", "5"]),
+            ("year_to=1995", vec!["1", "2", "6"]),
+            ("year_from=1990&year_to=1999", vec!["1", "2", "3", "4"]),
+            ("was_good=false", vec!["2", "4"]),
+            ("was_good=TRUE", vec!["1", "3", "5", "6"]),
+            ("year_from=1990&year_to=1999&was_good=true", vec!["1", "3"]),
+            ("year=1995&was_good=False", vec!["2"]),
+            ("year=2020", vec![]),
+        ] {
+            let (status, page) = list(&app, query).await;
+            assert_eq!(status, StatusCode::OK, "query {query}");
+            assert_eq!(ids(&page), expected, "query {query}");
+            assert_eq!(page["total"], expected.len(), "query {query}");
+        }
+
+        for query in [
+            "year=1995&year_from=1990",
+            "year=1995&year_to=1999",
+            "was_good=yes",
+        ] {
+            let (status, _) = list(&app, query).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "query {query}");
+        }
+    }
+
+    #[tokio::test]
+    async fn search_matches_name_substrings() {
+        let app = app();
+        seed(
+            &app,
+            &[
+                r#"{"id":"1","name":"The Matrix","year":1999,"was_good":true}"#,
+                r#"{"id":"2","name":"The Matrix Reloaded","year":2003,"was_good":false}"#,
+                r#"{"id":"3","name":"Amélie","year":2001,"was_good":true}"#,
+                r#"{"id":"4","name":"Heat","year":1995,"was_good":true}"#,
+            ],
+        )
+        .await;
+
+        let search = |query: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(
+                        Request::builder()
+                            .method("GET")
+                            .uri(format!("/movie/search{query}"))
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+
+                let status = response.status();
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                (status, serde_json::from_slice::<Value>(&body).unwrap())
+            }
+        };
+
+        for (query, expected) in [
+            ("?q=the%20matrix", vec!["1", "2"]),
+            ("?q=mAtRiX%20reL", vec!["2"]),
+            ("?q=am%C3%A9lie", vec!["3"]),
+            // "ame" followed by a combining acute accent.
+            ("?q=AME%CC%81", vec!["3"]),
+            ("?q=the%20matrix%20reloaded%20and%20revolutions", vec![]),
+            ("?q=alien", vec![]),
+        ] {
+            let (status, body) = search(query).await;
+            assert_eq!(status, StatusCode::OK, "query {query}");
+            let ids: Vec<&str> = body
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|m| m["id"].as_str().unwrap())
+                .collect();
+            assert_eq!(ids, expected, "query {query}");
+        }
+
+        for query in ["", "?q=", "?q=%20%20"] {
+            let (status, _) = search(query).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "query {query}");
+        }
+    }
+
+    #[tokio::test]
+    async fn create_movie_normalizes_name() {
+        for (raw, expected) in [
+            ("  The   Matrix ", "The Matrix"),
+            ("Ame\u{0301}lie", "Am\u{e9}lie"),
+            ("Ocean\u{2019}s Eleven", "Ocean's Eleven"),
+            ("\u{201C}Crocodile\u{201D} Dundee", "\"Crocodile\" Dundee"),
+        ] {
+            let body = json!({"id": "1", "name": raw, "year": 2000, "was_good": true});
+            let response = app()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/movie")
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .body(Body::from(body.to_string()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let movie: Movie = serde_json::from_slice(&body).unwrap();
+            assert_eq!(movie.name, expected);
+            assert_eq!(movie.sort_name, None);
+        }
+    }
+
+    #[test]
+    fn sort_name_moves_leading_articles() {
+        let config = Config {
+            sort_names: true,
+            articles: ["The", "A", "An", "La"].map(String::from).to_vec(),
+            ..Config::default()
+        };
+
+        assert_eq!(
+            config.sort_name("The Matrix").as_deref(),
+            Some("Matrix, The")
+        );
+        assert_eq!(
+            config.sort_name("a Bug's Life").as_deref(),
+            Some("Bug's Life, a")
+        );
+        assert_eq!(config.sort_name("La Haine").as_deref(), Some("Haine, La"));
+        assert_eq!(config.sort_name("Theodora"), None);
+        assert_eq!(config.sort_name("Heat"), None);
+        assert_eq!(Config::default().sort_name("The Matrix"), None);
+    }
+
+    #[tokio::test]
+    async fn update_movie_refreshes_sort_name() {
+        let app = app_with_config(Config {
+            sort_names: true,
+            ..Config::default()
+        });
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Matrix","year":1999,"was_good":true}"#],
+        )
+        .await;
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("PUT")
+                    .uri("/movie/1")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"{"id":"1","name":" The  Matrix","year":1999,"was_good":true,"sort_name":"bogus"}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let movie: Movie = serde_json::from_slice(&body).unwrap();
+        assert_eq!(movie.name, "The Matrix");
+        assert_eq!(movie.sort_name.as_deref(), Some("Matrix, The"));
+    }
+
+    async fn change_id(app: &Router, id: &str, new_id: &str) -> (StatusCode, serde_json::Value) {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/movie/{id}/change-id"))
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(json!({ "new_id": new_id }).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn slugify_builds_url_safe_slugs() {
+        assert_eq!(slugify("The Matrix", 1999), "the-matrix-1999");
+        assert_eq!(slugify("  Amélie  ", 2001), "amelie-2001");
+        assert_eq!(slugify("Don't Look Up!", 2021), "don-t-look-up-2021");
+        assert_eq!(slugify("千と千尋の神隠し", 2001), "2001");
+    }
+
+    async fn get_by_slug(app: &Router, slug: &str) -> (StatusCode, serde_json::Value) {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/movie/slug/{slug}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn slugs_resolve_and_survive_renames() {
+        let app = app();
+        seed(
+            &app,
+            &[
+                r#"{"id":"1","name":"Dune","year":2021,"was_good":true}"#,
+                r#"{"id":"2","name":"dune","year":2021,"was_good":false,"slug":"mine"}"#,
+            ],
+        )
+        .await;
+
+        let (status, movie) = get_by_slug(&app, "dune-2021").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(movie["id"], "1");
+
+        let (status, movie) = get_by_slug(&app, "dune-2021-2").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(movie["id"], "2");
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("PUT")
+                    .uri("/movie/1")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"{"id":"1","name":"Dune: Part One","year":2021,"was_good":true}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let movie: Movie = serde_json::from_slice(&body).unwrap();
+        assert_eq!(movie.slug, "dune-part-one-2021");
+
+        for slug in ["dune-part-one-2021", "dune-2021"] {
+            let (status, movie) = get_by_slug(&app, slug).await;
+            assert_eq!(status, StatusCode::OK, "{slug}");
+            assert_eq!(movie["id"], "1", "{slug}");
+            assert_eq!(movie["slug"], "dune-part-one-2021", "{slug}");
+        }
+
+        let (status, _) = get_by_slug(&app, "heat-1995").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn change_id_rekeys_and_redirects() {
+        let app = app();
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"The Matrix","year":1999,"was_good":true}"#],
+        )
+        .await;
+
+        let (status, movie) = change_id(&app, "1", "tt0133093").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(movie["id"], "tt0133093");
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/movie/tt0133093")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/movie/1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(response.headers()[header::LOCATION], "/movie/tt0133093");
+    }
+
+    #[tokio::test]
+    async fn change_id_redirect_expires() {
+        let app = app_with_config(Config {
+            id_redirect_grace: Duration::ZERO,
+            ..Config::default()
+        });
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"The Matrix","year":1999,"was_good":true}"#],
+        )
+        .await;
+
+        change_id(&app, "1", "tt0133093").await;
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/movie/1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn change_id_rejects_conflicts_and_invalid_ids() {
+        let app = app();
+        seed(
+            &app,
+            &[
+                r#"{"id":"1","name":"The Matrix","year":1999,"was_good":true}"#,
+                r#"{"id":"2","name":"Heat","year":1995,"was_good":true}"#,
+            ],
+        )
+        .await;
+
+        let (status, _) = change_id(&app, "1", "2").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (status, _) = change_id(&app, "1", "tt 0133093").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let (status, _) = change_id(&app, "999", "3").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn change_id_keeps_an_update_made_meanwhile() {
+        let repo = repo::SlowRepository {
+            inner: InMemoryRepository::new(),
+            delay: Duration::from_millis(20),
+        };
+        let app = router(AppState::with_repository(Config::default(), Arc::new(repo)));
+        let movie = json!({"id": "1", "name": "Heat", "year": 1995, "was_good": true});
+        let (status, _) = send_json(&app, "POST", "/v1/movie", movie).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        // The update is stored after the re-key read the movie and before it
+        // moves it, so the re-key finds it stale and moves the updated movie.
+        let update = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let movie = json!({"id": "1", "name": "Heat (1995)", "year": 1995, "was_good": true});
+            send_json(&app, "PUT", "/v1/movie/1", movie).await
+        };
+        let ((updated, _), (moved, movie)) = tokio::join!(update, change_id(&app, "1", "2"));
+        assert_eq!(updated, StatusCode::OK);
+        assert_eq!(moved, StatusCode::OK);
+        assert_eq!(movie["name"], "Heat (1995)");
+        assert_eq!(movie["version"], 3);
+    }
+
+    #[tokio::test]
+    async fn responses_follow_requested_case() {
+        let app = app();
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Heat","year":1995,"was_good":true,"custom":{"seen_with":"dad"}}"#],
+        )
+        .await;
+
+        for (uri, case_header, key) in [
+            ("/movie/1", None, "was_good"),
+            ("/movie/1?case=camel", None, "wasGood"),
+            ("/movie/1", Some("camel"), "wasGood"),
+        ] {
+            let mut request = Request::builder().method("GET").uri(uri);
+            if let Some(case) = case_header {
+                request = request.header("x-response-case", case);
+            }
+
+            let response = app
+                .clone()
+                .oneshot(request.body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let movie: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(movie[key], true, "{uri}");
+            assert_eq!(movie["custom"]["seen_with"], "dad");
+        }
+    }
+
+    #[tokio::test]
+    async fn create_movie_accepts_camel_case_body() {
+        let app = app();
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/movie?case=camel")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"{"id":"1","name":"Heat","year":1995,"wasGood":true}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let movie: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(movie["wasGood"], true);
+        assert_eq!(movie["lockedFields"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn streamed_responses_pass_the_casing_untouched() {
+        let app = app();
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#],
+        )
+        .await;
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/v1/movie?stream=true&case=camel")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body().size_hint().exact(), None);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let movies: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(movies[0]["was_good"], true);
+    }
+
+    #[tokio::test]
+    async fn unknown_case_is_rejected() {
+        let response = app()
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/movie?case=kebab")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn failures_share_error_envelope() {
+        let app = app();
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#],
+        )
+        .await;
+
+        let json = Some("application/json");
+        for (method, uri, content_type, body, status, code) in [
+            (
+                "GET",
+                "/movie/999",
+                None,
+                "",
+                StatusCode::NOT_FOUND,
+                "MOVIE_NOT_FOUND",
+            ),
+            (
+                "PUT",
+                "/movie/999",
+                json,
+                r#"{"id":"999","name":"Heat","year":1995,"was_good":true}"#,
+                StatusCode::NOT_FOUND,
+                "MOVIE_NOT_FOUND",
+            ),
+            (
+                "DELETE",
+                "/movie/999",
+                None,
+                "",
+                StatusCode::NOT_FOUND,
+                "MOVIE_NOT_FOUND",
+            ),
+            (
+                "POST",
+                "/movie",
+                json,
+                r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#,
+                StatusCode::CONFLICT,
+                "CONFLICT",
+            ),
+            (
+                "POST",
+                "/movie",
+                json,
+                r#"{"id":"","name":"Heat","year":1995,"was_good":true}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "VALIDATION_FAILED",
+            ),
+            (
+                "POST",
+                "/movie",
+                json,
+                r#"{"id":"2","name":"#,
+                StatusCode::BAD_REQUEST,
+                "INVALID_BODY",
+            ),
+            (
+                "POST",
+                "/movie",
+                json,
+                r#"{"id":"2","name":"Heat"}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "INVALID_BODY",
+            ),
+            (
+                "POST",
+                "/movie",
+                None,
+                r#"{"id":"2","name":"Heat","year":1995,"was_good":true}"#,
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "INVALID_BODY",
+            ),
+            (
+                "PUT",
+                "/movie/1",
+                json,
+                r#"{"id":"1","year":"soon"}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "INVALID_BODY",
+            ),
+            (
+                "GET",
+                "/movie/1?existence_only=maybe",
+                None,
+                "",
+                StatusCode::BAD_REQUEST,
+                "BAD_REQUEST",
+            ),
+        ] {
+            let mut request = Request::builder().method(method).uri(uri);
+            if let Some(content_type) = content_type {
+                request = request.header(header::CONTENT_TYPE, content_type);
+            }
+
+            let response = app
+                .clone()
+                .oneshot(request.body(Body::from(body)).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{method} {uri} {body}");
+
+            let bytes = response.into_body().collect().await.unwrap().to_bytes();
+            let error: Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(error["code"], code, "{method} {uri} {body}");
+            assert!(error["message"].is_string(), "{method} {uri} {body}");
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_store_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            database_url: Some(format!(
+                "sqlite://{}",
+                dir.path().join("movies.db").display()
+            )),
+            ..Config::default()
+        };
+
+        let app = router(AppState::open(config.clone()).await.unwrap());
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Heat","year":1995,"was_good":true,"custom":{"rewatches":2}}"#],
+        )
+        .await;
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/movie")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"{"id":"1","name":"Ronin","year":1998,"was_good":true}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let app = router(AppState::open(config).await.unwrap());
+        let (status, movie) = get_by_slug(&app, "heat-1995").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(movie["custom"]["rewatches"], 2);
+    }
+
+    #[tokio::test]
+    async fn database_url_and_db_path_are_exclusive() {
+        let config = Config {
+            db_path: Some(PathBuf::from("movies.json")),
+            database_url: Some("sqlite::memory:".to_string()),
+            ..Config::default()
+        };
+
+        let error = AppState::open(config).await.err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+
+        let config = Config {
+            db_path: Some(PathBuf::from("movies.json")),
+            spill_dir: Some(PathBuf::from("movies")),
+            ..Config::default()
+        };
+        let error = AppState::open(config).await.err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn a_spilled_store_serves_every_movie_and_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            spill_dir: Some(dir.path().to_path_buf()),
+            resident_movies: 2,
+            ..Config::default()
+        };
+
+        let app = router(AppState::open(config.clone()).await.unwrap());
+        for n in 0..10 {
+            add_movie(&app, &n.to_string(), &format!("Heat {n}"), 1990 + n).await;
+        }
+        for n in (0..10).rev() {
+            let (status, movie) = get_by_slug(&app, &format!("heat-{n}-{}", 1990 + n)).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(movie["id"], n.to_string());
+        }
+        let (_, page) = list(&app, "year_from=1995").await;
+        assert_eq!(ids(&page), ["5", "6", "7", "8", "9"]);
+
+        let app = router(AppState::open(config).await.unwrap());
+        let (_, page) = list(&app, "").await;
+        assert_eq!(ids(&page).len(), 10);
+        let (status, movie) = get_by_slug(&app, "heat-3-1993").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(movie["name"], "Heat 3");
+    }
+
+    #[tokio::test]
+    async fn handlers_work_on_any_repository() {
+        let app = app_with_repository(Arc::new(repo::VecRepository::default()));
+        seed(
+            &app,
+            &[
+                r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#,
+                r#"{"id":"2","name":"Ronin","year":1998,"was_good":true}"#,
+            ],
+        )
+        .await;
+        assert_eq!(change_id(&app, "2", "3").await.0, StatusCode::OK);
+
+        let (status, page) = list(&app, "sort=year&order=desc").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids(&page), ["3", "1"]);
+
+        let (status, movie) = get_by_slug(&app, "ronin-1998").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(movie["id"], "3");
+    }
+
+    #[tokio::test]
+    async fn repository_failures_answer_500() {
+        let app = app_with_repository(Arc::new(repo::FailingRepository));
+
+        for (method, uri, body) in [
+            ("GET", "/movie", ""),
+            ("GET", "/movie/1", ""),
+            ("DELETE", "/movie/1", ""),
+            (
+                "POST",
+                "/movie",
+                r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#,
+            ),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method(method)
+                        .uri(uri)
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .body(Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "{method} {uri}"
+            );
+
+            let bytes = response.into_body().collect().await.unwrap().to_bytes();
+            let error: Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(error["code"], "STORAGE_FAILED", "{method} {uri}");
+            assert!(
+                !error["message"].as_str().unwrap().contains("disk on fire"),
+                "{error}"
+            );
+        }
+    }
+
+    async fn probe(app: &Router, uri: &str) -> (StatusCode, Value) {
+        let response = app
+            .clone()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn responses_carry_a_request_id() {
+        let app = app();
+
+        let mut ids = Vec::new();
+        for uri in ["/movie", "/movie/999"] {
+            let response = app
+                .clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let id = response.headers()[X_REQUEST_ID]
+                .to_str()
+                .unwrap()
+                .to_string();
+            assert!(!id.is_empty(), "{uri}");
+            ids.push(id);
+        }
+        assert_ne!(ids[0], ids[1]);
+    }
+
+    #[tokio::test]
+    async fn provided_request_id_is_preserved() {
+        let response = app()
+            .oneshot(
+                Request::get("/movie/999")
+                    .header(X_REQUEST_ID, "upstream-42")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[X_REQUEST_ID], "upstream-42");
+    }
+
+    #[tokio::test]
+    async fn probes_report_liveness_and_readiness() {
+        let app = app();
+        assert_eq!(
+            probe(&app, "/healthz").await,
+            (StatusCode::OK, json!({"status": "ok"}))
+        );
+        assert_eq!(
+            probe(&app, "/readyz").await,
+            (StatusCode::OK, json!({"status": "ok"}))
+        );
+
+        // A dead backend fails readiness only; the process is still alive.
+        let app = app_with_repository(Arc::new(repo::FailingRepository));
+        assert_eq!(probe(&app, "/healthz").await.0, StatusCode::OK);
+        let (status, body) = probe(&app, "/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "UNAVAILABLE");
+        assert_eq!(body["component"], "storage");
+    }
+
+    /// Takes events unless it is `down`.
+    #[derive(Default)]
+    struct FakeBroker {
+        down: std::sync::atomic::AtomicBool,
+        published: std::sync::Mutex<Vec<Value>>,
+    }
+
+    #[async_trait::async_trait]
+    impl publisher::Publisher for FakeBroker {
+        async fn publish(&self, payload: &[u8]) -> std::io::Result<()> {
+            if self.down.load(std::sync::atomic::Ordering::SeqCst) {
+                return Err(std::io::Error::other("connection refused"));
+            }
+            let event = serde_json::from_slice(payload).unwrap();
+            self.published.lock().unwrap().push(event);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn every_change_is_published_once_and_outages_only_warn() {
+        let broker = Arc::new(FakeBroker::default());
+        let mut state = AppState::new(Config::default());
+        state.outbox = Some(publisher::spawn(&state.events, broker.clone(), 100));
+        let app = router(state);
+
+        add_movie(&app, "1", "Heat", 1995).await;
+        let (status, _) = send_json(&app, "PATCH", "/v1/movie/1", json!({"year": 1996})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(delete(&app, "/v1/movie/1").await, StatusCode::NO_CONTENT);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        {
+            let published = broker.published.lock().unwrap();
+            let kinds: Vec<&Value> = published.iter().map(|event| &event["kind"]).collect();
+            assert_eq!(kinds, ["created", "updated", "deleted"]);
+            assert_eq!(published[1]["movie"]["year"], 1996);
+        }
+
+        broker.down.store(true, std::sync::atomic::Ordering::SeqCst);
+        add_movie(&app, "2", "Ronin", 1998).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let (status, ready) = probe(&app, "/readyz").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            ready["warnings"],
+            json!([{
+                "component": "publisher",
+                "message": "publishing fails (connection refused), 1 events queued and 0 dropped",
+            }])
+        );
+
+        broker
+            .down
+            .store(false, std::sync::atomic::Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(broker.published.lock().unwrap().len(), 4);
+        assert_eq!(
+            probe(&app, "/readyz").await,
+            (StatusCode::OK, json!({"status": "ok"}))
+        );
+    }
+
+    async fn from_client(app: &Router, peer: &str, forwarded_for: Option<&str>) -> Response {
+        let mut request =
+            Request::get("/movie").extension(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+        if let Some(forwarded_for) = forwarded_for {
+            request = request.header("x-forwarded-for", forwarded_for);
+        }
+
+        app.clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    fn remaining(response: &Response) -> &str {
+        response.headers()["x-ratelimit-remaining"]
+            .to_str()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn clients_over_their_allowance_are_limited() {
+        let app = app_with_config(Config {
+            rate_limit_requests: 3,
+            rate_limit_window: Duration::from_secs(60),
+            ..Config::default()
+        });
+
+        for left in ["2", "1", "0"] {
+            let response = from_client(&app, "10.0.0.1:4000", None).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(remaining(&response), left);
+        }
+
+        let response = from_client(&app, "10.0.0.1:4001", None).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(remaining(&response), "0");
+        assert_eq!(response.headers()[header::RETRY_AFTER], "20");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "RATE_LIMITED");
+
+        // Other clients keep their own allowance.
+        let response = from_client(&app, "10.0.0.2:4000", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(remaining(&response), "2");
+    }
+
+    #[tokio::test]
+    async fn forwarded_for_is_only_trusted_when_configured() {
+        let config = Config {
+            rate_limit_requests: 1,
+            rate_limit_window: Duration::from_secs(60),
+            ..Config::default()
+        };
+
+        // Behind a proxy every request arrives from the proxy's address.
+        let app = app_with_config(Config {
+            trust_forwarded_for: true,
+            ..config.clone()
+        });
+        for client in ["203.0.113.1", "203.0.113.2, 10.0.0.9"] {
+            let response = from_client(&app, "10.0.0.9:4000", Some(client)).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let app = app_with_config(config);
+        for (client, status) in [
+            ("203.0.113.1", StatusCode::OK),
+            ("203.0.113.2", StatusCode::TOO_MANY_REQUESTS),
+        ] {
+            let response = from_client(&app, "10.0.0.9:4000", Some(client)).await;
+            assert_eq!(response.status(), status);
+        }
+    }
+
+    async fn rate(app: &Router, id: &str, score: u8) -> (StatusCode, Value) {
+        send_json(
+            app,
+            "POST",
+            &format!("/movie/{id}/rating"),
+            json!({"score": score, "comment": "seen it"}),
+        )
+        .await
+    }
+
+    async fn delete(app: &Router, uri: &str) -> StatusCode {
+        app.clone()
+            .oneshot(Request::delete(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn ratings_are_averaged_on_the_movie() {
+        let app = app();
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#],
+        )
+        .await;
+
+        let (_, movie) = probe(&app, "/movie/1").await;
+        assert_eq!(movie["average_rating"], Value::Null);
+        assert_eq!(movie["rating_count"], 0);
+
+        let mut rating_ids = Vec::new();
+        for score in [6, 7, 10] {
+            let (status, rating) = rate(&app, "1", score).await;
+            assert_eq!(status, StatusCode::CREATED);
+            assert_eq!(rating["movie_id"], "1");
+            assert_eq!(rating["comment"], "seen it");
+            rating_ids.push(rating["id"].as_u64().unwrap());
+        }
+
+        let (_, movie) = probe(&app, "/movie/1").await;
+        assert_eq!(movie["rating_count"], 3);
+        assert_eq!(movie["average_rating"].as_f64().unwrap() as f32, 23.0 / 3.0);
+        // Rating moved the movie on, so cached copies are revalidated.
+        assert_eq!(movie["version"], 4);
+
+        let (status, ratings) = probe(&app, "/movie/1/rating").await;
+        assert_eq!(status, StatusCode::OK);
+        let scores: Vec<_> = ratings
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|rating| rating["score"].as_u64().unwrap())
+            .collect();
+        assert_eq!(scores, [6, 7, 10]);
+
+        let uri = format!("/movie/1/rating/{}", rating_ids[0]);
+        assert_eq!(delete(&app, &uri).await, StatusCode::NO_CONTENT);
+        assert_eq!(delete(&app, &uri).await, StatusCode::NOT_FOUND);
+        let (_, movie) = probe(&app, "/movie/1").await;
+        assert_eq!(movie["rating_count"], 2);
+        assert_eq!(movie["average_rating"], 8.5);
+    }
+
+    #[tokio::test]
+    async fn ratings_need_a_movie_and_a_valid_score() {
+        let app = app();
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#],
+        )
+        .await;
+
+        let (status, body) = rate(&app, "999", 8).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "MOVIE_NOT_FOUND");
+        assert_eq!(
+            probe(&app, "/movie/999/rating").await.0,
+            StatusCode::NOT_FOUND
+        );
+
+        for score in [0, 11] {
+            let (status, body) = rate(&app, "1", score).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(failing_fields(&body), ["score"]);
+        }
+        assert_eq!(probe(&app, "/movie/1").await.1["rating_count"], 0);
+    }
+
+    #[tokio::test]
+    async fn purging_a_movie_deletes_its_ratings() {
+        let app = app();
+        let heat = r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#;
+        seed(&app, &[heat]).await;
+        rate(&app, "1", 9).await;
+        rate(&app, "1", 3).await;
+
+        assert_eq!(
+            delete(&app, "/movie/1?permanent=true").await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            probe(&app, "/movie/1/rating").await.0,
+            StatusCode::NOT_FOUND
+        );
+
+        // A movie created under the same id starts unrated.
+        seed(&app, &[heat]).await;
+        assert_eq!(probe(&app, "/movie/1/rating").await.1, json!([]));
+        assert_eq!(probe(&app, "/movie/1").await.1["rating_count"], 0);
+    }
+
+    #[tokio::test]
+    async fn genres_are_normalized_and_filterable() {
+        let app = app();
+        let (status, movie) = send_json(
+            &app,
+            "POST",
+            "/movie",
+            json!({
+                "id": "1", "name": "The Matrix", "year": 1999, "was_good": true,
+                "genres": ["Sci-Fi", " action ", "sci-fi"],
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(movie["genres"], json!(["sci-fi", "action"]));
+        // Payloads from before genres still work.
+        seed(
+            &app,
+            &[r#"{"id":"2","name":"Heat","year":1995,"was_good":true}"#],
+        )
+        .await;
+
+        for query in ["genre=sci-fi", "genre=SCI-FI"] {
+            let (status, page) = list(&app, query).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(ids(&page), ["1"], "{query}");
+        }
+        let (status, page) = list(&app, "genre=western").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(ids(&page).is_empty());
+    }
+
+    /// Adds `genres` to the taxonomy of `app`, each `(name, parent)`.
+    async fn add_genres(app: &Router, genres: &[(&str, Option<&str>)]) {
+        for (name, parent) in genres {
+            let (status, body) = send_json(
+                app,
+                "POST",
+                "/v1/genre",
+                json!({"name": name, "parent": parent}),
+            )
+            .await;
+            assert_eq!(status, StatusCode::CREATED, "{body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn genre_filters_match_the_genres_under_them() {
+        let app = app();
+        add_genres(
+            &app,
+            &[
+                ("Fiction", None),
+                ("Sci-Fi", Some("fiction")),
+                ("Cyberpunk", Some("sci-fi")),
+            ],
+        )
+        .await;
+        seed(
+            &app,
+            &[
+                r#"{"id":"1","name":"Blade Runner","year":1982,"was_good":true,"genres":["cyberpunk"]}"#,
+                r#"{"id":"2","name":"Alien","year":1979,"was_good":true,"genres":["sci-fi"]}"#,
+                r#"{"id":"3","name":"Heat","year":1995,"was_good":true,"genres":["crime"]}"#,
+            ],
+        )
+        .await;
+
+        for (query, expected) in [
+            ("genre=Fiction", vec!["1", "2"]),
+            ("genre=sci-fi", vec!["1", "2"]),
+            ("genre=cyberpunk", vec!["1"]),
+            ("genre=crime", vec!["3"]),
+        ] {
+            let (status, page) = list(&app, query).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(ids(&page), expected, "{query}");
+        }
+    }
+
+    #[tokio::test]
+    async fn the_genre_tree_rolls_counts_up() {
+        let app = app();
+        add_genres(
+            &app,
+            &[
+                ("fiction", None),
+                ("sci-fi", Some("fiction")),
+                ("cyberpunk", Some("sci-fi")),
+                ("fantasy", Some("fiction")),
+            ],
+        )
+        .await;
+        seed(
+            &app,
+            &[
+                r#"{"id":"1","name":"Blade Runner","year":1982,"was_good":true,"genres":["cyberpunk","sci-fi"]}"#,
+                r#"{"id":"2","name":"Alien","year":1979,"was_good":true,"genres":["sci-fi"]}"#,
+                r#"{"id":"3","name":"Heat","year":1995,"was_good":true,"genres":["crime"]}"#,
+            ],
+        )
+        .await;
+
+        let (status, tree) = probe(&app, "/v1/genre/tree").await;
+        assert_eq!(status, StatusCode::OK);
+        // A movie in a genre and one under it counts once towards the total.
+        assert_eq!(
+            tree,
+            json!([
+                {"name": "crime", "count": 1, "total": 1, "children": []},
+                {"name": "fiction", "count": 0, "total": 2, "children": [
+                    {"name": "fantasy", "count": 0, "total": 0, "children": []},
+                    {"name": "sci-fi", "count": 2, "total": 2, "children": [
+                        {"name": "cyberpunk", "count": 1, "total": 1, "children": []},
+                    ]},
+                ]},
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn genres_cannot_end_up_under_themselves() {
+        let app = app();
+        add_genres(
+            &app,
+            &[
+                ("fiction", None),
+                ("sci-fi", Some("fiction")),
+                ("cyberpunk", Some("sci-fi")),
+            ],
+        )
+        .await;
+
+        for (uri, parent) in [
+            ("/v1/genre/fiction", "cyberpunk"),
+            ("/v1/genre/sci-fi", "SCI-FI"),
+            ("/v1/genre/fiction", "western"),
+        ] {
+            let (status, body) = send_json(&app, "PUT", uri, json!({"parent": parent})).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{uri} {parent}");
+            assert_eq!(failing_fields(&body), ["parent"]);
+        }
+        let (status, body) = send_json(
+            &app,
+            "POST",
+            "/v1/genre",
+            json!({"name": "Drama", "parent": "drama"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(failing_fields(&body), ["parent"]);
+        let (status, body) =
+            send_json(&app, "POST", "/v1/genre", json!({"name": " Sci-Fi "})).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(
+            body["existing"],
+            json!({"name": "sci-fi", "parent": "fiction"})
+        );
+
+        let (status, genre) =
+            send_json(&app, "PUT", "/v1/genre/Cyberpunk", json!({"parent": null})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(genre, json!({"name": "cyberpunk", "parent": null}));
+        let (status, _) = send_json(
+            &app,
+            "PUT",
+            "/v1/genre/fiction",
+            json!({"parent": "cyberpunk"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn genres_in_use_are_only_deleted_with_force() {
+        let app = app();
+        add_genres(
+            &app,
+            &[
+                ("fiction", None),
+                ("sci-fi", Some("fiction")),
+                ("cyberpunk", Some("sci-fi")),
+            ],
+        )
+        .await;
+        seed(
+            &app,
+            &[
+                r#"{"id":"1","name":"Alien","year":1979,"was_good":true,"genres":["sci-fi","horror"]}"#,
+                r#"{"id":"2","name":"Heat","year":1995,"was_good":true,"genres":["crime"]}"#,
+            ],
+        )
+        .await;
+
+        let (status, body) = send_json(&app, "DELETE", "/v1/genre/sci-fi", Value::Null).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["conflict_type"], "referenced");
+        assert_eq!(
+            body["existing"],
+            json!({"genres": ["cyberpunk"], "movies": ["1"]})
+        );
+
+        assert_eq!(
+            delete(&app, "/v1/genre/Sci-Fi?force=true").await,
+            StatusCode::NO_CONTENT
+        );
+        let (_, movie) = probe(&app, "/movie/1").await;
+        assert_eq!(movie["genres"], json!(["horror"]));
+        assert_eq!(movie["version"], 2);
+        let (_, movie) = probe(&app, "/movie/2").await;
+        assert_eq!(movie["version"], 1);
+        let (_, tree) = probe(&app, "/v1/genre/tree").await;
+        let fiction = tree
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|node| node["name"] == "fiction")
+            .unwrap();
+        assert_eq!(fiction["children"][0]["name"], "cyberpunk");
+
+        assert_eq!(
+            delete(&app, "/v1/genre/cyberpunk").await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            delete(&app, "/v1/genre/sci-fi").await,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn genres_are_validated() {
+        let app = app();
+        let mut genres: Vec<String> = (0..11).map(|n| format!("genre {n}")).collect();
+        genres[3] = " ".to_string();
+        let (status, body) = send_json(
+            &app,
+            "POST",
+            "/movie",
+            json!({"id": "1", "name": "Heat", "year": 1995, "was_good": true, "genres": genres}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(failing_fields(&body), ["genres[3]"]);
+
+        genres[3] = "Genre 0".to_string();
+        let (status, _) = send_json(
+            &app,
+            "POST",
+            "/movie",
+            json!({"id": "1", "name": "Heat", "year": 1995, "was_good": true, "genres": genres}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        genres.push("one too many".to_string());
+        let (status, body) = send_json(&app, "PATCH", "/movie/1", json!({"genres": genres})).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(failing_fields(&body), ["genres"]);
+    }
+
+    #[tokio::test]
+    async fn genre_listing_counts_movies() {
+        let app = app();
+        for (id, name, genres) in [
+            ("1", "The Matrix", json!(["Sci-Fi", "Action"])),
+            ("2", "Dune", json!(["sci-fi"])),
+            ("3", "Heat", json!(["crime", "action"])),
+        ] {
+            let (status, _) = send_json(
+                &app,
+                "POST",
+                "/movie",
+                json!({"id": id, "name": name, "year": 1999, "was_good": true, "genres": genres}),
+            )
+            .await;
+            assert_eq!(status, StatusCode::CREATED);
+        }
+
+        assert_eq!(
+            probe(&app, "/genre").await,
+            (
+                StatusCode::OK,
+                json!([
+                    {"genre": "action", "count": 2},
+                    {"genre": "crime", "count": 1},
+                    {"genre": "sci-fi", "count": 2},
+                ])
+            )
+        );
+
+        let (status, _) = send_json(&app, "PATCH", "/movie/3", json!({"genres": ["Drama"]})).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send_json(
+            &app,
+            "PUT",
+            "/movie/2",
+            json!({"id": "2", "name": "Dune", "year": 2021, "was_good": true}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        assert_eq!(
+            probe(&app, "/genre").await.1,
+            json!([
+                {"genre": "action", "count": 1},
+                {"genre": "drama", "count": 1},
+                {"genre": "sci-fi", "count": 1},
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn watchlists_hold_movies_once() {
+        let app = app();
+        seed(
+            &app,
+            &[
+                r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#,
+                r#"{"id":"2","name":"Ronin","year":1998,"was_good":true}"#,
+            ],
+        )
+        .await;
+
+        let (status, watchlist) =
+            send_json(&app, "POST", "/watchlist", json!({"name": " Weekend "})).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(watchlist["name"], "Weekend");
+        let uri = format!("/watchlist/{}", watchlist["id"]);
+
+        for id in ["2", "1", "2"] {
+            let (status, watchlist) =
+                send_json(&app, "PUT", &format!("{uri}/movie/{id}"), Value::Null).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(watchlist["movie_ids"].as_array().unwrap()[0], "2");
+        }
+        let (_, watchlist) = probe(&app, &uri).await;
+        assert_eq!(ids(&json!({"items": watchlist["movies"]})), ["2", "1"]);
+
+        let (status, body) = send_json(&app, "PUT", &format!("{uri}/movie/999"), Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["message"], "movie 999 does not exist");
+        let (status, body) = send_json(&app, "PUT", "/watchlist/999/movie/1", Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "WATCHLIST_NOT_FOUND");
+
+        let (status, watchlist) =
+            send_json(&app, "DELETE", &format!("{uri}/movie/2"), Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(watchlist["movie_ids"], json!(["1"]));
+
+        let (status, watchlists) = probe(&app, "/watchlist").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(watchlists.as_array().unwrap().len(), 1);
+        assert_eq!(delete(&app, &uri).await, StatusCode::NO_CONTENT);
+        assert_eq!(probe(&app, &uri).await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn watchlists_report_deleted_movies_as_missing() {
+        let app = app();
+        seed(
+            &app,
+            &[
+                r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#,
+                r#"{"id":"2","name":"Ronin","year":1998,"was_good":true}"#,
+            ],
+        )
+        .await;
+        let (_, watchlist) = send_json(&app, "POST", "/watchlist", json!({"name": "Next"})).await;
+        let uri = format!("/watchlist/{}", watchlist["id"]);
+        for id in ["1", "2"] {
+            send_json(&app, "PUT", &format!("{uri}/movie/{id}"), Value::Null).await;
+        }
+
+        assert_eq!(delete(&app, "/movie/1").await, StatusCode::NO_CONTENT);
+
+        let (status, watchlist) = probe(&app, &uri).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids(&json!({"items": watchlist["movies"]})), ["2"]);
+        assert_eq!(watchlist["missing"], json!(["1"]));
+    }
+
+    #[tokio::test]
+    async fn saved_searches_run_against_the_catalogue_as_it_is() {
+        let app = app();
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#],
+        )
+        .await;
+
+        let (status, search) = send_json(
+            &app,
+            "POST",
+            "/v1/search",
+            json!({"name": "Good nineties", "query": "was_good=true&year_from=1990&year_to=1999&sort=-year"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let uri = format!("/v1/search/{}/results", search["id"]);
+        let (_, page) = probe(&app, &uri).await;
+        assert_eq!(ids(&page), ["1"]);
+
+        seed(
+            &app,
+            &[
+                r#"{"id":"2","name":"Ronin","year":1998,"was_good":true}"#,
+                r#"{"id":"3","name":"Gigli","year":2003,"was_good":false}"#,
+                r#"{"id":"4","name":"Hackers","year":1995,"was_good":false}"#,
+                r#"{"id":"5","name":"Fargo","year":1996,"was_good":true}"#,
+            ],
+        )
+        .await;
+        let (status, page) = probe(&app, &uri).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids(&page), ["2", "5", "1"]);
+        assert_eq!(page["total"], 3);
+
+        let (_, page) = probe(&app, &format!("{uri}?per_page=2&page=2")).await;
+        assert_eq!(ids(&page), ["1"]);
+        let (_, searches) = probe(&app, "/v1/search").await;
+        assert_eq!(searches, json!([search]));
+        assert_eq!(
+            probe(&app, "/v1/search/999/results").await.0,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn saved_searches_are_checked_when_saved() {
+        let app = app();
+        for (query, field) in [
+            ("year=1995&rating=5", "query.rating"),
+            ("page=2", "query.page"),
+            ("year=nineteen", "query"),
+            ("year=1995&year_from=1990", "query"),
+            ("sort=-budget", "query"),
+        ] {
+            let (status, body) = send_json(
+                &app,
+                "POST",
+                "/v1/search",
+                json!({"name": "Broken", "query": query}),
+            )
+            .await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{query}");
+            assert_eq!(failing_fields(&body), [field], "{query}");
+        }
+
+        let (status, _) = send_json(
+            &app,
+            "POST",
+            "/v1/search",
+            json!({"name": "Custom", "query": "custom.studio=A24&genre=Drama"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (_, searches) = probe(&app, "/v1/search").await;
+        assert_eq!(searches.as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn openapi_document_lists_exactly_the_mounted_routes() {
+        let app = app();
+        let (status, document) = probe(&app, openapi::DOCUMENT_PATH).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(document["openapi"].as_str().unwrap().starts_with("3."));
+
+        // Generators fail on references they cannot resolve.
+        let schemas = document["components"]["schemas"].as_object().unwrap();
+        let text = document.to_string();
+        for reference in text.split("\"#/components/schemas/").skip(1) {
+            let name = &reference[..reference.find('"').unwrap()];
+            assert!(schemas.contains_key(name), "{name} is not defined");
+        }
+
+        // Each documented method must be routed and every other one answer
+        // 405. Unmatched paths get axum's empty 404, while handlers always
+        // answer with a body, even when the resource is missing.
+        let paths = document["paths"].as_object().unwrap();
+        assert!(paths.contains_key("/v1/movie/{id}") && paths.contains_key("/readyz"));
+        for (path, item) in paths {
+            let uri: Vec<&str> = path
+                .split('/')
+                .map(|segment| {
+                    if segment.starts_with('{') {
+                        "1"
+                    } else {
+                        segment
+                    }
+                })
+                .collect();
+            let uri = uri.join("/");
+            for method in ["get", "post", "put", "patch", "delete"] {
+                let response = app
+                    .clone()
+                    .oneshot(
+                        Request::builder()
+                            .method(method.to_uppercase().as_str())
+                            .uri(&uri)
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                // Only 404 bodies are read, the event stream never ends.
+                let status = response.status();
+                let routed = match status {
+                    StatusCode::METHOD_NOT_ALLOWED => false,
+                    StatusCode::NOT_FOUND => {
+                        let body = response.into_body().collect().await.unwrap().to_bytes();
+                        !body.is_empty()
+                    }
+                    _ => true,
+                };
+                assert_eq!(routed, item.get(method).is_some(), "{method} {path}");
+            }
+        }
+
+        // Swagger UI is served whole by the server, pointed at the document.
+        let (status, _, page) = get_as(&app, "/swagger-ui/", "text/html").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(page.contains("swagger-ui-bundle.js"));
+        assert!(!page.contains("https://"), "{page}");
+        let (status, _, script) = get_as(&app, "/swagger-ui/swagger-initializer.js", "*/*").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(script.contains(openapi::DOCUMENT_PATH), "{script}");
+        let (status, _, _) = get_as(&app, "/swagger-ui/swagger-ui-bundle.js", "*/*").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    /// Reads `body` up to the next event, skipping keep-alive comments.
+    async fn next_event(body: &mut Body, buffer: &mut String) -> (String, Value) {
+        loop {
+            if let Some(end) = buffer.find("\n\n") {
+                let event: String = buffer.drain(..end + 2).collect();
+                if event.starts_with(':') {
+                    continue;
+                }
+                let field = |name: &str| {
+                    event
+                        .lines()
+                        .find_map(|line| line.strip_prefix(name))
+                        .unwrap()
+                        .to_string()
+                };
+                return (
+                    field("event: "),
+                    serde_json::from_str(&field("data: ")).unwrap(),
+                );
+            }
+
+            let frame = tokio::time::timeout(Duration::from_secs(5), body.frame())
+                .await
+                .expect("no event within five seconds")
+                .unwrap()
+                .unwrap();
+            if let Ok(data) = frame.into_data() {
+                buffer.push_str(std::str::from_utf8(&data).unwrap());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn movie_changes_are_streamed_as_events() {
+        let app = app();
+        let response = app
+            .clone()
+            .oneshot(Request::get("/movie/events").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/event-stream"
+        );
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+        let mut body = response.into_body();
+        let mut buffer = String::new();
+
+        let (_, created) = send_json(
+            &app,
+            "POST",
+            "/movie",
+            json!({"id": "1", "name": "Heat", "year": 1995, "was_good": true}),
+        )
+        .await;
+        assert_eq!(
+            next_event(&mut body, &mut buffer).await,
+            (
+                "created".to_string(),
+                json!({"kind": "created", "movie": created})
+            )
+        );
+
+        let (_, updated) = send_json(&app, "PATCH", "/movie/1", json!({"year": 1996})).await;
+        assert_eq!(
+            next_event(&mut body, &mut buffer).await,
+            (
+                "updated".to_string(),
+                json!({
+                    "kind": "updated",
+                    "movie": updated,
+                    "changes": [{"field": "year", "old": 1995, "new": 1996}],
+                })
+            )
+        );
+
+        // Updates changing nothing are neither stored nor announced.
+        let (status, unchanged) = send_json(
+            &app,
+            "PUT",
+            "/movie/1",
+            json!({"id": "1", "name": "Heat", "year": 1996, "was_good": true}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(unchanged["version"], updated["version"]);
+        let (_, updated) = send_json(
+            &app,
+            "PATCH",
+            "/movie/1",
+            json!({"genres": ["crime"], "custom": {"seen": true}}),
+        )
+        .await;
+        assert_eq!(
+            next_event(&mut body, &mut buffer).await.1["changes"],
+            json!([
+                {"field": "custom.seen", "old": null, "new": true},
+                {"field": "genres", "added": ["crime"], "removed": []},
+            ])
+        );
+        assert_eq!(updated["version"], 3);
+
+        assert_eq!(delete(&app, "/movie/1").await, StatusCode::NO_CONTENT);
+        let (name, event) = next_event(&mut body, &mut buffer).await;
+        assert_eq!(name, "deleted");
+        assert_eq!(event["movie"]["id"], "1");
+        assert!(event["movie"]["deleted_at"].is_string());
+    }
+
+    async fn get_as(app: &Router, uri: &str, accept: &str) -> (StatusCode, HeaderMap, String) {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get(uri)
+                    .header(header::ACCEPT, accept)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, headers, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn movies_are_served_in_the_accepted_format() {
+        let app = app();
+        seed(
+            &app,
+            &[
+                r#"{"id":"1","name":"Heat","year":1995,"was_good":true,"genres":["crime","drama"]}"#,
+                r#"{"id":"2","name":"Dune, Part One","year":2021,"was_good":true}"#,
+            ],
+        )
+        .await;
+
+        let (status, headers, xml) = get_as(&app, "/movie/1", "application/xml").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "application/xml");
+        assert_eq!(headers[header::VARY], "accept");
+        assert_eq!(headers[header::ETAG], "\"1\"");
+        let (_, json) = probe(&app, "/movie/1").await;
+        let movie: Movie = quick_xml::de::from_str(&xml).unwrap();
+        assert_eq!(movie, serde_json::from_value::<Movie>(json).unwrap());
+        // Elements come in the order of the JSON fields.
+        let positions: Vec<usize> = ["<id>", "<name>", "<year>", "<was_good>"]
+            .iter()
+            .map(|element| xml.find(element).unwrap())
+            .collect();
+        assert!(positions.is_sorted(), "{xml}");
+
+        let (status, headers, xml) = get_as(&app, "/movie?sort=id", "text/xml").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "application/xml");
+        let (_, json) = list(&app, "sort=id").await;
+        let page: Page<Movie> = quick_xml::de::from_str(&xml).unwrap();
+        assert_eq!(page, serde_json::from_value::<Page<Movie>>(json).unwrap());
+
+        let (status, headers, csv) = get_as(&app, "/movie?sort=id", "text/csv").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "text/csv; charset=utf-8");
+        assert_eq!(
+            csv,
+            "id,name,year,was_good\n1,Heat,1995,true\n2,\"Dune, Part One\",2021,true\n"
+        );
+
+        let (status, headers, _) = get_as(&app, "/movie", "application/json, */*").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "application/json");
+
+        for uri in ["/movie", "/movie/1"] {
+            let (status, _, body) = get_as(&app, uri, "application/yaml").await;
+            assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+            let body: Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(body["code"], "NOT_ACCEPTABLE");
+            assert!(body["supported"][1] == "application/xml", "{body}");
+        }
+        let (status, _, body) = get_as(&app, "/movie/1", "text/csv").await;
+        assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+        assert_eq!(
+            serde_json::from_str::<Value>(&body).unwrap()["supported"],
+            json!(["application/json", "application/xml"])
+        );
+    }
+
+    #[tokio::test]
+    async fn bodies_are_compressed_both_ways() {
+        use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+        use std::io::{Read, Write};
+
+        let app = app();
+        let movies: Vec<Value> = (1..=50)
+            .map(|i| json!({"id": i.to_string(), "name": format!("Movie {i}"), "year": 2000, "was_good": true}))
+            .collect();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(json!(movies).to_string().as_bytes())
+            .unwrap();
+        let response = app
+            .clone()
+            .oneshot(
+                Request::post("/movie/batch")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header(header::CONTENT_ENCODING, "gzip")
+                    .body(Body::from(encoder.finish().unwrap()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let (_, page) = list(&app, "per_page=100").await;
+        assert_eq!(page["total"], 50);
+
+        let get = |uri: &'static str| {
+            app.clone().oneshot(
+                Request::get(uri)
+                    .header(header::ACCEPT_ENCODING, "gzip")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+        let response = get("/movie?per_page=100").await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let mut json = String::new();
+        GzDecoder::new(&body[..]).read_to_string(&mut json).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&json).unwrap(), page);
+
+        // Small bodies and streams go out as they are.
+        let response = get("/movie/1").await.unwrap();
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+        let response = get("/movie/events").await.unwrap();
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+    }
+
+    #[tokio::test]
+    async fn older_api_versions_get_their_shape_and_a_sunset() {
+        let app = app();
+        add_movie(&app, "1", "Heat", 1995).await;
+        let get = |uri: &str, version: Option<&str>| {
+            let mut request = Request::get(uri);
+            if let Some(version) = version {
+                request = request.header("x-api-version", version);
+            }
+            app.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+
+        for version in [None, Some("2025-01")] {
+            let response = get("/v1/movie/1", version).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(!response.headers().contains_key("deprecation"));
+            assert!(!response.headers().contains_key("sunset"));
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let movie: Value = serde_json::from_slice(&body).unwrap();
+            assert!(movie["created_at"].is_string());
+            assert!(movie["updated_at"].is_string());
+        }
+
+        let response = get("/v1/movie/1", Some("2024-01")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["deprecation"], "true");
+        assert_eq!(
+            response.headers()["sunset"],
+            api_version::VERSIONS[0].sunset.unwrap()
+        );
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let movie: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(movie["name"], "Heat");
+        assert_eq!(movie["version"], 1);
+        assert!(movie.get("created_at").is_none());
+        assert!(movie.get("updated_at").is_none());
+
+        // Listings were a bare array, and stay one for the old version.
+        let response = get("/v1/movie", Some("2024-01")).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let movies: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(movies.as_array().unwrap().len(), 1);
+        assert_eq!(movies[0]["id"], "1");
+        assert!(movies[0].get("created_at").is_none());
+        let (_, page) = list(&app, "").await;
+        assert_eq!(page["total"], 1);
+
+        // Every page of movies, wherever it is served from.
+        let (_, search) = send_json(
+            &app,
+            "POST",
+            "/v1/search",
+            json!({"name": "Nineties", "query": "year_from=1990"}),
+        )
+        .await;
+        let uri = format!("/v1/search/{}/results", search["id"]);
+        let response = get(&uri, Some("2024-01")).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let movies: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(movies[0]["id"], "1");
+
+        // Streams are marked but not held to be reshaped.
+        let response = get("/v1/movie?stream=true", Some("2024-01")).await.unwrap();
+        assert_eq!(response.headers()["deprecation"], "true");
+        assert_eq!(response.body().size_hint().exact(), None);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let movies: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(movies[0]["id"], "1");
+
+        // With camelCase keys too.
+        let response = get("/v1/movie/1?case=camel", Some("2024-01"))
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let movie: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(movie["wasGood"], true);
+        assert!(movie.get("createdAt").is_none());
+
+        let response = get("/v1/movie/1", Some("2023-06")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let error: Value = serde_json::from_slice(&body).unwrap();
+        assert!(
+            error["message"]
+                .as_str()
+                .unwrap()
+                .ends_with("expected one of 2024-01, 2025-01"),
+            "{error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn legacy_paths_alias_v1_with_deprecation_headers() {
+        let app = app();
+        let (status, _) = send_json(
+            &app,
+            "POST",
+            "/v1/movie",
+            json!({"id": "1", "name": "Heat", "year": 1995, "was_good": true}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        send_json(
+            &app,
+            "POST",
+            "/movie",
+            json!({"id": "2", "name": "Dune", "year": 2021, "was_good": true}),
+        )
+        .await;
+
+        for uri in ["/movie", "/movie/1", "/movie/2", "/genre"] {
+            let v1 = app
+                .clone()
+                .oneshot(
+                    Request::get(format!("/v1{uri}"))
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let legacy = app
+                .clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(legacy.status(), v1.status(), "{uri}");
+            assert!(!v1.headers().contains_key("deprecation"), "{uri}");
+            assert_eq!(legacy.headers()["deprecation"], "true", "{uri}");
+            assert_eq!(legacy.headers()["sunset"], legacy::SUNSET, "{uri}");
+            assert_eq!(
+                legacy.headers()[header::LINK],
+                format!("</v1{uri}>; rel=\"successor-version\""),
+            );
+
+            let v1 = v1.into_body().collect().await.unwrap().to_bytes();
+            let legacy = legacy.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(legacy, v1, "{uri}");
+        }
+
+        // Redirects after a change of id stay on the version asked for.
+        send_json(
+            &app,
+            "POST",
+            "/v1/movie/1/change-id",
+            json!({"new_id": "3"}),
+        )
+        .await;
+        for (uri, location) in [("/v1/movie/1", "/v1/movie/3"), ("/movie/1", "/movie/3")] {
+            let response = app
+                .clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+            assert_eq!(response.headers()[header::LOCATION], location);
+        }
+    }
+
+    #[tokio::test]
+    async fn stats_summarize_the_catalogue() {
+        let app = app();
+        let empty = json!({
+            "total": 0,
+            "good": 0,
+            "bad": 0,
+            "earliest_year": null,
+            "latest_year": null,
+            "by_decade": {},
+            "by_genre": [],
+            "average_rating": null,
+            "rating_count": 0,
+            "largest": [],
+            "over_budget": [],
+        });
+        assert_eq!(
+            probe(&app, "/v1/movie/stats").await,
+            (StatusCode::OK, empty)
+        );
+
+        for (id, year, was_good, genres) in [
+            ("1", 1972, true, json!(["Crime", "drama"])),
+            ("2", 1995, true, json!(["crime"])),
+            ("3", 1999, false, json!([])),
+            ("4", 2000, true, json!(["drama"])),
+            ("5", 2014, false, json!(["sci-fi"])),
+        ] {
+            let movie = json!({"id": id, "name": format!("Movie {id}"), "year": year, "was_good": was_good, "genres": genres});
+            let (status, _) = send_json(&app, "POST", "/movie", movie).await;
+            assert_eq!(status, StatusCode::CREATED);
+        }
+        for (id, score) in [("1", 10), ("1", 9), ("2", 5), ("5", 2)] {
+            assert_eq!(rate(&app, id, score).await.0, StatusCode::CREATED);
+        }
+        // Trashed movies and their ratings are left out.
+        assert_eq!(delete(&app, "/movie/5").await, StatusCode::NO_CONTENT);
+
+        let (status, mut stats) = probe(&app, "/v1/movie/stats").await;
+        // Sizes depend on the timestamps, so only their order is checked.
+        let largest = stats.as_object_mut().unwrap().remove("largest").unwrap();
+        assert_eq!(largest[0]["id"], "1");
+        assert_eq!(largest.as_array().unwrap().len(), 4);
+        assert_eq!(
+            (status, stats),
+            (
+                StatusCode::OK,
+                json!({
+                    "total": 4,
+                    "good": 3,
+                    "bad": 1,
+                    "earliest_year": 1972,
+                    "latest_year": 2000,
+                    "by_decade": {"1970s": 1, "1990s": 2, "2000s": 1},
+                    "by_genre": [
+                        {"genre": "crime", "count": 2},
+                        {"genre": "drama", "count": 2},
+                    ],
+                    "average_rating": 8.0,
+                    "rating_count": 3,
+                    "over_budget": [],
+                })
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn movies_are_held_to_their_budgets() {
+        let app = app_with_config(Config {
+            movie_max_bytes: 2048,
+            movie_max_list_items: 3,
+            ..Config::default()
+        });
+        for id in ["a", "b", "c", "d"] {
+            add_person(&app, id, "Someone").await;
+        }
+        let padded = |keys: usize| -> Value {
+            (0..keys)
+                .map(|key| (format!("note_{key}"), json!("x".repeat(1000))))
+                .collect::<serde_json::Map<_, _>>()
+                .into()
+        };
+
+        let heat = json!({"id": "1", "name": "Heat", "year": 1995, "was_good": true, "cast": ["a", "b", "c"], "custom": padded(1)});
+        assert_eq!(
+            send_json(&app, "POST", "/movie", heat).await.0,
+            StatusCode::CREATED
+        );
+
+        let ronin = json!({"id": "2", "name": "Ronin", "year": 1998, "was_good": true, "cast": ["a", "b", "c", "d"]});
+        let (status, body) = send_json(&app, "POST", "/movie", ronin).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(failing_fields(&body), ["cast"]);
+        assert_eq!(
+            body["errors"][0]["message"],
+            "holds 4 items, over the MOVIE_MAX_LIST_ITEMS budget of 3"
+        );
+
+        let ronin = json!({"id": "2", "name": "Ronin", "year": 1998, "was_good": true, "custom": padded(3)});
+        let (status, body) = send_json(&app, "POST", "/movie", ronin.clone()).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(failing_fields(&body), ["movie"]);
+        assert!(
+            body["errors"][0]["message"]
+                .as_str()
+                .unwrap()
+                .ends_with("over the MOVIE_MAX_BYTES budget of 2048"),
+            "{body}"
+        );
+        let (status, body) = send_json(
+            &app,
+            "POST",
+            "/movie/transaction",
+            json!([{"op": "create", "movie": ronin}]),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(failing_fields(&body), ["[0].movie"]);
+
+        // Each field of a patch is within its limits, the movie it makes is
+        // not.
+        let (status, body) =
+            send_json(&app, "PATCH", "/movie/1", json!({"custom": padded(3)})).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(failing_fields(&body), ["movie"]);
+        let (_, heat) = probe(&app, "/movie/1").await;
+        assert_eq!(heat["version"], 1);
+    }
+
+    #[test]
+    fn a_movie_may_fill_its_budget_exactly() {
+        let movie = Movie {
+            id: "1".to_string(),
+            name: "Heat".to_string(),
+            year: 1995,
+            was_good: true,
+            locked_fields: Vec::new(),
+            custom: HashMap::from([("note".to_string(), json!("x".repeat(500)))]),
+            genres: vec!["crime".to_string(); 2],
+            sort_name: None,
+            director_id: None,
+            cast: Vec::new(),
+            slug: "heat-1995".to_string(),
+            version: 1,
+            created_at: Default::default(),
+            updated_at: Default::default(),
+            deleted_at: None,
+            has_poster: false,
+        };
+        let size = serialized_size(&movie);
+        let budget = |bytes: usize, items: usize| Config {
+            movie_max_bytes: bytes,
+            movie_max_list_items: items,
+            ..Config::default()
+        };
+
+        assert_eq!(validate_budget(&movie, &budget(size, 2), ""), []);
+        assert_eq!(
+            validate_budget(&movie, &budget(size - 1, 1), "[0]."),
+            [
+                FieldError::new(
+                    "[0].genres",
+                    "holds 2 items, over the MOVIE_MAX_LIST_ITEMS budget of 1"
+                ),
+                FieldError::new(
+                    "[0]",
+                    format!(
+                        "is {size} bytes serialized, over the MOVIE_MAX_BYTES budget of {}",
+                        size - 1
+                    )
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn movies_over_a_lowered_budget_are_flagged() {
+        let repo: Arc<dyn MovieRepository> = Arc::new(InMemoryRepository::new());
+        let app = app_with_repository(repo.clone());
+        add_movie(&app, "1", "Heat", 1995).await;
+        let ronin = json!({"id": "2", "name": "Ronin", "year": 1998, "was_good": true, "custom": {"note": "x".repeat(1000)}});
+        assert_eq!(
+            send_json(&app, "POST", "/movie", ronin).await.0,
+            StatusCode::CREATED
+        );
+
+        // As after a restart with a smaller budget.
+        let config = Config {
+            movie_max_bytes: 1024,
+            ..Config::default()
+        };
+        let app = router(AppState::with_repository(config, repo));
+        let (_, stats) = probe(&app, "/v1/movie/stats").await;
+        assert_eq!(stats["over_budget"], json!(["2"]));
+        assert_eq!(stats["largest"][0]["id"], "2");
+        assert!(stats["largest"][0]["bytes"].as_u64().unwrap() > 1024);
+
+        // Still served, and updates must bring it back within budget.
+        assert_eq!(probe(&app, "/movie/2").await.0, StatusCode::OK);
+        let (status, _) = send_json(&app, "PATCH", "/movie/2", json!({"year": 1999})).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let (status, _) = send_json(&app, "PATCH", "/movie/2", json!({"custom": {}})).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, stats) = probe(&app, "/v1/movie/stats").await;
+        assert_eq!(stats["over_budget"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn streamed_listing_sends_chunks_while_reading() {
+        let app = app();
+        for batch in 0..3 {
+            let movies: Vec<Value> = (0..1000)
+                .map(|i| {
+                    let id = batch * 1000 + i;
+                    json!({"id": format!("{id:04}"), "name": format!("Movie {id}"), "year": 1990, "was_good": id % 2 == 0})
+                })
+                .collect();
+            let (status, _) = send_json(&app, "POST", "/movie/batch", json!(movies)).await;
+            assert!(status.is_success(), "{status}");
+        }
+        assert_eq!(delete(&app, "/movie/0002").await, StatusCode::NO_CONTENT);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/v1/movie?stream=true&was_good=true")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        assert!(!response.headers().contains_key(header::ETAG));
+
+        let mut body = response.into_body();
+        let first = body.frame().await.unwrap().unwrap().into_data().unwrap();
+        assert!(first.starts_with(b"["));
+        assert!(serde_json::from_slice::<Value>(&first).is_err());
+
+        // Written after the first chunk went out, and still listed: the
+        // store is read as the body is sent, not before.
+        let (status, _) = send_json(
+            &app,
+            "POST",
+            "/movie",
+            json!({"id": "9999", "name": "Late", "year": 1990, "was_good": true}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let mut chunks = vec![first];
+        while let Some(frame) = body.frame().await {
+            chunks.push(frame.unwrap().into_data().unwrap());
+        }
+        assert!(chunks.len() >= 3, "{} chunks", chunks.len());
+
+        let movies: Vec<Value> = serde_json::from_slice(&chunks.concat()).unwrap();
+        let ids: Vec<&str> = movies.iter().map(|m| m["id"].as_str().unwrap()).collect();
+        assert_eq!(ids.len(), 1500);
+        assert_eq!(ids[..3], ["0000", "0004", "0006"]);
+        assert!(ids.is_sorted());
+        assert_eq!(ids.last(), Some(&"9999"));
+
+        let (status, _) = probe(&app, "/v1/movie?stream=true&sort=name").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _, _) = get_as(&app, "/v1/movie?stream=true", "application/xml").await;
+        assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+    }
+
+    #[tokio::test]
+    async fn restoring_a_backup_brings_the_store_back_exactly() {
+        let app = app();
+        for (id, name) in [("1", "Heat"), ("2", "Ronin"), ("3", "Thief")] {
+            let movie = json!({"id": id, "name": name, "year": 1995, "was_good": true, "genres": ["crime"]});
+            let (status, _) = send_json(&app, "POST", "/movie", movie).await;
+            assert_eq!(status, StatusCode::CREATED);
+        }
+        assert_eq!(rate(&app, "1", 9).await.0, StatusCode::CREATED);
+        assert_eq!(delete(&app, "/movie/3").await, StatusCode::NO_CONTENT);
+
+        let (status, backup) = probe(&app, "/admin/backup").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(backup["version"], 1);
+        assert!(backup["exported_at"].is_string());
+        let ids: Vec<&str> = backup["movies"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|movie| movie["id"].as_str().unwrap())
+            .collect();
+        // Trashed movies are backed up too.
+        assert_eq!(ids, ["1", "2", "3"]);
+
+        let (status, patched) = send_json(
+            &app,
+            "PATCH",
+            "/movie/1",
+            json!({"name": "Heat (Director's Cut)"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let renamed = format!("/v1/movie/slug/{}", patched["slug"].as_str().unwrap());
+        assert_eq!(delete(&app, "/movie/2").await, StatusCode::NO_CONTENT);
+        let (status, _) = send_json(
+            &app,
+            "POST",
+            "/movie",
+            json!({"id": "4", "name": "Collateral", "year": 2004, "was_good": true}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(rate(&app, "4", 7).await.0, StatusCode::CREATED);
+
+        let (status, report) = send_json(&app, "POST", "/admin/restore", backup.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report, json!({"restored": 3, "removed": 1}));
+
+        let (_, restored) = probe(&app, "/admin/backup").await;
+        assert_eq!(restored["movies"], backup["movies"]);
+        assert_eq!(
+            probe(&app, "/v1/movie/slug/heat-1995").await.1["name"],
+            "Heat"
+        );
+        // The slug of the rename is gone with it.
+        assert_eq!(probe(&app, &renamed).await.0, StatusCode::NOT_FOUND);
+        assert_eq!(probe(&app, "/v1/movie/4").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(
+            probe(&app, "/v1/movie/1/rating")
+                .await
+                .1
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // Merging upserts what the document has and keeps everything else.
+        let mut heat = backup["movies"][0].clone();
+        heat["name"] = json!("Heat (1995)");
+        let document = json!({"version": 1, "exported_at": backup["exported_at"], "movies": [heat, {"id": "5", "name": "Blackhat", "year": 2015, "was_good": false}]});
+        let (status, report) = send_json(&app, "POST", "/admin/restore?merge=true", document).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report, json!({"restored": 2, "removed": 0}));
+        let (_, merged) = probe(&app, "/admin/backup").await;
+        let names: Vec<&str> = merged["movies"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|movie| movie["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["Heat (1995)", "Ronin", "Thief", "Blackhat"]);
+        assert_eq!(merged["movies"][3]["slug"], "blackhat-2015");
+        assert_eq!(merged["movies"][3]["version"], 1);
+    }
+
+    #[tokio::test]
+    async fn invalid_backups_are_refused_before_anything_is_written() {
+        let app = app();
+        let movie = |id: &str| json!({"id": id, "name": "Ronin", "year": 1998, "was_good": true});
+        let (status, _) = send_json(&app, "POST", "/movie", movie("1")).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        for (document, field) in [
+            (
+                json!({"version": 2, "exported_at": "2026-01-01T00:00:00Z", "movies": [movie("2")]}),
+                "version",
+            ),
+            (
+                json!({"version": 1, "exported_at": "2026-01-01T00:00:00Z", "movies": [movie("2"), movie("3"), movie("2")]}),
+                "movies[2].id",
+            ),
+            (
+                json!({"version": 1, "exported_at": "2026-01-01T00:00:00Z", "movies": [movie("2"), movie("not an id")]}),
+                "movies[1].id",
+            ),
+            (
+                json!({"version": 1, "exported_at": "2026-01-01T00:00:00Z", "movies": [], "people": [{"id": "not an id", "name": "Al Pacino"}]}),
+                "people[0].id",
+            ),
+            (
+                json!({"version": 1, "exported_at": "2026-01-01T00:00:00Z", "movies": [{"id": "2", "name": "Heat", "year": 1995, "was_good": true, "cast": ["pacino"]}]}),
+                "movies[0].cast[0]",
+            ),
+        ] {
+            for uri in ["/admin/restore", "/admin/restore?merge=true"] {
+                let (status, body) = send_json(&app, "POST", uri, document.clone()).await;
+                assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{body}");
+                assert_eq!(body["errors"][0]["field"], field);
+            }
+        }
+
+        let (_, backup) = probe(&app, "/admin/backup").await;
+        let ids: Vec<&str> = backup["movies"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|movie| movie["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids, ["1"]);
+    }
+
+    #[tokio::test]
+    async fn backups_carry_the_people_movies_name() {
+        let app = app();
+        add_person(&app, "mann", "Michael Mann").await;
+        add_person(&app, "pacino", "Al Pacino").await;
+        let (status, _) = send_json(
+            &app,
+            "POST",
+            "/movie",
+            json!({"id": "1", "name": "Heat", "year": 1995, "was_good": true, "director_id": "mann"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (status, backup) = probe(&app, "/admin/backup").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            backup["people"],
+            json!([
+                {"id": "mann", "name": "Michael Mann"},
+                {"id": "pacino", "name": "Al Pacino"},
+            ])
+        );
+
+        // Replacing drops the people the document lacks, once nothing
+        // names them.
+        let mut document = backup.clone();
+        document["people"] = json!([{"id": "mann", "name": "Michael Mann"}]);
+        let (status, _) = send_json(&app, "POST", "/admin/restore", document).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, people) = probe(&app, "/v1/person").await;
+        assert_eq!(people, json!([{"id": "mann", "name": "Michael Mann"}]));
+
+        // Merging keeps the stored people for the document's movies to name.
+        let document = json!({"version": 1, "exported_at": backup["exported_at"], "movies": [
+            {"id": "2", "name": "Thief", "year": 1981, "was_good": true, "director_id": "mann", "cast": ["caan"]},
+        ], "people": [{"id": "caan", "name": "James Caan"}]});
+        let (status, body) =
+            send_json(&app, "POST", "/admin/restore?merge=true", document.clone()).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let (_, people) = probe(&app, "/v1/person").await;
+        assert_eq!(people.as_array().unwrap().len(), 2);
+        // Replacing with it would leave Thief naming no one.
+        let (status, body) = send_json(&app, "POST", "/admin/restore", document).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"][0]["field"], "movies[0].director_id");
+
+        let (status, _) = send_json(&app, "POST", "/admin/restore", backup).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, people) = probe(&app, "/v1/person").await;
+        assert_eq!(people.as_array().unwrap().len(), 2);
+        assert_eq!(
+            probe(&app, "/v1/person/caan").await.0,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    /// Knows two Blade Runners and two Dunes, and fails on cue for the
+    /// titles `Nothing`, `Busy` and `Offline`.
+    struct FakeProvider;
+
+    #[async_trait::async_trait]
+    impl MetadataProvider for FakeProvider {
+        async fn search(
+            &self,
+            title: &str,
+            _: Option<u16>,
+        ) -> Result<Vec<metadata::Candidate>, metadata::MetadataError> {
+            use metadata::MetadataError;
+            match title {
+                "Nothing" => return Err(MetadataError::NotFound),
+                "Busy" => {
+                    return Err(MetadataError::RateLimited {
+                        retry_after: Some(Duration::from_secs(30)),
+                    });
+                }
+                "Offline" => return Err(MetadataError::Upstream("connection refused".into())),
+                _ => {}
+            }
+            Ok(FAKE_TITLES
+                .iter()
+                .map(|(id, title, year, _)| metadata::Candidate {
+                    id: id.to_string(),
+                    title: title.to_string(),
+                    year: *year,
+                })
+                .collect())
+        }
+
+        async fn details(&self, id: &str) -> Result<metadata::Details, metadata::MetadataError> {
+            let (id, title, year, genres) = FAKE_TITLES
+                .iter()
+                .find(|(candidate, ..)| *candidate == id)
+                .ok_or(metadata::MetadataError::NotFound)?;
+            Ok(metadata::Details {
+                id: id.to_string(),
+                title: title.to_string(),
+                year: *year,
+                genres: genres.iter().map(|genre| genre.to_string()).collect(),
+            })
+        }
+    }
+
+    const FAKE_TITLES: [(&str, &str, u16, &[&str]); 4] = [
+        ("tt1856101", "Blade Runner 2049", 2017, &["Drama", "Sci-Fi"]),
+        ("tt0083658", "Blade Runner", 1982, &["Action", "Sci-Fi"]),
+        ("tt1160419", "Dune", 2021, &["Adventure"]),
+        ("tt0087182", "Dune", 1984, &["Adventure", "Sci-Fi"]),
+    ];
+
+    fn app_with_metadata() -> Router {
+        let mut state = AppState::new(Config::default());
+        state.metadata = Some(Arc::new(FakeProvider));
+        router(state)
+    }
+
+    #[tokio::test]
+    async fn external_imports_create_the_matching_movie() {
+        let app = app_with_metadata();
+
+        let (status, movie) = send_json(
+            &app,
+            "POST",
+            "/v1/movie/import/external",
+            json!({"title": "blade runner", "was_good": true}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED, "{movie}");
+        assert_eq!(movie["id"], "tt0083658");
+        assert_eq!(movie["name"], "Blade Runner");
+        assert_eq!(movie["year"], 1982);
+        assert_eq!(movie["was_good"], true);
+        assert_eq!(movie["genres"], json!(["action", "sci-fi"]));
+        assert_eq!(movie["slug"], "blade-runner-1982");
+        assert_eq!(probe(&app, "/v1/movie/tt0083658").await.0, StatusCode::OK);
+
+        // The year settles between remakes; the id can be chosen.
+        let (status, movie) = send_json(
+            &app,
+            "POST",
+            "/v1/movie/import/external",
+            json!({"title": "Dune", "year": 1984, "id": "dune"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED, "{movie}");
+        assert_eq!(
+            (&movie["id"], &movie["year"]),
+            (&json!("dune"), &json!(1984))
+        );
+        assert_eq!(movie["was_good"], false);
+
+        let (status, body) = send_json(
+            &app,
+            "POST",
+            "/v1/movie/import/external",
+            json!({"title": "Blade Runner"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["conflict_type"], "duplicate_id");
+    }
+
+    #[tokio::test]
+    async fn external_import_failures_map_onto_statuses() {
+        let app = app_with_metadata();
+        let import = |body: Value| {
+            let app = app.clone();
+            async move { send_json(&app, "POST", "/v1/movie/import/external", body).await }
+        };
+
+        let (status, body) = import(json!({"title": "Nothing"})).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            (&body["code"], &body["id"]),
+            (&json!("TITLE_NOT_FOUND"), &json!("Nothing"))
+        );
+        // Known, but not from that year.
+        let (status, body) = import(json!({"title": "Dune", "year": 1999})).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["id"], "Dune (1999)");
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::post("/v1/movie/import/external")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"title": "Busy"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "30");
+
+        let (status, body) = import(json!({"title": "Offline"})).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert!(!body["message"].as_str().unwrap().contains("refused"));
+
+        let (status, _) = import(json!({"title": " ", "year": 1200})).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        // Nothing was created along the way.
+        assert_eq!(probe(&app, "/v1/movie/stats").await.1["total"], 0);
+
+        let (status, body) = send_json(
+            &super::app(),
+            "POST",
+            "/v1/movie/import/external",
+            json!({"title": "Blade Runner"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "NOT_CONFIGURED");
+    }
+
+    const POSTER: &[u8] = include_bytes!("../tests/fixtures/poster.png");
+    const BOUNDARY: &str = "poster-boundary";
+
+    /// The head of a form whose `poster` field holds a file of `content_type`.
+    fn poster_form_head(content_type: &str) -> Vec<u8> {
+        format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"poster\"; filename=\"poster\"\r\nContent-Type: {content_type}\r\n\r\n"
+        )
+        .into_bytes()
+    }
+
+    async fn upload_poster(
+        app: &Router,
+        uri: &str,
+        content_type: &str,
+        image: &[u8],
+    ) -> (StatusCode, Value) {
+        let mut form = poster_form_head(content_type);
+        form.extend_from_slice(image);
+        form.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::put(uri)
+                    .header(
+                        header::CONTENT_TYPE,
+                        format!("multipart/form-data; boundary={BOUNDARY}"),
+                    )
+                    .body(Body::from(form))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn app_with_media_dir(media_dir: &std::path::Path) -> Router {
+        app_with_config(Config {
+            media_dir: media_dir.to_path_buf(),
+            ..Config::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn posters_are_served_as_uploaded() {
+        let media = tempfile::tempdir().unwrap();
+        let app = app_with_media_dir(media.path());
+        let (status, _) = send_json(
+            &app,
+            "POST",
+            "/movie",
+            json!({"id": "1", "name": "Heat", "year": 1995, "was_good": true}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (status, body) = probe(&app, "/v1/movie/1/poster").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "POSTER_NOT_FOUND");
+        let (status, _) = upload_poster(&app, "/v1/movie/9/poster", "image/png", POSTER).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, movie) = upload_poster(&app, "/v1/movie/1/poster", "image/png", POSTER).await;
+        assert_eq!(status, StatusCode::OK, "{movie}");
+        assert_eq!(movie["has_poster"], true);
+        assert_eq!(movie["version"], 2);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/v1/movie/1/poster")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+        assert_eq!(response.headers()[header::ETAG], "\"2\"");
+        assert_eq!(
+            response.headers()[header::CACHE_CONTROL],
+            "private, max-age=0, must-revalidate"
+        );
+        let image = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(image, POSTER);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/v1/movie/1/poster")
+                    .header(header::IF_NONE_MATCH, "\"2\"")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        assert_eq!(
+            delete(&app, "/v1/movie/1/poster").await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            probe(&app, "/v1/movie/1/poster").await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(probe(&app, "/v1/movie/1").await.1["has_poster"], false);
+        assert_eq!(
+            delete(&app, "/v1/movie/1/poster").await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(std::fs::read_dir(media.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn poster_uploads_must_be_small_images() {
+        let media = tempfile::tempdir().unwrap();
+        let app = app_with_media_dir(media.path());
+        let (status, _) = send_json(
+            &app,
+            "POST",
+            "/movie",
+            json!({"id": "1", "name": "Heat", "year": 1995, "was_good": true}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) = upload_poster(&app, "/v1/movie/1/poster", "image/png", POSTER).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Declared a PNG, but the bytes say otherwise; the poster stays.
+        let (status, body) =
+            upload_poster(&app, "/v1/movie/1/poster", "image/png", b"definitely a PNG").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"][0]["field"], "poster");
+        let (status, _) = upload_poster(&app, "/v1/movie/1/poster", "text/plain", POSTER).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        // Not a form at all, or one that breaks off inside the image.
+        let (status, body) = send_json(&app, "PUT", "/v1/movie/1/poster", json!({})).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(body["code"], "INVALID_BODY");
+        let mut form = poster_form_head("image/png");
+        form.extend_from_slice(POSTER);
+        let response = app
+            .clone()
+            .oneshot(
+                Request::put("/v1/movie/1/poster")
+                    .header(
+                        header::CONTENT_TYPE,
+                        format!("multipart/form-data; boundary={BOUNDARY}"),
+                    )
+                    .body(Body::from(form))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // An endless upload is cut off once it passes the limit, which it
+        // could not be if it were read whole first.
+        let mut head = poster_form_head("image/png");
+        head.extend_from_slice(POSTER);
+        let filler = axum::body::Bytes::from(vec![0; 64 * 1024]);
+        let chunks =
+            std::iter::once(axum::body::Bytes::from(head)).chain(std::iter::repeat(filler));
+        let response = app
+            .clone()
+            .oneshot(
+                Request::put("/v1/movie/1/poster")
+                    .header(
+                        header::CONTENT_TYPE,
+                        format!("multipart/form-data; boundary={BOUNDARY}"),
+                    )
+                    .body(Body::from_stream(futures_util::stream::iter(
+                        chunks.map(Ok::<_, std::io::Error>),
+                    )))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/v1/movie/1/poster")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let image = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(image, POSTER);
+        // No temporary files are left behind.
+        let mut files: Vec<_> = std::fs::read_dir(media.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files.len(), 2, "{files:?}");
+        assert_eq!(files[0], "1.poster");
+        assert!(files[1].ends_with(".image"), "{files:?}");
+    }
+
+    #[tokio::test]
+    async fn posters_follow_their_movie() {
+        let media = tempfile::tempdir().unwrap();
+        let app = app_with_media_dir(media.path());
+        for id in ["1", "2"] {
+            let (status, _) = send_json(
+                &app,
+                "POST",
+                "/movie",
+                json!({"id": id, "name": "Heat", "year": 1995, "was_good": true}),
+            )
+            .await;
+            assert_eq!(status, StatusCode::CREATED);
+            let (status, _) =
+                upload_poster(&app, &format!("/v1/movie/{id}/poster"), "image/png", POSTER).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+
+        let (status, _) = send_json(
+            &app,
+            "POST",
+            "/v1/movie/1/change-id",
+            json!({"new_id": "heat"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(probe(&app, "/v1/movie/heat").await.1["has_poster"], true);
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/v1/movie/heat/poster")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The trash keeps the poster for a restore; deleting for good does
+        // not.
+        assert_eq!(delete(&app, "/v1/movie/heat").await, StatusCode::NO_CONTENT);
+        assert!(media.path().join("heat.poster").exists());
+        assert_eq!(
+            delete(&app, "/v1/movie/heat?permanent=true").await,
+            StatusCode::NO_CONTENT
+        );
+        assert!(!media.path().join("heat.poster").exists());
+        assert_eq!(
+            delete(&app, "/v1/movie/2?permanent=true").await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(std::fs::read_dir(media.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn identical_posters_are_stored_once() {
+        let media = tempfile::tempdir().unwrap();
+        let app = app_with_media_dir(media.path());
+        for id in ["1", "2", "3"] {
+            add_movie(&app, id, "Heat", 1995).await;
+        }
+        for id in ["1", "2"] {
+            let (status, _) =
+                upload_poster(&app, &format!("/v1/movie/{id}/poster"), "image/png", POSTER).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let report = |images: usize, references: usize| {
+            json!({
+                "images": images,
+                "bytes": images * POSTER.len(),
+                "references": references,
+                "orphaned_images": [],
+                "orphaned_posters": [],
+            })
+        };
+        assert_eq!(
+            probe(&app, "/admin/posters").await,
+            (StatusCode::OK, report(1, 2))
+        );
+
+        // The image stays while a movie still shows it.
+        assert_eq!(
+            delete(&app, "/v1/movie/1?permanent=true").await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(probe(&app, "/admin/posters").await.1, report(1, 1));
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/v1/movie/2/poster")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let image = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(image, POSTER);
+
+        // Replacing a poster lets go of the image it showed.
+        let (status, _) = upload_poster(&app, "/v1/movie/3/poster", "image/png", POSTER).await;
+        assert_eq!(status, StatusCode::OK);
+        let mut jpeg = b"\xff\xd8\xff\xe0\0\x10JFIF".to_vec();
+        jpeg.resize(POSTER.len(), 0);
+        let (status, _) = upload_poster(&app, "/v1/movie/2/poster", "image/jpeg", &jpeg).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(probe(&app, "/admin/posters").await.1, report(2, 2));
+        assert_eq!(
+            delete(&app, "/v1/movie/3/poster").await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(probe(&app, "/admin/posters").await.1, report(1, 1));
+        assert_eq!(
+            delete(&app, "/v1/movie/2/poster").await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(probe(&app, "/admin/posters").await.1, report(0, 0));
+        assert_eq!(std::fs::read_dir(media.path()).unwrap().count(), 0);
+
+        // Leftovers are reported, not removed.
+        std::fs::write(media.path().join(format!("{}.image", "0".repeat(64))), "x").unwrap();
+        std::fs::write(media.path().join("gone.poster"), "0".repeat(64)).unwrap();
+        let (_, report) = probe(&app, "/admin/posters").await;
+        assert_eq!(report["orphaned_images"], json!(["0".repeat(64)]));
+        assert_eq!(report["orphaned_posters"], json!(["gone"]));
+    }
+
+    async fn post_body(
+        app: &Router,
+        uri: &str,
+        content_type: &str,
+        body: Body,
+    ) -> (StatusCode, Value) {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::post(uri)
+                    .header(header::CONTENT_TYPE, content_type)
+                    .body(body)
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    /// A movie padded to exactly `len` bytes of compact JSON, which is what
+    /// the casing middleware passes on.
+    fn padded_movie(id: &str, len: usize) -> String {
+        let movie = |note: &str| {
+            json!({"id": id, "name": "Heat", "year": 1995, "was_good": true, "custom": {"note": note}})
+                .to_string()
+        };
+        movie(&"x".repeat(len - movie("").len()))
+    }
+
+    #[tokio::test]
+    async fn bodies_past_their_limit_are_refused() {
+        let app = app_with_config(Config {
+            body_limit: 1024,
+            upload_body_limit: 4096,
+            ..Config::default()
+        });
+
+        let (status, _) = post_body(
+            &app,
+            "/v1/movie",
+            "application/json",
+            Body::from(padded_movie("1", 1024)),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, body) = post_body(
+            &app,
+            "/v1/movie",
+            "application/json",
+            Body::from(padded_movie("2", 1025)),
+        )
+        .await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["code"], "PAYLOAD_TOO_LARGE");
+        // Refused by its Content-Length before the handler runs, which
+        // would have answered 415.
+        let response = app
+            .clone()
+            .oneshot(
+                Request::post("/v1/movie")
+                    .header(header::CONTENT_TYPE, "text/plain")
+                    .header(header::CONTENT_LENGTH, 1025)
+                    .body(Body::from(vec![b'x'; 1025]))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(probe(&app, "/v1/movie/2").await.0, StatusCode::NOT_FOUND);
+
+        // Uploads may be larger, up to the limit of every request.
+        let mut csv = "id,name,year,was_good\n".to_string();
+        for id in 10..70 {
+            csv.push_str(&format!("{id},Heat,1995,true\n"));
+        }
+        assert!((1024..4096).contains(&csv.len()) && csv.len() * 4 > 4096);
+        let (status, body) = post_body(
+            &app,
+            "/v1/movie/import",
+            "text/csv",
+            Body::from(csv.clone()),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let (status, body) =
+            post_body(&app, "/movie/import", "text/csv", Body::from(csv.repeat(4))).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["code"], "PAYLOAD_TOO_LARGE");
+
+        // A body without a length is cut off once it passes the limit.
+        let chunks = std::iter::repeat_n(axum::body::Bytes::from(vec![b' '; 1024]), 5);
+        let body = Body::from_stream(futures_util::stream::iter(
+            chunks.map(Ok::<_, std::io::Error>),
+        ));
+        let (status, body) = post_body(&app, "/v1/movie", "application/json", body).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["code"], "PAYLOAD_TOO_LARGE");
+    }
+
+    #[tokio::test]
+    async fn flushed_writes_are_answered_once_synced() {
+        use durability::{Durability, X_DURABLE};
+
+        async fn post(app: &Router, uri: &str, id: &str) -> Response {
+            let movie = json!({"id": id, "name": "Heat", "year": 1995, "was_good": true});
+            app.clone()
+                .oneshot(
+                    Request::post(uri)
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .body(Body::from(movie.to_string()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap()
+        }
+        /// Posts the movie `id` to `uri` and checks the answer waits for the
+        /// sync exactly when `waits`.
+        async fn check(
+            app: &Router,
+            repo: &repo::UnsyncedRepository,
+            uri: &str,
+            id: &str,
+            waits: bool,
+        ) {
+            let request = tokio::spawn({
+                let (app, uri, id) = (app.clone(), uri.to_string(), id.to_string());
+                async move { post(&app, &uri, &id).await }
+            });
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            assert_eq!(request.is_finished(), !waits, "{uri}");
+            if waits {
+                repo.synced.notify_one();
+            }
+            let response = request.await.unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED, "{uri}");
+            let durable = if waits { "true" } else { "false" };
+            assert_eq!(response.headers()[X_DURABLE], durable, "{uri}");
+        }
+
+        for (default, waits) in [(Durability::Buffered, false), (Durability::Flush, true)] {
+            let repo = Arc::new(repo::UnsyncedRepository::default());
+            let config = Config {
+                write_durability: default,
+                ..Config::default()
+            };
+            let app = router(AppState::with_repository(config, repo.clone()));
+            check(&app, &repo, "/v1/movie", "1", waits).await;
+            check(&app, &repo, "/v1/movie?durability=flush", "2", true).await;
+            check(&app, &repo, "/v1/movie?durability=buffered", "3", false).await;
+
+            // Reads never wait.
+            let response = app
+                .clone()
+                .oneshot(Request::get("/v1/movie").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(!response.headers().contains_key(X_DURABLE));
+            let (status, body) = send_json(
+                &app,
+                "POST",
+                "/v1/movie?durability=eventually",
+                json!({"id": "9", "name": "Heat", "year": 1995, "was_good": true}),
+            )
+            .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert!(
+                body["message"].as_str().unwrap().contains("durability"),
+                "{body}"
+            );
+        }
+
+        // Only a store that keeps writes reports them durable.
+        let response = post(&app(), "/v1/movie", "1").await;
+        assert_eq!(response.headers()[X_DURABLE], "false");
+        let dir = tempfile::tempdir().unwrap();
+        let app = app_with_store(dir.path().join("movies.json")).await;
+        let response = post(&app, "/v1/movie", "1").await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()[X_DURABLE], "true");
+    }
+
+    #[tokio::test]
+    async fn writes_are_fenced_while_a_restore_runs() {
+        use std::sync::atomic::Ordering;
+
+        /// Starts a restore of `backup` on `app` and waits until it holds
+        /// the fence, stalled in its batch.
+        async fn start_restore(
+            app: &Router,
+            state: &AppState,
+            backup: &Value,
+        ) -> tokio::task::JoinHandle<StatusCode> {
+            let restore = {
+                let (app, backup) = (app.clone(), backup.clone());
+                tokio::spawn(
+                    async move { send_json(&app, "POST", "/admin/restore", backup).await.0 },
+                )
+            };
+            while state.bulk.try_read().is_ok() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            restore
+        }
+        let rename = |name: &str| json!({"id": "1", "name": name, "year": 1995, "was_good": true});
+
+        let repo = Arc::new(repo::StalledRepository::default());
+        let config = Config {
+            bulk_write_wait: Duration::ZERO,
+            ..Config::default()
+        };
+        let state = AppState::with_repository(config, repo.clone());
+        let app = router(state.clone());
+        add_movie(&app, "1", "Heat", 1995).await;
+        let (_, backup) = probe(&app, "/admin/backup").await;
+
+        let restore = start_restore(&app, &state, &backup).await;
+        let response = app
+            .clone()
+            .oneshot(
+                Request::put("/v1/movie/1")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(rename("Heat (1995)").to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let error: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["code"], "BULK_IN_PROGRESS");
+        assert_eq!(rate(&app, "1", 8).await.0, StatusCode::CONFLICT);
+        // Reads go on.
+        let (status, movie) = probe(&app, "/v1/movie/1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(movie["name"], "Heat");
+
+        repo.release.notify_one();
+        assert_eq!(restore.await.unwrap(), StatusCode::OK);
+        let (status, _) = send_json(&app, "PUT", "/v1/movie/1", rename("Heat (1995)")).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // A failed restore lets go of the fence too.
+        repo.fail.store(true, Ordering::SeqCst);
+        let restore = start_restore(&app, &state, &backup).await;
+        repo.release.notify_one();
+        assert_eq!(restore.await.unwrap(), StatusCode::INTERNAL_SERVER_ERROR);
+        let (status, _) = send_json(&app, "PUT", "/v1/movie/1", rename("Heat")).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Given time, a write waits its turn instead.
+        let repo = Arc::new(repo::StalledRepository::default());
+        let state = AppState::with_repository(Config::default(), repo.clone());
+        let app = router(state.clone());
+        add_movie(&app, "1", "Heat", 1995).await;
+        let (_, backup) = probe(&app, "/admin/backup").await;
+        let restore = start_restore(&app, &state, &backup).await;
+        let write = {
+            let app = app.clone();
+            tokio::spawn(async move {
+                send_json(&app, "PUT", "/v1/movie/1", rename("Heat (1995)")).await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!write.is_finished());
+        repo.release.notify_one();
+        assert_eq!(restore.await.unwrap(), StatusCode::OK);
+        let (status, movie) = write.await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(movie["name"], "Heat (1995)");
+    }
+
+    #[tokio::test]
+    async fn transactions_start_over_when_a_movie_changes_under_them() {
+        let app = app_with_repository(Arc::new(repo::RacedRepository::default()));
+        add_movie(&app, "1", "Heat", 1995).await;
+
+        let (status, results) = send_json(
+            &app,
+            "POST",
+            "/movie/transaction",
+            json!([
+                {"op": "update", "id": "1", "movie": {"id": "1", "name": "Heat", "year": 1996, "was_good": true}},
+                {"op": "create", "movie": {"id": "2", "name": "Ronin", "year": 1998, "was_good": true}},
+            ]),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{results}");
+        // Planned again on top of the concurrent write, not over it.
+        assert_eq!(results[0]["movie"]["version"], 3);
+        let (_, heat) = probe(&app, "/movie/1").await;
+        assert_eq!(
+            (heat["year"].clone(), heat["version"].clone()),
+            (json!(1996), json!(3))
+        );
+        assert_eq!(probe(&app, "/movie/2").await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn the_limits_report_counts_what_each_limit_turned_away() {
+        let repo = repo::SlowRepository {
+            inner: InMemoryRepository::new(),
+            delay: Duration::from_millis(300),
+        };
+        let config = Config {
+            body_limit: 64,
+            request_timeout: Duration::from_millis(100),
+            rate_limit_requests: 2,
+            rate_limit_window: Duration::from_secs(600),
+            slow_request: Duration::from_millis(50),
+            ..Config::default()
+        };
+        let app = router(AppState::with_repository(config, Arc::new(repo)));
+        let from = |peer: &str| {
+            app.clone().oneshot(
+                Request::get("/admin/limits")
+                    .extension(ConnectInfo(peer.parse::<SocketAddr>().unwrap()))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+
+        let (status, _) = post_body(
+            &app,
+            "/v1/movie",
+            "application/json",
+            Body::from(padded_movie("1", 100)),
+        )
+        .await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            probe(&app, "/v1/movie/1").await.0,
+            StatusCode::GATEWAY_TIMEOUT
+        );
+        for expected in [
+            StatusCode::OK,
+            StatusCode::OK,
+            StatusCode::TOO_MANY_REQUESTS,
+        ] {
+            assert_eq!(from("10.0.0.1:4000").await.unwrap().status(), expected);
+        }
+
+        let (status, report) = probe(&app, "/admin/limits").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["body_size_bytes"]["requests"], 64);
+        assert_eq!(
+            report["rate_limit"],
+            json!({"requests": 2, "window_secs": 600})
+        );
+        assert_eq!(report["slow_request_ms"], 50);
+        assert_eq!(
+            report["rejected"],
+            json!({"body_size": 1, "timeout": 1, "rate_limit": 1})
+        );
+        // Only the request that timed out took that long.
+        assert_eq!(report["slow_requests"], 1);
+
+        let (status, body) = send_json(
+            &app,
+            "PATCH",
+            "/admin/limits",
+            json!({"rate_limit_window_secs": 0, "slow_request_ms": 0}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(failing_fields(&body), ["rate_limit_window_secs"]);
+
+        let (status, report) = send_json(
+            &app,
+            "PATCH",
+            "/admin/limits",
+            json!({"rate_limit_requests": 3, "slow_request_ms": 0}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            report["rate_limit"],
+            json!({"requests": 3, "window_secs": 600})
+        );
+        assert_eq!(report["slow_request_ms"], 0);
+
+        // The client turned away before has the new allowance, and a slow
+        // request is no longer counted.
+        for expected in [StatusCode::OK, StatusCode::OK, StatusCode::OK] {
+            assert_eq!(from("10.0.0.1:4000").await.unwrap().status(), expected);
+        }
+        assert_eq!(
+            from("10.0.0.1:4000").await.unwrap().status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            probe(&app, "/v1/movie/1").await.0,
+            StatusCode::GATEWAY_TIMEOUT
+        );
+        let (_, report) = probe(&app, "/admin/limits").await;
+        assert_eq!(report["slow_requests"], 1);
+        assert_eq!(report["rejected"]["rate_limit"], 2);
+        assert_eq!(report["rejected"]["timeout"], 2);
+    }
+
+    #[tokio::test]
+    async fn slow_requests_time_out() {
+        let repo = repo::SlowRepository {
+            inner: InMemoryRepository::new(),
+            delay: Duration::from_secs(60),
+        };
+        let config = Config {
+            request_timeout: Duration::from_millis(50),
+            ..Config::default()
+        };
+        let app = router(AppState::with_repository(config, Arc::new(repo)));
+
+        let started = Instant::now();
+        let (status, body) = probe(&app, "/v1/movie/1").await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(body["code"], "TIMED_OUT");
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    async fn add_person(app: &Router, id: &str, name: &str) {
+        let (status, _) =
+            send_json(app, "POST", "/v1/person", json!({"id": id, "name": name})).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn movies_may_only_refer_to_stored_people() {
+        let app = app();
+        let (status, person) = send_json(
+            &app,
+            "POST",
+            "/v1/person",
+            json!({"id": "mann", "name": " Michael  Mann "}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(person["name"], "Michael Mann");
+        let (status, body) = send_json(
+            &app,
+            "POST",
+            "/v1/person",
+            json!({"id": "mann", "name": "Someone Else"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["conflict_type"], "duplicate_id");
+
+        let heat = json!({
+            "id": "1", "name": "Heat", "year": 1995, "was_good": true,
+            "director_id": "ghost", "cast": ["mann", "pacino", "de-niro"],
+        });
+        let (status, body) = send_json(&app, "POST", "/movie", heat.clone()).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(failing_fields(&body), ["director_id", "cast[1]", "cast[2]"]);
+        assert_eq!(body["errors"][1]["message"], "no person with id pacino");
+
+        add_person(&app, "pacino", "Al Pacino").await;
+        add_person(&app, "de-niro", "Robert De Niro").await;
+        let heat = json!({
+            "id": "1", "name": "Heat", "year": 1995, "was_good": true,
+            "director_id": "mann", "cast": ["pacino", "de-niro"],
+        });
+        let (status, movie) = send_json(&app, "POST", "/movie", heat.clone()).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(movie["director_id"], "mann");
+
+        let (status, body) =
+            send_json(&app, "PATCH", "/movie/1", json!({"cast": ["kilmer"]})).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(failing_fields(&body), ["cast[0]"]);
+        let mut update = heat.clone();
+        update["director_id"] = json!("kilmer");
+        let (status, body) = send_json(&app, "PUT", "/movie/1", update).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(failing_fields(&body), ["director_id"]);
+        let (status, body) = send_json(
+            &app,
+            "POST",
+            "/movie/batch",
+            json!([{"id": "2", "name": "Ronin", "year": 1998, "was_good": true, "cast": ["kilmer"]}]),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body[0]["status"], "invalid");
+
+        add_person(&app, "kilmer", "Val Kilmer").await;
+        let (status, movie) =
+            send_json(&app, "PATCH", "/movie/1", json!({"cast": ["kilmer"]})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(movie["cast"], json!(["kilmer"]));
+        assert_eq!(movie["director_id"], "mann");
+
+        // Leaving the director out keeps them, while null removes them.
+        let (status, movie) = send_json(&app, "PATCH", "/movie/1", json!({"year": 1996})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(movie["director_id"], "mann");
+        let (status, body) =
+            send_json(&app, "PATCH", "/movie/1", json!({"director_id": "ghost"})).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(failing_fields(&body), ["director_id"]);
+        let (status, movie) =
+            send_json(&app, "PATCH", "/movie/1", json!({"director_id": null})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(movie.get("director_id").is_none());
+        assert_eq!(movie["cast"], json!(["kilmer"]));
+    }
+
+    #[tokio::test]
+    async fn movies_embed_the_people_they_refer_to_on_request() {
+        let repo = Arc::new(InMemoryRepository::new());
+        let app = app_with_repository(repo.clone());
+        add_person(&app, "mann", "Michael Mann").await;
+        add_person(&app, "pacino", "Al Pacino").await;
+        let heat = json!({
+            "id": "1", "name": "Heat", "year": 1995, "was_good": true,
+            "director_id": "mann", "cast": ["pacino"],
+        });
+        let (status, _) = send_json(&app, "POST", "/v1/movie", heat).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (status, movie) = probe(&app, "/v1/movie/1?expand=director,cast").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            movie["expanded"],
+            json!({
+                "director": {"id": "mann", "name": "Michael Mann"},
+                "cast": [{"id": "pacino", "name": "Al Pacino"}],
+            })
+        );
+        assert_eq!(movie["director_id"], "mann");
+        let (_, movie) = probe(&app, "/v1/movie/1?expand=cast").await;
+        assert_eq!(
+            movie["expanded"],
+            json!({"cast": [{"id": "pacino", "name": "Al Pacino"}]})
+        );
+        let (_, movie) = probe(&app, "/v1/movie/1").await;
+        assert!(movie.get("expanded").is_none());
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/v1/movie/1?expand=director,cast")
+                    .header(header::ACCEPT, "application/xml")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let xml = response.into_body().collect().await.unwrap().to_bytes();
+        let xml = String::from_utf8(xml.to_vec()).unwrap();
+        assert!(xml.contains("<name>Michael Mann</name>"), "{xml}");
+
+        for (expand, message) in [
+            ("director,related", "related"),
+            ("cast,cast", "more than once"),
+            ("", "unknown variant"),
+        ] {
+            let (status, body) = probe(&app, &format!("/v1/movie/1?expand={expand}")).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{expand}");
+            assert!(
+                body["message"].as_str().unwrap().contains(message),
+                "{body}"
+            );
+        }
+
+        // Written around the checks, as by an older version.
+        let mut ronin: Movie = serde_json::from_value(json!({
+            "id": "2", "name": "Ronin", "year": 1998, "was_good": true,
+            "cast": ["pacino", "de-niro"],
+        }))
+        .unwrap();
+        ronin.version = 1;
+        repo.insert(ronin).await.unwrap();
+        let (status, movie) = probe(&app, "/v1/movie/2?expand=director,cast").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            movie["expanded"],
+            json!({
+                "director": null,
+                "cast": [
+                    {"id": "pacino", "name": "Al Pacino"},
+                    {"id": "de-niro", "missing": true},
+                ],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn people_list_the_movies_they_are_in() {
+        let app = app();
+        add_person(&app, "mann", "Michael Mann").await;
+        add_person(&app, "pacino", "Al Pacino").await;
+        add_person(&app, "de-palma", "Brian De Palma").await;
+        for (id, name, year, director, cast) in [
+            ("3", "The Insider", 1999, "mann", vec!["pacino"]),
+            ("1", "Heat", 1995, "mann", vec!["pacino"]),
+            ("2", "Scarface", 1983, "de-palma", vec![]),
+            ("4", "Collateral", 2004, "mann", vec![]),
+        ] {
+            let (status, _) = send_json(
+                &app,
+                "POST",
+                "/movie",
+                json!({
+                    "id": id, "name": name, "year": year, "was_good": true,
+                    "director_id": director, "cast": cast,
+                }),
+            )
+            .await;
+            assert_eq!(status, StatusCode::CREATED);
+        }
+        let (status, _) = send_json(&app, "PATCH", "/movie/2", json!({"cast": ["pacino"]})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(delete(&app, "/movie/4").await, StatusCode::NO_CONTENT);
+
+        let (status, movies) = probe(&app, "/v1/person/pacino/movies").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids(&json!({"items": movies})), ["2", "1", "3"]);
+        let (_, movies) = probe(&app, "/v1/person/mann/movies").await;
+        assert_eq!(ids(&json!({"items": movies})), ["1", "3"]);
+
+        let (status, body) = probe(&app, "/v1/person/nobody/movies").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["resource"], "person");
+        let (_, people) = probe(&app, "/v1/person").await;
+        let names: Vec<&str> = people
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|person| person["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["de-palma", "mann", "pacino"]);
+    }
+
+    #[tokio::test]
+    async fn referenced_people_are_only_deleted_with_force() {
+        let app = app();
+        add_person(&app, "mann", "Michael Mann").await;
+        add_person(&app, "pacino", "Al Pacino").await;
+        for (id, director) in [("1", "mann"), ("2", "pacino")] {
+            let (status, _) = send_json(
+                &app,
+                "POST",
+                "/movie",
+                json!({
+                    "id": id, "name": format!("Movie {id}"), "year": 1995, "was_good": true,
+                    "director_id": director, "cast": ["pacino"],
+                }),
+            )
+            .await;
+            assert_eq!(status, StatusCode::CREATED);
+        }
+        // Movies in the trash refer to people as much as the others.
+        assert_eq!(delete(&app, "/movie/2").await, StatusCode::NO_CONTENT);
+
+        let (status, body) = send_json(&app, "DELETE", "/v1/person/pacino", Value::Null).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["conflict_type"], "referenced");
+        assert_eq!(body["existing"], json!(["1", "2"]));
+        let (status, _) = probe(&app, "/v1/person/pacino").await;
+        assert_eq!(status, StatusCode::OK);
+
+        assert_eq!(
+            delete(&app, "/v1/person/pacino?force=true").await,
+            StatusCode::NO_CONTENT
+        );
+        let (status, _) = probe(&app, "/v1/person/pacino").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (_, movie) = probe(&app, "/movie/1").await;
+        assert_eq!(movie["cast"], json!([]));
+        assert_eq!(movie["director_id"], "mann");
+        assert_eq!(movie["version"], 2);
+        let (_, trash) = probe(&app, "/movie/trash").await;
+        assert_eq!(trash[0]["cast"], json!([]));
+        assert!(trash[0].get("director_id").is_none());
+
+        assert_eq!(delete(&app, "/v1/person/mann").await, StatusCode::CONFLICT);
+        assert_eq!(
+            delete(&app, "/v1/person/pacino").await,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn person_deletes_and_movie_writes_wait_for_each_other() {
+        let state = AppState::new(Config::default());
+        let app = router(state.clone());
+        add_person(&app, "pacino", "Al Pacino").await;
+
+        // As held by a create that found the person and is yet to store
+        // the movie.
+        let checking = state.people.checking().await;
+        let deleting = tokio::spawn({
+            let app = app.clone();
+            async move { send_json(&app, "DELETE", "/v1/person/pacino", Value::Null).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!deleting.is_finished());
+        let heat: Movie = serde_json::from_value(json!({
+            "id": "1", "name": "Heat", "year": 1995, "was_good": true, "cast": ["pacino"],
+        }))
+        .unwrap();
+        state.repo.insert(heat).await.unwrap();
+        drop(checking);
+
+        let (status, body) = deleting.await.unwrap();
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["existing"], json!(["1"]));
+
+        // As held by a delete that found no movie naming the person.
+        let changing = state.people.changing().await;
+        let creating = tokio::spawn({
+            let app = app.clone();
+            let ronin = json!({
+                "id": "2", "name": "Ronin", "year": 1998, "was_good": true, "cast": ["pacino"],
+            });
+            async move { send_json(&app, "POST", "/movie", ronin).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!creating.is_finished());
+        state.people.replace(std::collections::BTreeMap::new());
+        drop(changing);
+
+        let (status, body) = creating.await.unwrap();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(failing_fields(&body), ["cast[0]"]);
+    }
+
+    #[tokio::test]
+    async fn people_outlive_a_restart_with_the_movies_naming_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            db_path: Some(dir.path().join("movies.json")),
+            ..Config::default()
+        };
+        let heat = json!({
+            "id": "1", "name": "Heat", "year": 1995, "was_good": true,
+            "director_id": "mann", "cast": ["pacino"],
+        });
+
+        let app = router(AppState::open(config.clone()).await.unwrap());
+        add_person(&app, "mann", "Michael Mann").await;
+        add_person(&app, "pacino", "Al Pacino").await;
+        add_person(&app, "kilmer", "Val Kilmer").await;
+        let (status, _) = send_json(&app, "POST", "/movie", heat.clone()).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) = send_json(
+            &app,
+            "PUT",
+            "/v1/person/pacino",
+            json!({"name": "Alfredo Pacino"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            delete(&app, "/v1/person/kilmer").await,
+            StatusCode::NO_CONTENT
+        );
+
+        drop(app);
+
+        // Once from the log alone, then from the snapshot the shutdown saves.
+        for _ in 0..2 {
+            let state = AppState::open(config.clone()).await.unwrap();
+            let app = router(state.clone());
+            let (_, people) = probe(&app, "/v1/person").await;
+            assert_eq!(
+                people,
+                json!([
+                    {"id": "mann", "name": "Michael Mann"},
+                    {"id": "pacino", "name": "Alfredo Pacino"},
+                ])
+            );
+            // The unchanged cast still names stored people.
+            let (status, body) = send_json(&app, "PUT", "/movie/1", heat.clone()).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            state.shutdown().await.unwrap();
+        }
+    }
+
+    async fn add_movie(app: &Router, id: &str, name: &str, year: u16) {
+        let (status, _) = send_json(
+            app,
+            "POST",
+            "/movie",
+            json!({"id": id, "name": name, "year": year, "was_good": true}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn cursors_walk_a_changing_catalogue() {
+        let app = app();
+        for n in 0..25 {
+            add_movie(
+                &app,
+                &format!("m{n:02}"),
+                &format!("Movie {n}"),
+                1990 + n % 5,
+            )
+            .await;
+        }
+        let (_, everything) = list(&app, "sort=year&per_page=100").await;
+        let mut expected: Vec<String> = ids(&everything).iter().map(|id| id.to_string()).collect();
+
+        let mut walked: Vec<String> = Vec::new();
+        let mut query = "sort=year&per_page=10".to_string();
+        loop {
+            let (status, page) = list(&app, &query).await;
+            assert_eq!(status, StatusCode::OK, "{page}");
+            assert_eq!(page["page"], 1);
+            walked.extend(ids(&page).iter().map(|id| id.to_string()));
+            let Some(cursor) = page["next_cursor"].as_str() else {
+                break;
+            };
+            if walked.len() == 10 {
+                // One lands on the pages already read, which would shift
+                // every later offset by one, the other on those to come.
+                add_movie(&app, "a0", "Early", 1990).await;
+                add_movie(&app, "z9", "Late", 2010).await;
+            }
+            query = format!("sort=year&per_page=10&cursor={cursor}");
+        }
+
+        expected.push("z9".to_string());
+        assert_eq!(walked, expected);
+
+        // The last page has no cursor, and a page that ends right before the
+        // end still has one.
+        let (_, page) = list(&app, "per_page=26").await;
+        assert!(page["next_cursor"].is_string());
+        let (_, page) = list(&app, "per_page=27").await;
+        assert!(page.get("next_cursor").is_none());
+
+        let cursor = page_cursor(&app, "sort=year&per_page=5").await;
+        for query in [
+            "cursor=not-a-cursor".to_string(),
+            format!("cursor={}x", &cursor[..cursor.len() - 1]),
+            format!("sort=name&per_page=5&cursor={cursor}"),
+            format!("sort=year&per_page=5&page=2&cursor={cursor}"),
+            format!("stream=true&cursor={cursor}"),
+        ] {
+            let (status, body) = list(&app, &query).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "query {query}: {body}");
+        }
+        // Well-formed but made up, with a year that is not one.
+        use base64::Engine;
+        let forged = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(r#"{"sort":"year;asc","after":["1999"],"id":"m00"}"#);
+        let (status, _) = list(&app, &format!("sort=year&cursor={forged}")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    async fn page_cursor(app: &Router, query: &str) -> String {
+        let (_, page) = list(app, query).await;
+        page["next_cursor"].as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn movies_sort_on_several_fields() {
+        let app = app();
+        for (id, name, year) in [
+            ("a", "Heat", 1995),
+            ("b", "Casino", 1995),
+            ("c", "Ronin", 1998),
+            ("d", "Thief", 1981),
+            ("e", "Apollo 13", 1995),
+            ("f", "Heat", 1995),
+        ] {
+            add_movie(&app, id, name, year).await;
+        }
+
+        let (status, page) = list(&app, "sort=-year,name").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids(&page), ["c", "e", "b", "a", "f", "d"]);
+        let (_, page) = list(&app, "sort=-year,-name").await;
+        assert_eq!(ids(&page), ["c", "a", "f", "b", "e", "d"]);
+        // `order` turns the fields without a `-` and the ties around.
+        let (_, page) = list(&app, "sort=-year,name&order=desc").await;
+        assert_eq!(ids(&page), ["c", "f", "a", "b", "e", "d"]);
+
+        // Cursors keep to the same order, ties included.
+        let mut walked = Vec::new();
+        let mut query = "sort=-year,name&per_page=2".to_string();
+        loop {
+            let (_, page) = list(&app, &query).await;
+            walked.extend(ids(&page).iter().map(|id| id.to_string()));
+            let Some(cursor) = page["next_cursor"].as_str() else {
+                break;
+            };
+            query = format!("sort=-year,name&per_page=2&cursor={cursor}");
+        }
+        assert_eq!(walked, ["c", "e", "b", "a", "f", "d"]);
+
+        for query in ["sort=year,rating", "sort=year,,name", "sort=year,-year"] {
+            let (status, _) = list(&app, query).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "query {query}");
+        }
+    }
+
+    #[tokio::test]
+    async fn names_sort_naturally_unless_binary_is_asked_for() {
+        let app = app();
+        for (id, name) in [
+            ("a", "Zodiac"),
+            ("b", "Élite"),
+            ("c", "Movie 10"),
+            ("d", "Movie 2"),
+            ("e", "movie 1"),
+            ("f", "alien"),
+            ("g", "Eden"),
+        ] {
+            add_movie(&app, id, name, 2000).await;
+        }
+
+        // Accents and casing are ignored and sequels go by number.
+        let (status, page) = list(&app, "sort=name").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids(&page), ["f", "g", "b", "e", "d", "c", "a"]);
+        let (_, page) = list(&app, "sort=-name&collation=natural").await;
+        assert_eq!(ids(&page), ["a", "c", "d", "e", "b", "g", "f"]);
+
+        let (_, page) = list(&app, "sort=name&collation=binary").await;
+        assert_eq!(ids(&page), ["g", "c", "d", "a", "f", "e", "b"]);
+
+        // A cursor belongs to its collation.
+        let (_, page) = list(&app, "sort=name&per_page=3").await;
+        let cursor = page["next_cursor"].as_str().unwrap();
+        let (_, page) = list(&app, &format!("sort=name&per_page=3&cursor={cursor}")).await;
+        assert_eq!(ids(&page), ["e", "d", "c"]);
+        let query = format!("sort=name&collation=binary&cursor={cursor}");
+        assert_eq!(list(&app, &query).await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            list(&app, "sort=name&collation=icu").await.0,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    /// The `Link` header of `GET uri`, asked for behind a proxy sending
+    /// `forwarded_prefix` if given.
+    async fn link(app: &Router, uri: &str, forwarded_prefix: Option<&str>) -> Option<String> {
+        let mut request = Request::builder().uri(uri);
+        if let Some(prefix) = forwarded_prefix {
+            request = request.header("x-forwarded-prefix", prefix);
+        }
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        response
+            .headers()
+            .get(header::LINK)
+            .map(|link| link.to_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn pages_link_to_their_neighbours() {
+        let app = app();
+        for year in 1991..=1997 {
+            add_movie(&app, &year.to_string(), "Heat", year).await;
+        }
+
+        let query = "/v1/movie?year_from=1990&sort=-year,name&per_page=2";
+        let (first, prev, next, last) = (
+            format!(r#"<{query}&page=1>; rel="first""#),
+            format!(r#"<{query}&page=1>; rel="prev""#),
+            format!(r#"<{query}&page=3>; rel="next""#),
+            format!(r#"<{query}&page=4>; rel="last""#),
+        );
+        assert_eq!(
+            link(&app, &format!("{query}&page=2"), None).await.unwrap(),
+            [first.as_str(), &prev, &next, &last].join(", ")
+        );
+        // The page moves to the end, wherever it was given.
+        assert_eq!(
+            link(
+                &app,
+                "/v1/movie?page=2&year_from=1990&sort=-year,name&per_page=2",
+                None
+            )
+            .await
+            .unwrap(),
+            [first.as_str(), &prev, &next, &last].join(", ")
+        );
+        let next = format!(r#"<{query}&page=2>; rel="next""#);
+        assert_eq!(
+            link(&app, query, None).await.unwrap(),
+            [first.as_str(), &next, &last].join(", ")
+        );
+        let prev = format!(r#"<{query}&page=3>; rel="prev""#);
+        assert_eq!(
+            link(&app, &format!("{query}&page=4"), None).await.unwrap(),
+            [first.as_str(), &prev, &last].join(", ")
+        );
+
+        // Only where paging was asked for, and not by cursor.
+        assert_eq!(link(&app, "/v1/movie?sort=name", None).await, None);
+        let (_, page) = list(&app, "per_page=2").await;
+        let cursor = page["next_cursor"].as_str().unwrap();
+        let uri = format!("/v1/movie?per_page=2&cursor={cursor}");
+        assert_eq!(link(&app, &uri, None).await, None);
+
+        // Behind a proxy links carry the path it serves the API under.
+        let app = app_with_config(Config {
+            base_path: "/api".to_string(),
+            ..Config::default()
+        });
+        let first = r#"</api/movie?page=1>; rel="first""#;
+        let link_to_first =
+            |link: Option<String>| link.unwrap().split(", ").next().map(String::from);
+        assert_eq!(
+            link_to_first(link(&app, "/movie?page=1", Some("/edge")).await).as_deref(),
+            Some(first)
+        );
+        let app = app_with_config(Config {
+            base_path: "/api".to_string(),
+            trust_forwarded_for: true,
+            ..Config::default()
+        });
+        assert_eq!(
+            link_to_first(link(&app, "/movie?page=1", Some("/edge/")).await).as_deref(),
+            Some(r#"</edge/movie?page=1>; rel="first""#)
+        );
+    }
+
+    #[tokio::test]
+    async fn duplicates_are_grouped_by_normalized_name_and_year() {
+        let app = app();
+        for (id, name, year) in [
+            ("1", "The Matrix", 1999),
+            ("2", "Matrix, The", 1999),
+            ("3", "the  MATRIX!", 1999),
+            ("4", "The Matrix", 2003),
+            ("5", "Heat", 1995),
+            ("6", "Heat", 1995),
+            ("7", "Heat", 1995),
+        ] {
+            add_movie(&app, id, name, year).await;
+        }
+        assert_eq!(delete(&app, "/movie/7").await, StatusCode::NO_CONTENT);
+
+        let (status, groups) = probe(&app, "/v1/movie/duplicates").await;
+        assert_eq!(status, StatusCode::OK);
+        let groups: Vec<(&str, u64, Vec<&str>)> = groups
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|group| {
+                let movies = group["movies"].as_array().unwrap();
+                (
+                    group["key"].as_str().unwrap(),
+                    group["year"].as_u64().unwrap(),
+                    movies.iter().map(|m| m["id"].as_str().unwrap()).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            groups,
+            [
+                ("heat", 1995, vec!["5", "6"]),
+                ("matrix the", 1999, vec!["1", "2", "3"]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn merging_folds_duplicates_into_the_kept_movie() {
+        let app = app();
+        for (id, name) in [
+            ("1", "The Matrix"),
+            ("2", "Matrix, The"),
+            ("3", "the matrix"),
+        ] {
+            add_movie(&app, id, name, 1999).await;
+        }
+        for (id, score) in [("1", 10), ("2", 6), ("3", 8), ("3", 4)] {
+            let (status, _) = rate(&app, id, score).await;
+            assert_eq!(status, StatusCode::CREATED);
+        }
+        let (_, watchlist) = send_json(&app, "POST", "/watchlist", json!({"name": "Next"})).await;
+        let watchlist_uri = format!("/watchlist/{}", watchlist["id"]);
+        for id in ["3", "1", "2"] {
+            send_json(
+                &app,
+                "PUT",
+                &format!("{watchlist_uri}/movie/{id}"),
+                Value::Null,
+            )
+            .await;
+        }
+        let (_, kept) = probe(&app, "/movie/1").await;
+
+        let (status, movie) = send_json(
+            &app,
+            "POST",
+            "/v1/movie/merge",
+            json!({"keep": "1", "remove": ["2", "3"]}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{movie}");
+        assert_eq!(movie["id"], "1");
+        assert_eq!(movie["rating_count"], 4);
+        assert_eq!(movie["average_rating"], 7.0);
+        assert_eq!(movie["version"], kept["version"].as_u64().unwrap() + 1);
+
+        let (_, ratings) = probe(&app, "/movie/1/rating").await;
+        let ratings = ratings.as_array().unwrap();
+        assert_eq!(ratings.len(), 4);
+        assert!(ratings.iter().all(|rating| rating["movie_id"] == "1"));
+        let (_, watchlist) = probe(&app, &watchlist_uri).await;
+        assert_eq!(ids(&json!({"items": watchlist["movies"]})), ["1"]);
+
+        let (_, page) = list(&app, "").await;
+        assert_eq!(ids(&page), ["1"]);
+        let (_, trash) = probe(&app, "/movie/trash").await;
+        assert_eq!(trash, json!([]));
+        for id in ["2", "3"] {
+            let (status, headers, _) =
+                get_as(&app, &format!("/movie/{id}"), "application/json").await;
+            assert_eq!(status, StatusCode::PERMANENT_REDIRECT);
+            assert_eq!(headers[header::LOCATION], "/movie/1");
+        }
+        let (_, groups) = probe(&app, "/v1/movie/duplicates").await;
+        assert_eq!(groups, json!([]));
+    }
+
+    #[tokio::test]
+    async fn a_three_way_merge_moves_every_rating_over_at_once() {
+        let state = AppState::new(Config::default());
+        let app = router(state.clone());
+        for id in ["1", "2", "3"] {
+            add_movie(&app, id, "Heat", 1995).await;
+        }
+        let mut rated = Vec::new();
+        for (id, score) in [("2", 6), ("1", 10), ("3", 8), ("2", 4)] {
+            let (status, rating) = rate(&app, id, score).await;
+            assert_eq!(status, StatusCode::CREATED);
+            rated.push(rating["id"].clone());
+        }
+
+        // As held by a read of a movie with its ratings.
+        let reading = state.rekeying.read().await;
+        let merging = tokio::spawn({
+            let app = app.clone();
+            async move {
+                send_json(
+                    &app,
+                    "POST",
+                    "/v1/movie/merge",
+                    json!({"keep": "1", "remove": ["2", "3"]}),
+                )
+                .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!merging.is_finished());
+        assert!(state.repo.get("2").await.unwrap().is_some());
+        assert_eq!(state.ratings.list("2").len(), 2);
+        drop(reading);
+
+        let (status, movie) = merging.await.unwrap();
+        assert_eq!(status, StatusCode::OK, "{movie}");
+        assert_eq!(movie["rating_count"], 4);
+        assert_eq!(movie["average_rating"], 7.0);
+
+        // Every rating keeps its id and place, oldest first, under the kept
+        // movie.
+        let (_, ratings) = probe(&app, "/movie/1/rating").await;
+        let ratings = ratings.as_array().unwrap();
+        let ids: Vec<&Value> = ratings.iter().map(|rating| &rating["id"]).collect();
+        assert_eq!(ids, rated.iter().collect::<Vec<_>>());
+        let scores: Vec<&Value> = ratings.iter().map(|rating| &rating["score"]).collect();
+        assert_eq!(scores, [6, 10, 8, 4]);
+        assert!(ratings.iter().all(|rating| rating["movie_id"] == "1"));
+        for id in ["2", "3"] {
+            assert!(state.repo.get(id).await.unwrap().is_none());
+            assert_eq!(
+                probe(&app, &format!("/movie/{id}/rating")).await.0,
+                StatusCode::NOT_FOUND
+            );
+            let (status, headers, _) =
+                get_as(&app, &format!("/movie/{id}"), "application/json").await;
+            assert_eq!(status, StatusCode::PERMANENT_REDIRECT);
+            assert_eq!(headers[header::LOCATION], "/movie/1");
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_merges_change_nothing() {
+        let app = app();
+        for id in ["1", "2", "3"] {
+            add_movie(&app, id, "Heat", 1995).await;
+        }
+        rate(&app, "2", 9).await;
+        let (_, before) = list(&app, "").await;
+
+        for (request, fields) in [
+            (
+                json!({"keep": "1", "remove": ["2", "9"]}),
+                vec!["remove[1]"],
+            ),
+            (json!({"keep": "9", "remove": ["2"]}), vec!["keep"]),
+            (
+                json!({"keep": "1", "remove": ["2", "1"]}),
+                vec!["remove[1]"],
+            ),
+            (
+                json!({"keep": "1", "remove": ["3", "3"]}),
+                vec!["remove[1]"],
+            ),
+            (json!({"keep": "1", "remove": []}), vec!["remove"]),
+        ] {
+            let (status, body) = send_json(&app, "POST", "/v1/movie/merge", request.clone()).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{request}");
+            assert_eq!(failing_fields(&body), fields, "{request}");
+        }
+
+        let (_, after) = list(&app, "").await;
+        assert_eq!(after, before);
+        let (_, ratings) = probe(&app, "/movie/2/rating").await;
+        assert_eq!(ratings.as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn timestamps_are_managed_by_the_server() {
+        let app = app();
+        let timestamp = |movie: &Value, field: &str| {
+            chrono::DateTime::parse_from_rfc3339(movie[field].as_str().unwrap()).unwrap()
+        };
+        let forged = "1999-01-01T00:00:00Z";
+
+        let (status, created) = send_json(
+            &app,
+            "POST",
+            "/movie",
+            json!({
+                "id": "1", "name": "Heat", "year": 1995, "was_good": true,
+                "created_at": forged, "updated_at": forged,
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(created["created_at"], created["updated_at"]);
+        assert_ne!(created["created_at"], forged);
+
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        let (status, updated) = send_json(
+            &app,
+            "PUT",
+            "/movie/1",
+            json!({
+                "id": "1", "name": "Heat", "year": 1995, "was_good": false,
+                "created_at": forged, "updated_at": forged,
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(updated["created_at"], created["created_at"]);
+        assert!(timestamp(&updated, "updated_at") > timestamp(&created, "updated_at"));
+
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        let (_, patched) = send_json(&app, "PATCH", "/movie/1", json!({"year": 1996})).await;
+        assert_eq!(patched["created_at"], created["created_at"]);
+        assert!(timestamp(&patched, "updated_at") > timestamp(&updated, "updated_at"));
+    }
+
+    #[tokio::test]
+    async fn list_movies_sorts_by_creation() {
+        let app = app();
+        for id in ["2", "3", "1"] {
+            let (status, _) = send_json(
+                &app,
+                "POST",
+                "/movie",
+                json!({"id": id, "name": format!("Movie {id}"), "year": 2000, "was_good": true}),
+            )
+            .await;
+            assert_eq!(status, StatusCode::CREATED);
+        }
+
+        assert_eq!(ids(&list(&app, "sort=created_at").await.1), ["2", "3", "1"]);
+        assert_eq!(
+            ids(&list(&app, "sort=created_at&order=desc").await.1),
+            ["1", "3", "2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn deleted_movies_wait_in_the_trash() {
+        let app = app();
+        let heat = r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#;
+        seed(
+            &app,
+            &[
+                heat,
+                r#"{"id":"2","name":"Ronin","year":1998,"was_good":true}"#,
+            ],
+        )
+        .await;
+        rate(&app, "1", 9).await;
+
+        assert_eq!(delete(&app, "/movie/1").await, StatusCode::NO_CONTENT);
+        assert_eq!(ids(&list(&app, "").await.1), ["2"]);
+        assert_eq!(probe(&app, "/movie/1").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(delete(&app, "/movie/1").await, StatusCode::NOT_FOUND);
+        let export = app
+            .clone()
+            .oneshot(Request::get("/movie/export").body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes();
+        assert_eq!(export, "id,name,year,was_good\n2,Ronin,1998,true\n");
+
+        let (status, trash) = probe(&app, "/movie/trash").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(trash[0]["id"], "1");
+        assert!(trash[0]["deleted_at"].is_string());
+
+        // The id stays taken while the movie can still be restored.
+        let (status, body) =
+            send_json(&app, "POST", "/movie", serde_json::from_str(heat).unwrap()).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["conflict_type"], "trashed");
+
+        let (status, movie) = send_json(&app, "POST", "/movie/1/restore", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(movie["version"], 4);
+        assert!(movie.get("deleted_at").is_none());
+        assert_eq!(ids(&list(&app, "").await.1), ["1", "2"]);
+        assert_eq!(probe(&app, "/movie/1").await.1["rating_count"], 1);
+        assert_eq!(probe(&app, "/movie/trash").await.1, json!([]));
+
+        let (status, body) = send_json(&app, "POST", "/movie/1/restore", Value::Null).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["conflict_type"], "not_trashed");
+        let (status, _) = send_json(&app, "POST", "/movie/9/restore", Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Transactions move movies to the trash too.
+        let (status, _) = send_json(
+            &app,
+            "POST",
+            "/movie/transaction",
+            json!([{"op": "delete", "id": "2"}]),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(probe(&app, "/movie/trash").await.1[0]["id"], "2");
+    }
+
+    #[tokio::test]
+    async fn permanent_deletes_empty_the_trash() {
+        let app = app();
+        let heat = r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#;
+        seed(&app, &[heat]).await;
+
+        assert_eq!(delete(&app, "/movie/1").await, StatusCode::NO_CONTENT);
+        assert_eq!(
+            delete(&app, "/movie/1?permanent=true").await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(probe(&app, "/movie/trash").await.1, json!([]));
+        let (status, _) = send_json(&app, "POST", "/movie/1/restore", Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // The id is free again.
+        let (status, _) =
+            send_json(&app, "POST", "/movie", serde_json::from_str(heat).unwrap()).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn poisoned_store_keeps_serving() {
+        let state = AppState::new(Config::default());
+        let app = router(state.clone());
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#],
+        )
+        .await;
+
+        let slugs = state.slugs.clone();
+        std::thread::spawn(move || {
+            let _guard = slugs.write().unwrap();
+            panic!("handler bug while holding the slugs");
+        })
+        .join()
+        .unwrap_err();
+        assert!(state.slugs.is_poisoned());
+
+        let (status, movie) = get_by_slug(&app, "heat-1995").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(movie["name"], "Heat");
+
+        seed(
+            &app,
+            &[r#"{"id":"2","name":"Ronin","year":1998,"was_good":true}"#],
+        )
+        .await;
+        assert!(!state.slugs.is_poisoned());
+    }
+
+    #[tokio::test]
+    async fn file_store_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movies.json");
+
+        let app = app_with_store(path.clone()).await;
+        assert_eq!(
+            serde_json::from_slice::<Value>(&std::fs::read(&path).unwrap()).unwrap(),
+            json!({"schema_version": 1, "movies": [], "people": []})
+        );
+        seed(
+            &app,
+            &[
+                r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#,
+                r#"{"id":"2","name":"Ronin","year":1998,"was_good":true}"#,
+            ],
+        )
+        .await;
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/movie/2")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
 
-    Json(movies)
-}
+        let app = app_with_store(path).await;
+        let (status, page) = list(&app, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids(&page), ["1"]);
 
-async fn get_movie(Path(id): Path<String>, State(state): State<AppState>) -> impl IntoResponse {
-    match state.data.read().expect("lock was poisoned").get(&id) {
-        Some(movie) => (StatusCode::OK, Json(json!(movie))),
-        None => (StatusCode::NOT_FOUND, Json(json!("movie not found"))),
+        let (status, movie) = get_by_slug(&app, "heat-1995").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(movie["id"], "1");
     }
-}
 
-async fn update_movie(
-    Path(id): Path<String>,
-    State(state): State<AppState>,
-    EJson(payload): EJson<Movie>,
-) -> impl IntoResponse {
-    let mut s = state.data.write().expect("lock was poisoned");
+    #[tokio::test]
+    async fn shutdown_flushes_the_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movies.json");
+        let state = AppState::open(Config {
+            db_path: Some(path.clone()),
+            ..Config::default()
+        })
+        .await
+        .unwrap();
 
-    if !s.contains_key(&id) {
-        return (StatusCode::NOT_FOUND, Json(json!("movie not found")));
+        // As if the last save after a change had failed.
+        std::fs::remove_file(&path).unwrap();
+        state.shutdown().await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Value>(&std::fs::read(&path).unwrap()).unwrap(),
+            json!({"schema_version": 1, "movies": [], "people": []})
+        );
     }
 
-    let movie = Movie { id, ..payload };
-    s.insert(movie.id.clone(), movie.clone());
-
-    (StatusCode::OK, Json(json!(movie)))
-}
+    #[tokio::test]
+    async fn bare_array_snapshots_are_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movies.json");
+        std::fs::write(&path, include_str!("../tests/fixtures/legacy-movies.json")).unwrap();
+        let config = Config {
+            db_path: Some(path.clone()),
+            ..Config::default()
+        };
+        let state = AppState::open(config).await.unwrap();
+        let app = router(state.clone());
 
-async fn delete_movie(Path(id): Path<String>, State(state): State<AppState>) -> impl IntoResponse {
-    let mut s = state.data.write().expect("lock was poisoned");
+        let (status, page) = list(&app, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids(&page), ["1", "2", "3"]);
+        let (_, movie) = probe(&app, "/v1/movie/2").await;
+        assert_eq!(
+            (movie["version"].clone(), movie["slug"].clone()),
+            (json!(1), json!("ronin-1998"))
+        );
+        let (status, movie) = get_by_slug(&app, "heat-1995-2").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(movie["id"], "3");
 
-    match s.remove(&id) {
-        Some(_) => StatusCode::NO_CONTENT,
-        None => StatusCode::NOT_FOUND,
+        state.shutdown().await.unwrap();
+        let saved: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(saved["schema_version"], 1);
+        assert_eq!(saved["people"], json!([]));
+        assert_eq!(saved["movies"][0]["slug"], "heat-1995");
+        assert_eq!(saved["movies"].as_array().unwrap().len(), 3);
     }
-}
 
-async fn create_movie(
-    State(state): State<AppState>,
-    EJson(payload): EJson<Movie>,
-) -> impl IntoResponse {
-    let mut s = state.data.write().expect("lock was poisoned");
+    #[tokio::test]
+    async fn snapshots_repeating_an_id_fail_startup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movies.json");
+        let movie = |id: &str| json!({"id": id, "name": "Heat", "year": 1995, "was_good": true});
+        let snapshot = json!([movie("2"), movie("1"), movie("2"), movie("1"), movie("3")]);
+        std::fs::write(&path, snapshot.to_string()).unwrap();
 
-    s.insert(payload.id.clone(), payload.clone());
+        let config = Config {
+            db_path: Some(path.clone()),
+            ..Config::default()
+        };
+        let error = AppState::open(config).await.err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(
+            error.to_string().ends_with("repeats movie ids: 1, 2"),
+            "{error}"
+        );
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            snapshot.to_string()
+        );
+    }
 
-    (StatusCode::CREATED, Json(payload))
-}
+    #[tokio::test]
+    async fn corrupt_snapshot_fails_startup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movies.json");
+        std::fs::write(&path, "[{\"id\":").unwrap();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use axum::{
-        body::Body,
-        http::{Request, header},
-    };
-    use http_body_util::BodyExt;
-    use tower::ServiceExt;
+        let config = Config {
+            db_path: Some(path.clone()),
+            ..Config::default()
+        };
+        let error = AppState::open(config).await.err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("corrupt snapshot"), "{error}");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "[{\"id\":");
+    }
 
     #[tokio::test]
-    async fn create_movie_returns_created() {
-        let response = app()
-            .oneshot(
-                Request::builder()
-                    .method("POST")
-                    .uri("/movie")
-                    .header(header::CONTENT_TYPE, "application/json")
-                    .body(Body::from(
-                        r#"{"id":"1","name":"Test Movie","year":2024,"was_good":true}"#,
-                    ))
-                    .unwrap(),
-            )
+    async fn serve_reports_ephemeral_port() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let config = Config {
+            bind_address: std::net::Ipv4Addr::LOCALHOST.into(),
+            port: 0,
+            ..Config::default()
+        };
+        let (addr, server) = serve(config, std::future::pending()).await.unwrap();
+        assert_ne!(addr.port(), 0);
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /movie HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
             .await
             .unwrap();
 
-        assert_eq!(response.status(), StatusCode::CREATED);
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.contains(r#""items":[]"#), "{response}");
 
-        let body = response.into_body().collect().await.unwrap().to_bytes();
-        let movie: Movie = serde_json::from_slice(&body).unwrap();
-        assert_eq!(movie.id, "1");
-        assert_eq!(movie.name, "Test Movie");
-        assert_eq!(movie.year, 2024);
-        assert!(movie.was_good);
+        server.abort();
     }
 
     #[tokio::test]
-    async fn get_movie_not_found() {
-        let response = app()
-            .oneshot(
-                Request::builder()
-                    .method("GET")
-                    .uri("/movie/999")
-                    .body(Body::empty())
-                    .unwrap(),
-            )
-            .await
-            .unwrap();
+    async fn conflicts_embed_existing_resource() {
+        let app = app();
+        seed(
+            &app,
+            &[
+                r#"{"id":"1","name":"Dune","year":1984,"was_good":false}"#,
+                r#"{"id":"2","name":"Dune","year":2021,"was_good":true}"#,
+            ],
+        )
+        .await;
 
-        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let (status, body) = change_id(&app, "1", "2").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["conflict_type"], "duplicate_id");
+        assert_eq!(body["existing"]["id"], "2");
+        assert_eq!(body["existing"]["year"], 2021);
+        assert_eq!(body["existing"]["was_good"], true);
 
-        let body = response.into_body().collect().await.unwrap().to_bytes();
-        let body: String = serde_json::from_slice(&body).unwrap();
-        assert_eq!(body, "movie not found");
+        let (status, body) = get_by_name(&app, "dune").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["conflict_type"], "ambiguous_name");
+        assert_eq!(body["candidates"].as_array().unwrap().len(), 2);
     }
 
     #[tokio::test]
-    async fn create_and_get_movie() {
-        let app = app();
+    async fn conflicts_can_embed_minimal_projection() {
+        let app = app_with_config(Config {
+            conflict_detail: ConflictDetail::Minimal,
+            ..Config::default()
+        });
+        seed(
+            &app,
+            &[
+                r#"{"id":"1","name":"Dune","year":1984,"was_good":false}"#,
+                r#"{"id":"2","name":"Dune","year":2021,"was_good":true,"custom":{"private":"note"}}"#,
+            ],
+        )
+        .await;
 
-        // Create a movie
-        let create_response = app
-            .clone()
-            .oneshot(
-                Request::builder()
-                    .method("POST")
-                    .uri("/movie")
-                    .header(header::CONTENT_TYPE, "application/json")
-                    .body(Body::from(
-                        r#"{"id":"1","name":"The Matrix","year":1999,"was_good":true}"#,
-                    ))
-                    .unwrap(),
-            )
-            .await
-            .unwrap();
+        let (status, body) = change_id(&app, "1", "2").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(
+            body["existing"],
+            json!({"id": "2", "name": "Dune", "year": 2021})
+        );
+    }
 
-        assert_eq!(create_response.status(), StatusCode::CREATED);
+    #[tokio::test]
+    async fn existence_checks_skip_serialization() {
+        let repo = Arc::new(repo::CountingRepository::default());
+        let app = app_with_repository(repo.clone());
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#],
+        )
+        .await;
+        change_id(&app, "1", "2").await;
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#],
+        )
+        .await;
+        change_id(&app, "1", "3").await;
 
-        // Get the movie
-        let get_response = app
-            .oneshot(
-                Request::builder()
-                    .method("GET")
-                    .uri("/movie/1")
-                    .body(Body::empty())
-                    .unwrap(),
-            )
-            .await
-            .unwrap();
+        let request = |method: &str, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+        };
 
-        assert_eq!(get_response.status(), StatusCode::OK);
+        // "2" exists, "1" redirects to "3" and "999" is missing.
+        for id in ["2", "1", "999"] {
+            let before = repo.reads();
+            let full = app
+                .clone()
+                .oneshot(request("GET", &format!("/movie/{id}")))
+                .await
+                .unwrap();
+            let reads = repo.reads();
+            assert_eq!(reads > before, id == "2", "{id}");
 
-        let body = get_response.into_body().collect().await.unwrap().to_bytes();
-        let movie: Movie = serde_json::from_slice(&body).unwrap();
-        assert_eq!(movie.id, "1");
-        assert_eq!(movie.name, "The Matrix");
-        assert_eq!(movie.year, 1999);
-        assert!(movie.was_good);
+            for fast in [
+                request("HEAD", &format!("/movie/{id}")),
+                request("GET", &format!("/movie/{id}?existence_only=true")),
+            ] {
+                let response = app.clone().oneshot(fast).await.unwrap();
+                assert_eq!(response.status(), full.status(), "{id}");
+                assert_eq!(
+                    response.headers().get(header::CONTENT_TYPE),
+                    full.headers().get(header::CONTENT_TYPE),
+                    "{id}"
+                );
+                assert_eq!(
+                    response.headers().get(header::LOCATION),
+                    full.headers().get(header::LOCATION),
+                    "{id}"
+                );
+
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                assert!(body.is_empty());
+            }
+
+            assert_eq!(repo.reads(), reads, "{id}");
+        }
     }
 
     #[tokio::test]
-    async fn list_movies_empty() {
-        let response = app()
+    async fn popular_movies_ranks_by_reads() {
+        let app = app();
+        seed(
+            &app,
+            &[
+                r#"{"id":"1","name":"The Matrix","year":1999,"was_good":true}"#,
+                r#"{"id":"2","name":"Heat","year":1995,"was_good":true}"#,
+                r#"{"id":"3","name":"Dune","year":2021,"was_good":true}"#,
+            ],
+        )
+        .await;
+
+        for (id, reads) in [("1", 1), ("2", 3), ("3", 2)] {
+            for _ in 0..reads {
+                let response = app
+                    .clone()
+                    .oneshot(
+                        Request::builder()
+                            .uri(format!("/movie/{id}"))
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+            }
+        }
+
+        let response = app
             .oneshot(
                 Request::builder()
-                    .method("GET")
-                    .uri("/movie")
+                    .uri("/movie/popular?limit=2")
                     .body(Body::empty())
                     .unwrap(),
             )
             .await
             .unwrap();
-
         assert_eq!(response.status(), StatusCode::OK);
 
         let body = response.into_body().collect().await.unwrap().to_bytes();
-        let movies: Vec<Movie> = serde_json::from_slice(&body).unwrap();
-        assert!(movies.is_empty());
+        let popular: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            popular,
+            json!([
+                {"id": "2", "name": "Heat", "year": 1995, "count": 3},
+                {"id": "3", "name": "Dune", "year": 2021, "count": 2},
+            ])
+        );
+    }
+
+    fn ndjson_body(frames: Vec<Result<&'static str, std::io::Error>>) -> Body {
+        Body::from_stream(futures_util::stream::iter(frames))
     }
 
     #[tokio::test]
-    async fn list_movies_with_data() {
-        let app = app();
+    async fn streaming_import_reports_progress_per_chunk() {
+        let state = AppState::new(Config {
+            import_chunk_size: 2,
+            ..Config::default()
+        });
+        let app = router(state.clone());
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Old Name","year":1999,"was_good":false}"#],
+        )
+        .await;
 
-        // Create a movie first
-        app.clone()
-            .oneshot(
-                Request::builder()
-                    .method("POST")
-                    .uri("/movie")
-                    .header(header::CONTENT_TYPE, "application/json")
-                    .body(Body::from(
-                        r#"{"id":"1","name":"Test Movie","year":2024,"was_good":true}"#,
-                    ))
-                    .unwrap(),
-            )
-            .await
-            .unwrap();
+        // Frames deliberately split lines to exercise the line buffering.
+        let body = ndjson_body(vec![
+            Ok("{\"id\":\"1\",\"name\":\"The Matrix\",\"year\":1999,"),
+            Ok(
+                "\"was_good\":true}\n{\"id\":\"2\",\"name\":\"Heat\",\"year\":1995,\"was_good\":true}\n",
+            ),
+            Ok("not json\n\n{\"id\":\"3\",\"name\":\"Dune\",\"year\":2021,\"was_good\":true}"),
+        ]);
 
-        // List movies
         let response = app
             .oneshot(
                 Request::builder()
-                    .method("GET")
-                    .uri("/movie")
-                    .body(Body::empty())
+                    .method("POST")
+                    .uri("/movie/import/stream")
+                    .header(header::CONTENT_TYPE, "application/x-ndjson")
+                    .header(header::ACCEPT, "text/event-stream")
+                    .body(body)
                     .unwrap(),
             )
             .await
             .unwrap();
-
         assert_eq!(response.status(), StatusCode::OK);
 
         let body = response.into_body().collect().await.unwrap().to_bytes();
-        let movies: Vec<Movie> = serde_json::from_slice(&body).unwrap();
-        assert_eq!(movies.len(), 1);
+        let events: Vec<(String, Value)> = String::from_utf8(body.to_vec())
+            .unwrap()
+            .split("\n\n")
+            .filter(|event| !event.is_empty())
+            .map(|event| {
+                let field = |name: &str| {
+                    event
+                        .lines()
+                        .find_map(|line| line.strip_prefix(name))
+                        .unwrap()
+                        .to_string()
+                };
+                (
+                    field("event: "),
+                    serde_json::from_str(&field("data: ")).unwrap(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            events,
+            [
+                (
+                    "progress".to_string(),
+                    json!({"lines": 2, "created": 1, "updated": 1, "skipped": 0, "failed": 0})
+                ),
+                (
+                    "summary".to_string(),
+                    json!({
+                        "lines": 5,
+                        "created": 2,
+                        "updated": 1,
+                        "skipped": 0,
+                        "failed": 1,
+                        "errors": [{
+                            "field": "line 3",
+                            "message": "expected ident at line 1 column 2",
+                        }],
+                    })
+                ),
+            ]
+        );
+
+        assert_eq!(state.repo.list().await.unwrap().len(), 3);
+        assert_eq!(
+            state.repo.get("1").await.unwrap().unwrap().name,
+            "The Matrix"
+        );
+        assert_eq!(
+            state.repo.get("3").await.unwrap().unwrap().slug,
+            "dune-2021"
+        );
     }
 
     #[tokio::test]
-    async fn update_movie_success() {
-        let app = app();
+    async fn interrupted_import_keeps_completed_chunks() {
+        let state = AppState::new(Config {
+            import_chunk_size: 2,
+            ..Config::default()
+        });
 
-        // Create a movie
-        app.clone()
+        let body = ndjson_body(vec![
+            Ok(concat!(
+                r#"{"id":"1","name":"The Matrix","year":1999,"was_good":true}"#,
+                "\n",
+                r#"{"id":"2","name":"Heat","year":1995,"was_good":true}"#,
+                "\n",
+                r#"{"id":"3","name":"Dune","year":2021,"was_good":true}"#,
+                "\n",
+            )),
+            Err(std::io::Error::from(std::io::ErrorKind::ConnectionReset)),
+        ]);
+
+        let response = router(state.clone())
             .oneshot(
                 Request::builder()
                     .method("POST")
-                    .uri("/movie")
-                    .header(header::CONTENT_TYPE, "application/json")
-                    .body(Body::from(
-                        r#"{"id":"1","name":"Old Name","year":2020,"was_good":false}"#,
-                    ))
+                    .uri("/movie/import/stream")
+                    .header(header::CONTENT_TYPE, "application/x-ndjson")
+                    .body(body)
                     .unwrap(),
             )
             .await
             .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
 
-        // Update the movie
-        let response = app
+        let mut ids: Vec<String> = state
+            .repo
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|m| m.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, ["1", "2"]);
+    }
+
+    async fn preflight(app: &Router, origin: &str) -> axum::response::Response {
+        app.clone()
             .oneshot(
                 Request::builder()
-                    .method("PUT")
+                    .method("OPTIONS")
                     .uri("/movie/1")
-                    .header(header::CONTENT_TYPE, "application/json")
-                    .body(Body::from(
-                        r#"{"id":"1","name":"New Name","year":2024,"was_good":true}"#,
-                    ))
+                    .header(header::ORIGIN, origin)
+                    .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PATCH")
+                    .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type")
+                    .body(Body::empty())
                     .unwrap(),
             )
             .await
-            .unwrap();
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn cors_allows_only_the_configured_origins() {
+        let app = app_with_config(Config {
+            cors: CorsPolicy {
+                allowed_origins: Some(CorsPolicy::parse_origins(
+                    "https://app.example.com/, https://admin.example.com",
+                )),
+                max_age: Duration::from_secs(600),
+            },
+            ..Config::default()
+        });
 
+        let response = preflight(&app, "https://app.example.com").await;
         assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        let methods = headers[header::ACCESS_CONTROL_ALLOW_METHODS]
+            .to_str()
+            .unwrap();
+        assert!(
+            methods.contains("PATCH") && methods.contains("DELETE"),
+            "{methods}"
+        );
+        let allowed = headers[header::ACCESS_CONTROL_ALLOW_HEADERS]
+            .to_str()
+            .unwrap();
+        assert!(allowed.contains("content-type") && allowed.contains("authorization"));
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
 
-        let body = response.into_body().collect().await.unwrap().to_bytes();
-        let movie: Movie = serde_json::from_slice(&body).unwrap();
-        assert_eq!(movie.name, "New Name");
-        assert_eq!(movie.year, 2024);
-        assert!(movie.was_good);
-    }
+        let response = preflight(&app, "https://evil.example.com").await;
+        assert!(
+            !response
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
 
-    #[tokio::test]
-    async fn update_movie_not_found() {
-        let response = app()
+        // Actual requests are answered either way; only the header differs.
+        let response = app
+            .clone()
             .oneshot(
-                Request::builder()
-                    .method("PUT")
-                    .uri("/movie/999")
-                    .header(header::CONTENT_TYPE, "application/json")
-                    .body(Body::from(
-                        r#"{"id":"999","name":"Test","year":2024,"was_good":true}"#,
-                    ))
+                Request::get("/movie")
+                    .header(header::ORIGIN, "https://admin.example.com")
+                    .body(Body::empty())
                     .unwrap(),
             )
             .await
             .unwrap();
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://admin.example.com"
+        );
+        let exposed = response.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS]
+            .to_str()
+            .unwrap();
+        assert!(exposed.contains("etag"), "{exposed}");
+    }
 
-        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    #[tokio::test]
+    async fn cors_allows_every_origin_by_default() {
+        let response = preflight(&app(), "http://localhost:5173").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
     }
 
     #[tokio::test]
-    async fn delete_movie_success() {
-        let app = app();
+    async fn responses_carry_cache_policy_per_route_class() {
+        let app = app_with_config(Config {
+            cache: CachePolicies {
+                list: "public, max-age=30".to_string(),
+                ..CachePolicies::default()
+            },
+            ..Config::default()
+        });
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#],
+        )
+        .await;
 
-        // Create a movie
-        app.clone()
-            .oneshot(
-                Request::builder()
-                    .method("POST")
-                    .uri("/movie")
-                    .header(header::CONTENT_TYPE, "application/json")
-                    .body(Body::from(
-                        r#"{"id":"1","name":"Test","year":2024,"was_good":true}"#,
-                    ))
-                    .unwrap(),
-            )
+        for (method, uri, policy) in [
+            (
+                "GET",
+                "/movie/1",
+                Some("private, max-age=0, must-revalidate"),
+            ),
+            (
+                "GET",
+                "/movie/999",
+                Some("private, max-age=0, must-revalidate"),
+            ),
+            ("GET", "/movie", Some("public, max-age=30")),
+            ("GET", "/movie/popular", Some("public, max-age=30")),
+            ("DELETE", "/movie/1", None),
+            ("GET", openapi::DOCUMENT_PATH, Some("public, max-age=300")),
+            ("GET", "/swagger-ui/", Some("public, max-age=300")),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method(method)
+                        .uri(uri)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(
+                response
+                    .headers()
+                    .get(header::CACHE_CONTROL)
+                    .map(|v| v.to_str().unwrap()),
+                policy,
+                "{method} {uri}"
+            );
+        }
+    }
+
+    #[cfg(feature = "chaos")]
+    #[tokio::test]
+    async fn chaos_mode_fails_movie_routes_only() {
+        let app = app_with_config(Config {
+            chaos: true,
+            ..Config::default()
+        });
+        seed(
+            &app,
+            &[r#"{"id":"1","name":"Heat","year":1995,"was_good":true}"#],
+        )
+        .await;
+
+        let configure = |body: &'static str| {
+            Request::builder()
+                .method("POST")
+                .uri("/admin/chaos")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(configure(r#"{"error_rate":1.5,"error_status":200}"#))
             .await
             .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
 
-        // Delete the movie
         let response = app
-            .oneshot(
+            .clone()
+            .oneshot(configure(r#"{"error_rate":1.0,"error_status":503}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let movie_routes = || {
+            [
+                Request::builder()
+                    .uri("/movie")
+                    .body(Body::empty())
+                    .unwrap(),
+                Request::builder()
+                    .uri("/movie/1")
+                    .body(Body::empty())
+                    .unwrap(),
                 Request::builder()
                     .method("DELETE")
                     .uri("/movie/1")
                     .body(Body::empty())
                     .unwrap(),
+            ]
+        };
+
+        for request in movie_routes() {
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        }
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/admin/chaos")
+                    .body(Body::empty())
+                    .unwrap(),
             )
             .await
             .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
 
-        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        app.clone().oneshot(configure("{}")).await.unwrap();
+
+        let statuses: Vec<StatusCode> = {
+            let mut statuses = Vec::new();
+            for request in movie_routes() {
+                statuses.push(app.clone().oneshot(request).await.unwrap().status());
+            }
+            statuses
+        };
+        assert_eq!(
+            statuses,
+            [StatusCode::OK, StatusCode::OK, StatusCode::NO_CONTENT]
+        );
     }
 
+    #[cfg(feature = "chaos")]
     #[tokio::test]
-    async fn delete_movie_not_found() {
+    async fn chaos_routes_are_absent_unless_enabled() {
         let response = app()
             .oneshot(
                 Request::builder()
-                    .method("DELETE")
-                    .uri("/movie/999")
+                    .uri("/admin/chaos")
                     .body(Body::empty())
                     .unwrap(),
             )
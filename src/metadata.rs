@@ -0,0 +1,482 @@
+//! `POST /movie/import/external`: creates a movie from a title looked up in
+//! an external metadata service instead of typed-out fields. Providers sit
+//! behind `MetadataProvider`; the server talks to OMDb when built with the
+//! `metadata` feature and given `OMDB_API_KEY`, and answers 503 otherwise.
+//! Providers call out through the shared `outbound` client.
+
+#[cfg(feature = "metadata")]
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::{extract::State, http::StatusCode, response::IntoResponse, response::Json};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use crate::errors::{ApiError, ErrorBody};
+use crate::extract::JsonBody;
+use crate::{AppState, Movie, store_new_movie, validate_name, validate_year};
+#[cfg(feature = "metadata")]
+use crate::{Config, outbound::Outbound};
+
+/// How long a rate-limited client waits when the provider does not say.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// A search hit, enough to pick between several.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    /// The provider's own id, e.g. `tt0083658`.
+    pub id: String,
+    pub title: String,
+    pub year: u16,
+}
+
+/// Everything the provider knows that a movie can hold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Details {
+    pub id: String,
+    pub title: String,
+    pub year: u16,
+    pub genres: Vec<String>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(not(feature = "metadata"), allow(dead_code))]
+pub enum MetadataError {
+    /// Nothing matched the search.
+    NotFound,
+    /// The provider refuses requests for now.
+    RateLimited { retry_after: Option<Duration> },
+    /// The provider could not be reached or gave an unusable answer.
+    Upstream(String),
+}
+
+#[async_trait]
+pub trait MetadataProvider: Send + Sync {
+    /// Movies titled like `title`, from `year` only when given.
+    async fn search(&self, title: &str, year: Option<u16>)
+    -> Result<Vec<Candidate>, MetadataError>;
+
+    /// The details of the candidate with `id`.
+    async fn details(&self, id: &str) -> Result<Details, MetadataError>;
+}
+
+/// The provider the config asks for, calling out through `http`, `None`
+/// when it names none.
+#[cfg(feature = "metadata")]
+pub fn provider(config: &Config, http: Arc<dyn Outbound>) -> Option<Arc<dyn MetadataProvider>> {
+    let api_key = config.omdb_api_key.as_ref()?;
+    Some(Arc::new(omdb::Omdb::new(
+        http,
+        config.omdb_base_url.clone(),
+        api_key.clone(),
+    )))
+}
+
+/// The candidate meant by `title` and `year`: with a year only candidates
+/// from that year qualify, and an exact title (ignoring case) beats the
+/// provider's ranking.
+fn pick<'a>(candidates: &'a [Candidate], title: &str, year: Option<u16>) -> Option<&'a Candidate> {
+    let mut qualifying = candidates
+        .iter()
+        .filter(|candidate| year.is_none_or(|year| candidate.year == year));
+    let first = qualifying.clone().next();
+
+    qualifying
+        .find(|candidate| candidate.title.eq_ignore_ascii_case(title.trim()))
+        .or(first)
+}
+
+impl MetadataError {
+    /// The answer to a failed lookup of `searched`. Upstream details are
+    /// logged rather than sent, like storage failures.
+    fn for_lookup(self, searched: &str) -> ApiError {
+        match self {
+            MetadataError::NotFound => ApiError::not_found("title", searched),
+            MetadataError::RateLimited { retry_after } => ApiError::RateLimited {
+                retry_after: retry_after.unwrap_or(DEFAULT_RETRY_AFTER),
+            },
+            MetadataError::Upstream(message) => {
+                tracing::warn!(%message, "metadata provider failed");
+                ApiError::BadGateway("the metadata provider failed".to_string())
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct ExternalImport {
+    pub title: String,
+    /// Narrows the search to one year, which also settles between remakes.
+    pub year: Option<u16>,
+    /// Defaults to the provider's id, e.g. `tt0083658`.
+    pub id: Option<String>,
+    #[serde(default)]
+    pub was_good: bool,
+}
+
+/// Searches the provider for the title, picks the match, and creates it
+/// like `POST /movie` would with the provider's title, year and genres.
+#[utoipa::path(
+    post,
+    path = "/movie/import/external",
+    tag = "movies",
+    summary = "Create a movie from external metadata",
+    request_body = ExternalImport,
+    responses(
+        (status = CREATED, description = "The created movie", body = Movie),
+        (status = NOT_FOUND, description = "The provider knows no such title", body = ErrorBody),
+        (status = CONFLICT, description = "The id is taken, possibly by a movie in the trash", body = ErrorBody),
+        (status = UNPROCESSABLE_ENTITY, description = "Invalid title or year", body = ErrorBody),
+        (status = TOO_MANY_REQUESTS, description = "The provider is rate limiting the server", body = ErrorBody),
+        (status = BAD_GATEWAY, description = "The provider could not be reached", body = ErrorBody),
+        (status = SERVICE_UNAVAILABLE, description = "No provider is configured", body = ErrorBody),
+    )
+)]
+pub async fn import_external(
+    State(state): State<AppState>,
+    JsonBody(request): JsonBody<ExternalImport>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Some(provider) = &state.metadata else {
+        return Err(ApiError::NotConfigured(
+            if cfg!(feature = "metadata") {
+                "external imports need OMDB_API_KEY to be set"
+            } else {
+                "external imports need a build with the metadata feature"
+            }
+            .to_string(),
+        ));
+    };
+
+    let mut errors: Vec<_> = validate_name(&request.title, "title".to_string())
+        .into_iter()
+        .collect();
+    if let Some(year) = request.year {
+        errors.extend(validate_year(year, "year".to_string()));
+    }
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+
+    let searched = match request.year {
+        Some(year) => format!("{} ({year})", request.title),
+        None => request.title.clone(),
+    };
+    let failed = |error: MetadataError| error.for_lookup(&searched);
+
+    let candidates = provider
+        .search(&request.title, request.year)
+        .await
+        .map_err(failed)?;
+    let candidate = pick(&candidates, &request.title, request.year)
+        .ok_or_else(|| failed(MetadataError::NotFound))?;
+    let details = provider.details(&candidate.id).await.map_err(failed)?;
+
+    let _fence = state.fence().await?;
+    let movie = store_new_movie(
+        &state,
+        Movie {
+            id: request.id.unwrap_or(details.id),
+            name: details.title,
+            year: details.year,
+            was_good: request.was_good,
+            locked_fields: Vec::new(),
+            custom: Default::default(),
+            genres: details.genres,
+            sort_name: None,
+            director_id: None,
+            cast: Vec::new(),
+            slug: String::new(),
+            version: 0,
+            created_at: Default::default(),
+            updated_at: Default::default(),
+            deleted_at: None,
+            has_poster: false,
+        },
+    )
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(movie)))
+}
+
+#[cfg(feature = "metadata")]
+mod omdb {
+    //! The OMDb API (<https://www.omdbapi.com>): a search by title, then a
+    //! lookup of the picked IMDb id for its genres.
+
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use reqwest::{StatusCode, header};
+    use serde::Deserialize;
+    use serde::de::DeserializeOwned;
+
+    use super::{Candidate, Details, MetadataError, MetadataProvider};
+    use crate::outbound::Outbound;
+
+    pub struct Omdb {
+        http: Arc<dyn Outbound>,
+        base_url: String,
+        api_key: String,
+    }
+
+    /// OMDb answers errors with 200 or 401 and `"Response": "False"`.
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Envelope<T> {
+        response: String,
+        error: Option<String>,
+        #[serde(flatten)]
+        body: Option<T>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Search {
+        search: Vec<Hit>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Hit {
+        #[serde(rename = "imdbID")]
+        id: String,
+        title: String,
+        year: String,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Title {
+        #[serde(rename = "imdbID")]
+        id: String,
+        title: String,
+        year: String,
+        /// Comma-separated, `N/A` when unknown.
+        #[serde(default)]
+        genre: String,
+    }
+
+    /// `"1982"`, or the first year of a range like `"2005–2007"`.
+    fn first_year(value: &str) -> Option<u16> {
+        value.get(..4)?.parse().ok()
+    }
+
+    impl Omdb {
+        pub fn new(http: Arc<dyn Outbound>, base_url: String, api_key: String) -> Self {
+            Omdb {
+                http,
+                base_url,
+                api_key,
+            }
+        }
+
+        async fn get<T: DeserializeOwned>(
+            &self,
+            query: &[(&str, &str)],
+        ) -> Result<T, MetadataError> {
+            let mut query = query.to_vec();
+            query.insert(0, ("apikey", self.api_key.as_str()));
+            let response = self
+                .http
+                .get(&self.base_url, &query)
+                .await
+                .map_err(|error| MetadataError::Upstream(error.to_string()))?;
+
+            if response.status == StatusCode::TOO_MANY_REQUESTS {
+                let retry_after = response
+                    .headers
+                    .get(header::RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok()?.parse().ok())
+                    .map(Duration::from_secs);
+                return Err(MetadataError::RateLimited { retry_after });
+            }
+
+            let envelope: Envelope<T> = serde_json::from_slice(&response.body)
+                .map_err(|error| MetadataError::Upstream(error.to_string()))?;
+            match (envelope.response.as_str(), envelope.error, envelope.body) {
+                ("True", _, Some(body)) => Ok(body),
+                (_, Some(error), _) if error.contains("not found") => Err(MetadataError::NotFound),
+                (_, Some(error), _) if error.contains("limit reached") => {
+                    Err(MetadataError::RateLimited { retry_after: None })
+                }
+                (_, error, _) => Err(MetadataError::Upstream(
+                    error.unwrap_or_else(|| "unexpected response".to_string()),
+                )),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl MetadataProvider for Omdb {
+        async fn search(
+            &self,
+            title: &str,
+            year: Option<u16>,
+        ) -> Result<Vec<Candidate>, MetadataError> {
+            let year = year.map(|year| year.to_string());
+            let mut query = vec![("s", title), ("type", "movie")];
+            if let Some(year) = &year {
+                query.push(("y", year));
+            }
+
+            let search: Search = self.get(&query).await?;
+            Ok(search
+                .search
+                .into_iter()
+                .filter_map(|hit| {
+                    Some(Candidate {
+                        year: first_year(&hit.year)?,
+                        id: hit.id,
+                        title: hit.title,
+                    })
+                })
+                .collect())
+        }
+
+        async fn details(&self, id: &str) -> Result<Details, MetadataError> {
+            let title: Title = self.get(&[("i", id)]).await?;
+            Ok(Details {
+                year: first_year(&title.year).ok_or_else(|| {
+                    MetadataError::Upstream(format!("unreadable year {:?}", title.year))
+                })?,
+                id: title.id,
+                title: title.title,
+                genres: title
+                    .genre
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|genre| !genre.is_empty() && *genre != "N/A")
+                    .map(str::to_string)
+                    .collect(),
+            })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use axum::{Router, extract::Query, http::HeaderMap, routing::get};
+        use serde_json::{Value, json};
+        use std::collections::HashMap;
+
+        use super::*;
+        use crate::config::Config;
+        use crate::outbound::HttpClient;
+
+        /// OMDb's answers for a handful of queries, served on a local port.
+        async fn fake_omdb() -> String {
+            async fn answer(
+                Query(query): Query<HashMap<String, String>>,
+            ) -> (StatusCode, HeaderMap, axum::Json<Value>) {
+                let mut headers = HeaderMap::new();
+                let (status, body) = match (query.get("s"), query.get("i")) {
+                    (Some(title), _) if title == "Blade Runner" => (
+                        StatusCode::OK,
+                        json!({"Response": "True", "totalResults": "2", "Search": [
+                            {"Title": "Blade Runner", "Year": "1982", "imdbID": "tt0083658", "Type": "movie"},
+                            {"Title": "Blade Runner 2049", "Year": "2017", "imdbID": "tt1856101", "Type": "movie"},
+                        ]}),
+                    ),
+                    (Some(title), _) if title == "Busy" => {
+                        headers.insert(header::RETRY_AFTER, "30".parse().unwrap());
+                        (StatusCode::TOO_MANY_REQUESTS, json!({}))
+                    }
+                    (Some(title), _) if title == "Spent" => (
+                        StatusCode::UNAUTHORIZED,
+                        json!({"Response": "False", "Error": "Request limit reached!"}),
+                    ),
+                    (Some(_), _) => (
+                        StatusCode::OK,
+                        json!({"Response": "False", "Error": "Movie not found!"}),
+                    ),
+                    (_, Some(id)) if id == "tt0083658" => (
+                        StatusCode::OK,
+                        json!({"Response": "True", "Title": "Blade Runner", "Year": "1982", "imdbID": "tt0083658", "Genre": "Action, Drama, Sci-Fi"}),
+                    ),
+                    _ => (
+                        StatusCode::UNAUTHORIZED,
+                        json!({"Response": "False", "Error": "Invalid API key!"}),
+                    ),
+                };
+                (status, headers, axum::Json(body))
+            }
+
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            tokio::spawn(async move {
+                axum::serve(listener, Router::new().route("/", get(answer)))
+                    .await
+                    .unwrap();
+            });
+            format!("http://{address}/")
+        }
+
+        #[tokio::test]
+        async fn omdb_answers_map_onto_candidates_and_errors() {
+            let http = Arc::new(HttpClient::new(&Config::default()));
+            let omdb = Omdb::new(http, fake_omdb().await, "key".to_string());
+
+            let candidates = omdb.search("Blade Runner", None).await.unwrap();
+            assert_eq!(
+                candidates.iter().map(|c| c.year).collect::<Vec<_>>(),
+                [1982, 2017]
+            );
+            assert_eq!(
+                omdb.details("tt0083658").await.unwrap(),
+                Details {
+                    id: "tt0083658".to_string(),
+                    title: "Blade Runner".to_string(),
+                    year: 1982,
+                    genres: vec!["Action".into(), "Drama".into(), "Sci-Fi".into()],
+                }
+            );
+
+            assert!(matches!(
+                omdb.search("Nothing Like It", None).await,
+                Err(MetadataError::NotFound)
+            ));
+            assert!(matches!(
+                omdb.search("Busy", None).await,
+                Err(MetadataError::RateLimited { retry_after: Some(after) }) if after.as_secs() == 30
+            ));
+            assert!(matches!(
+                omdb.search("Spent", None).await,
+                Err(MetadataError::RateLimited { retry_after: None })
+            ));
+            assert!(matches!(
+                omdb.details("tt0000000").await,
+                Err(MetadataError::Upstream(message)) if message == "Invalid API key!"
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(id: &str, title: &str, year: u16) -> Candidate {
+        Candidate {
+            id: id.to_string(),
+            title: title.to_string(),
+            year,
+        }
+    }
+
+    #[test]
+    fn pick_prefers_the_exact_year_then_the_exact_title() {
+        let candidates = [
+            candidate("1", "Dune: Part One", 2021),
+            candidate("2", "Dune", 2021),
+            candidate("3", "Dune", 1984),
+        ];
+        let picked = |title, year| pick(&candidates, title, year).map(|c| c.id.as_str());
+
+        assert_eq!(picked("Dune", Some(1984)), Some("3"));
+        assert_eq!(picked("dune ", Some(2021)), Some("2"));
+        assert_eq!(picked("Dune", None), Some("2"));
+        assert_eq!(picked("Dune Messiah", None), Some("1"));
+        assert_eq!(picked("Dune", Some(2000)), None);
+    }
+}
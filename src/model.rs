@@ -0,0 +1,214 @@
+//! The resources the API exchanges, shared by the server and the client so
+//! both always agree on the wire format.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Movie {
+    pub id: String,
+    pub name: String,
+    pub year: u16,
+    pub was_good: bool,
+    #[serde(default)]
+    pub locked_fields: Vec<String>,
+    #[serde(default)]
+    pub custom: HashMap<String, Value>,
+    /// Stored lowercased and without duplicates.
+    #[serde(default)]
+    pub genres: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort_name: Option<String>,
+    /// The id of a person under `/person`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub director_id: Option<String>,
+    /// Ids of people under `/person`, in billing order.
+    #[serde(default)]
+    pub cast: Vec<String>,
+    /// Derived from name and year; anything sent by the client is ignored.
+    #[serde(default)]
+    #[schema(read_only)]
+    pub slug: String,
+    /// Starts at 1 and grows with every change, sent as the `ETag`; anything
+    /// sent by the client is ignored.
+    #[serde(default)]
+    #[schema(read_only)]
+    pub version: u64,
+    /// Managed by the server like `version`; movies stored before timestamps
+    /// existed report the Unix epoch.
+    #[serde(default)]
+    #[schema(read_only)]
+    pub created_at: DateTime<Utc>,
+    /// When the movie's own fields last changed, managed like `created_at`.
+    #[serde(default)]
+    #[schema(read_only)]
+    pub updated_at: DateTime<Utc>,
+    /// Set while the movie is in the trash; managed by the server like
+    /// `version`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(read_only)]
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// Whether `GET /movie/{id}/poster` has an image to serve; set by
+    /// uploading one.
+    #[serde(default)]
+    #[schema(read_only)]
+    pub has_poster: bool,
+}
+
+/// Body of `PATCH /movie/{id}`: fields left as `None` keep their stored
+/// values, and a given `custom` replaces the whole map.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, ToSchema)]
+pub struct MoviePatch {
+    /// Always rejected, ids change through `change-id`. Kept so a patch
+    /// naming the id fails instead of being silently ignored.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub year: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub was_good: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom: Option<HashMap<String, Value>>,
+    /// Replaces all genres.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub genres: Option<Vec<String>>,
+    /// Replaces the director; `null` removes them.
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schema(value_type = Option<String>)]
+    pub director_id: Option<Option<String>>,
+    /// Replaces the whole cast.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cast: Option<Vec<String>>,
+}
+
+/// A field that is present, `null` included, so a patch tells a `null`
+/// that clears the field apart from leaving it out.
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// A score out of 10 given to a movie, listed under `/movie/{id}/rating`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Rating {
+    /// Assigned by the server, unique across all movies.
+    pub id: u64,
+    pub movie_id: String,
+    /// 1 to 10.
+    pub score: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Body of `POST /movie/{id}/rating`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct NewRating {
+    pub score: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+/// A named list of movies to watch, under `/watchlist`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Watchlist {
+    /// Assigned by the server.
+    pub id: u64,
+    pub name: String,
+    /// In the order they were added, each at most once.
+    pub movie_ids: Vec<String>,
+}
+
+/// Body of `POST /watchlist`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct NewWatchlist {
+    pub name: String,
+}
+
+/// A named `GET /movie` query kept for reuse, under `/search`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct SavedSearch {
+    /// Assigned by the server.
+    pub id: u64,
+    pub name: String,
+    /// The filters and ordering of `GET /movie` as a query string, e.g.
+    /// `genre=drama&year_from=1990&sort=-year`.
+    pub query: String,
+}
+
+/// Body of `POST /search`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct NewSavedSearch {
+    pub name: String,
+    pub query: String,
+}
+
+/// A genre of the taxonomy under `/genre`, which movies' `genres` name.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Genre {
+    /// Normalized like a movie's genres.
+    pub name: String,
+    /// The genre this one is under, or `None` for a top-level genre.
+    #[serde(default)]
+    pub parent: Option<String>,
+}
+
+/// Body of `PUT /genre/{name}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct GenreParent {
+    #[serde(default)]
+    pub parent: Option<String>,
+}
+
+/// A director or actor movies refer to by id, under `/person`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Person {
+    /// Chosen by the client on creation, like a movie's; ignored on updates.
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+}
+
+/// One page of `GET /movie`, with the totals a client needs to build a pager.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Matching items across all pages.
+    pub total: usize,
+    /// 1-based; stays 1 when paging by cursor.
+    pub page: usize,
+    pub per_page: usize,
+    /// Pass as `?cursor=` for the page after this one; absent on the last
+    /// page.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+/// One entry of a validation error's `errors` list.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        FieldError {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Content negotiation for the reads legacy consumers need in other formats:
+//! `GET /movie` answers JSON, XML or CSV and `GET /movie/{id}` JSON or XML.
+//! The `Accept` header is matched by q-value and then specificity, JSON is
+//! the answer when it is missing, and a request nothing offered satisfies
+//! gets 406. Errors are always JSON.
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue, header};
+use serde::Serialize;
+
+use crate::errors::ApiError;
+
+/// Sent with every negotiated response so caches keep one copy per format.
+pub const VARY_ACCEPT: (HeaderName, HeaderValue) =
+    (header::VARY, HeaderValue::from_static("accept"));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Xml,
+    Csv,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Xml => "application/xml",
+            Format::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    /// Every media type naming the format; the first is the canonical one.
+    fn media_types(self) -> &'static [&'static str] {
+        match self {
+            Format::Json => &["application/json"],
+            Format::Xml => &["application/xml", "text/xml"],
+            Format::Csv => &["text/csv"],
+        }
+    }
+
+    /// Wildcards only cover the canonical type, so `text/*` is CSV and not
+    /// XML by way of `text/xml`.
+    fn matches(self, range: &str) -> bool {
+        let types = self.media_types();
+        match range.strip_suffix("/*") {
+            Some("*") => true,
+            Some(kind) => types[0]
+                .split_once('/')
+                .is_some_and(|(t, _)| t.eq_ignore_ascii_case(kind)),
+            None => types.iter().any(|t| t.eq_ignore_ascii_case(range)),
+        }
+    }
+
+    /// The format among `offered` the client prefers; the first offered is
+    /// the default for a missing `Accept` or a wildcard. Types listed with
+    /// `q=0` are refused even where a wildcard would cover them.
+    pub fn negotiate(headers: &HeaderMap, offered: &[Format]) -> Result<Format, ApiError> {
+        let accept = headers
+            .get(header::ACCEPT)
+            .map(|value| value.to_str().unwrap_or_default())
+            .unwrap_or_default();
+        if accept.trim().is_empty() {
+            return Ok(offered[0]);
+        }
+
+        let mut ranges: Vec<(f32, &str)> = accept
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let media_range = parts.next()?.trim();
+                let quality = match parts.find_map(|p| p.trim().strip_prefix("q=")) {
+                    Some(q) => q.trim().parse().ok()?,
+                    None => 1.0,
+                };
+                (!media_range.is_empty()).then_some((quality, media_range))
+            })
+            .collect();
+        // Stable, so equally ranked ranges keep the client's order.
+        ranges
+            .sort_by(|(qa, a), (qb, b)| qb.total_cmp(qa).then(specificity(b).cmp(&specificity(a))));
+
+        let refused: Vec<Format> = offered
+            .iter()
+            .copied()
+            .filter(|format| {
+                ranges
+                    .iter()
+                    .any(|(q, range)| *q <= 0.0 && !range.ends_with("/*") && format.matches(range))
+            })
+            .collect();
+        ranges
+            .iter()
+            .filter(|(q, _)| *q > 0.0)
+            .find_map(|(_, range)| {
+                offered
+                    .iter()
+                    .copied()
+                    .find(|format| !refused.contains(format) && format.matches(range))
+            })
+            .ok_or_else(|| ApiError::NotAcceptable {
+                supported: offered.iter().map(|f| f.media_types()[0]).collect(),
+            })
+    }
+}
+
+/// `*/*` ranks below `text/*`, which ranks below `text/csv`.
+fn specificity(range: &str) -> u8 {
+    match range.strip_suffix("/*") {
+        Some("*") => 0,
+        Some(_) => 1,
+        None => 2,
+    }
+}
+
+/// Serializes `value` as an XML document with `root` as its element. Fields
+/// keep their JSON names and order, and lists repeat their element once per
+/// item.
+pub fn xml(root: &str, value: &impl Serialize) -> String {
+    let body =
+        quick_xml::se::to_string_with_root(root, value).expect("movies always serialize to XML");
+    format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{body}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: &[Format] = &[Format::Json, Format::Xml, Format::Csv];
+
+    fn negotiate(accept: &str, offered: &[Format]) -> Option<Format> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_str(accept).unwrap());
+        Format::negotiate(&headers, offered).ok()
+    }
+
+    #[test]
+    fn picks_the_preferred_offered_format() {
+        let cases = [
+            ("", ALL, Some(Format::Json)),
+            ("*/*", ALL, Some(Format::Json)),
+            ("application/xml", ALL, Some(Format::Xml)),
+            ("TEXT/XML", ALL, Some(Format::Xml)),
+            (
+                "text/csv;q=0.5, application/xml;q=0.8",
+                ALL,
+                Some(Format::Xml),
+            ),
+            ("text/*", ALL, Some(Format::Csv)),
+            ("*/*, text/csv", ALL, Some(Format::Csv)),
+            ("application/json;q=0, */*", ALL, Some(Format::Xml)),
+            ("text/csv", &[Format::Json, Format::Xml], None),
+            ("application/yaml", ALL, None),
+            ("application/xml;q=nope", ALL, None),
+        ];
+
+        for (accept, offered, format) in cases {
+            assert_eq!(negotiate(accept, offered), format, "{accept}");
+        }
+    }
+}
@@ -0,0 +1,36 @@
+//! The OpenAPI document at `/api-docs/openapi.json` and Swagger UI at
+//! `/swagger-ui`. Routes are mounted from their `#[utoipa::path]`
+//! annotations (see `router`), so the document lists exactly the paths and
+//! methods the server answers.
+
+use axum::Router;
+use utoipa::OpenApi;
+use utoipa::openapi::OpenApi as Document;
+use utoipa_swagger_ui::SwaggerUi;
+
+pub const DOCUMENT_PATH: &str = "/api-docs/openapi.json";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Movies API",
+        description = "A catalogue of movies with ratings and watchlists."
+    ),
+    tags(
+        (name = "movies", description = "The movie catalogue"),
+        (name = "ratings", description = "Scores given to movies"),
+        (name = "watchlists", description = "Named lists of movies to watch"),
+        (name = "people", description = "Directors and actors movies refer to"),
+        (name = "admin", description = "Backing up and restoring the store"),
+        (name = "health", description = "Probes for orchestrators"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Serves `document` and Swagger UI pointed at it. Swagger UI's assets are
+/// built into the binary, so the page works offline and under a strict CSP.
+pub fn routes(document: Document) -> Router {
+    SwaggerUi::new("/swagger-ui")
+        .url(DOCUMENT_PATH, document)
+        .into()
+}
@@ -0,0 +1,370 @@
+//! Outbound HTTP, shared by every integration that calls another service so
+//! they all follow one policy, configured once from the `OUTBOUND_*`
+//! settings: connect and read timeouts, a user agent, a cap on requests in
+//! flight to each host, and a bounded number of retries with jittered
+//! exponential backoff for idempotent requests that fail or meet a 502, 503
+//! or 504. Integrations hold an `Arc<dyn Outbound>`, so tests can stand in
+//! for the network. `GET /admin/outbound` reports what was sent, by host.
+
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::{extract::State, response::Json};
+use reqwest::header::HeaderMap;
+use reqwest::{Method, StatusCode, Url};
+use serde::Serialize;
+use tokio::sync::Semaphore;
+use utoipa::ToSchema;
+
+use crate::AppState;
+use crate::config::Config;
+
+/// A whole answer, read before the permit for its host is given back.
+#[derive(Debug)]
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum OutboundError {
+    /// The service took longer to connect or answer than allowed.
+    Timeout,
+    /// The service could not be reached, or its answer not read.
+    Failed(String),
+}
+
+impl std::fmt::Display for OutboundError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutboundError::Timeout => f.write_str("the request timed out"),
+            OutboundError::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+#[async_trait]
+pub trait Outbound: Send + Sync {
+    /// `url` with `query` added to it. GETs are idempotent, so they are
+    /// retried.
+    async fn get(&self, url: &str, query: &[(&str, &str)]) -> Result<Response, OutboundError>;
+}
+
+/// What was sent to one host.
+#[derive(Serialize, Debug, Default, Clone, PartialEq, ToSchema)]
+pub struct HostReport {
+    /// `host`, or `host:port` for a port other than the scheme's.
+    pub host: String,
+    /// Every request sent, retries included.
+    pub attempts: u64,
+    /// The attempts that repeated a failed one.
+    pub retries: u64,
+    /// Calls that failed in the end: no answer, or a 5xx after every retry.
+    pub failures: u64,
+}
+
+#[derive(Serialize, Debug, Default, ToSchema)]
+pub struct OutboundReport {
+    pub attempts: u64,
+    pub retries: u64,
+    pub failures: u64,
+    /// By host name.
+    pub hosts: Vec<HostReport>,
+}
+
+struct Host {
+    permits: Arc<Semaphore>,
+    report: HostReport,
+}
+
+pub struct HttpClient {
+    client: reqwest::Client,
+    retries: u32,
+    backoff: Duration,
+    max_per_host: usize,
+    hosts: Mutex<BTreeMap<String, Host>>,
+}
+
+impl HttpClient {
+    pub fn new(config: &Config) -> Self {
+        HttpClient {
+            client: reqwest::Client::builder()
+                .connect_timeout(config.outbound_connect_timeout)
+                .read_timeout(config.outbound_read_timeout)
+                .user_agent(config.outbound_user_agent.clone())
+                .build()
+                .expect("a client without TLS always builds"),
+            retries: config.outbound_retries,
+            backoff: config.outbound_backoff,
+            max_per_host: config.outbound_max_per_host,
+            hosts: Mutex::default(),
+        }
+    }
+
+    pub fn report(&self) -> OutboundReport {
+        let hosts = self.hosts.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut report = OutboundReport::default();
+        for host in hosts.values() {
+            report.attempts += host.report.attempts;
+            report.retries += host.report.retries;
+            report.failures += host.report.failures;
+            report.hosts.push(host.report.clone());
+        }
+        report
+    }
+
+    /// The permits of `host`, counting an attempt on it.
+    fn attempt(&self, host: &str, retry: bool) -> Arc<Semaphore> {
+        let mut hosts = self.hosts.lock().unwrap_or_else(PoisonError::into_inner);
+        let host = hosts.entry(host.to_string()).or_insert_with(|| Host {
+            permits: Arc::new(Semaphore::new(self.max_per_host)),
+            report: HostReport {
+                host: host.to_string(),
+                ..HostReport::default()
+            },
+        });
+        host.report.attempts += 1;
+        host.report.retries += u64::from(retry);
+        host.permits.clone()
+    }
+
+    fn failed(&self, host: &str) {
+        if let Some(host) = self
+            .hosts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_mut(host)
+        {
+            host.report.failures += 1;
+        }
+    }
+
+    /// How long to wait before retry `retry`, 1-based: the backoff doubled
+    /// for each earlier retry, scaled by a random factor between 0.5 and 1.5
+    /// so clients that failed together do not retry together.
+    fn delay(&self, retry: u32) -> Duration {
+        let random = std::collections::hash_map::RandomState::new()
+            .build_hasher()
+            .finish();
+        let jitter = 0.5 + (random % 1000) as f64 / 1000.0;
+        self.backoff
+            .saturating_mul(1 << (retry - 1).min(16))
+            .mul_f64(jitter)
+    }
+
+    async fn send(&self, method: Method, url: Url) -> Result<Response, OutboundError> {
+        let host = url.host_str().unwrap_or_default();
+        let host = match url.port() {
+            Some(port) => format!("{host}:{port}"),
+            None => host.to_string(),
+        };
+        let retries = if method.is_idempotent() {
+            self.retries
+        } else {
+            0
+        };
+
+        let mut retry = 0;
+        loop {
+            let permits = self.attempt(&host, retry > 0);
+            let permit = permits
+                .acquire()
+                .await
+                .expect("host permits are never closed");
+            let result = self.try_once(method.clone(), url.clone()).await;
+            drop(permit);
+
+            let retryable = match &result {
+                Ok(response) => matches!(
+                    response.status,
+                    StatusCode::BAD_GATEWAY
+                        | StatusCode::SERVICE_UNAVAILABLE
+                        | StatusCode::GATEWAY_TIMEOUT
+                ),
+                Err(_) => true,
+            };
+            if retryable && retry < retries {
+                retry += 1;
+                tracing::debug!(%host, retry, "retrying an outbound request");
+                tokio::time::sleep(self.delay(retry)).await;
+                continue;
+            }
+
+            if result
+                .as_ref()
+                .map_or(true, |response| response.status.is_server_error())
+            {
+                self.failed(&host);
+            }
+            return result;
+        }
+    }
+
+    async fn try_once(&self, method: Method, url: Url) -> Result<Response, OutboundError> {
+        let failed = |error: reqwest::Error| {
+            if error.is_timeout() {
+                OutboundError::Timeout
+            } else {
+                OutboundError::Failed(error.to_string())
+            }
+        };
+        let response = self
+            .client
+            .request(method, url)
+            .send()
+            .await
+            .map_err(failed)?;
+        Ok(Response {
+            status: response.status(),
+            headers: response.headers().clone(),
+            body: response.bytes().await.map_err(failed)?.to_vec(),
+        })
+    }
+}
+
+#[async_trait]
+impl Outbound for HttpClient {
+    async fn get(&self, url: &str, query: &[(&str, &str)]) -> Result<Response, OutboundError> {
+        let url = Url::parse_with_params(url, query)
+            .map_err(|error| OutboundError::Failed(format!("invalid URL {url:?}: {error}")))?;
+        self.send(Method::GET, url).await
+    }
+}
+
+/// Requests sent to other services since startup, by host.
+#[utoipa::path(
+    get,
+    path = "/outbound",
+    tag = "admin",
+    summary = "Report outbound requests",
+    operation_id = "outbound_report",
+    responses(
+        (status = OK, description = "Attempts, retries and failures, in total and by host", body = OutboundReport),
+    )
+)]
+pub async fn report(State(state): State<AppState>) -> Json<OutboundReport> {
+    Json(state.outbound.report())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{Router, extract::State, http::StatusCode, routing::get};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Server {
+        calls: AtomicUsize,
+        in_flight: AtomicUsize,
+        most_in_flight: AtomicUsize,
+    }
+
+    /// Serves `/slow`, answering after a second; `/flaky`, failing with 503
+    /// until its third call; and `/busy`, taking 50 ms and tracking how many
+    /// calls overlap.
+    async fn serve() -> (String, Arc<Server>) {
+        async fn slow() -> &'static str {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            "late"
+        }
+        async fn flaky(State(server): State<Arc<Server>>) -> StatusCode {
+            match server.calls.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => StatusCode::SERVICE_UNAVAILABLE,
+                _ => StatusCode::OK,
+            }
+        }
+        async fn busy(State(server): State<Arc<Server>>) -> StatusCode {
+            let now = server.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            server.most_in_flight.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            server.in_flight.fetch_sub(1, Ordering::SeqCst);
+            StatusCode::OK
+        }
+
+        let server = Arc::new(Server::default());
+        let app = Router::new()
+            .route("/slow", get(slow))
+            .route("/flaky", get(flaky))
+            .route("/busy", get(busy))
+            .with_state(server.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{address}"), server)
+    }
+
+    fn client(retries: u32) -> HttpClient {
+        HttpClient::new(&Config {
+            outbound_read_timeout: Duration::from_millis(200),
+            outbound_retries: retries,
+            outbound_backoff: Duration::from_millis(1),
+            outbound_max_per_host: 2,
+            ..Config::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn slow_answers_time_out_after_every_retry() {
+        let (base, _) = serve().await;
+        let client = client(1);
+
+        let error = client.get(&format!("{base}/slow"), &[]).await.unwrap_err();
+        assert!(matches!(error, OutboundError::Timeout), "{error}");
+        let report = client.report();
+        assert_eq!(
+            (report.attempts, report.retries, report.failures),
+            (2, 1, 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_calls_are_retried_a_bounded_number_of_times() {
+        let (base, server) = serve().await;
+        let response = client(2).get(&format!("{base}/flaky"), &[]).await.unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(server.calls.load(Ordering::SeqCst), 3);
+
+        let (base, server) = serve().await;
+        let client = client(1);
+        let response = client.get(&format!("{base}/flaky"), &[]).await.unwrap();
+        assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(server.calls.load(Ordering::SeqCst), 2);
+
+        let report = client.report();
+        let host = base.trim_start_matches("http://");
+        assert_eq!(
+            report.hosts,
+            [HostReport {
+                host: host.to_string(),
+                attempts: 2,
+                retries: 1,
+                failures: 1,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_to_one_host_are_capped() {
+        let (base, server) = serve().await;
+        let client = Arc::new(client(0));
+        let url = format!("{base}/busy");
+
+        let calls: Vec<_> = (0..6)
+            .map(|_| {
+                let (client, url) = (client.clone(), url.clone());
+                tokio::spawn(async move { client.get(&url, &[]).await })
+            })
+            .collect();
+        for call in calls {
+            assert_eq!(call.await.unwrap().unwrap().status, StatusCode::OK);
+        }
+        assert_eq!(server.most_in_flight.load(Ordering::SeqCst), 2);
+        assert_eq!(client.report().attempts, 6);
+    }
+}
@@ -0,0 +1,353 @@
+//! Ordering and cursors of `GET /movie`. `?sort=` takes fields such as
+//! `-year,name`, and the id always breaks ties, so any two movies have a
+//! definite order. A cursor records where a page ended in that order: the
+//! sort it was issued for and the last movie's values for each field. The
+//! next page starts right after those values, wherever the movies around
+//! them have moved in the meantime, so creating or deleting movies between
+//! requests neither skips nor repeats one.
+//!
+//! Names sort naturally unless `?collation=binary` asks for code point
+//! order: accents and casing are ignored and numbers compare by value, so
+//! "Élite" comes before "Zodiac" and "Movie 2" before "Movie 10".
+
+use std::cmp::Ordering;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Utc};
+use serde::de::IntoDeserializer;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+use utoipa::ToSchema;
+
+use crate::Movie;
+use crate::errors::ApiError;
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    #[default]
+    Id,
+    /// Orders by `sort_name` when one was derived, so leading articles are
+    /// skipped.
+    Name,
+    Year,
+    CreatedAt,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// How `name` compares, the other fields are unaffected.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Collation {
+    /// Ignoring accents and casing, with numbers compared by value. Names
+    /// equal that way fall back to `binary`.
+    #[default]
+    Natural,
+    /// By code point, so "Zodiac" before "Élite" and "Movie 10" before
+    /// "Movie 2".
+    Binary,
+}
+
+/// A run of digits or of anything else in a name, as `natural` compares
+/// them. Digits come first, like they do by code point.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Piece {
+    /// The digits without leading zeros, which compare by value once the
+    /// shorter run sorts first.
+    Number(usize, String),
+    /// Lowercased, with accents stripped.
+    Text(String),
+}
+
+fn pieces(name: &str) -> Vec<Piece> {
+    let folded: String = name
+        .nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .collect();
+
+    let mut pieces = Vec::new();
+    let mut rest = folded.as_str();
+    while let Some(first) = rest.chars().next() {
+        let digits = first.is_ascii_digit();
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != digits)
+            .unwrap_or(rest.len());
+        let (run, tail) = rest.split_at(end);
+        pieces.push(if digits {
+            let value = run.trim_start_matches('0');
+            Piece::Number(value.len(), value.to_string())
+        } else {
+            Piece::Text(run.to_string())
+        });
+        rest = tail;
+    }
+
+    pieces
+}
+
+impl Collation {
+    fn compare(self, a: &str, b: &str) -> Ordering {
+        match self {
+            Collation::Natural => pieces(a).cmp(&pieces(b)).then_with(|| a.cmp(b)),
+            Collation::Binary => a.cmp(b),
+        }
+    }
+}
+
+impl SortOrder {
+    fn apply(self, ordering: Ordering) -> Ordering {
+        match self {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        }
+    }
+}
+
+/// A movie's value for one sort field. The field decides the variant, so
+/// only values of the same variant are ever compared.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum SortValue {
+    Text(String),
+    Year(u16),
+    Time(DateTime<Utc>),
+}
+
+impl SortValue {
+    fn of(movie: &Movie, key: SortKey) -> Self {
+        match key {
+            SortKey::Id => SortValue::Text(movie.id.clone()),
+            SortKey::Name => SortValue::Text(
+                movie
+                    .sort_name
+                    .clone()
+                    .unwrap_or_else(|| movie.name.clone()),
+            ),
+            SortKey::Year => SortValue::Year(movie.year),
+            SortKey::CreatedAt => SortValue::Time(movie.created_at),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        match self {
+            SortValue::Text(text) => Value::from(text.as_str()),
+            SortValue::Year(year) => Value::from(*year),
+            SortValue::Time(time) => Value::from(time.to_rfc3339()),
+        }
+    }
+
+    /// The value `json` holds for `key`, `None` if it holds none.
+    fn from_json(json: &Value, key: SortKey) -> Option<Self> {
+        match key {
+            SortKey::Id | SortKey::Name => json.as_str().map(|text| SortValue::Text(text.into())),
+            SortKey::Year => json
+                .as_u64()
+                .and_then(|year| u16::try_from(year).ok())
+                .map(SortValue::Year),
+            SortKey::CreatedAt => json
+                .as_str()
+                .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+                .map(|time| SortValue::Time(time.to_utc())),
+        }
+    }
+}
+
+/// Where a movie stands in a sort: its value for each field, then its id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Position {
+    values: Vec<SortValue>,
+    id: String,
+}
+
+/// What a cursor holds before it is encoded.
+#[derive(Serialize, Deserialize, Debug)]
+struct CursorData {
+    /// The sort the cursor was issued for, as `Sort::describe` spells it.
+    sort: String,
+    after: Vec<Value>,
+    id: String,
+}
+
+/// A parsed `?sort=`, with the direction of every field resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sort {
+    fields: Vec<(SortKey, SortOrder)>,
+    /// The direction of the id breaking ties.
+    ties: SortOrder,
+    collation: Collation,
+}
+
+fn invalid_cursor() -> ApiError {
+    ApiError::BadRequest("cursor is not one this server issued".to_string())
+}
+
+impl Sort {
+    /// By `key` alone, ascending and collated naturally.
+    pub fn by(key: SortKey) -> Self {
+        Sort {
+            fields: vec![(key, SortOrder::Asc)],
+            ties: SortOrder::Asc,
+            collation: Collation::Natural,
+        }
+    }
+
+    /// Reads comma-separated fields, each descending with a leading `-` and
+    /// in `order` otherwise. `order` also sets the direction of the id
+    /// breaking ties.
+    pub fn parse(sort: &str, order: SortOrder, collation: Collation) -> Result<Self, ApiError> {
+        let mut fields = Vec::new();
+        for field in sort.split(',').filter(|_| !sort.is_empty()) {
+            let (name, direction) = match field.strip_prefix('-') {
+                Some(name) => (name, SortOrder::Desc),
+                None => (field, order),
+            };
+            let key = SortKey::deserialize(name.trim().into_deserializer()).map_err(
+                |error: serde::de::value::Error| ApiError::BadRequest(format!("sort: {error}")),
+            )?;
+            if fields.iter().any(|(seen, _)| *seen == key) {
+                return Err(ApiError::BadRequest(format!(
+                    "sort: {} is given more than once",
+                    name.trim()
+                )));
+            }
+            fields.push((key, direction));
+        }
+
+        Ok(Sort {
+            fields,
+            ties: order,
+            collation,
+        })
+    }
+
+    /// Whether movies come in plain id order.
+    pub fn is_by_id(&self) -> bool {
+        self.ties == SortOrder::Asc
+            && self
+                .fields
+                .iter()
+                .all(|field| *field == (SortKey::Id, SortOrder::Asc))
+    }
+
+    pub fn position(&self, movie: &Movie) -> Position {
+        Position {
+            values: self
+                .fields
+                .iter()
+                .map(|(key, _)| SortValue::of(movie, *key))
+                .collect(),
+            id: movie.id.clone(),
+        }
+    }
+
+    pub fn compare(&self, a: &Position, b: &Position) -> Ordering {
+        self.fields
+            .iter()
+            .zip(a.values.iter().zip(&b.values))
+            .map(|((key, order), values)| {
+                order.apply(match (key, values) {
+                    (SortKey::Name, (SortValue::Text(a), SortValue::Text(b))) => {
+                        self.collation.compare(a, b)
+                    }
+                    (_, (a, b)) => a.cmp(b),
+                })
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| self.ties.apply(a.id.cmp(&b.id)))
+    }
+
+    /// Puts `movies` in this order.
+    pub fn order(&self, movies: &mut Vec<Movie>) {
+        let mut positioned: Vec<(Position, Movie)> = movies
+            .drain(..)
+            .map(|movie| (self.position(&movie), movie))
+            .collect();
+        positioned.sort_by(|(a, _), (b, _)| self.compare(a, b));
+        movies.extend(positioned.into_iter().map(|(_, movie)| movie));
+    }
+
+    /// The fields with their directions, the tie-breaking direction and the
+    /// collation, e.g. `-year,name;asc;natural`, which tells sorts apart
+    /// however they were written.
+    fn describe(&self) -> String {
+        let fields: Vec<String> = self
+            .fields
+            .iter()
+            .map(|(key, order)| {
+                let name = match key {
+                    SortKey::Id => "id",
+                    SortKey::Name => "name",
+                    SortKey::Year => "year",
+                    SortKey::CreatedAt => "created_at",
+                };
+                match order {
+                    SortOrder::Asc => name.to_string(),
+                    SortOrder::Desc => format!("-{name}"),
+                }
+            })
+            .collect();
+        let ties = match self.ties {
+            SortOrder::Asc => "asc",
+            SortOrder::Desc => "desc",
+        };
+        let collation = match self.collation {
+            Collation::Natural => "natural",
+            Collation::Binary => "binary",
+        };
+
+        format!("{};{ties};{collation}", fields.join(","))
+    }
+
+    /// An opaque cursor to continue right after `movie`.
+    pub fn cursor(&self, movie: &Movie) -> String {
+        let position = self.position(movie);
+        let data = CursorData {
+            sort: self.describe(),
+            after: position.values.iter().map(SortValue::to_json).collect(),
+            id: position.id,
+        };
+
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(&data).expect("cursors always serialize"))
+    }
+
+    /// The position a cursor from `cursor` continues after. Cursors that do
+    /// not decode, or were issued for another sort, answer 400.
+    pub fn after(&self, cursor: &str) -> Result<Position, ApiError> {
+        let data: CursorData = URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or_else(invalid_cursor)?;
+        if data.sort != self.describe() {
+            return Err(ApiError::BadRequest(
+                "cursor was issued for another sort, start over without it".to_string(),
+            ));
+        }
+        if data.after.len() != self.fields.len() {
+            return Err(invalid_cursor());
+        }
+
+        let values = self
+            .fields
+            .iter()
+            .zip(&data.after)
+            .map(|((key, _), json)| SortValue::from_json(json, *key))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid_cursor)?;
+
+        Ok(Position {
+            values,
+            id: data.id,
+        })
+    }
+}
@@ -0,0 +1,469 @@
+//! Directors and actors, under `/person`. Movies refer to people by id
+//! through `director_id` and `cast`, and every id a movie is created or
+//! updated with must name a stored person. People are stored by the
+//! repository next to the movies, so references outlive a restart exactly
+//! when movies do, and served from memory; a person still referenced by a
+//! movie is only deleted with `?force=true`, which takes them off those
+//! movies first. `GET /movie/{id}?expand=director,cast` embeds the people a
+//! movie refers to.
+
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Json},
+};
+use chrono::Utc;
+use movies::model::Person;
+use serde::de::IntoDeserializer;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::errors::{ApiError, ConflictType, ErrorBody, FieldError};
+use crate::extract::{JsonBody, QueryParams};
+use crate::repo::RepoError;
+use crate::sync::LockExt;
+use crate::{AppState, INVALID_ID, Movie, clean_name, is_valid_id, validate_name};
+
+#[derive(Debug, Default)]
+pub struct People {
+    /// Ordered by id, which is how they are listed.
+    by_id: RwLock<BTreeMap<String, Person>>,
+    /// Held for reading by every write of movies that checks their
+    /// references, from the check until the movies are stored, and for
+    /// writing by every change to people, from the check that allows it
+    /// until it is stored. So a person is never deleted between a movie's
+    /// check and its write, and people reach the repository in the order
+    /// they change.
+    changes: tokio::sync::RwLock<()>,
+}
+
+impl People {
+    /// The people `stored` by the repository, as loaded on start.
+    pub fn load(stored: Vec<Person>) -> Self {
+        People {
+            by_id: RwLock::new(
+                stored
+                    .into_iter()
+                    .map(|person| (person.id.clone(), person))
+                    .collect(),
+            ),
+            changes: tokio::sync::RwLock::default(),
+        }
+    }
+
+    /// Held from checking references with `missing` until the movies
+    /// checked are stored.
+    pub async fn checking(&self) -> tokio::sync::RwLockReadGuard<'_, ()> {
+        self.changes.read().await
+    }
+
+    /// Held while people change, see `changes`.
+    pub async fn changing(&self) -> tokio::sync::RwLockWriteGuard<'_, ()> {
+        self.changes.write().await
+    }
+
+    /// Every person, by id.
+    pub fn all(&self) -> BTreeMap<String, Person> {
+        self.by_id.read_or_recover().clone()
+    }
+
+    /// Replaces every person with `people`, once the repository has them.
+    pub fn replace(&self, people: BTreeMap<String, Person>) {
+        *self.by_id.write_or_recover() = people;
+    }
+
+    fn get(&self, id: &str) -> Option<Person> {
+        self.by_id.read_or_recover().get(id).cloned()
+    }
+
+    /// An error under `prefix` for each of `director_id` and `cast` naming
+    /// no stored person.
+    pub fn missing(
+        &self,
+        director_id: Option<&str>,
+        cast: &[String],
+        prefix: &str,
+    ) -> Vec<FieldError> {
+        missing_in(&self.by_id.read_or_recover(), director_id, cast, prefix)
+    }
+
+    /// The errors of `movie`'s references, as `missing` reports them.
+    pub fn missing_from(&self, movie: &Movie, prefix: &str) -> Vec<FieldError> {
+        self.missing(movie.director_id.as_deref(), &movie.cast, prefix)
+    }
+
+    /// The people `movie` refers to through the `targets`, all read at
+    /// once.
+    pub fn expand(&self, movie: &Movie, targets: &[Expand]) -> Expanded {
+        let people = self.by_id.read_or_recover();
+        let resolve = |id: &String| match people.get(id) {
+            Some(person) => Referenced::Found(person.clone()),
+            None => Referenced::Missing {
+                id: id.clone(),
+                missing: true,
+            },
+        };
+        Expanded {
+            director: targets
+                .contains(&Expand::Director)
+                .then(|| movie.director_id.as_ref().map(resolve)),
+            cast: targets
+                .contains(&Expand::Cast)
+                .then(|| movie.cast.iter().map(resolve).collect()),
+        }
+    }
+}
+
+/// A reference of a movie to embed in place of its id.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Expand {
+    Director,
+    Cast,
+}
+
+impl Expand {
+    /// Reads comma-separated targets, each at most once.
+    pub fn parse(expand: &str) -> Result<Vec<Self>, ApiError> {
+        let mut targets = Vec::new();
+        for name in expand.split(',').map(str::trim) {
+            let target = Expand::deserialize(name.into_deserializer()).map_err(
+                |error: serde::de::value::Error| ApiError::BadRequest(format!("expand: {error}")),
+            )?;
+            if targets.contains(&target) {
+                return Err(ApiError::BadRequest(format!(
+                    "expand: {name} is given more than once"
+                )));
+            }
+            targets.push(target);
+        }
+        Ok(targets)
+    }
+}
+
+/// A person a movie refers to, or just the id when no such person is
+/// stored, so one dangling reference does not fail the whole movie.
+#[derive(Serialize, Debug, PartialEq, ToSchema)]
+#[serde(untagged)]
+pub enum Referenced {
+    Found(Person),
+    Missing {
+        id: String,
+        /// Always `true`.
+        missing: bool,
+    },
+}
+
+/// The people a movie refers to, as `?expand=` asked for them.
+#[derive(Serialize, Debug, Default, PartialEq, ToSchema)]
+pub struct Expanded {
+    /// With `director`; `null` for a movie without one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub director: Option<Option<Referenced>>,
+    /// With `cast`, in billing order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cast: Option<Vec<Referenced>>,
+}
+
+/// The errors of references to anyone but `people`, as `People::missing`
+/// reports them.
+pub fn missing_in(
+    people: &BTreeMap<String, Person>,
+    director_id: Option<&str>,
+    cast: &[String],
+    prefix: &str,
+) -> Vec<FieldError> {
+    let director = director_id
+        .filter(|id| !people.contains_key(*id))
+        .map(|id| (format!("{prefix}director_id"), id));
+    let cast = cast
+        .iter()
+        .enumerate()
+        .filter(|(_, id)| !people.contains_key(id.as_str()))
+        .map(|(index, id)| (format!("{prefix}cast[{index}]"), id.as_str()));
+
+    director
+        .into_iter()
+        .chain(cast)
+        .map(|(field, id)| FieldError::new(field, format!("no person with id {id}")))
+        .collect()
+}
+
+/// Whether `movie` names `id` as its director or in its cast.
+fn features(movie: &Movie, id: &str) -> bool {
+    movie.director_id.as_deref() == Some(id) || movie.cast.iter().any(|cast| cast == id)
+}
+
+fn person_not_found(id: impl Into<String>) -> ApiError {
+    ApiError::not_found("person", id)
+}
+
+fn validate_person(person: &Person) -> Result<(), ApiError> {
+    match validate_name(&person.name, "name".to_string()) {
+        Some(error) => Err(ApiError::Validation(vec![error])),
+        None => Ok(()),
+    }
+}
+
+#[derive(Deserialize, Debug, Default, IntoParams)]
+#[serde(default)]
+#[into_params(parameter_in = Query)]
+pub struct DeletePersonParams {
+    /// Delete the person even while movies refer to them, taking them off
+    /// those movies.
+    force: bool,
+}
+
+#[utoipa::path(
+    post,
+    path = "/person",
+    tag = "people",
+    summary = "Create a person",
+    operation_id = "create_person",
+    request_body = Person,
+    responses(
+        (status = CREATED, description = "The new person", body = Person),
+        (status = CONFLICT, description = "The id is taken", body = ErrorBody),
+        (status = UNPROCESSABLE_ENTITY, description = "An invalid id or name", body = ErrorBody),
+    )
+)]
+pub async fn create(
+    State(state): State<AppState>,
+    JsonBody(payload): JsonBody<Person>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !is_valid_id(&payload.id) {
+        return Err(ApiError::validation("id", INVALID_ID));
+    }
+    validate_person(&payload)?;
+
+    let person = Person {
+        name: clean_name(&payload.name),
+        ..payload
+    };
+    let _fence = state.fence().await?;
+    let _changing = state.people.changing().await;
+    if let Some(existing) = state.people.get(&person.id) {
+        return Err(ApiError::Conflict {
+            conflict_type: ConflictType::DuplicateId,
+            message: format!("person {} already exists", person.id),
+            existing: Some(json!(existing)),
+            candidates: None,
+        });
+    }
+    state.repo.put_person(&person).await?;
+    state
+        .people
+        .by_id
+        .write_or_recover()
+        .insert(person.id.clone(), person.clone());
+
+    Ok((StatusCode::CREATED, Json(person)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/person",
+    tag = "people",
+    summary = "List people",
+    operation_id = "list_people",
+    responses(
+        (status = OK, description = "Every person, by id", body = Vec<Person>),
+    )
+)]
+pub async fn list(State(state): State<AppState>) -> Json<Vec<Person>> {
+    Json(
+        state
+            .people
+            .by_id
+            .read_or_recover()
+            .values()
+            .cloned()
+            .collect(),
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/person/{id}",
+    tag = "people",
+    summary = "Get a person",
+    operation_id = "get_person",
+    params(("id" = String, Path, description = "The id of the person")),
+    responses(
+        (status = OK, description = "The person", body = Person),
+        (status = NOT_FOUND, description = "No such person", body = ErrorBody),
+    )
+)]
+pub async fn get(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<Person>, ApiError> {
+    state
+        .people
+        .get(&id)
+        .map(Json)
+        .ok_or_else(|| person_not_found(id))
+}
+
+/// Renames the person; an id in the body is ignored.
+#[utoipa::path(
+    put,
+    path = "/person/{id}",
+    tag = "people",
+    summary = "Update a person",
+    operation_id = "update_person",
+    params(("id" = String, Path, description = "The id of the person")),
+    request_body = Person,
+    responses(
+        (status = OK, description = "The updated person", body = Person),
+        (status = NOT_FOUND, description = "No such person", body = ErrorBody),
+        (status = UNPROCESSABLE_ENTITY, description = "An invalid name", body = ErrorBody),
+    )
+)]
+pub async fn update(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    JsonBody(payload): JsonBody<Person>,
+) -> Result<Json<Person>, ApiError> {
+    validate_person(&payload)?;
+
+    let _fence = state.fence().await?;
+    let _changing = state.people.changing().await;
+    let mut person = state.people.get(&id).ok_or_else(|| person_not_found(&id))?;
+    person.name = clean_name(&payload.name);
+    state.repo.put_person(&person).await?;
+    state
+        .people
+        .by_id
+        .write_or_recover()
+        .insert(id, person.clone());
+
+    Ok(Json(person))
+}
+
+/// Refused with 409 while a movie, in the trash or not, still refers to the
+/// person, unless `?force=true` asks to take them off those movies; each
+/// such movie moves to its next version.
+#[utoipa::path(
+    delete,
+    path = "/person/{id}",
+    tag = "people",
+    summary = "Delete a person",
+    operation_id = "delete_person",
+    params(
+        ("id" = String, Path, description = "The id of the person"),
+        DeletePersonParams,
+    ),
+    responses(
+        (status = NO_CONTENT, description = "The person was deleted"),
+        (status = NOT_FOUND, description = "No such person", body = ErrorBody),
+        (status = CONFLICT, description = "Movies still refer to the person; `existing` lists their ids", body = ErrorBody),
+    )
+)]
+pub async fn delete(
+    Path(id): Path<String>,
+    QueryParams(params): QueryParams<DeletePersonParams>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, ApiError> {
+    let _fence = state.fence().await?;
+    // No movie comes to name the person while this is held, so the ones
+    // found are all there will be.
+    let _changing = state.people.changing().await;
+    if state.people.get(&id).is_none() {
+        return Err(person_not_found(id));
+    }
+
+    let mut referencing: Vec<String> = state
+        .repo
+        .list()
+        .await?
+        .into_iter()
+        .filter(|movie| features(movie, &id))
+        .map(|movie| movie.id)
+        .collect();
+    referencing.sort();
+    if !referencing.is_empty() && !params.force {
+        return Err(ApiError::Conflict {
+            conflict_type: ConflictType::Referenced,
+            message: format!(
+                "person {id} is referred to by {} movies, delete with ?force=true to remove them from those",
+                referencing.len()
+            ),
+            existing: Some(json!(referencing)),
+            candidates: None,
+        });
+    }
+
+    state.repo.delete_person(&id).await?;
+    state.people.by_id.write_or_recover().remove(&id);
+    for movie_id in referencing {
+        unlink(&state, &movie_id, &id).await?;
+    }
+    tracing::info!(event = "person.deleted", id = %id, "person deleted");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Takes the person `id` off the movie `movie_id`, at its next version.
+async fn unlink(state: &AppState, movie_id: &str, id: &str) -> Result<(), ApiError> {
+    loop {
+        let Some(stored) = state.repo.get(movie_id).await? else {
+            return Ok(());
+        };
+        if !features(&stored, id) {
+            return Ok(());
+        }
+
+        let mut movie = stored.clone();
+        if movie.director_id.as_deref() == Some(id) {
+            movie.director_id = None;
+        }
+        movie.cast.retain(|cast| cast != id);
+        movie.version += 1;
+        movie.updated_at = Utc::now();
+        match state.repo.update(movie.clone()).await {
+            Err(RepoError::Stale(_)) => continue,
+            // Purged meanwhile, which took the reference along.
+            Err(RepoError::NotFound(_)) => return Ok(()),
+            result => result?,
+        }
+        state.events.publish_update(&stored, movie);
+        return Ok(());
+    }
+}
+
+/// The movies the person directed or is cast in, oldest first.
+#[utoipa::path(
+    get,
+    path = "/person/{id}/movies",
+    tag = "people",
+    summary = "List a person's movies",
+    operation_id = "list_person_movies",
+    params(("id" = String, Path, description = "The id of the person")),
+    responses(
+        (status = OK, description = "Movies naming the person as director or in the cast, by year", body = Vec<Movie>),
+        (status = NOT_FOUND, description = "No such person", body = ErrorBody),
+    )
+)]
+pub async fn movies(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<Movie>>, ApiError> {
+    if state.people.get(&id).is_none() {
+        return Err(person_not_found(id));
+    }
+
+    let mut movies: Vec<Movie> = state
+        .live_movies()
+        .await?
+        .into_iter()
+        .filter(|movie| features(movie, &id))
+        .collect();
+    movies.sort_by(|a, b| a.year.cmp(&b.year).then_with(|| a.id.cmp(&b.id)));
+
+    Ok(Json(movies))
+}
@@ -0,0 +1,155 @@
+//! Per-movie read counters behind `GET /movie/popular`. The map is capped so a
+//! crawler walking every id cannot grow it without bound, and all counts are
+//! halved once per decay interval so the ranking follows recent traffic.
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::sync::LockExt;
+
+#[derive(Debug)]
+pub struct Popularity {
+    counts: RwLock<HashMap<String, AtomicU64>>,
+    capacity: usize,
+    decay_interval: Duration,
+    started: Instant,
+    /// Number of decay intervals since `started` already applied to `counts`.
+    epoch: AtomicU64,
+}
+
+impl Popularity {
+    pub fn new(capacity: usize, decay_interval: Duration, now: Instant) -> Self {
+        Popularity {
+            counts: RwLock::new(HashMap::new()),
+            capacity,
+            decay_interval,
+            started: now,
+            epoch: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts one read of `id`. Known ids only take the read lock and bump
+    /// an atomic; the write lock is needed for unseen ids and for decay.
+    pub fn record(&self, id: &str, now: Instant) {
+        self.decay(now);
+
+        if let Some(count) = self.counts.read_or_recover().get(id) {
+            count.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        let mut counts = self.counts.write_or_recover();
+        if !counts.contains_key(id) && counts.len() >= self.capacity {
+            // Make room by forgetting the least read movie, so a newcomer can
+            // still work its way up.
+            let coldest = counts
+                .iter()
+                .min_by_key(|(_, count)| count.load(Ordering::Relaxed))
+                .map(|(id, _)| id.clone());
+            if let Some(coldest) = coldest {
+                counts.remove(&coldest);
+            }
+        }
+        counts
+            .entry(id.to_string())
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Forgets `id`, e.g. once the movie is deleted or re-keyed.
+    pub fn remove(&self, id: &str) {
+        self.counts.write_or_recover().remove(id);
+    }
+
+    /// The `limit` most read ids with their (decayed) counts, most read
+    /// first and ties broken by id.
+    pub fn top(&self, limit: usize, now: Instant) -> Vec<(String, u64)> {
+        self.decay(now);
+
+        let mut top: Vec<(String, u64)> = self
+            .counts
+            .read_or_recover()
+            .iter()
+            .map(|(id, count)| (id.clone(), count.load(Ordering::Relaxed)))
+            .collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(limit);
+        top
+    }
+
+    /// Halves every count once per interval elapsed since the last decay and
+    /// drops the ones that reach zero. Only the caller that advances the
+    /// epoch does the work.
+    fn decay(&self, now: Instant) {
+        if self.decay_interval.is_zero() {
+            return;
+        }
+
+        let epoch = (now.saturating_duration_since(self.started).as_millis()
+            / self.decay_interval.as_millis().max(1)) as u64;
+        let previous = self.epoch.load(Ordering::Relaxed);
+        if epoch <= previous
+            || self
+                .epoch
+                .compare_exchange(previous, epoch, Ordering::Relaxed, Ordering::Relaxed)
+                .is_err()
+        {
+            return;
+        }
+
+        let halvings = (epoch - previous).min(u64::BITS as u64) as u32;
+        self.counts.write_or_recover().retain(|_, count| {
+            let decayed = count.get_mut().checked_shr(halvings).unwrap_or(0);
+            *count.get_mut() = decayed;
+            decayed > 0
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    #[test]
+    fn counts_decay_per_interval() {
+        let start = Instant::now();
+        let popularity = Popularity::new(10, HOUR, start);
+
+        for _ in 0..8 {
+            popularity.record("1", start);
+        }
+        popularity.record("2", start);
+        assert_eq!(
+            popularity.top(10, start),
+            [("1".into(), 8), ("2".into(), 1)]
+        );
+
+        // One interval halves the counts and forgets movies that hit zero.
+        let later = start + HOUR;
+        assert_eq!(popularity.top(10, later), [("1".into(), 4)]);
+
+        // Several elapsed intervals are applied at once.
+        assert_eq!(popularity.top(10, later + 2 * HOUR), [("1".into(), 1)]);
+    }
+
+    #[test]
+    fn full_map_evicts_coldest_movie() {
+        let start = Instant::now();
+        let popularity = Popularity::new(2, HOUR, start);
+
+        for (id, reads) in [("1", 3), ("2", 1), ("3", 2)] {
+            for _ in 0..reads {
+                popularity.record(id, start);
+            }
+        }
+
+        assert_eq!(
+            popularity.top(10, start),
+            [("1".into(), 3), ("3".into(), 2)]
+        );
+    }
+}
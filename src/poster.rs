@@ -0,0 +1,577 @@
+//! Movie posters: `PUT /movie/{id}/poster` uploads a PNG or JPEG as the
+//! `poster` field of a multipart form, `GET` serves it back and `DELETE`
+//! removes it. Images live in `MEDIA_DIR` as `<sha256>.image` files, named
+//! by their content so movies sharing artwork share one file, and each
+//! movie's `<id>.poster` holds the hash of its image. The movie's
+//! `has_poster` says whether there is one. Uploads are streamed to a
+//! temporary file next to them, so an oversized one is refused once it
+//! passes the limit rather than after it was buffered.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io;
+use std::path::{Path as FilePath, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::{
+    extract::{Multipart, Path, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Json, Response},
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{MappedMutexGuard, Mutex, MutexGuard};
+use utoipa::ToSchema;
+
+use crate::conditional::{self, IfNoneMatch};
+use crate::errors::{ApiError, ErrorBody};
+use crate::extract::MultipartBody;
+use crate::repo::RepoError;
+use crate::{AppState, Movie};
+
+/// Largest poster accepted.
+pub const MAX_POSTER_BYTES: u64 = 5 * 1024 * 1024;
+/// The form field the image is sent in.
+const FIELD: &str = "poster";
+
+/// Tells temporary files apart.
+static UPLOADS: AtomicU64 = AtomicU64::new(0);
+
+/// The image types accepted, by the bytes every file of the type starts
+/// with.
+const SIGNATURES: [(&[u8], &str); 2] = [
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+];
+
+/// The type of the image starting with `head`, whatever it was declared as.
+fn sniff(head: &[u8]) -> Option<&'static str> {
+    SIGNATURES
+        .iter()
+        .find(|(signature, _)| head.starts_with(signature))
+        .map(|(_, content_type)| *content_type)
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// The hash a poster file holds, or `None` for an image written there
+/// whole, as before images were shared.
+fn as_hash(contents: &[u8]) -> Option<String> {
+    let hash = std::str::from_utf8(contents).ok()?;
+    (hash.len() == 64 && hash.bytes().all(|byte| byte.is_ascii_hexdigit()))
+        .then(|| hash.to_string())
+}
+
+/// A name in `dir` for a file being written, which no poster or image
+/// has.
+fn temp_path(dir: &FilePath, id: &str) -> PathBuf {
+    dir.join(format!(
+        ".{id}.{}.upload",
+        UPLOADS.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// What the posters take up on disk.
+#[derive(Serialize, Debug, Default, PartialEq, ToSchema)]
+pub struct PosterReport {
+    /// Images stored, each once however many movies share it.
+    pub images: u64,
+    /// Their size in bytes.
+    pub bytes: u64,
+    /// Posters, each pointing at one image.
+    pub references: u64,
+    /// Hashes of images no poster points at, left by an interrupted write.
+    pub orphaned_images: Vec<String>,
+    /// Ids of posters whose movie is not stored, not even in the trash.
+    pub orphaned_posters: Vec<String>,
+}
+
+/// The posters in `MEDIA_DIR`, with how many point at each image, so an
+/// image is removed with the last poster showing it.
+pub struct Posters {
+    dir: PathBuf,
+    /// References to each image by hash, counted from `dir` on first use.
+    /// Held while a poster is read, filed, moved or removed.
+    refs: Mutex<Option<HashMap<String, u64>>>,
+}
+
+impl Posters {
+    pub fn new(dir: PathBuf) -> Self {
+        Posters {
+            dir,
+            refs: Mutex::default(),
+        }
+    }
+
+    /// Where the poster of `id` is kept. Ids hold no path separators, so
+    /// the file is always inside `dir`.
+    fn poster(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.poster"))
+    }
+
+    fn image(&self, hash: &str) -> PathBuf {
+        self.dir.join(format!("{hash}.image"))
+    }
+
+    async fn refs(&self) -> io::Result<MappedMutexGuard<'_, HashMap<String, u64>>> {
+        let mut refs = self.refs.lock().await;
+        if refs.is_none() {
+            *refs = Some(self.count().await?);
+        }
+        Ok(MutexGuard::map(refs, |refs| refs.get_or_insert_default()))
+    }
+
+    /// Counts the posters pointing at each image, filing images that
+    /// earlier versions kept in the poster files themselves.
+    async fn count(&self) -> io::Result<HashMap<String, u64>> {
+        let mut refs = HashMap::new();
+        let mut entries = match fs::read_dir(&self.dir).await {
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(refs),
+            entries => entries?,
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let Some(id) = name.to_str().and_then(|name| name.strip_suffix(".poster")) else {
+                continue;
+            };
+            let contents = fs::read(entry.path()).await?;
+            let hash = match as_hash(&contents) {
+                Some(hash) => hash,
+                None => {
+                    let hash = hex(&Sha256::digest(&contents));
+                    let temp = temp_path(&self.dir, id);
+                    fs::write(&temp, &contents).await?;
+                    fs::rename(&temp, self.image(&hash)).await?;
+                    self.point(id, &hash).await?;
+                    tracing::info!(%id, %hash, "filed a poster by its hash");
+                    hash
+                }
+            };
+            *refs.entry(hash).or_default() += 1;
+        }
+        Ok(refs)
+    }
+
+    /// The hash of the image `id` shows, if it has a poster.
+    async fn pointed(&self, id: &str) -> io::Result<Option<String>> {
+        match fs::read(self.poster(id)).await {
+            Ok(contents) => Ok(as_hash(&contents)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Points the poster of `id` at the image `hash`, in one step.
+    async fn point(&self, id: &str, hash: &str) -> io::Result<()> {
+        let temp = temp_path(&self.dir, id);
+        fs::write(&temp, hash).await?;
+        fs::rename(&temp, self.poster(id)).await
+    }
+
+    /// Drops a reference to the image `hash`, removing it with the last.
+    async fn release(&self, refs: &mut HashMap<String, u64>, hash: &str) {
+        let Some(count) = refs.get_mut(hash) else {
+            return;
+        };
+        *count -= 1;
+        if *count == 0 {
+            refs.remove(hash);
+            if let Err(error) = fs::remove_file(self.image(hash)).await {
+                tracing::warn!(%hash, %error, "failed to remove a poster image");
+            }
+        }
+    }
+
+    /// Makes the image uploaded to `temp`, whose hash is `hash`, the poster
+    /// of `id`. An image already stored is not stored again.
+    async fn file(&self, id: &str, temp: &FilePath, hash: &str) -> io::Result<()> {
+        let mut refs = self.refs().await?;
+        if refs.contains_key(hash) {
+            fs::remove_file(temp).await?;
+        } else {
+            fs::rename(temp, self.image(hash)).await?;
+        }
+        let previous = self.pointed(id).await?;
+        self.point(id, hash).await?;
+        *refs.entry(hash.to_string()).or_default() += 1;
+        if let Some(previous) = previous {
+            self.release(&mut refs, &previous).await;
+        }
+        Ok(())
+    }
+
+    /// The poster of `id`, or `None` if it has none.
+    async fn read(&self, id: &str) -> io::Result<Option<Vec<u8>>> {
+        let _refs = self.refs().await?;
+        let Some(hash) = self.pointed(id).await? else {
+            return Ok(None);
+        };
+        match fs::read(self.image(&hash)).await {
+            Ok(image) => Ok(Some(image)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Moves the poster of `id` to `new_id`, e.g. with a changed id.
+    pub async fn rename(&self, id: &str, new_id: &str) {
+        let renamed = match self.refs().await {
+            Ok(_refs) => fs::rename(self.poster(id), self.poster(new_id)).await,
+            Err(error) => Err(error),
+        };
+        if let Err(error) = renamed {
+            tracing::warn!(%id, %new_id, %error, "failed to move a poster");
+        }
+    }
+
+    /// Removes the poster of `id` if there is one, e.g. with its movie.
+    pub async fn remove(&self, id: &str) {
+        let removed = async {
+            let mut refs = self.refs().await?;
+            let Some(hash) = self.pointed(id).await? else {
+                return Ok(());
+            };
+            fs::remove_file(self.poster(id)).await?;
+            self.release(&mut refs, &hash).await;
+            Ok::<_, io::Error>(())
+        };
+        if let Err(error) = removed.await {
+            tracing::warn!(%id, %error, "failed to remove a poster");
+        }
+    }
+
+    /// What is in `dir`, its posters checked against the `stored` ids.
+    async fn report(&self, stored: &HashSet<String>) -> io::Result<PosterReport> {
+        let refs = self.refs().await?;
+        let mut report = PosterReport::default();
+        let mut images = BTreeSet::new();
+        let mut posters = BTreeSet::new();
+        let mut entries = match fs::read_dir(&self.dir).await {
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(report),
+            entries => entries?,
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            if let Some(hash) = name.strip_suffix(".image") {
+                report.images += 1;
+                report.bytes += entry.metadata().await?.len();
+                images.insert(hash.to_string());
+            } else if let Some(id) = name.strip_suffix(".poster") {
+                report.references += 1;
+                posters.insert(id.to_string());
+            }
+        }
+        report.orphaned_images = images
+            .into_iter()
+            .filter(|hash| !refs.contains_key(hash))
+            .collect();
+        report.orphaned_posters = posters
+            .into_iter()
+            .filter(|id| !stored.contains(id))
+            .collect();
+        Ok(report)
+    }
+}
+
+fn not_an_image() -> ApiError {
+    ApiError::validation(FIELD, "must be a PNG or JPEG image")
+}
+
+fn storage_failed(error: io::Error) -> ApiError {
+    tracing::error!(%error, "failed to store a poster");
+    ApiError::Storage
+}
+
+/// Sets `has_poster` on the live movie `id`, at its next version.
+async fn mark(state: &AppState, id: &str, has_poster: bool) -> Result<Movie, ApiError> {
+    loop {
+        let Some(stored) = state.live_movie(id).await? else {
+            return Err(ApiError::movie_not_found(id));
+        };
+
+        let mut movie = stored.clone();
+        movie.has_poster = has_poster;
+        movie.version += 1;
+        match state.repo.update(movie.clone()).await {
+            Err(RepoError::Stale(_)) => continue,
+            result => result?,
+        }
+        state.events.publish_update(&stored, movie.clone());
+        break Ok(movie);
+    }
+}
+
+/// Writes the `poster` field of `multipart` to `temp`, checking its size as
+/// it arrives and its type once it is complete, and returns its hash.
+async fn receive(multipart: &mut Multipart, temp: &FilePath) -> Result<String, ApiError> {
+    let mut field = loop {
+        match multipart.next_field().await? {
+            Some(field) if field.name() == Some(FIELD) => break field,
+            Some(_) => continue,
+            None => return Err(ApiError::validation(FIELD, "missing from the form")),
+        }
+    };
+    if field
+        .content_type()
+        .is_some_and(|declared| !SIGNATURES.iter().any(|(_, accepted)| *accepted == declared))
+    {
+        return Err(not_an_image());
+    }
+
+    let mut file = fs::File::create(temp).await.map_err(storage_failed)?;
+    let mut head = Vec::new();
+    let mut hasher = Sha256::new();
+    let mut written = 0;
+    while let Some(chunk) = field.chunk().await? {
+        written += chunk.len() as u64;
+        if written > MAX_POSTER_BYTES {
+            return Err(ApiError::PayloadTooLarge(format!(
+                "a poster holds at most {} MB",
+                MAX_POSTER_BYTES / 1024 / 1024
+            )));
+        }
+        if head.len() < 8 {
+            head.extend(chunk.iter().take(8 - head.len()));
+        }
+        hasher.update(&chunk);
+        file.write_all(&chunk).await.map_err(storage_failed)?;
+    }
+    file.sync_all().await.map_err(storage_failed)?;
+
+    sniff(&head).ok_or_else(not_an_image)?;
+    Ok(hex(&hasher.finalize()))
+}
+
+/// Replaces the movie's poster. The image's type is judged by its content,
+/// not the declared `Content-Type`.
+#[utoipa::path(
+    put,
+    path = "/movie/{id}/poster",
+    tag = "movies",
+    summary = "Upload a movie's poster",
+    params(
+        ("id" = String, Path, description = "The id of the movie"),
+    ),
+    request_body(content_type = "multipart/form-data", description = "The image as the `poster` field, PNG or JPEG, at most 5 MB"),
+    responses(
+        (status = OK, description = "The movie, now with `has_poster`", body = Movie, headers(("ETag" = String, description = "The quoted version"))),
+        (status = BAD_REQUEST, description = "A malformed form", body = ErrorBody),
+        (status = NOT_FOUND, description = "No such movie", body = ErrorBody),
+        (status = PAYLOAD_TOO_LARGE, description = "The image is over 5 MB", body = ErrorBody),
+        (status = UNSUPPORTED_MEDIA_TYPE, description = "Not a multipart form", body = ErrorBody),
+        (status = UNPROCESSABLE_ENTITY, description = "No `poster` field, or not a PNG or JPEG image", body = ErrorBody),
+    )
+)]
+pub async fn upload(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    MultipartBody(mut multipart): MultipartBody,
+) -> Result<impl IntoResponse, ApiError> {
+    let _fence = state.fence().await?;
+    if state.live_movie(&id).await?.is_none() {
+        return Err(ApiError::movie_not_found(id));
+    }
+
+    let media_dir = &state.config.media_dir;
+    fs::create_dir_all(media_dir)
+        .await
+        .map_err(storage_failed)?;
+    let temp = temp_path(media_dir, &id);
+    let received = receive(&mut multipart, &temp).await;
+    // Not while receiving, which may take long, only while the image is
+    // filed under the id.
+    let _rekeying = state.rekeying.read().await;
+    let stored = match received {
+        Ok(hash) => state
+            .posters
+            .file(&id, &temp, &hash)
+            .await
+            .map_err(storage_failed),
+        Err(error) => Err(error),
+    };
+    if let Err(error) = stored {
+        let _ = fs::remove_file(&temp).await;
+        return Err(error);
+    }
+
+    let movie = match mark(&state, &id, true).await {
+        // Deleted while uploading; the image goes with it.
+        Err(error @ ApiError::NotFound { .. }) => {
+            state.posters.remove(&id).await;
+            return Err(error);
+        }
+        result => result?,
+    };
+    tracing::info!(event = "poster.uploaded", id = %id, "poster uploaded");
+
+    Ok((
+        [(header::ETAG, conditional::etag(movie.version))],
+        Json(movie),
+    ))
+}
+
+/// The image as uploaded, tagged with the movie's version, which changes
+/// with every upload.
+#[utoipa::path(
+    get,
+    path = "/movie/{id}/poster",
+    tag = "movies",
+    summary = "Get a movie's poster",
+    params(
+        ("id" = String, Path, description = "The id of the movie"),
+    ),
+    responses(
+        (status = OK, description = "The image", content_type = "image/png", body = Vec<u8>, headers(("ETag" = String, description = "The quoted version of the movie"))),
+        (status = NOT_MODIFIED, description = "The `If-None-Match` tag is current"),
+        (status = NOT_FOUND, description = "No such movie, or it has no poster", body = ErrorBody),
+    )
+)]
+pub async fn get(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let _rekeying = state.rekeying.read().await;
+    let Some(movie) = state.live_movie(&id).await? else {
+        return Err(ApiError::movie_not_found(id));
+    };
+    if !movie.has_poster {
+        return Err(ApiError::not_found("poster", id));
+    }
+
+    let etag = conditional::etag(movie.version);
+    if IfNoneMatch::from_headers(&headers).is_some_and(|tag| tag.matches(&etag)) {
+        return Ok(conditional::not_modified(etag));
+    }
+
+    let Some(image) = state.posters.read(&id).await.map_err(storage_failed)? else {
+        tracing::warn!(%id, "poster file is missing");
+        return Err(ApiError::not_found("poster", id));
+    };
+    let content_type = sniff(&image).unwrap_or("application/octet-stream");
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static(content_type)),
+            (header::ETAG, etag),
+        ],
+        image,
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/movie/{id}/poster",
+    tag = "movies",
+    summary = "Remove a movie's poster",
+    params(
+        ("id" = String, Path, description = "The id of the movie"),
+    ),
+    responses(
+        (status = NO_CONTENT, description = "The poster is gone"),
+        (status = NOT_FOUND, description = "No such movie, or it has no poster", body = ErrorBody),
+    )
+)]
+pub async fn delete(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, ApiError> {
+    let _fence = state.fence().await?;
+    let _rekeying = state.rekeying.read().await;
+    let Some(movie) = state.live_movie(&id).await? else {
+        return Err(ApiError::movie_not_found(id));
+    };
+    if !movie.has_poster {
+        return Err(ApiError::not_found("poster", id));
+    }
+
+    mark(&state, &id, false).await?;
+    state.posters.remove(&id).await;
+    tracing::info!(event = "poster.deleted", id = %id, "poster deleted");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// What the posters take up in `MEDIA_DIR`: images, each stored once
+/// however many movies show it, and what no longer belongs to anything.
+#[utoipa::path(
+    get,
+    path = "/posters",
+    tag = "admin",
+    summary = "Report stored posters",
+    operation_id = "posters_report",
+    responses(
+        (status = OK, description = "Images, their size and references, and orphans", body = PosterReport),
+        (status = INTERNAL_SERVER_ERROR, description = "The media directory could not be read", body = ErrorBody),
+    )
+)]
+pub async fn report(State(state): State<AppState>) -> Result<Json<PosterReport>, ApiError> {
+    let stored = state
+        .repo
+        .list()
+        .await?
+        .into_iter()
+        .map(|movie| movie.id)
+        .collect();
+    let report = state
+        .posters
+        .report(&stored)
+        .await
+        .map_err(storage_failed)?;
+    Ok(Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_are_recognized_by_their_first_bytes() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Some("image/png"));
+        assert_eq!(sniff(b"\xff\xd8\xff\xe0\0\x10JFIF"), Some("image/jpeg"));
+        assert_eq!(sniff(b"\x89PNG"), None);
+        assert_eq!(sniff(b"PNG, honestly"), None);
+        assert_eq!(sniff(b""), None);
+    }
+
+    #[test]
+    fn poster_files_hold_a_hash_or_an_old_image() {
+        let hash = hex(&Sha256::digest(b"poster"));
+        assert_eq!(hash.len(), 64);
+        assert_eq!(as_hash(hash.as_bytes()), Some(hash));
+        assert_eq!(as_hash(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), None);
+        assert_eq!(as_hash(b"not a hash"), None);
+    }
+
+    #[tokio::test]
+    async fn images_kept_in_poster_files_are_filed_by_their_hash() {
+        const POSTER: &[u8] = include_bytes!("../tests/fixtures/poster.png");
+        let media = tempfile::tempdir().unwrap();
+        for id in ["1", "2"] {
+            std::fs::write(media.path().join(format!("{id}.poster")), POSTER).unwrap();
+        }
+
+        let posters = Posters::new(media.path().to_path_buf());
+        assert_eq!(posters.read("1").await.unwrap().as_deref(), Some(POSTER));
+        let hash = hex(&Sha256::digest(POSTER));
+        for id in ["1", "2"] {
+            let pointer = std::fs::read(media.path().join(format!("{id}.poster"))).unwrap();
+            assert_eq!(pointer, hash.as_bytes());
+        }
+        let stored = HashSet::from(["1".to_string(), "2".to_string()]);
+        let report = posters.report(&stored).await.unwrap();
+        assert_eq!((report.images, report.references), (1, 2));
+
+        posters.remove("1").await;
+        assert!(media.path().join(format!("{hash}.image")).exists());
+        posters.remove("2").await;
+        assert_eq!(std::fs::read_dir(media.path()).unwrap().count(), 0);
+    }
+}
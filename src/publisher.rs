@@ -0,0 +1,436 @@
+//! Movie changes forwarded to a message queue for other services. A
+//! forwarder subscribes to `Events` like an open stream does, queues each
+//! `MovieEvent` as JSON and hands them to a `Publisher` one at a time, in
+//! order, trying the oldest again until the broker takes it. Delivery is at
+//! least once: an event whose acknowledgement was lost is sent again.
+//!
+//! While the broker is out the queue grows, up to `PUBLISH_BUFFER` events;
+//! the ones arriving beyond that are dropped and logged. The queue is only
+//! held in memory. `/readyz` reports a failing broker or dropped events as a
+//! warning but stays ready, since the API itself still works. The server
+//! publishes to NATS when built with the `nats` feature and given `NATS_URL`.
+
+// Without `nats` only the tests start a forwarder.
+#![cfg_attr(not(feature = "nats"), allow(dead_code))]
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::sync::Notify;
+use tokio::sync::broadcast::error::RecvError;
+
+#[cfg(feature = "nats")]
+use crate::Config;
+use crate::events::Events;
+
+/// Wait before the first retry of a failed publish, doubled for each one
+/// after up to `MAX_BACKOFF`.
+const BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+#[async_trait]
+pub trait Publisher: Send + Sync {
+    /// Hands one event, as JSON, to the broker and returns once it has
+    /// taken it.
+    async fn publish(&self, payload: &[u8]) -> std::io::Result<()>;
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    events: VecDeque<Vec<u8>>,
+    /// Events lost to a full queue since the start.
+    dropped: u64,
+    /// Why the last publish failed, until one succeeds.
+    failing: Option<String>,
+    /// No more events will arrive.
+    closed: bool,
+}
+
+/// The events on their way to the broker, and how that is going.
+#[derive(Debug)]
+pub struct Outbox {
+    capacity: usize,
+    queue: Mutex<Queue>,
+    queued: Notify,
+}
+
+impl Outbox {
+    fn queue(&self) -> std::sync::MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Takes the event, or drops it when the queue is full.
+    fn push(&self, payload: Vec<u8>) {
+        let mut queue = self.queue();
+        if queue.events.len() >= self.capacity {
+            queue.dropped += 1;
+            tracing::warn!(
+                event = "publisher.dropped",
+                dropped = queue.dropped,
+                "the publish queue is full, dropping a movie event"
+            );
+            return;
+        }
+        queue.events.push_back(payload);
+        drop(queue);
+        self.queued.notify_one();
+    }
+
+    /// What `/readyz` warns about: a broker that refuses events, or events
+    /// that never reached it.
+    pub fn warning(&self) -> Option<String> {
+        let queue = self.queue();
+        match (&queue.failing, queue.dropped) {
+            (Some(error), _) => Some(format!(
+                "publishing fails ({error}), {} events queued and {} dropped",
+                queue.events.len(),
+                queue.dropped
+            )),
+            (None, 0) => None,
+            (None, dropped) => Some(format!("{dropped} events were dropped")),
+        }
+    }
+}
+
+/// Forwards every event published on `events` from now on to `publisher`,
+/// queueing up to `capacity` of them while it fails. The forwarding ends once
+/// `events` is gone and the queue is drained.
+pub fn spawn(events: &Events, publisher: Arc<dyn Publisher>, capacity: usize) -> Arc<Outbox> {
+    let outbox = Arc::new(Outbox {
+        capacity,
+        queue: Mutex::default(),
+        queued: Notify::new(),
+    });
+
+    let mut receiver = events.subscribe();
+    let receiving = outbox.clone();
+    tokio::spawn(async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => receiving
+                    .push(serde_json::to_vec(&event).expect("movie events always serialize")),
+                Err(RecvError::Lagged(missed)) => {
+                    let mut queue = receiving.queue();
+                    queue.dropped += missed;
+                    tracing::warn!(
+                        event = "publisher.dropped",
+                        dropped = queue.dropped,
+                        missed,
+                        "the publisher fell behind the movie events, dropping some"
+                    );
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+        receiving.queue().closed = true;
+        receiving.queued.notify_one();
+    });
+
+    let sending = outbox.clone();
+    tokio::spawn(async move {
+        let mut backoff = BACKOFF;
+        loop {
+            // Only this task takes events off, so the front stays put while
+            // it is published.
+            let front = {
+                let queue = sending.queue();
+                match queue.events.front() {
+                    Some(payload) => Some(payload.clone()),
+                    None if queue.closed => return,
+                    None => None,
+                }
+            };
+            let Some(payload) = front else {
+                sending.queued.notified().await;
+                continue;
+            };
+
+            match publisher.publish(&payload).await {
+                Ok(()) => {
+                    let mut queue = sending.queue();
+                    queue.events.pop_front();
+                    if queue.failing.take().is_some() {
+                        tracing::info!(event = "publisher.recovered", "publishing works again");
+                    }
+                    backoff = BACKOFF;
+                }
+                Err(error) => {
+                    {
+                        let mut queue = sending.queue();
+                        if queue.failing.is_none() {
+                            tracing::warn!(
+                                event = "publisher.failing",
+                                %error,
+                                "publishing a movie event failed, retrying"
+                            );
+                        }
+                        queue.failing = Some(error.to_string());
+                    }
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    });
+
+    outbox
+}
+
+/// Forwards `events` to the NATS server `config` names, if it names one.
+#[cfg(feature = "nats")]
+pub fn from_config(config: &Config, events: &Events) -> Option<Arc<Outbox>> {
+    let address = config.nats_url.clone()?;
+    tracing::info!(
+        address,
+        subject = %config.nats_subject,
+        "publishing movie changes to NATS"
+    );
+    let nats = nats::NatsPublisher::new(address, config.nats_subject.clone());
+    Some(spawn(events, Arc::new(nats), config.publish_buffer))
+}
+
+/// A NATS client speaking just enough of the protocol to publish: every
+/// event is followed by a `PING`, and the server's `PONG` confirms it has
+/// taken the event.
+#[cfg(feature = "nats")]
+mod nats {
+    use std::io::{Error, ErrorKind};
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufStream};
+    use tokio::net::TcpStream;
+    use tokio::sync::Mutex;
+
+    use super::Publisher;
+
+    /// How long the server gets to connect or confirm an event.
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub(super) struct NatsPublisher {
+        /// `host:port`.
+        address: String,
+        subject: String,
+        connection: Mutex<Option<BufStream<TcpStream>>>,
+    }
+
+    impl NatsPublisher {
+        pub(super) fn new(address: String, subject: String) -> Self {
+            NatsPublisher {
+                address,
+                subject,
+                connection: Mutex::default(),
+            }
+        }
+
+        async fn connect(&self) -> std::io::Result<BufStream<TcpStream>> {
+            let mut connection = BufStream::new(TcpStream::connect(&self.address).await?);
+            let info = read_line(&mut connection).await?;
+            if !info.starts_with("INFO ") {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("expected INFO from the server, got {info:?}"),
+                ));
+            }
+            connection
+                .write_all(
+                    b"CONNECT {\"verbose\":false,\"pedantic\":false,\"name\":\"movies\"}\r\n",
+                )
+                .await?;
+            Ok(connection)
+        }
+
+        async fn send(
+            &self,
+            connection: &mut BufStream<TcpStream>,
+            payload: &[u8],
+        ) -> std::io::Result<()> {
+            let header = format!("PUB {} {}\r\n", self.subject, payload.len());
+            connection.write_all(header.as_bytes()).await?;
+            connection.write_all(payload).await?;
+            connection.write_all(b"\r\nPING\r\n").await?;
+            connection.flush().await?;
+
+            loop {
+                let line = read_line(connection).await?;
+                match line.split_whitespace().next() {
+                    Some("PONG") => return Ok(()),
+                    Some("PING") => {
+                        connection.write_all(b"PONG\r\n").await?;
+                        connection.flush().await?;
+                    }
+                    Some("-ERR") => return Err(Error::other(line)),
+                    // `+OK` and updated `INFO`.
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    async fn read_line(connection: &mut BufStream<TcpStream>) -> std::io::Result<String> {
+        let mut line = String::new();
+        if connection.read_line(&mut line).await? == 0 {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "the server closed the connection",
+            ));
+        }
+        Ok(line.trim_end().to_string())
+    }
+
+    #[async_trait]
+    impl Publisher for NatsPublisher {
+        async fn publish(&self, payload: &[u8]) -> std::io::Result<()> {
+            let mut connection = self.connection.lock().await;
+            let published = tokio::time::timeout(TIMEOUT, async {
+                if connection.is_none() {
+                    *connection = Some(self.connect().await?);
+                }
+                let stream = connection.as_mut().expect("connected above");
+                self.send(stream, payload).await
+            })
+            .await
+            .unwrap_or_else(|_| Err(Error::from(ErrorKind::TimedOut)));
+            // Whatever went wrong may have left the stream mid-message.
+            if published.is_err() {
+                *connection = None;
+            }
+            published
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use movies::model::Movie;
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::events::EventKind;
+
+    /// Takes events only while the broker is up.
+    #[derive(Default)]
+    struct Broker {
+        down: AtomicBool,
+        published: Mutex<Vec<Value>>,
+    }
+
+    #[async_trait]
+    impl Publisher for Broker {
+        async fn publish(&self, payload: &[u8]) -> std::io::Result<()> {
+            if self.down.load(Ordering::SeqCst) {
+                return Err(std::io::Error::other("broker down"));
+            }
+            let event = serde_json::from_slice(payload).unwrap();
+            self.published.lock().unwrap().push(event);
+            Ok(())
+        }
+    }
+
+    impl Broker {
+        fn ids(&self) -> Vec<String> {
+            let published = self.published.lock().unwrap();
+            published
+                .iter()
+                .map(|event| event["movie"]["id"].as_str().unwrap().to_string())
+                .collect()
+        }
+    }
+
+    fn movie(id: &str) -> Movie {
+        serde_json::from_value(json!({ "id": id, "name": "Heat", "year": 1995, "was_good": true }))
+            .unwrap()
+    }
+
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    #[tokio::test]
+    async fn outages_are_buffered_up_to_the_capacity() {
+        let events = Events::default();
+        let broker = Arc::new(Broker::default());
+        let outbox = spawn(&events, broker.clone(), 3);
+
+        events.publish(EventKind::Created, movie("1"));
+        settle().await;
+        assert_eq!(broker.ids(), ["1"]);
+        assert_eq!(outbox.warning(), None);
+
+        broker.down.store(true, Ordering::SeqCst);
+        for id in ["2", "3", "4", "5", "6"] {
+            events.publish(EventKind::Created, movie(id));
+        }
+        settle().await;
+        assert_eq!(broker.ids(), ["1"]);
+        assert_eq!(
+            outbox.warning().unwrap(),
+            "publishing fails (broker down), 3 events queued and 2 dropped"
+        );
+
+        // The queued ones follow in order once the broker is back.
+        broker.down.store(false, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(broker.ids(), ["1", "2", "3", "4"]);
+        assert_eq!(outbox.warning().unwrap(), "2 events were dropped");
+
+        let published = broker.published.lock().unwrap();
+        assert_eq!(published[1]["kind"], "created");
+        assert_eq!(published[1]["movie"]["name"], "Heat");
+    }
+
+    #[cfg(feature = "nats")]
+    #[tokio::test]
+    async fn nats_gets_each_event_confirmed() {
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+
+        use super::nats::NatsPublisher;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufStream::new(stream);
+            stream.write_all(b"INFO {}\r\n").await.unwrap();
+            stream.flush().await.unwrap();
+
+            let mut lines = Vec::new();
+            let mut payloads = Vec::new();
+            // CONNECT, then each event's PUB.
+            for line in 0..3 {
+                let mut read = String::new();
+                stream.read_line(&mut read).await.unwrap();
+                lines.push(read.trim_end().to_string());
+                if line == 0 {
+                    continue;
+                }
+                let length = lines.last().unwrap().rsplit(' ').next().unwrap();
+                let mut payload = vec![0; length.parse::<usize>().unwrap() + 2];
+                stream.read_exact(&mut payload).await.unwrap();
+                payload.truncate(payload.len() - 2);
+                payloads.push(String::from_utf8(payload).unwrap());
+                let mut ping = String::new();
+                stream.read_line(&mut ping).await.unwrap();
+                assert_eq!(ping, "PING\r\n");
+                stream.write_all(b"+OK\r\nPONG\r\n").await.unwrap();
+                stream.flush().await.unwrap();
+            }
+            (lines, payloads)
+        });
+
+        let publisher = NatsPublisher::new(address, "movies.events".to_string());
+        publisher.publish(b"{\"a\":1}").await.unwrap();
+        publisher.publish(b"{}").await.unwrap();
+
+        let (lines, payloads) = server.await.unwrap();
+        assert!(lines[0].starts_with("CONNECT {"), "{lines:?}");
+        assert_eq!(lines[1], "PUB movies.events 7");
+        // The connection is kept for the next event.
+        assert_eq!(lines[2], "PUB movies.events 2");
+        assert_eq!(payloads, ["{\"a\":1}", "{}"]);
+    }
+}
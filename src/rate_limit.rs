@@ -0,0 +1,204 @@
+//! Per-client token buckets in front of every route. Each IP may make
+//! `capacity` requests in a burst, refilled evenly over `window`; requests
+//! beyond that answer 429 until a token is back. Buckets left idle for a
+//! whole window are full again, so they are swept instead of kept forever.
+//! The allowance can be changed while the server runs, see
+//! `PATCH /admin/limits`; an allowance of 0 lets every request through.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::errors::ApiError;
+use crate::sync::LockExt;
+
+const X_RATELIMIT_REMAINING: &str = "x-ratelimit-remaining";
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    by_ip: HashMap<IpAddr, Bucket>,
+    swept: Instant,
+    capacity: u32,
+    window: Duration,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    buckets: RwLock<Buckets>,
+    /// Requests answered 429 since startup.
+    rejected: AtomicU64,
+    /// Key on the first `X-Forwarded-For` address instead of the peer's,
+    /// for deployments behind a proxy that sets it.
+    trust_forwarded_for: bool,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: u32, window: Duration, trust_forwarded_for: bool, now: Instant) -> Self {
+        RateLimiter {
+            buckets: RwLock::new(Buckets {
+                by_ip: HashMap::new(),
+                swept: now,
+                capacity,
+                window,
+            }),
+            rejected: AtomicU64::new(0),
+            trust_forwarded_for,
+        }
+    }
+
+    /// Takes a token from `ip`'s bucket, answering with the tokens left, or
+    /// with how long until the next one when the bucket is empty.
+    pub fn check(&self, ip: IpAddr, now: Instant) -> Result<u32, Duration> {
+        let mut buckets = self.buckets.write_or_recover();
+        let window = buckets.window;
+        let capacity = f64::from(buckets.capacity);
+        let per_second = capacity / window.as_secs_f64().max(f64::EPSILON);
+
+        if now.saturating_duration_since(buckets.swept) >= window {
+            buckets
+                .by_ip
+                .retain(|_, bucket| now.saturating_duration_since(bucket.updated) < window);
+            buckets.swept = now;
+        }
+
+        let bucket = buckets.by_ip.entry(ip).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(bucket.tokens as u32)
+        } else {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        }
+    }
+
+    /// The requests a client may burst and how long they take to refill.
+    pub fn allowance(&self) -> (u32, Duration) {
+        let buckets = self.buckets.read_or_recover();
+        (buckets.capacity, buckets.window)
+    }
+
+    /// Changes the allowance from now on; every client starts over with a
+    /// full bucket of the new size.
+    pub fn set_allowance(&self, capacity: u32, window: Duration) {
+        let mut buckets = self.buckets.write_or_recover();
+        buckets.by_ip.clear();
+        buckets.capacity = capacity;
+        buckets.window = window;
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// The address requests are counted against, if the connection has one.
+    fn client_ip(&self, request: &Request) -> Option<IpAddr> {
+        let forwarded = self
+            .trust_forwarded_for
+            .then(|| request.headers().get("x-forwarded-for"))
+            .flatten()
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .and_then(|first| first.trim().parse().ok());
+
+        forwarded.or_else(|| {
+            request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip())
+        })
+    }
+
+    #[cfg(test)]
+    fn tracked(&self) -> usize {
+        self.buckets.read_or_recover().by_ip.len()
+    }
+}
+
+/// Requests without a known client address, which only happen in-process,
+/// are let through uncounted, as is everything while the allowance is 0.
+pub async fn limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(ip) = limiter.client_ip(&request) else {
+        return next.run(request).await;
+    };
+    if limiter.allowance().0 == 0 {
+        return next.run(request).await;
+    }
+
+    let (mut response, remaining) = match limiter.check(ip, Instant::now()) {
+        Ok(remaining) => (next.run(request).await, remaining),
+        Err(wait) => {
+            // Retry-After counts whole seconds, so round up rather than
+            // telling the client to retry before a token is back.
+            let retry_after = Duration::from_secs(wait.as_secs_f64().ceil() as u64);
+            (ApiError::RateLimited { retry_after }.into_response(), 0)
+        }
+    };
+    response
+        .headers_mut()
+        .insert(X_RATELIMIT_REMAINING, HeaderValue::from(remaining));
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 1));
+    const B: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 2));
+
+    #[test]
+    fn buckets_refill_over_the_window() {
+        let start = Instant::now();
+        let limiter = RateLimiter::new(2, Duration::from_secs(10), false, start);
+
+        assert_eq!(limiter.check(A, start), Ok(1));
+        assert_eq!(limiter.check(A, start), Ok(0));
+        assert_eq!(limiter.check(A, start), Err(Duration::from_secs(5)));
+        assert_eq!(limiter.check(B, start), Ok(1));
+
+        // One token comes back every five seconds.
+        assert_eq!(limiter.check(A, start + Duration::from_secs(5)), Ok(0));
+        assert!(limiter.check(A, start + Duration::from_secs(6)).is_err());
+        assert_eq!(limiter.check(A, start + Duration::from_secs(60)), Ok(1));
+    }
+
+    #[test]
+    fn idle_buckets_are_swept() {
+        let start = Instant::now();
+        let limiter = RateLimiter::new(2, Duration::from_secs(10), false, start);
+
+        limiter.check(A, start).unwrap();
+        limiter.check(B, start + Duration::from_secs(5)).unwrap();
+        assert_eq!(limiter.tracked(), 2);
+
+        limiter.check(B, start + Duration::from_secs(12)).unwrap();
+        assert_eq!(limiter.tracked(), 1);
+    }
+}
@@ -0,0 +1,333 @@
+//! Scores out of 10 given to movies, under `/movie/{id}/rating`. Ratings are
+//! kept in memory next to the repository and summarized in the movie's own
+//! response. Every rating change moves the movie to a new version, so its
+//! `ETag` covers the summary too.
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Json},
+};
+use chrono::Utc;
+use movies::model::{NewRating, Rating};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::errors::{ApiError, ErrorBody, FieldError};
+use crate::extract::JsonBody;
+use crate::people::Expanded;
+use crate::repo::RepoError;
+use crate::sync::LockExt;
+use crate::{AppState, Movie};
+
+const SCORES: std::ops::RangeInclusive<u8> = 1..=10;
+const MAX_COMMENT_CHARS: usize = 2000;
+
+#[derive(Debug, Default)]
+pub struct Ratings {
+    by_movie: RwLock<HashMap<String, Vec<Rating>>>,
+    last_id: AtomicU64,
+}
+
+/// What `GET /movie/{id}` reports about a movie's ratings.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+pub struct RatingSummary {
+    /// Mean score, or `None` while the movie is unrated.
+    pub average_rating: Option<f32>,
+    pub rating_count: usize,
+}
+
+impl Ratings {
+    pub fn add(&self, movie_id: &str, rating: NewRating) -> Rating {
+        let rating = Rating {
+            id: self.last_id.fetch_add(1, Ordering::Relaxed) + 1,
+            movie_id: movie_id.to_string(),
+            score: rating.score,
+            comment: rating.comment,
+            created_at: Utc::now(),
+        };
+        self.by_movie
+            .write_or_recover()
+            .entry(movie_id.to_string())
+            .or_default()
+            .push(rating.clone());
+
+        rating
+    }
+
+    /// The ratings of `movie_id`, oldest first.
+    pub fn list(&self, movie_id: &str) -> Vec<Rating> {
+        self.by_movie
+            .read_or_recover()
+            .get(movie_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Removes one rating, if it belongs to `movie_id`.
+    pub fn remove(&self, movie_id: &str, rating_id: u64) -> Option<Rating> {
+        let mut by_movie = self.by_movie.write_or_recover();
+        let ratings = by_movie.get_mut(movie_id)?;
+        let index = ratings.iter().position(|rating| rating.id == rating_id)?;
+        let rating = ratings.remove(index);
+        if ratings.is_empty() {
+            by_movie.remove(movie_id);
+        }
+
+        Some(rating)
+    }
+
+    /// Forgets every rating of a deleted movie.
+    pub fn remove_movie(&self, movie_id: &str) {
+        self.by_movie.write_or_recover().remove(movie_id);
+    }
+
+    /// Moves the ratings of a re-keyed movie to its new id.
+    pub fn rename(&self, from: &str, to: &str) {
+        let mut by_movie = self.by_movie.write_or_recover();
+        if let Some(mut ratings) = by_movie.remove(from) {
+            for rating in &mut ratings {
+                rating.movie_id = to.to_string();
+            }
+            by_movie.insert(to.to_string(), ratings);
+        }
+    }
+
+    /// Moves the ratings of every movie in `from` to `to` at once, keeping
+    /// them oldest first.
+    pub fn merge(&self, from: &[String], to: &str) {
+        let mut by_movie = self.by_movie.write_or_recover();
+        let mut merged = by_movie.remove(to).unwrap_or_default();
+        for movie_id in from {
+            merged.extend(by_movie.remove(movie_id).into_iter().flatten());
+        }
+        if merged.is_empty() {
+            return;
+        }
+
+        for rating in &mut merged {
+            rating.movie_id = to.to_string();
+        }
+        merged.sort_by_key(|rating| (rating.created_at, rating.id));
+        by_movie.insert(to.to_string(), merged);
+    }
+
+    pub fn summary(&self, movie_id: &str) -> RatingSummary {
+        self.overall([movie_id])
+    }
+
+    /// One summary over the ratings of all of `movie_ids` together.
+    pub fn overall<'a>(&self, movie_ids: impl IntoIterator<Item = &'a str>) -> RatingSummary {
+        let by_movie = self.by_movie.read_or_recover();
+        let (total, count) = movie_ids
+            .into_iter()
+            .filter_map(|movie_id| by_movie.get(movie_id))
+            .flatten()
+            .fold((0u32, 0usize), |(total, count), rating| {
+                (total + u32::from(rating.score), count + 1)
+            });
+
+        RatingSummary {
+            average_rating: (count > 0).then(|| total as f32 / count as f32),
+            rating_count: count,
+        }
+    }
+}
+
+/// A movie as `GET /movie/{id}` answers it: the stored fields plus a
+/// summary of its ratings, and the people it refers to when asked.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct RatedMovie {
+    #[serde(flatten)]
+    pub movie: Movie,
+    #[serde(flatten)]
+    pub ratings: RatingSummary,
+    /// The references named by `?expand=`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expanded: Option<Expanded>,
+}
+
+fn validate(rating: &NewRating) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    if !SCORES.contains(&rating.score) {
+        errors.push(FieldError::new(
+            "score",
+            format!("must be between {} and {}", SCORES.start(), SCORES.end()),
+        ));
+    }
+    if let Some(comment) = &rating.comment
+        && comment.chars().count() > MAX_COMMENT_CHARS
+    {
+        errors.push(FieldError::new(
+            "comment",
+            format!("must be at most {MAX_COMMENT_CHARS} characters"),
+        ));
+    }
+
+    errors
+}
+
+/// Moves the movie to its next version after its ratings changed. A movie
+/// changed concurrently is simply read again; one deleted meanwhile is not
+/// found.
+async fn touch(state: &AppState, id: &str) -> Result<(), ApiError> {
+    loop {
+        let Some(stored) = state.live_movie(id).await? else {
+            return Err(ApiError::movie_not_found(id));
+        };
+        let mut movie = stored.clone();
+        movie.version += 1;
+        match state.repo.update(movie.clone()).await {
+            Err(RepoError::Stale(_)) => continue,
+            result => result?,
+        }
+        state.events.publish_update(&stored, movie);
+        return Ok(());
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/movie/{id}/rating",
+    tag = "ratings",
+    summary = "Rate a movie",
+    operation_id = "create_rating",
+    params(("id" = String, Path, description = "The id of the movie")),
+    request_body = NewRating,
+    responses(
+        (status = CREATED, description = "The new rating", body = Rating),
+        (status = NOT_FOUND, description = "No such movie", body = ErrorBody),
+        (status = UNPROCESSABLE_ENTITY, description = "A score out of range or a comment too long", body = ErrorBody),
+    )
+)]
+pub async fn create(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    JsonBody(payload): JsonBody<NewRating>,
+) -> Result<impl IntoResponse, ApiError> {
+    let errors = validate(&payload);
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+
+    let _fence = state.fence().await?;
+    let _rekeying = state.rekeying.read().await;
+    touch(&state, &id).await?;
+    let rating = state.ratings.add(&id, payload);
+
+    Ok((StatusCode::CREATED, Json(rating)))
+}
+
+/// The movie's ratings, oldest first.
+#[utoipa::path(
+    get,
+    path = "/movie/{id}/rating",
+    tag = "ratings",
+    summary = "List a movie's ratings",
+    operation_id = "list_ratings",
+    params(("id" = String, Path, description = "The id of the movie")),
+    responses(
+        (status = OK, description = "The ratings, oldest first", body = Vec<Rating>),
+        (status = NOT_FOUND, description = "No such movie", body = ErrorBody),
+    )
+)]
+pub async fn list(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<Rating>>, ApiError> {
+    let _rekeying = state.rekeying.read().await;
+    if state.live_movie(&id).await?.is_none() {
+        return Err(ApiError::movie_not_found(id));
+    }
+
+    Ok(Json(state.ratings.list(&id)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/movie/{id}/rating/{rating_id}",
+    tag = "ratings",
+    summary = "Delete a rating",
+    operation_id = "delete_rating",
+    params(
+        ("id" = String, Path, description = "The id of the movie"),
+        ("rating_id" = u64, Path, description = "The id of the rating"),
+    ),
+    responses(
+        (status = NO_CONTENT, description = "The rating was deleted"),
+        (status = NOT_FOUND, description = "No such movie, or no such rating of it", body = ErrorBody),
+    )
+)]
+pub async fn delete(
+    Path((id, rating_id)): Path<(String, u64)>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, ApiError> {
+    let _fence = state.fence().await?;
+    let _rekeying = state.rekeying.read().await;
+    if state.live_movie(&id).await?.is_none() {
+        return Err(ApiError::movie_not_found(id));
+    }
+    if state.ratings.remove(&id, rating_id).is_none() {
+        return Err(ApiError::not_found("rating", rating_id.to_string()));
+    }
+    touch(&state, &id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(score: u8) -> NewRating {
+        NewRating {
+            score,
+            comment: None,
+        }
+    }
+
+    #[test]
+    fn summary_averages_scores() {
+        let ratings = Ratings::default();
+        assert_eq!(
+            ratings.summary("1"),
+            RatingSummary {
+                average_rating: None,
+                rating_count: 0
+            }
+        );
+
+        let first = ratings.add("1", score(6));
+        ratings.add("1", score(7));
+        ratings.add("1", score(10));
+        ratings.add("2", score(1));
+        assert_eq!(
+            ratings.summary("1"),
+            RatingSummary {
+                average_rating: Some(23.0 / 3.0),
+                rating_count: 3
+            }
+        );
+
+        assert!(ratings.remove("2", first.id).is_none());
+        assert_eq!(ratings.remove("1", first.id), Some(first));
+        assert_eq!(ratings.summary("1").average_rating, Some(8.5));
+    }
+
+    #[test]
+    fn renamed_ratings_follow_the_movie() {
+        let ratings = Ratings::default();
+        ratings.add("1", score(8));
+
+        ratings.rename("1", "2");
+        assert!(ratings.list("1").is_empty());
+        assert_eq!(ratings.list("2")[0].movie_id, "2");
+
+        ratings.remove_movie("2");
+        assert_eq!(ratings.summary("2").rating_count, 0);
+    }
+}
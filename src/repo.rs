@@ -0,0 +1,1322 @@
+//! Storage behind the handlers. Handlers only see `dyn MovieRepository`, so a
+//! database can replace the in-memory map without touching them. The slug
+//! and redirect indexes stay in `AppState`; repositories only hold movies
+//! and the people they refer to.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::{BuildHasher, RandomState};
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use movies::model::{Movie, Person};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+use crate::encryption::Key;
+use crate::snapshot::Stored;
+use crate::storage::Storage;
+use crate::sync::LockExt;
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod tiered;
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteRepository;
+pub use tiered::{FileRepository, TieredRepository};
+
+#[derive(Debug)]
+pub enum RepoError {
+    /// An insert hit a taken id; carries the stored movie.
+    Conflict(Box<Movie>),
+    NotFound(String),
+    /// An update or conditional delete found another version than it
+    /// expected; carries the stored movie.
+    Stale(Box<Movie>),
+    /// The backend itself failed, e.g. a lost connection or a full disk.
+    Backend(String),
+}
+
+impl fmt::Display for RepoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepoError::Conflict(existing) => write!(f, "movie {} already exists", existing.id),
+            RepoError::NotFound(id) => write!(f, "movie {id} does not exist"),
+            RepoError::Stale(current) => {
+                write!(f, "movie {} is at version {}", current.id, current.version)
+            }
+            RepoError::Backend(message) => write!(f, "storage backend failed: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for RepoError {}
+
+impl From<RepoError> for io::Error {
+    fn from(error: RepoError) -> Self {
+        io::Error::other(error)
+    }
+}
+
+/// One change of a [`MovieRepository::apply`] batch, checked like the
+/// single-movie method of the same name.
+#[derive(Debug, Clone)]
+pub enum Write {
+    Insert(Movie),
+    /// Expects the stored version to be one below the movie's.
+    Update(Movie),
+    /// Inserts or replaces, never fails on its own.
+    Upsert(Movie),
+    /// Expects the stored version to be the one given, if any.
+    Delete(String, Option<u64>),
+    /// Removes every movie, so the writes after it start from an empty
+    /// store.
+    Clear,
+}
+
+impl Write {
+    /// The movie written, `None` for `Clear`, which touches them all.
+    fn id(&self) -> Option<&str> {
+        match self {
+            Write::Insert(movie) | Write::Update(movie) | Write::Upsert(movie) => Some(&movie.id),
+            Write::Delete(id, _) => Some(id),
+            Write::Clear => None,
+        }
+    }
+}
+
+#[async_trait]
+pub trait MovieRepository: Send + Sync {
+    /// Succeeds while the backend can serve requests, for readiness probes.
+    async fn ping(&self) -> Result<(), RepoError>;
+
+    /// Every movie, in no particular order.
+    async fn list(&self) -> Result<Vec<Movie>, RepoError>;
+
+    /// Up to `limit` movies with ids after `after`, or from the first one
+    /// without it, in id order, so the store can be walked a chunk at a time.
+    async fn list_after(&self, after: Option<&str>, limit: usize) -> Result<Vec<Movie>, RepoError> {
+        let mut movies = self.list().await?;
+        movies.retain(|movie| after.is_none_or(|after| movie.id.as_str() > after));
+        movies.sort_by(|a, b| a.id.cmp(&b.id));
+        movies.truncate(limit);
+        Ok(movies)
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Movie>, RepoError>;
+
+    /// The version of the movie `id` if it is stored and not in the trash,
+    /// for telling whether it exists without copying the rest of it. This
+    /// default reads the movie whole; backends override it with a lookup of
+    /// the id.
+    async fn exists(&self, id: &str) -> Result<Option<u64>, RepoError> {
+        Ok(self
+            .get(id)
+            .await?
+            .filter(|movie| movie.deleted_at.is_none())
+            .map(|movie| movie.version))
+    }
+
+    /// The movies with `ids`, in the same order, all read at one point in
+    /// time: a batch written meanwhile shows up in all of them or in none.
+    /// Backends whose `list` is read at one point in time can keep this
+    /// default, which picks them out of it.
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Option<Movie>>, RepoError> {
+        let movies: HashMap<String, Movie> = self
+            .list()
+            .await?
+            .into_iter()
+            .map(|movie| (movie.id.clone(), movie))
+            .collect();
+        Ok(ids.iter().map(|id| movies.get(id).cloned()).collect())
+    }
+
+    /// Fails with `Conflict` when the id is taken.
+    async fn insert(&self, movie: Movie) -> Result<(), RepoError>;
+
+    /// Replaces the movie with the same id; `NotFound` when there is none.
+    /// `movie.version` must be one above the stored version, otherwise the
+    /// movie changed since it was read and the update fails with `Stale`.
+    async fn update(&self, movie: Movie) -> Result<(), RepoError>;
+
+    /// Removes and returns the movie; `NotFound` when there is none, `Stale`
+    /// when `version` is given and the stored movie is at another one.
+    async fn delete(&self, id: &str, version: Option<u64>) -> Result<Movie, RepoError>;
+
+    /// Applies `writes` in order, all or none: each is checked against the
+    /// state the preceding ones leave behind, and the first failing one
+    /// fails the batch without changing anything.
+    async fn apply(&self, writes: Vec<Write>) -> Result<(), RepoError>;
+
+    /// Folds what was written since the last call into the backend's compact
+    /// form, every `SNAPSHOT_INTERVAL_SECS` while serving. Backends without
+    /// one have nothing to do.
+    async fn compact(&self) -> Result<(), RepoError> {
+        Ok(())
+    }
+
+    /// Makes every change durable, once, when the server shuts down.
+    /// Backends that persist each write as it happens have nothing to do.
+    async fn flush(&self) -> Result<(), RepoError> {
+        Ok(())
+    }
+
+    /// Waits until every write made so far is on disk, and answers whether
+    /// it is: backends that keep nothing across restarts answer `false`.
+    async fn sync(&self) -> Result<bool, RepoError> {
+        Ok(false)
+    }
+
+    /// Every stored person, loaded into `AppState` on start so the people
+    /// movies refer to outlive a restart as the movies do. Backends that
+    /// keep nothing across restarts store no people and leave them to
+    /// `AppState` alone.
+    async fn people(&self) -> Result<Vec<Person>, RepoError> {
+        Ok(Vec::new())
+    }
+
+    /// Stores `person`, replacing the one with the same id.
+    async fn put_person(&self, _person: &Person) -> Result<(), RepoError> {
+        Ok(())
+    }
+
+    /// Removes the person `id`, if stored.
+    async fn delete_person(&self, _id: &str) -> Result<(), RepoError> {
+        Ok(())
+    }
+}
+
+/// Shards of the in-memory map. Reads and writes of one movie lock only the
+/// shard its id hashes to, so requests for different movies rarely contend.
+const SHARDS: usize = 16;
+
+type Shard = RwLock<HashMap<String, Movie>>;
+
+/// The movies in a sharded map, optionally kept on disk by [`Storage`].
+#[derive(Debug)]
+pub struct InMemoryRepository {
+    shards: Arc<[Shard]>,
+    /// One per shard, held by a write from checking it until it is applied.
+    /// The log is written in between, so writes to a shard stay in order
+    /// while readers, which only take the shard itself, never wait for the
+    /// disk.
+    writers: Box<[Arc<Mutex<()>>]>,
+    hasher: RandomState,
+    storage: Option<Arc<Storage>>,
+    /// Only kept with storage, for compaction to save.
+    people: Arc<RwLock<BTreeMap<String, Person>>>,
+    /// Held by a change to the people from logging it until it is applied,
+    /// like the writer lock of a shard.
+    people_writer: Arc<Mutex<()>>,
+}
+
+impl Default for InMemoryRepository {
+    fn default() -> Self {
+        Self::with_shards(SHARDS, (HashMap::new(), BTreeMap::new()), None)
+    }
+}
+
+fn shard_of(hasher: &RandomState, count: usize, id: &str) -> usize {
+    (hasher.hash_one(id) % count as u64) as usize
+}
+
+/// The writer locks of some of the shards. Locks are always taken in shard
+/// order, so two writers never each hold a shard the other waits for. They
+/// are owned, so a write the log has taken is applied even when its caller
+/// stops waiting for it.
+struct Locked {
+    shards: Arc<[Shard]>,
+    hasher: RandomState,
+    storage: Option<Arc<Storage>>,
+    /// Sorted, one per guard.
+    indexes: Vec<usize>,
+    _writers: Vec<OwnedMutexGuard<()>>,
+}
+
+impl Locked {
+    fn position(&self, id: &str) -> usize {
+        let shard = shard_of(&self.hasher, self.shards.len(), id);
+        self.indexes
+            .binary_search(&shard)
+            .expect("the shard of every written id is locked")
+    }
+
+    /// The stored movie, which no one else can change while it is locked.
+    fn get(&self, id: &str) -> Option<Movie> {
+        let shard = self.indexes[self.position(id)];
+        self.shards[shard].read_or_recover().get(id).cloned()
+    }
+
+    fn check(&self, writes: &[Write]) -> Result<(), RepoError> {
+        let shards: Vec<_> = self
+            .indexes
+            .iter()
+            .map(|index| self.shards[*index].read_or_recover())
+            .collect();
+        check(|id| shards[self.position(id)].get(id), writes)
+    }
+
+    /// Logs `writes` and then applies them. With storage both happen on a
+    /// blocking thread, since the log waits for the disk; a write the log
+    /// refuses fails without changing anything.
+    async fn commit(self, writes: Vec<Write>) -> Result<(), RepoError> {
+        if self.storage.is_none() {
+            self.apply(writes);
+            return Ok(());
+        }
+
+        tokio::task::spawn_blocking(move || {
+            self.log(&writes)?;
+            self.apply(writes);
+            Ok(())
+        })
+        .await
+        .map_err(|error| RepoError::Backend(error.to_string()))?
+    }
+
+    fn log(&self, writes: &[Write]) -> Result<(), RepoError> {
+        let Some(storage) = &self.storage else {
+            return Ok(());
+        };
+
+        storage.append(writes).map_err(|error| {
+            tracing::error!(path = %storage.path().display(), %error, "failed to log a write");
+            RepoError::Backend(error.to_string())
+        })
+    }
+
+    /// Write-locks the shards only for as long as the map changes.
+    fn apply(&self, writes: Vec<Write>) {
+        let mut shards: Vec<_> = self
+            .indexes
+            .iter()
+            .map(|index| self.shards[*index].write_or_recover())
+            .collect();
+
+        for write in writes {
+            match write {
+                Write::Insert(movie) | Write::Update(movie) | Write::Upsert(movie) => {
+                    let position = self.position(&movie.id);
+                    shards[position].insert(movie.id.clone(), movie);
+                }
+                Write::Delete(id, _) => {
+                    shards[self.position(&id)].remove(&id);
+                }
+                // Every shard is locked for a batch that clears.
+                Write::Clear => shards.iter_mut().for_each(|shard| shard.clear()),
+            }
+        }
+    }
+}
+
+impl InMemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the movies and people from the snapshot at `path` and its log,
+    /// and keeps them stored there, sealed with `key` if there is one.
+    pub fn open(path: PathBuf, key: Option<Key>) -> io::Result<Self> {
+        let (storage, stored) = Storage::open(path, key)?;
+        Ok(Self::with_shards(SHARDS, stored, Some(Arc::new(storage))))
+    }
+
+    fn with_shards(count: usize, (movies, people): Stored, storage: Option<Arc<Storage>>) -> Self {
+        let repo = InMemoryRepository {
+            shards: (0..count).map(|_| Shard::default()).collect(),
+            writers: (0..count).map(|_| Arc::default()).collect(),
+            hasher: RandomState::new(),
+            storage,
+            people: Arc::new(RwLock::new(people)),
+            people_writer: Arc::default(),
+        };
+        for (id, movie) in movies {
+            let shard = repo.shard(&id);
+            repo.shards[shard].write_or_recover().insert(id, movie);
+        }
+
+        repo
+    }
+
+    fn shard(&self, id: &str) -> usize {
+        shard_of(&self.hasher, self.shards.len(), id)
+    }
+
+    /// Takes the writer locks of the shards `writes` touch: every shard if
+    /// one of them clears the store.
+    async fn lock_writes(&self, writes: &[Write]) -> Locked {
+        let ids: Option<Vec<&str>> = writes.iter().map(Write::id).collect();
+        let indexes = match ids {
+            Some(ids) => ids.into_iter().map(|id| self.shard(id)).collect(),
+            None => (0..self.shards.len()).collect(),
+        };
+        self.lock_shards(indexes).await
+    }
+
+    /// Logs the change to the person `id`, `None` for a removal, and then
+    /// applies it, on a blocking thread like `Locked::commit` so a change
+    /// the log has taken is applied even when its caller stops waiting.
+    async fn change_person(&self, id: &str, person: Option<Person>) -> Result<(), RepoError> {
+        let Some(storage) = self.storage.clone() else {
+            return Ok(());
+        };
+
+        let writer = self.people_writer.clone().lock_owned().await;
+        let people = self.people.clone();
+        let id = id.to_string();
+        tokio::task::spawn_blocking(move || {
+            storage
+                .append_person(&id, person.as_ref())
+                .map_err(|error| {
+                    tracing::error!(path = %storage.path().display(), %error, "failed to log a person");
+                    RepoError::Backend(error.to_string())
+                })?;
+            let mut people = people.write_or_recover();
+            match person {
+                Some(person) => people.insert(id, person),
+                None => people.remove(&id),
+            };
+            drop(writer);
+            Ok(())
+        })
+        .await
+        .map_err(|error| RepoError::Backend(error.to_string()))?
+    }
+
+    async fn lock_shards(&self, mut indexes: Vec<usize>) -> Locked {
+        indexes.sort_unstable();
+        indexes.dedup();
+
+        let mut writers = Vec::with_capacity(indexes.len());
+        for index in &indexes {
+            writers.push(self.writers[*index].clone().lock_owned().await);
+        }
+        Locked {
+            shards: self.shards.clone(),
+            hasher: self.hasher.clone(),
+            storage: self.storage.clone(),
+            indexes,
+            _writers: writers,
+        }
+    }
+}
+
+/// Checks `writes` against the `stored` movies without applying them,
+/// tracking what earlier writes in the batch would create or remove.
+fn check<'a>(
+    stored: impl Fn(&str) -> Option<&'a Movie>,
+    writes: &'a [Write],
+) -> Result<(), RepoError> {
+    let mut pending: HashMap<&str, Option<&Movie>> = HashMap::new();
+    // Once the batch clears the store, nothing stored counts any more.
+    let mut cleared = false;
+
+    for write in writes {
+        let Some(id) = write.id() else {
+            pending.clear();
+            cleared = true;
+            continue;
+        };
+        let current = match pending.get(id) {
+            Some(pending) => *pending,
+            None if cleared => None,
+            None => stored(id),
+        };
+
+        match (write, current) {
+            (Write::Insert(_), Some(existing)) => {
+                return Err(RepoError::Conflict(Box::new(existing.clone())));
+            }
+            (Write::Update(_) | Write::Delete(..), None) => {
+                return Err(RepoError::NotFound(id.to_string()));
+            }
+            (Write::Update(movie), Some(current)) if current.version + 1 != movie.version => {
+                return Err(RepoError::Stale(Box::new(current.clone())));
+            }
+            (Write::Delete(_, Some(version)), Some(current)) if current.version != *version => {
+                return Err(RepoError::Stale(Box::new(current.clone())));
+            }
+            (Write::Delete(..), Some(_)) => pending.insert(id, None),
+            (Write::Insert(movie) | Write::Update(movie) | Write::Upsert(movie), _) => {
+                pending.insert(id, Some(movie))
+            }
+            (Write::Clear, _) => unreachable!("handled above"),
+        };
+    }
+
+    Ok(())
+}
+
+#[async_trait]
+impl MovieRepository for InMemoryRepository {
+    async fn ping(&self) -> Result<(), RepoError> {
+        for shard in self.shards.iter() {
+            drop(shard.read_or_recover());
+        }
+        Ok(())
+    }
+
+    /// Copies one shard at a time, so a listing never holds up writers for
+    /// the whole store. A batch written meanwhile may show up only in part.
+    async fn list(&self) -> Result<Vec<Movie>, RepoError> {
+        let mut movies = Vec::new();
+        for shard in self.shards.iter() {
+            movies.extend(shard.read_or_recover().values().cloned());
+        }
+        Ok(movies)
+    }
+
+    /// Clones at most `limit` movies per shard, reading one shard at a time
+    /// like `list`.
+    async fn list_after(&self, after: Option<&str>, limit: usize) -> Result<Vec<Movie>, RepoError> {
+        let mut movies = Vec::new();
+        for shard in self.shards.iter() {
+            let shard = shard.read_or_recover();
+            let mut candidates: Vec<&Movie> = shard
+                .values()
+                .filter(|movie| after.is_none_or(|after| movie.id.as_str() > after))
+                .collect();
+            if candidates.len() > limit {
+                candidates.select_nth_unstable_by(limit, |a, b| a.id.cmp(&b.id));
+                candidates.truncate(limit);
+            }
+            movies.extend(candidates.into_iter().cloned());
+        }
+
+        movies.sort_by(|a, b| a.id.cmp(&b.id));
+        movies.truncate(limit);
+        Ok(movies)
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Movie>, RepoError> {
+        Ok(self.shards[self.shard(id)]
+            .read_or_recover()
+            .get(id)
+            .cloned())
+    }
+
+    async fn exists(&self, id: &str) -> Result<Option<u64>, RepoError> {
+        Ok(self.shards[self.shard(id)]
+            .read_or_recover()
+            .get(id)
+            .filter(|movie| movie.deleted_at.is_none())
+            .map(|movie| movie.version))
+    }
+
+    /// Holds the writer locks of the shards `ids` fall in while reading
+    /// them, so no batch touching those shards is half applied.
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Option<Movie>>, RepoError> {
+        let _locked = self
+            .lock_shards(ids.iter().map(|id| self.shard(id)).collect())
+            .await;
+        Ok(ids
+            .iter()
+            .map(|id| {
+                self.shards[self.shard(id)]
+                    .read_or_recover()
+                    .get(id)
+                    .cloned()
+            })
+            .collect())
+    }
+
+    async fn insert(&self, movie: Movie) -> Result<(), RepoError> {
+        self.apply(vec![Write::Insert(movie)]).await
+    }
+
+    async fn update(&self, movie: Movie) -> Result<(), RepoError> {
+        self.apply(vec![Write::Update(movie)]).await
+    }
+
+    async fn delete(&self, id: &str, version: Option<u64>) -> Result<Movie, RepoError> {
+        let locked = self.lock_shards(vec![self.shard(id)]).await;
+        let current = locked
+            .get(id)
+            .ok_or_else(|| RepoError::NotFound(id.to_string()))?;
+        if version.is_some_and(|version| version != current.version) {
+            return Err(RepoError::Stale(Box::new(current)));
+        }
+
+        locked
+            .commit(vec![Write::Delete(id.to_string(), version)])
+            .await?;
+        Ok(current)
+    }
+
+    async fn apply(&self, writes: Vec<Write>) -> Result<(), RepoError> {
+        let locked = self.lock_writes(&writes).await;
+        locked.check(&writes)?;
+        locked.commit(writes).await
+    }
+
+    /// Copies the store holding every writer lock, so no write is logged
+    /// but not yet applied and the copy matches the log up to its current
+    /// end exactly. Readers go on meanwhile, and the snapshot is written on
+    /// a blocking thread without holding up anyone.
+    async fn compact(&self) -> Result<(), RepoError> {
+        let Some(storage) = &self.storage else {
+            return Ok(());
+        };
+
+        let _compacting = storage.compacting().await;
+        let (movies, people, covered) = {
+            let _writers = self.lock_shards((0..self.shards.len()).collect()).await;
+            let _people_writer = self.people_writer.lock().await;
+            let mut movies = Vec::new();
+            for shard in self.shards.iter() {
+                movies.extend(shard.read_or_recover().values().cloned());
+            }
+            let people: Vec<Person> = self.people.read_or_recover().values().cloned().collect();
+            (movies, people, storage.logged())
+        };
+
+        let storage = storage.clone();
+        tokio::task::spawn_blocking(move || storage.compact(&movies, &people, covered))
+            .await
+            .map_err(|error| RepoError::Backend(error.to_string()))?
+            .map_err(|error| RepoError::Backend(error.to_string()))
+    }
+
+    /// Compacts once more, so the next start has no log to replay.
+    async fn flush(&self) -> Result<(), RepoError> {
+        self.compact().await
+    }
+
+    /// Syncs the log, which holds every write since the last compaction.
+    async fn sync(&self) -> Result<bool, RepoError> {
+        let Some(storage) = self.storage.clone() else {
+            return Ok(false);
+        };
+        tokio::task::spawn_blocking(move || storage.sync())
+            .await
+            .map_err(|error| RepoError::Backend(error.to_string()))?
+            .map_err(|error| RepoError::Backend(error.to_string()))?;
+        Ok(true)
+    }
+
+    async fn people(&self) -> Result<Vec<Person>, RepoError> {
+        Ok(self.people.read_or_recover().values().cloned().collect())
+    }
+
+    async fn put_person(&self, person: &Person) -> Result<(), RepoError> {
+        self.change_person(&person.id, Some(person.clone())).await
+    }
+
+    async fn delete_person(&self, id: &str) -> Result<(), RepoError> {
+        self.change_person(id, None).await
+    }
+}
+
+/// A deliberately different repository for tests: a plain list searched
+/// linearly, so tests can show handlers do not depend on the map.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct VecRepository {
+    movies: std::sync::Mutex<Vec<Movie>>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl MovieRepository for VecRepository {
+    async fn ping(&self) -> Result<(), RepoError> {
+        drop(self.movies.lock().unwrap());
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<Movie>, RepoError> {
+        Ok(self.movies.lock().unwrap().clone())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Movie>, RepoError> {
+        Ok(self
+            .movies
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|movie| movie.id == id)
+            .cloned())
+    }
+
+    async fn insert(&self, movie: Movie) -> Result<(), RepoError> {
+        self.apply(vec![Write::Insert(movie)]).await
+    }
+
+    async fn update(&self, movie: Movie) -> Result<(), RepoError> {
+        self.apply(vec![Write::Update(movie)]).await
+    }
+
+    async fn delete(&self, id: &str, version: Option<u64>) -> Result<Movie, RepoError> {
+        let mut movies = self.movies.lock().unwrap();
+        let index = movies
+            .iter()
+            .position(|movie| movie.id == id)
+            .ok_or_else(|| RepoError::NotFound(id.to_string()))?;
+        if version.is_some_and(|version| version != movies[index].version) {
+            return Err(RepoError::Stale(Box::new(movies[index].clone())));
+        }
+
+        Ok(movies.remove(index))
+    }
+
+    async fn apply(&self, writes: Vec<Write>) -> Result<(), RepoError> {
+        let mut movies = self.movies.lock().unwrap();
+
+        // Works on a copy and swaps it in, instead of checking up front.
+        let mut next = movies.clone();
+        for write in writes {
+            let index = next
+                .iter()
+                .position(|movie| Some(movie.id.as_str()) == write.id());
+            match (write, index) {
+                (Write::Clear, _) => next.clear(),
+                (Write::Insert(_), Some(index)) => {
+                    return Err(RepoError::Conflict(Box::new(next[index].clone())));
+                }
+                (Write::Update(movie), Some(index)) if next[index].version + 1 != movie.version => {
+                    return Err(RepoError::Stale(Box::new(next[index].clone())));
+                }
+                (Write::Update(movie) | Write::Upsert(movie), Some(index)) => next[index] = movie,
+                (Write::Insert(movie) | Write::Upsert(movie), None) => next.push(movie),
+                (Write::Delete(_, Some(version)), Some(index))
+                    if next[index].version != version =>
+                {
+                    return Err(RepoError::Stale(Box::new(next[index].clone())));
+                }
+                (Write::Delete(..), Some(index)) => {
+                    next.remove(index);
+                }
+                (Write::Update(movie), None) => return Err(RepoError::NotFound(movie.id)),
+                (Write::Delete(id, _), None) => return Err(RepoError::NotFound(id)),
+            }
+        }
+        *movies = next;
+
+        Ok(())
+    }
+}
+
+/// Takes `delay` over every call to the map it wraps, for exercising
+/// timeouts.
+#[cfg(test)]
+#[derive(Debug)]
+pub struct SlowRepository {
+    pub inner: InMemoryRepository,
+    pub delay: std::time::Duration,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl MovieRepository for SlowRepository {
+    async fn ping(&self) -> Result<(), RepoError> {
+        tokio::time::sleep(self.delay).await;
+        self.inner.ping().await
+    }
+
+    async fn list(&self) -> Result<Vec<Movie>, RepoError> {
+        tokio::time::sleep(self.delay).await;
+        self.inner.list().await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Movie>, RepoError> {
+        tokio::time::sleep(self.delay).await;
+        self.inner.get(id).await
+    }
+
+    async fn insert(&self, movie: Movie) -> Result<(), RepoError> {
+        tokio::time::sleep(self.delay).await;
+        self.inner.insert(movie).await
+    }
+
+    async fn update(&self, movie: Movie) -> Result<(), RepoError> {
+        tokio::time::sleep(self.delay).await;
+        self.inner.update(movie).await
+    }
+
+    async fn delete(&self, id: &str, version: Option<u64>) -> Result<Movie, RepoError> {
+        tokio::time::sleep(self.delay).await;
+        self.inner.delete(id, version).await
+    }
+
+    async fn apply(&self, writes: Vec<Write>) -> Result<(), RepoError> {
+        tokio::time::sleep(self.delay).await;
+        self.inner.apply(writes).await
+    }
+}
+
+/// Counts the movies read whole from the map it wraps, so tests can tell
+/// a lookup that only checks for a movie apart from one that copies it.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct CountingRepository {
+    pub inner: InMemoryRepository,
+    pub reads: std::sync::atomic::AtomicUsize,
+}
+
+#[cfg(test)]
+impl CountingRepository {
+    pub fn reads(&self) -> usize {
+        self.reads.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    fn count(&self, movies: usize) {
+        self.reads
+            .fetch_add(movies, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl MovieRepository for CountingRepository {
+    async fn ping(&self) -> Result<(), RepoError> {
+        self.inner.ping().await
+    }
+
+    async fn list(&self) -> Result<Vec<Movie>, RepoError> {
+        let movies = self.inner.list().await?;
+        self.count(movies.len());
+        Ok(movies)
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Movie>, RepoError> {
+        let movie = self.inner.get(id).await?;
+        self.count(usize::from(movie.is_some()));
+        Ok(movie)
+    }
+
+    async fn exists(&self, id: &str) -> Result<Option<u64>, RepoError> {
+        self.inner.exists(id).await
+    }
+
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Option<Movie>>, RepoError> {
+        let movies = self.inner.get_many(ids).await?;
+        self.count(movies.iter().flatten().count());
+        Ok(movies)
+    }
+
+    async fn insert(&self, movie: Movie) -> Result<(), RepoError> {
+        self.inner.insert(movie).await
+    }
+
+    async fn update(&self, movie: Movie) -> Result<(), RepoError> {
+        self.inner.update(movie).await
+    }
+
+    async fn delete(&self, id: &str, version: Option<u64>) -> Result<Movie, RepoError> {
+        self.inner.delete(id, version).await
+    }
+
+    async fn apply(&self, writes: Vec<Write>) -> Result<(), RepoError> {
+        self.inner.apply(writes).await
+    }
+}
+
+/// Lets another writer change every movie the first batch updates just
+/// before that batch is applied, for exercising retries.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct RacedRepository {
+    pub inner: InMemoryRepository,
+    pub raced: std::sync::atomic::AtomicBool,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl MovieRepository for RacedRepository {
+    async fn ping(&self) -> Result<(), RepoError> {
+        self.inner.ping().await
+    }
+
+    async fn list(&self) -> Result<Vec<Movie>, RepoError> {
+        self.inner.list().await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Movie>, RepoError> {
+        self.inner.get(id).await
+    }
+
+    async fn insert(&self, movie: Movie) -> Result<(), RepoError> {
+        self.inner.insert(movie).await
+    }
+
+    async fn update(&self, movie: Movie) -> Result<(), RepoError> {
+        self.inner.update(movie).await
+    }
+
+    async fn delete(&self, id: &str, version: Option<u64>) -> Result<Movie, RepoError> {
+        self.inner.delete(id, version).await
+    }
+
+    async fn apply(&self, writes: Vec<Write>) -> Result<(), RepoError> {
+        if !self.raced.swap(true, std::sync::atomic::Ordering::SeqCst) {
+            for write in &writes {
+                if let Write::Update(movie) = write
+                    && let Some(mut stored) = self.inner.get(&movie.id).await?
+                {
+                    stored.was_good = !stored.was_good;
+                    stored.version += 1;
+                    self.inner.update(stored).await?;
+                }
+            }
+        }
+        self.inner.apply(writes).await
+    }
+}
+
+/// Holds every batch until `release` is notified, then fails it if `fail`
+/// is set, for exercising what runs alongside a slow restore.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct StalledRepository {
+    pub inner: InMemoryRepository,
+    pub release: tokio::sync::Notify,
+    pub fail: std::sync::atomic::AtomicBool,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl MovieRepository for StalledRepository {
+    async fn ping(&self) -> Result<(), RepoError> {
+        self.inner.ping().await
+    }
+
+    async fn list(&self) -> Result<Vec<Movie>, RepoError> {
+        self.inner.list().await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Movie>, RepoError> {
+        self.inner.get(id).await
+    }
+
+    async fn insert(&self, movie: Movie) -> Result<(), RepoError> {
+        self.inner.insert(movie).await
+    }
+
+    async fn update(&self, movie: Movie) -> Result<(), RepoError> {
+        self.inner.update(movie).await
+    }
+
+    async fn delete(&self, id: &str, version: Option<u64>) -> Result<Movie, RepoError> {
+        self.inner.delete(id, version).await
+    }
+
+    async fn apply(&self, writes: Vec<Write>) -> Result<(), RepoError> {
+        self.release.notified().await;
+        if self.fail.load(std::sync::atomic::Ordering::SeqCst) {
+            return broken();
+        }
+        self.inner.apply(writes).await
+    }
+}
+
+/// Holds every `sync` until `synced` is notified, for telling writes that
+/// wait for the disk from those that do not.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct UnsyncedRepository {
+    pub inner: InMemoryRepository,
+    pub synced: tokio::sync::Notify,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl MovieRepository for UnsyncedRepository {
+    async fn ping(&self) -> Result<(), RepoError> {
+        self.inner.ping().await
+    }
+
+    async fn list(&self) -> Result<Vec<Movie>, RepoError> {
+        self.inner.list().await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Movie>, RepoError> {
+        self.inner.get(id).await
+    }
+
+    async fn insert(&self, movie: Movie) -> Result<(), RepoError> {
+        self.inner.insert(movie).await
+    }
+
+    async fn update(&self, movie: Movie) -> Result<(), RepoError> {
+        self.inner.update(movie).await
+    }
+
+    async fn delete(&self, id: &str, version: Option<u64>) -> Result<Movie, RepoError> {
+        self.inner.delete(id, version).await
+    }
+
+    async fn apply(&self, writes: Vec<Write>) -> Result<(), RepoError> {
+        self.inner.apply(writes).await
+    }
+
+    async fn sync(&self) -> Result<bool, RepoError> {
+        self.synced.notified().await;
+        Ok(true)
+    }
+}
+
+/// Fails every call, for exercising the handlers' 500 path.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct FailingRepository;
+
+#[cfg(test)]
+fn broken<T>() -> Result<T, RepoError> {
+    Err(RepoError::Backend("disk on fire".to_string()))
+}
+
+#[cfg(test)]
+#[async_trait]
+impl MovieRepository for FailingRepository {
+    async fn ping(&self) -> Result<(), RepoError> {
+        broken()
+    }
+
+    async fn list(&self) -> Result<Vec<Movie>, RepoError> {
+        broken()
+    }
+
+    async fn get(&self, _: &str) -> Result<Option<Movie>, RepoError> {
+        broken()
+    }
+
+    async fn insert(&self, _: Movie) -> Result<(), RepoError> {
+        broken()
+    }
+
+    async fn update(&self, _: Movie) -> Result<(), RepoError> {
+        broken()
+    }
+
+    async fn delete(&self, _: &str, _: Option<u64>) -> Result<Movie, RepoError> {
+        broken()
+    }
+
+    async fn apply(&self, _: Vec<Write>) -> Result<(), RepoError> {
+        broken()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    pub(super) fn movie(id: &str, name: &str) -> Movie {
+        Movie {
+            id: id.to_string(),
+            name: name.to_string(),
+            year: 1995,
+            was_good: true,
+            locked_fields: Vec::new(),
+            custom: HashMap::new(),
+            genres: Vec::new(),
+            sort_name: None,
+            director_id: None,
+            cast: Vec::new(),
+            slug: String::new(),
+            version: 1,
+            created_at: Default::default(),
+            updated_at: Default::default(),
+            deleted_at: None,
+            has_poster: false,
+        }
+    }
+
+    pub(super) fn version(mut movie: Movie, version: u64) -> Movie {
+        movie.version = version;
+        movie
+    }
+
+    fn ids(mut movies: Vec<Movie>) -> Vec<String> {
+        movies.sort_by(|a, b| a.id.cmp(&b.id));
+        movies.into_iter().map(|movie| movie.id).collect()
+    }
+
+    /// The behavior every repository must share.
+    pub(super) async fn contract(repo: &dyn MovieRepository) {
+        repo.ping().await.unwrap();
+        assert!(repo.list().await.unwrap().is_empty());
+        assert_eq!(repo.get("1").await.unwrap(), None);
+
+        repo.insert(movie("1", "Heat")).await.unwrap();
+        repo.insert(movie("2", "Ronin")).await.unwrap();
+        assert_eq!(repo.get("1").await.unwrap(), Some(movie("1", "Heat")));
+        assert_eq!(ids(repo.list().await.unwrap()), ["1", "2"]);
+        assert_eq!(repo.exists("1").await.unwrap(), Some(1));
+        assert_eq!(repo.exists("9").await.unwrap(), None);
+        assert_eq!(
+            repo.get_many(&["2".to_string(), "9".to_string(), "1".to_string()])
+                .await
+                .unwrap(),
+            [Some(movie("2", "Ronin")), None, Some(movie("1", "Heat"))]
+        );
+
+        match repo.insert(movie("1", "Thief")).await {
+            Err(RepoError::Conflict(existing)) => assert_eq!(existing.name, "Heat"),
+            other => panic!("expected a conflict, got {other:?}"),
+        }
+
+        repo.update(version(movie("1", "Heat (Director's Cut)"), 2))
+            .await
+            .unwrap();
+        assert_eq!(
+            repo.get("1").await.unwrap().unwrap().name,
+            "Heat (Director's Cut)"
+        );
+        assert!(matches!(
+            repo.update(version(movie("9", "Alien"), 2)).await,
+            Err(RepoError::NotFound(id)) if id == "9"
+        ));
+
+        // Writing over a version that was never read is refused.
+        match repo.update(version(movie("1", "Heat"), 2)).await {
+            Err(RepoError::Stale(current)) => assert_eq!(current.version, 2),
+            other => panic!("expected a stale update, got {other:?}"),
+        }
+        assert!(matches!(
+            repo.delete("2", Some(7)).await,
+            Err(RepoError::Stale(current)) if current.version == 1
+        ));
+
+        assert_eq!(repo.delete("2", Some(1)).await.unwrap().name, "Ronin");
+        assert!(matches!(
+            repo.delete("2", None).await,
+            Err(RepoError::NotFound(_))
+        ));
+
+        // A failing write leaves the whole batch unapplied.
+        let failed = repo
+            .apply(vec![
+                Write::Insert(movie("3", "Collateral")),
+                Write::Delete("1".to_string(), None),
+                Write::Update(version(movie("1", "Heat"), 3)),
+            ])
+            .await;
+        assert!(matches!(failed, Err(RepoError::NotFound(_))));
+        assert_eq!(ids(repo.list().await.unwrap()), ["1"]);
+        let failed = repo
+            .apply(vec![
+                Write::Insert(movie("3", "Collateral")),
+                Write::Delete("1".to_string(), Some(1)),
+            ])
+            .await;
+        assert!(matches!(failed, Err(RepoError::Stale(current)) if current.version == 2));
+        assert_eq!(ids(repo.list().await.unwrap()), ["1"]);
+
+        repo.apply(vec![
+            Write::Insert(movie("3", "Collateral")),
+            Write::Update(version(movie("3", "Collateral (2004)"), 2)),
+            Write::Upsert(movie("4", "Thief")),
+            Write::Delete("1".to_string(), Some(2)),
+        ])
+        .await
+        .unwrap();
+        assert_eq!(ids(repo.list().await.unwrap()), ["3", "4"]);
+        assert_eq!(
+            repo.get("3").await.unwrap().unwrap().name,
+            "Collateral (2004)"
+        );
+
+        repo.apply(vec![
+            Write::Insert(movie("10", "Manhunter")),
+            Write::Insert(movie("5", "Miami Vice")),
+        ])
+        .await
+        .unwrap();
+        let walk = |after, limit| async move {
+            let movies = repo.list_after(after, limit).await.unwrap();
+            movies.into_iter().map(|movie| movie.id).collect::<Vec<_>>()
+        };
+        assert_eq!(walk(None, 2).await, ["10", "3"]);
+        assert_eq!(walk(Some("3"), 2).await, ["4", "5"]);
+        assert_eq!(walk(Some("5"), 2).await, Vec::<String>::new());
+
+        // After a clear, the batch sees an empty store: ids that existed
+        // can be inserted again and can no longer be updated.
+        let failed = repo
+            .apply(vec![
+                Write::Clear,
+                Write::Update(version(movie("3", "Collateral"), 3)),
+            ])
+            .await;
+        assert!(matches!(failed, Err(RepoError::NotFound(_))));
+        assert_eq!(ids(repo.list().await.unwrap()), ["10", "3", "4", "5"]);
+
+        repo.apply(vec![
+            Write::Clear,
+            Write::Insert(movie("4", "Thief (1981)")),
+            Write::Upsert(movie("6", "Blackhat")),
+        ])
+        .await
+        .unwrap();
+        assert_eq!(ids(repo.list().await.unwrap()), ["4", "6"]);
+        assert_eq!(repo.get("4").await.unwrap().unwrap().name, "Thief (1981)");
+
+        // Movies in the trash are stored but do not exist for lookups.
+        let mut trashed = movie("7", "The Keep");
+        trashed.deleted_at = Some(Default::default());
+        repo.insert(trashed).await.unwrap();
+        assert_eq!(repo.exists("7").await.unwrap(), None);
+        assert_eq!(repo.exists("6").await.unwrap(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn in_memory_repository_meets_contract() {
+        contract(&InMemoryRepository::new()).await;
+    }
+
+    #[tokio::test]
+    async fn stored_repository_meets_contract() {
+        let dir = tempfile::tempdir().unwrap();
+        contract(&InMemoryRepository::open(dir.path().join("movies.json"), None).unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn sharded_repository_meets_contract() {
+        contract(&InMemoryRepository::with_shards(1, Stored::default(), None)).await;
+        contract(&InMemoryRepository::with_shards(3, Stored::default(), None)).await;
+    }
+
+    #[tokio::test]
+    async fn many_movies_are_read_between_batches() {
+        let repo = Arc::new(InMemoryRepository::with_shards(
+            SHARDS,
+            Stored::default(),
+            None,
+        ));
+        let ids: Vec<String> = (0..SHARDS * 4).map(|id| id.to_string()).collect();
+        for id in &ids {
+            repo.insert(movie(id, "Heat")).await.unwrap();
+        }
+
+        // As held by a batch updating every movie, between its check and
+        // its commit.
+        let writes: Vec<Write> = ids
+            .iter()
+            .map(|id| Write::Update(version(movie(id, "Heat (1995)"), 2)))
+            .collect();
+        let locked = repo.lock_writes(&writes).await;
+        let read = tokio::spawn({
+            let (repo, ids) = (repo.clone(), ids.clone());
+            async move { repo.get_many(&ids).await.unwrap() }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!read.is_finished());
+
+        locked.commit(writes).await.unwrap();
+        let movies = read.await.unwrap();
+        assert!(
+            movies
+                .iter()
+                .all(|movie| movie.as_ref().unwrap().version == 2)
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_creates_and_deletes_of_one_id_stay_consistent() {
+        let repo = Arc::new(InMemoryRepository::new());
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let repo = repo.clone();
+                tokio::spawn(async move {
+                    let (mut created, mut deleted) = (0, 0);
+                    for _ in 0..2000 {
+                        if repo.insert(movie("1", "Heat")).await.is_ok() {
+                            created += 1;
+                        }
+                        match repo.delete("1", None).await {
+                            Ok(movie) => {
+                                assert_eq!(movie.id, "1");
+                                deleted += 1;
+                            }
+                            Err(RepoError::NotFound(_)) => {}
+                            Err(error) => panic!("unexpected {error:?}"),
+                        }
+                        let listed = repo.list().await.unwrap();
+                        assert!(listed.len() <= 1, "{listed:?}");
+                    }
+                    (created, deleted)
+                })
+            })
+            .collect();
+
+        let (mut created, mut deleted) = (0, 0);
+        for task in tasks {
+            let (c, d) = task.await.unwrap();
+            created += c;
+            deleted += d;
+        }
+
+        let stored = repo.get("1").await.unwrap().is_some();
+        assert!(created > 0);
+        assert_eq!(created - deleted, usize::from(stored));
+        assert_eq!(repo.list().await.unwrap().len(), usize::from(stored));
+    }
+
+    /// 95% reads and 5% updates over 10k movies from many tasks at once,
+    /// against the sharded map and against a single shard, which is how the
+    /// map was locked before it was sharded. Run with
+    /// `cargo test --release -- --ignored --nocapture read_heavy` to see the
+    /// rates logged.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    #[ignore = "benchmark"]
+    async fn read_heavy_load() {
+        const KEYS: usize = 10_000;
+        const TASKS: usize = 64;
+        const OPERATIONS: usize = 50_000;
+
+        let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+        let ids: Arc<[String]> = (0..KEYS).map(|id| id.to_string()).collect();
+        for shards in [1, SHARDS] {
+            let movies = ids
+                .iter()
+                .map(|id| (id.clone(), movie(id, "Heat")))
+                .collect();
+            let repo = Arc::new(InMemoryRepository::with_shards(
+                shards,
+                (movies, BTreeMap::new()),
+                None,
+            ));
+
+            let start = Instant::now();
+            let tasks: Vec<_> = (0..TASKS)
+                .map(|task| {
+                    let (repo, ids) = (repo.clone(), ids.clone());
+                    tokio::spawn(async move {
+                        for operation in 0..OPERATIONS {
+                            let id = &ids[(task * 7919 + operation * 104_729) % KEYS];
+                            let movie = repo.get(id).await.unwrap().unwrap();
+                            if operation % 20 == 0 {
+                                // Another task may have updated it since.
+                                let _ =
+                                    repo.update(version(movie.clone(), movie.version + 1)).await;
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for task in tasks {
+                task.await.unwrap();
+            }
+
+            let elapsed = start.elapsed();
+            let rate = (TASKS * OPERATIONS) as f64 / elapsed.as_secs_f64();
+            tracing::info!(
+                shards,
+                ?elapsed,
+                per_second = rate as u64,
+                "operations done"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn vec_repository_meets_contract() {
+        contract(&VecRepository::default()).await;
+    }
+}
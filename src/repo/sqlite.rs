@@ -0,0 +1,526 @@
+//! A durable repository on SQLite. `rusqlite` is synchronous, so every call
+//! runs on tokio's blocking pool with the one connection locked.
+
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use movies::model::{Movie, Person};
+use rusqlite::types::Type;
+use rusqlite::{Connection, ErrorCode, OptionalExtension, Row, Transaction, params};
+use serde::de::DeserializeOwned;
+
+use super::{MovieRepository, RepoError, Write};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS movies (
+        id TEXT PRIMARY KEY NOT NULL,
+        name TEXT NOT NULL,
+        year INTEGER NOT NULL,
+        was_good INTEGER NOT NULL,
+        locked_fields TEXT NOT NULL,
+        custom TEXT NOT NULL,
+        sort_name TEXT,
+        slug TEXT NOT NULL,
+        version INTEGER NOT NULL DEFAULT 0,
+        genres TEXT NOT NULL DEFAULT '[]',
+        deleted_at TEXT,
+        created_at TEXT NOT NULL DEFAULT '1970-01-01T00:00:00Z',
+        updated_at TEXT NOT NULL DEFAULT '1970-01-01T00:00:00Z',
+        has_poster INTEGER NOT NULL DEFAULT 0,
+        director_id TEXT,
+        cast_ids TEXT NOT NULL DEFAULT '[]'
+    );
+    CREATE TABLE IF NOT EXISTS people (
+        id TEXT PRIMARY KEY NOT NULL,
+        name TEXT NOT NULL
+    );
+";
+
+/// Columns added after the first release, added to older databases with
+/// the same definition as in `SCHEMA`.
+const ADDED_COLUMNS: [(&str, &str); 8] = [
+    ("version", "INTEGER NOT NULL DEFAULT 0"),
+    ("genres", "TEXT NOT NULL DEFAULT '[]'"),
+    ("deleted_at", "TEXT"),
+    ("created_at", "TEXT NOT NULL DEFAULT '1970-01-01T00:00:00Z'"),
+    ("updated_at", "TEXT NOT NULL DEFAULT '1970-01-01T00:00:00Z'"),
+    ("has_poster", "INTEGER NOT NULL DEFAULT 0"),
+    ("director_id", "TEXT"),
+    ("cast_ids", "TEXT NOT NULL DEFAULT '[]'"),
+];
+
+const COLUMNS: &str = "id, name, year, was_good, locked_fields, custom, sort_name, slug, version, \
+     genres, deleted_at, created_at, updated_at, has_poster, director_id, cast_ids";
+
+#[derive(Debug, Clone)]
+pub struct SqliteRepository {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteRepository {
+    /// Opens `url`, either `sqlite://<path>` for a file created when missing
+    /// or `sqlite::memory:` for a private in-memory database, and creates the
+    /// schema if needed.
+    pub fn open(url: &str) -> Result<Self, RepoError> {
+        let conn = match url.strip_prefix("sqlite:") {
+            Some(":memory:") => Connection::open_in_memory(),
+            Some(path) => Connection::open(path.strip_prefix("//").unwrap_or(path)),
+            None => {
+                return Err(RepoError::Backend(format!(
+                    "unsupported database url {url}, expected sqlite://<path> or sqlite::memory:"
+                )));
+            }
+        }
+        .map_err(backend)?;
+        conn.execute_batch(SCHEMA).map_err(backend)?;
+        migrate(&conn).map_err(backend)?;
+
+        Ok(SqliteRepository {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Runs `f` on the blocking pool with the connection locked.
+    async fn with_conn<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> Result<T, RepoError> + Send + 'static,
+    ) -> Result<T, RepoError> {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn
+                .lock()
+                .map_err(|_| RepoError::Backend("connection lock was poisoned".to_string()))?;
+            f(&mut conn)
+        })
+        .await
+        .map_err(|error| RepoError::Backend(error.to_string()))?
+    }
+}
+
+/// Brings a database created by an earlier release up to the schema.
+fn migrate(conn: &Connection) -> rusqlite::Result<()> {
+    for (column, definition) in ADDED_COLUMNS {
+        let exists = conn
+            .prepare("SELECT 1 FROM pragma_table_info('movies') WHERE name = ?1")?
+            .exists([column])?;
+        if !exists {
+            conn.execute_batch(&format!(
+                "ALTER TABLE movies ADD COLUMN {column} {definition}"
+            ))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn backend(error: impl ToString) -> RepoError {
+    RepoError::Backend(error.to_string())
+}
+
+/// Reads a column holding JSON text, as `locked_fields`, `custom`, `genres`
+/// and `cast_ids` do.
+fn json_column<T: DeserializeOwned>(row: &Row, index: usize) -> rusqlite::Result<T> {
+    let text: String = row.get(index)?;
+    serde_json::from_str(&text).map_err(|error| {
+        rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(error))
+    })
+}
+
+/// SQLite integers are signed, so versions are stored as `i64`.
+fn version_column(row: &Row, index: usize) -> rusqlite::Result<u64> {
+    let version: i64 = row.get(index)?;
+    u64::try_from(version).map_err(|error| {
+        rusqlite::Error::FromSqlConversionFailure(index, Type::Integer, Box::new(error))
+    })
+}
+
+fn from_row(row: &Row) -> rusqlite::Result<Movie> {
+    Ok(Movie {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        year: row.get(2)?,
+        was_good: row.get(3)?,
+        locked_fields: json_column(row, 4)?,
+        custom: json_column(row, 5)?,
+        sort_name: row.get(6)?,
+        slug: row.get(7)?,
+        version: version_column(row, 8)?,
+        genres: json_column(row, 9)?,
+        deleted_at: row.get(10)?,
+        created_at: row.get(11)?,
+        updated_at: row.get(12)?,
+        has_poster: row.get(13)?,
+        director_id: row.get(14)?,
+        cast: json_column(row, 15)?,
+    })
+}
+
+fn get(conn: &Connection, id: &str) -> Result<Option<Movie>, RepoError> {
+    conn.query_row(
+        &format!("SELECT {COLUMNS} FROM movies WHERE id = ?1"),
+        params![id],
+        from_row,
+    )
+    .optional()
+    .map_err(backend)
+}
+
+/// Runs one write of a batch inside `tx`.
+fn write(tx: &Transaction, write: &Write) -> Result<(), RepoError> {
+    let changed = match write {
+        Write::Insert(movie) => {
+            match tx.execute(
+                &format!(
+                    "INSERT INTO movies ({COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)"
+                ),
+                bind(movie)?,
+            ) {
+                Err(rusqlite::Error::SqliteFailure(error, _))
+                    if error.code == ErrorCode::ConstraintViolation =>
+                {
+                    let existing = get(tx, &movie.id)?.ok_or_else(|| {
+                        backend(format!("constraint violated inserting movie {}", movie.id))
+                    })?;
+                    return Err(RepoError::Conflict(Box::new(existing)));
+                }
+                result => result.map_err(backend)?,
+            }
+        }
+        Write::Update(movie) => {
+            let changed = tx
+                .execute(
+                    "UPDATE movies SET name = ?2, year = ?3, was_good = ?4, locked_fields = ?5,
+                        custom = ?6, sort_name = ?7, slug = ?8, version = ?9, genres = ?10,
+                        deleted_at = ?11, created_at = ?12, updated_at = ?13, has_poster = ?14,
+                        director_id = ?15, cast_ids = ?16
+                     WHERE id = ?1 AND version = ?9 - 1",
+                    bind(movie)?,
+                )
+                .map_err(backend)?;
+            if changed == 0
+                && let Some(current) = get(tx, &movie.id)?
+            {
+                return Err(RepoError::Stale(Box::new(current)));
+            }
+            changed
+        }
+        Write::Upsert(movie) => tx
+            .execute(
+                &format!(
+                    "INSERT INTO movies ({COLUMNS})
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)
+                     ON CONFLICT(id) DO UPDATE SET name = ?2, year = ?3, was_good = ?4,
+                        locked_fields = ?5, custom = ?6, sort_name = ?7, slug = ?8,
+                        version = ?9, genres = ?10, deleted_at = ?11,
+                        created_at = ?12, updated_at = ?13, has_poster = ?14,
+                        director_id = ?15, cast_ids = ?16"
+                ),
+                bind(movie)?,
+            )
+            .map_err(backend)?,
+        Write::Delete(id, version) => {
+            let changed = tx
+                .execute(
+                    "DELETE FROM movies WHERE id = ?1 AND (?2 IS NULL OR version = ?2)",
+                    params![id, version.map(i64::try_from).transpose().map_err(backend)?],
+                )
+                .map_err(backend)?;
+            if changed == 0
+                && let Some(current) = get(tx, id)?
+            {
+                return Err(RepoError::Stale(Box::new(current)));
+            }
+            changed
+        }
+        Write::Clear => {
+            tx.execute("DELETE FROM movies", []).map_err(backend)?;
+            return Ok(());
+        }
+    };
+
+    if changed == 0
+        && let Some(id) = write.id()
+    {
+        return Err(RepoError::NotFound(id.to_string()));
+    }
+
+    Ok(())
+}
+
+type Binding = (
+    String,
+    String,
+    u16,
+    bool,
+    String,
+    String,
+    Option<String>,
+    String,
+    i64,
+    String,
+    Option<DateTime<Utc>>,
+    DateTime<Utc>,
+    DateTime<Utc>,
+    bool,
+    Option<String>,
+    String,
+);
+
+fn bind(movie: &Movie) -> Result<Binding, RepoError> {
+    Ok((
+        movie.id.clone(),
+        movie.name.clone(),
+        movie.year,
+        movie.was_good,
+        serde_json::to_string(&movie.locked_fields).map_err(backend)?,
+        serde_json::to_string(&movie.custom).map_err(backend)?,
+        movie.sort_name.clone(),
+        movie.slug.clone(),
+        i64::try_from(movie.version).map_err(backend)?,
+        serde_json::to_string(&movie.genres).map_err(backend)?,
+        movie.deleted_at,
+        movie.created_at,
+        movie.updated_at,
+        movie.has_poster,
+        movie.director_id.clone(),
+        serde_json::to_string(&movie.cast).map_err(backend)?,
+    ))
+}
+
+#[async_trait]
+impl MovieRepository for SqliteRepository {
+    async fn ping(&self) -> Result<(), RepoError> {
+        self.with_conn(|conn| conn.query_row("SELECT 1", [], |_| Ok(())).map_err(backend))
+            .await
+    }
+
+    async fn list(&self) -> Result<Vec<Movie>, RepoError> {
+        self.with_conn(|conn| {
+            let mut statement = conn
+                .prepare(&format!("SELECT {COLUMNS} FROM movies"))
+                .map_err(backend)?;
+            statement
+                .query_map([], from_row)
+                .map_err(backend)?
+                .collect::<rusqlite::Result<_>>()
+                .map_err(backend)
+        })
+        .await
+    }
+
+    async fn list_after(&self, after: Option<&str>, limit: usize) -> Result<Vec<Movie>, RepoError> {
+        // Ids are never empty, so "" starts from the first.
+        let after = after.unwrap_or_default().to_string();
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+        self.with_conn(move |conn| {
+            let mut statement = conn
+                .prepare(&format!(
+                    "SELECT {COLUMNS} FROM movies WHERE id > ?1 ORDER BY id LIMIT ?2"
+                ))
+                .map_err(backend)?;
+            statement
+                .query_map(rusqlite::params![after, limit], from_row)
+                .map_err(backend)?
+                .collect::<rusqlite::Result<_>>()
+                .map_err(backend)
+        })
+        .await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Movie>, RepoError> {
+        let id = id.to_string();
+        self.with_conn(move |conn| get(conn, &id)).await
+    }
+
+    async fn exists(&self, id: &str) -> Result<Option<u64>, RepoError> {
+        let id = id.to_string();
+        self.with_conn(move |conn| {
+            conn.query_row(
+                "SELECT version FROM movies WHERE id = ?1 AND deleted_at IS NULL",
+                params![id],
+                |row| version_column(row, 0),
+            )
+            .optional()
+            .map_err(backend)
+        })
+        .await
+    }
+
+    /// Reads every movie in one transaction.
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Option<Movie>>, RepoError> {
+        let ids = ids.to_vec();
+        self.with_conn(move |conn| {
+            let tx = conn.transaction().map_err(backend)?;
+            ids.iter().map(|id| get(&tx, id)).collect()
+        })
+        .await
+    }
+
+    async fn insert(&self, movie: Movie) -> Result<(), RepoError> {
+        self.apply(vec![Write::Insert(movie)]).await
+    }
+
+    async fn update(&self, movie: Movie) -> Result<(), RepoError> {
+        self.apply(vec![Write::Update(movie)]).await
+    }
+
+    async fn delete(&self, id: &str, version: Option<u64>) -> Result<Movie, RepoError> {
+        let id = id.to_string();
+        self.with_conn(move |conn| {
+            let tx = conn.transaction().map_err(backend)?;
+            let movie = get(&tx, &id)?.ok_or_else(|| RepoError::NotFound(id.clone()))?;
+            if version.is_some_and(|version| version != movie.version) {
+                return Err(RepoError::Stale(Box::new(movie)));
+            }
+            write(&tx, &Write::Delete(id, None))?;
+            tx.commit().map_err(backend)?;
+
+            Ok(movie)
+        })
+        .await
+    }
+
+    async fn apply(&self, writes: Vec<Write>) -> Result<(), RepoError> {
+        self.with_conn(move |conn| {
+            // Dropping the transaction on an early return rolls it back.
+            let tx = conn.transaction().map_err(backend)?;
+            for w in &writes {
+                write(&tx, w)?;
+            }
+            tx.commit().map_err(backend)
+        })
+        .await
+    }
+
+    async fn people(&self) -> Result<Vec<Person>, RepoError> {
+        self.with_conn(|conn| {
+            let mut statement = conn
+                .prepare("SELECT id, name FROM people")
+                .map_err(backend)?;
+            statement
+                .query_map([], |row| {
+                    Ok(Person {
+                        id: row.get(0)?,
+                        name: row.get(1)?,
+                    })
+                })
+                .map_err(backend)?
+                .collect::<rusqlite::Result<_>>()
+                .map_err(backend)
+        })
+        .await
+    }
+
+    async fn put_person(&self, person: &Person) -> Result<(), RepoError> {
+        let person = person.clone();
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO people (id, name) VALUES (?1, ?2)
+                 ON CONFLICT(id) DO UPDATE SET name = ?2",
+                params![person.id, person.name],
+            )
+            .map(drop)
+            .map_err(backend)
+        })
+        .await
+    }
+
+    async fn delete_person(&self, id: &str) -> Result<(), RepoError> {
+        let id = id.to_string();
+        self.with_conn(move |conn| {
+            conn.execute("DELETE FROM people WHERE id = ?1", params![id])
+                .map(drop)
+                .map_err(backend)
+        })
+        .await
+    }
+
+    /// Every statement is committed to the file before it returns, so there
+    /// is nothing to wait for; only an in-memory database is not durable.
+    async fn sync(&self) -> Result<bool, RepoError> {
+        self.with_conn(|conn| Ok(conn.path().is_some_and(|path| !path.is_empty())))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::tests::{contract, movie};
+
+    #[tokio::test]
+    async fn sqlite_repository_meets_contract() {
+        contract(&SqliteRepository::open("sqlite::memory:").unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn sqlite_repository_survives_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite://{}", dir.path().join("movies.db").display());
+
+        let mut heat = movie("1", "Heat");
+        heat.custom.insert("rewatches".to_string(), 2.into());
+        heat.locked_fields = vec!["name".to_string()];
+        heat.sort_name = Some("Heat".to_string());
+        heat.genres = vec!["crime".to_string()];
+        heat.created_at = Utc::now();
+        heat.updated_at = heat.created_at;
+        heat.deleted_at = Some(Utc::now());
+        SqliteRepository::open(&url)
+            .unwrap()
+            .insert(heat.clone())
+            .await
+            .unwrap();
+
+        let pacino = Person {
+            id: "pacino".to_string(),
+            name: "Al Pacino".to_string(),
+        };
+        let repo = SqliteRepository::open(&url).unwrap();
+        repo.put_person(&pacino).await.unwrap();
+        repo.put_person(&Person {
+            id: "mann".to_string(),
+            name: "Michael Mann".to_string(),
+        })
+        .await
+        .unwrap();
+        repo.delete_person("mann").await.unwrap();
+
+        let repo = SqliteRepository::open(&url).unwrap();
+        assert_eq!(repo.get("1").await.unwrap(), Some(heat));
+        assert_eq!(repo.people().await.unwrap(), vec![pacino]);
+    }
+
+    #[tokio::test]
+    async fn databases_from_the_first_release_are_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movies.db");
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE movies (
+                    id TEXT PRIMARY KEY NOT NULL, name TEXT NOT NULL, year INTEGER NOT NULL,
+                    was_good INTEGER NOT NULL, locked_fields TEXT NOT NULL, custom TEXT NOT NULL,
+                    sort_name TEXT, slug TEXT NOT NULL
+                );
+                INSERT INTO movies VALUES ('1', 'Heat', 1995, 1, '[]', '{}', NULL, 'heat-1995');",
+            )
+            .unwrap();
+
+        let repo = SqliteRepository::open(&format!("sqlite://{}", path.display())).unwrap();
+        let movie = repo.get("1").await.unwrap().unwrap();
+        assert_eq!(movie.version, 0);
+        assert!(movie.genres.is_empty());
+        assert_eq!(movie.deleted_at, None);
+        assert_eq!(movie.created_at, DateTime::<Utc>::default());
+    }
+
+    #[test]
+    fn unsupported_urls_are_rejected() {
+        assert!(matches!(
+            SqliteRepository::open("postgres://localhost/movies"),
+            Err(RepoError::Backend(_))
+        ));
+    }
+}
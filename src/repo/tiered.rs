@@ -0,0 +1,583 @@
+//! A store for libraries bigger than should stay in memory: every movie is
+//! kept in a file of its own by [`FileRepository`], and [`TieredRepository`]
+//! keeps the most recently used of them resident in front of it. Cold
+//! movies are read back from their file when asked for, so only latency
+//! differs from a store held in memory entirely.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use movies::model::{Movie, Person};
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::{MovieRepository, RepoError, Write, check};
+use crate::sync::LockExt;
+
+/// One JSON file per movie under `movies/` and per person under `people/`,
+/// named after the hex of the id so any id makes a valid file name. Files
+/// are written to a temporary name, synced and renamed into place, so a
+/// crash leaves each movie either as it was or as written.
+#[derive(Debug, Clone)]
+pub struct FileRepository {
+    dir: Arc<Dir>,
+    /// Held by a write from checking it until its files are in place, owned
+    /// so a write its caller stopped waiting for still completes.
+    writer: Arc<Mutex<()>>,
+}
+
+#[derive(Debug)]
+struct Dir {
+    movies: PathBuf,
+    people: PathBuf,
+}
+
+/// What `exists` reads of a movie file, skipping the rest of it.
+#[derive(Deserialize)]
+struct Existing {
+    #[serde(default)]
+    version: u64,
+    #[serde(default)]
+    deleted_at: Option<IgnoredAny>,
+}
+
+fn backend(error: impl ToString) -> RepoError {
+    RepoError::Backend(error.to_string())
+}
+
+fn file_name(id: &str) -> String {
+    let mut name: String = id.bytes().map(|byte| format!("{byte:02x}")).collect();
+    name.push_str(".json");
+    name
+}
+
+fn read<T: DeserializeOwned>(path: &Path) -> io::Result<Option<T>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Every record in `dir`, skipping temporary files a crash left behind.
+fn read_all<T: DeserializeOwned>(dir: &Path) -> io::Result<Vec<T>> {
+    let mut records = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            records.extend(read(&path)?);
+        }
+    }
+    Ok(records)
+}
+
+fn write<T: Serialize>(path: &Path, record: &T) -> io::Result<()> {
+    let temp = path.with_extension("tmp");
+    let mut file = fs::File::create(&temp)?;
+    file.write_all(&serde_json::to_vec(record)?)?;
+    file.sync_all()?;
+    fs::rename(&temp, path)
+}
+
+fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+impl Dir {
+    fn movie(&self, id: &str) -> PathBuf {
+        self.movies.join(file_name(id))
+    }
+
+    fn person(&self, id: &str) -> PathBuf {
+        self.people.join(file_name(id))
+    }
+
+    /// Checks `writes` against the files and then writes them. Every check
+    /// happens before the first file changes, so only a failing disk can
+    /// leave a batch applied in part.
+    fn apply(&self, writes: &[Write]) -> Result<(), RepoError> {
+        let mut stored: HashMap<&str, Option<Movie>> = HashMap::new();
+        for id in writes.iter().filter_map(Write::id) {
+            if !stored.contains_key(id) {
+                stored.insert(id, read(&self.movie(id)).map_err(backend)?);
+            }
+        }
+        check(|id| stored.get(id).and_then(Option::as_ref), writes)?;
+
+        for write in writes {
+            match write {
+                Write::Insert(movie) | Write::Update(movie) | Write::Upsert(movie) => {
+                    self::write(&self.movie(&movie.id), movie)
+                }
+                Write::Delete(id, _) => remove(&self.movie(id)),
+                Write::Clear => fs::read_dir(&self.movies).and_then(|mut entries| {
+                    entries.try_for_each(|entry| fs::remove_file(entry?.path()))
+                }),
+            }
+            .map_err(|error| {
+                tracing::error!(path = %self.movies.display(), %error, "failed to write a movie file");
+                backend(error)
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl FileRepository {
+    /// Keeps the movies and people in `dir`, creating it if needed.
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = Dir {
+            movies: dir.as_ref().join("movies"),
+            people: dir.as_ref().join("people"),
+        };
+        fs::create_dir_all(&dir.movies)?;
+        fs::create_dir_all(&dir.people)?;
+        Ok(FileRepository {
+            dir: Arc::new(dir),
+            writer: Arc::default(),
+        })
+    }
+
+    /// Runs `f` on a blocking thread, since every call waits for the disk.
+    async fn blocking<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Dir) -> Result<T, RepoError> + Send + 'static,
+    ) -> Result<T, RepoError> {
+        let dir = self.dir.clone();
+        tokio::task::spawn_blocking(move || f(&dir))
+            .await
+            .map_err(backend)?
+    }
+}
+
+#[async_trait]
+impl MovieRepository for FileRepository {
+    async fn ping(&self) -> Result<(), RepoError> {
+        self.blocking(|dir| fs::metadata(&dir.movies).map(drop).map_err(backend))
+            .await
+    }
+
+    /// Reads one file at a time, so a batch written meanwhile may show up
+    /// only in part.
+    async fn list(&self) -> Result<Vec<Movie>, RepoError> {
+        self.blocking(|dir| read_all(&dir.movies).map_err(backend))
+            .await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Movie>, RepoError> {
+        let id = id.to_string();
+        self.blocking(move |dir| read(&dir.movie(&id)).map_err(backend))
+            .await
+    }
+
+    async fn exists(&self, id: &str) -> Result<Option<u64>, RepoError> {
+        let id = id.to_string();
+        self.blocking(move |dir| {
+            let existing = read::<Existing>(&dir.movie(&id)).map_err(backend)?;
+            Ok(existing
+                .filter(|existing| existing.deleted_at.is_none())
+                .map(|existing| existing.version))
+        })
+        .await
+    }
+
+    /// Holds the writer lock while reading, so no batch is half written.
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Option<Movie>>, RepoError> {
+        let writer = self.writer.clone().lock_owned().await;
+        let ids = ids.to_vec();
+        self.blocking(move |dir| {
+            let movies: io::Result<Vec<Option<Movie>>> =
+                ids.iter().map(|id| read(&dir.movie(id))).collect();
+            drop(writer);
+            movies.map_err(backend)
+        })
+        .await
+    }
+
+    async fn insert(&self, movie: Movie) -> Result<(), RepoError> {
+        self.apply(vec![Write::Insert(movie)]).await
+    }
+
+    async fn update(&self, movie: Movie) -> Result<(), RepoError> {
+        self.apply(vec![Write::Update(movie)]).await
+    }
+
+    async fn delete(&self, id: &str, version: Option<u64>) -> Result<Movie, RepoError> {
+        let writer = self.writer.clone().lock_owned().await;
+        let id = id.to_string();
+        self.blocking(move |dir| {
+            let current = read::<Movie>(&dir.movie(&id))
+                .map_err(backend)?
+                .ok_or_else(|| RepoError::NotFound(id.clone()))?;
+            if version.is_some_and(|version| version != current.version) {
+                return Err(RepoError::Stale(Box::new(current)));
+            }
+            remove(&dir.movie(&id)).map_err(backend)?;
+            drop(writer);
+            Ok(current)
+        })
+        .await
+    }
+
+    async fn apply(&self, writes: Vec<Write>) -> Result<(), RepoError> {
+        let writer = self.writer.clone().lock_owned().await;
+        self.blocking(move |dir| {
+            dir.apply(&writes)?;
+            drop(writer);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Every file is synced before it is renamed into place.
+    async fn sync(&self) -> Result<bool, RepoError> {
+        Ok(true)
+    }
+
+    async fn people(&self) -> Result<Vec<Person>, RepoError> {
+        self.blocking(|dir| read_all(&dir.people).map_err(backend))
+            .await
+    }
+
+    async fn put_person(&self, person: &Person) -> Result<(), RepoError> {
+        let person = person.clone();
+        self.blocking(move |dir| write(&dir.person(&person.id), &person).map_err(backend))
+            .await
+    }
+
+    async fn delete_person(&self, id: &str) -> Result<(), RepoError> {
+        let id = id.to_string();
+        self.blocking(move |dir| remove(&dir.person(&id)).map_err(backend))
+            .await
+    }
+}
+
+/// The resident movies, each with the tick it was last used at.
+#[derive(Debug)]
+struct Resident {
+    target: usize,
+    movies: HashMap<String, (Movie, u64)>,
+    /// The ids by the tick they were last used at, least recent first.
+    order: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+impl Resident {
+    fn get(&mut self, id: &str) -> Option<Movie> {
+        let (movie, used) = self.movies.get_mut(id)?;
+        self.order.remove(&*used);
+        self.tick += 1;
+        *used = self.tick;
+        self.order.insert(self.tick, id.to_string());
+        Some(movie.clone())
+    }
+
+    /// Makes `movie` the most recently used, and spills the least recently
+    /// used ones beyond the target.
+    fn put(&mut self, movie: Movie) {
+        self.remove(&movie.id);
+        self.tick += 1;
+        self.order.insert(self.tick, movie.id.clone());
+        self.movies.insert(movie.id.clone(), (movie, self.tick));
+
+        while self.movies.len() > self.target {
+            let Some((_, id)) = self.order.pop_first() else {
+                break;
+            };
+            self.movies.remove(&id);
+        }
+    }
+
+    fn remove(&mut self, id: &str) {
+        if let Some((_, used)) = self.movies.remove(id) {
+            self.order.remove(&used);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.movies.clear();
+        self.order.clear();
+    }
+}
+
+/// Keeps up to a target number of recently used movies in memory in front
+/// of `cold`, which holds them all. Writes go through to `cold` before they
+/// are answered, so evicting a movie only drops it from memory; lists are
+/// read from `cold` and stay complete, without turning every movie resident.
+#[derive(Debug)]
+pub struct TieredRepository<R> {
+    cold: R,
+    resident: RwLock<Resident>,
+    /// Taken exclusively by writes and shared by reads that miss, so a miss
+    /// never makes resident a movie a write is replacing.
+    writing: tokio::sync::RwLock<()>,
+}
+
+impl<R: MovieRepository> TieredRepository<R> {
+    /// Keeps at most `target` movies of `cold` resident; at least one.
+    pub fn new(cold: R, target: usize) -> Self {
+        TieredRepository {
+            cold,
+            resident: RwLock::new(Resident {
+                target: target.max(1),
+                movies: HashMap::new(),
+                order: BTreeMap::new(),
+                tick: 0,
+            }),
+            writing: tokio::sync::RwLock::new(()),
+        }
+    }
+
+    /// Drops what `writes` touch from memory before they reach `cold`, so a
+    /// write its caller stopped waiting for leaves no stale movie behind.
+    fn evict(&self, writes: &[Write]) {
+        let mut resident = self.resident.write_or_recover();
+        for write in writes {
+            match write.id() {
+                Some(id) => resident.remove(id),
+                None => resident.clear(),
+            }
+        }
+    }
+
+    /// Makes what `writes` left behind resident, once `cold` has them.
+    fn keep(&self, writes: Vec<Write>) {
+        let mut resident = self.resident.write_or_recover();
+        for write in writes {
+            match write {
+                Write::Insert(movie) | Write::Update(movie) | Write::Upsert(movie) => {
+                    resident.put(movie)
+                }
+                Write::Delete(id, _) => resident.remove(&id),
+                Write::Clear => resident.clear(),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    fn resident(&self) -> usize {
+        self.resident.read_or_recover().movies.len()
+    }
+}
+
+#[async_trait]
+impl<R: MovieRepository> MovieRepository for TieredRepository<R> {
+    async fn ping(&self) -> Result<(), RepoError> {
+        self.cold.ping().await
+    }
+
+    async fn list(&self) -> Result<Vec<Movie>, RepoError> {
+        self.cold.list().await
+    }
+
+    async fn list_after(&self, after: Option<&str>, limit: usize) -> Result<Vec<Movie>, RepoError> {
+        self.cold.list_after(after, limit).await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Movie>, RepoError> {
+        if let Some(movie) = self.resident.write_or_recover().get(id) {
+            return Ok(Some(movie));
+        }
+        Ok(self.get_many(&[id.to_string()]).await?.remove(0))
+    }
+
+    /// Answered from memory when the movie is resident, without making it
+    /// resident otherwise.
+    async fn exists(&self, id: &str) -> Result<Option<u64>, RepoError> {
+        let resident = self
+            .resident
+            .read_or_recover()
+            .movies
+            .get(id)
+            .map(|(movie, _)| (movie.deleted_at.is_none(), movie.version));
+        match resident {
+            Some((live, version)) => Ok(live.then_some(version)),
+            None => self.cold.exists(id).await,
+        }
+    }
+
+    /// Reads the movies not resident with `cold.get_many`, which waits for
+    /// a write `cold` is still applying, and makes them resident.
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Option<Movie>>, RepoError> {
+        let _writing = self.writing.read().await;
+        let mut movies: Vec<Option<Movie>> = {
+            let mut resident = self.resident.write_or_recover();
+            ids.iter().map(|id| resident.get(id)).collect()
+        };
+        let missing: Vec<String> = ids
+            .iter()
+            .zip(&movies)
+            .filter(|(_, movie)| movie.is_none())
+            .map(|(id, _)| id.clone())
+            .collect();
+        if missing.is_empty() {
+            return Ok(movies);
+        }
+
+        let mut loaded = self.cold.get_many(&missing).await?.into_iter();
+        let mut resident = self.resident.write_or_recover();
+        for movie in movies.iter_mut().filter(|movie| movie.is_none()) {
+            *movie = loaded.next().flatten();
+            if let Some(movie) = movie {
+                resident.put(movie.clone());
+            }
+        }
+        Ok(movies)
+    }
+
+    async fn insert(&self, movie: Movie) -> Result<(), RepoError> {
+        self.apply(vec![Write::Insert(movie)]).await
+    }
+
+    async fn update(&self, movie: Movie) -> Result<(), RepoError> {
+        self.apply(vec![Write::Update(movie)]).await
+    }
+
+    async fn delete(&self, id: &str, version: Option<u64>) -> Result<Movie, RepoError> {
+        let _writing = self.writing.write().await;
+        self.resident.write_or_recover().remove(id);
+        self.cold.delete(id, version).await
+    }
+
+    async fn apply(&self, writes: Vec<Write>) -> Result<(), RepoError> {
+        let _writing = self.writing.write().await;
+        self.evict(&writes);
+        self.cold.apply(writes.clone()).await?;
+        self.keep(writes);
+        Ok(())
+    }
+
+    async fn compact(&self) -> Result<(), RepoError> {
+        self.cold.compact().await
+    }
+
+    async fn flush(&self) -> Result<(), RepoError> {
+        self.cold.flush().await
+    }
+
+    async fn sync(&self) -> Result<bool, RepoError> {
+        self.cold.sync().await
+    }
+
+    async fn people(&self) -> Result<Vec<Person>, RepoError> {
+        self.cold.people().await
+    }
+
+    async fn put_person(&self, person: &Person) -> Result<(), RepoError> {
+        self.cold.put_person(person).await
+    }
+
+    async fn delete_person(&self, id: &str) -> Result<(), RepoError> {
+        self.cold.delete_person(id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::InMemoryRepository;
+    use crate::repo::tests::{contract, movie, version};
+
+    #[tokio::test]
+    async fn file_repository_meets_contract() {
+        let dir = tempfile::tempdir().unwrap();
+        contract(&FileRepository::open(dir.path()).unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn tiered_repository_meets_contract() {
+        let dir = tempfile::tempdir().unwrap();
+        contract(&TieredRepository::new(
+            FileRepository::open(dir.path()).unwrap(),
+            1,
+        ))
+        .await;
+        contract(&TieredRepository::new(InMemoryRepository::new(), 2)).await;
+    }
+
+    #[tokio::test]
+    async fn cold_movies_are_spilled_and_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = TieredRepository::new(FileRepository::open(dir.path()).unwrap(), 3);
+        let ids: Vec<String> = (0..20).map(|id| id.to_string()).collect();
+        for id in &ids {
+            repo.insert(movie(id, "Heat")).await.unwrap();
+        }
+        assert_eq!(repo.resident(), 3);
+
+        // Hot movies stay resident while cold ones are read back in turn.
+        for round in 0..3 {
+            for id in &ids {
+                assert_eq!(repo.get("0").await.unwrap().unwrap().id, "0");
+                let read = repo.get(id).await.unwrap().unwrap();
+                assert_eq!(read.id, *id);
+                assert_eq!(read.version, round + 1);
+                repo.update(version(read, round + 2)).await.unwrap();
+                assert!(repo.resident() <= 3);
+            }
+        }
+        assert!(repo.resident.read_or_recover().movies.contains_key("0"));
+
+        let mut listed: Vec<String> = repo
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|movie| movie.id)
+            .collect();
+        listed.sort();
+        let mut expected = ids.clone();
+        expected.sort();
+        assert_eq!(listed, expected);
+        assert!(
+            repo.get_many(&ids)
+                .await
+                .unwrap()
+                .iter()
+                .all(|movie| movie.as_ref().unwrap().version == 4)
+        );
+        assert!(repo.resident() <= 3);
+
+        repo.delete("7", None).await.unwrap();
+        assert_eq!(repo.get("7").await.unwrap(), None);
+
+        // Everything not deleted is read back from the files after a restart.
+        let reopened = TieredRepository::new(FileRepository::open(dir.path()).unwrap(), 3);
+        assert_eq!(reopened.resident(), 0);
+        assert_eq!(reopened.list().await.unwrap().len(), 19);
+        assert_eq!(reopened.get("19").await.unwrap().unwrap().version, 4);
+        assert_eq!(fs::read_dir(dir.path().join("movies")).unwrap().count(), 19);
+    }
+
+    #[tokio::test]
+    async fn people_are_kept_in_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let pacino = Person {
+            id: "pacino".to_string(),
+            name: "Al Pacino".to_string(),
+        };
+        let repo = FileRepository::open(dir.path()).unwrap();
+        repo.put_person(&pacino).await.unwrap();
+        repo.put_person(&Person {
+            id: "mann".to_string(),
+            name: "Michael Mann".to_string(),
+        })
+        .await
+        .unwrap();
+        repo.delete_person("mann").await.unwrap();
+
+        let repo = FileRepository::open(dir.path()).unwrap();
+        assert_eq!(repo.people().await.unwrap(), vec![pacino]);
+    }
+}
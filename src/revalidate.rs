@@ -0,0 +1,86 @@
+//! `POST /movie/revalidate`: many cached movies checked in one request. The
+//! client sends the ETag it holds for each id and hears, per id, whether
+//! that copy is still current, what replaced it, or that the movie is gone.
+//! Every movie is read at one point in time, so a batch written meanwhile
+//! shows up in all of its movies or in none.
+
+use std::collections::BTreeMap;
+
+use axum::{extract::State, response::Json};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::AppState;
+use crate::conditional::{self, IfNoneMatch};
+use crate::errors::{ApiError, ErrorBody};
+use crate::extract::JsonBody;
+use crate::ratings::RatedMovie;
+
+/// Most ids one request may revalidate.
+pub const MAX_REVALIDATE: usize = 500;
+
+/// What became of one cached movie.
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Revalidated {
+    /// The tag sent is the movie's current one.
+    Unchanged,
+    /// The movie changed since; `movie` is what `GET /movie/{id}` answers
+    /// now.
+    Changed { movie: Box<RatedMovie> },
+    /// No such movie, or it is in the trash.
+    Gone,
+}
+
+/// Tags are compared like `If-None-Match` compares them, so a weak `W/`
+/// tag matches too.
+#[utoipa::path(
+    post,
+    path = "/movie/revalidate",
+    tag = "movies",
+    summary = "Revalidate many cached movies",
+    operation_id = "revalidate_movies",
+    request_body(content = BTreeMap<String, String>, description = "The ETag held for each movie id"),
+    responses(
+        (status = OK, description = "What became of each movie, by id", body = BTreeMap<String, Revalidated>),
+        (status = PAYLOAD_TOO_LARGE, description = "More than 500 ids", body = ErrorBody),
+    )
+)]
+pub async fn revalidate(
+    State(state): State<AppState>,
+    JsonBody(held): JsonBody<BTreeMap<String, String>>,
+) -> Result<Json<BTreeMap<String, Revalidated>>, ApiError> {
+    if held.len() > MAX_REVALIDATE {
+        return Err(ApiError::PayloadTooLarge(format!(
+            "at most {MAX_REVALIDATE} movies can be revalidated at once, got {}",
+            held.len()
+        )));
+    }
+
+    let ids: Vec<String> = held.keys().cloned().collect();
+    let movies = state.repo.get_many(&ids).await?;
+    Ok(Json(
+        held.into_iter()
+            .zip(movies)
+            .map(|((id, tag), movie)| {
+                let revalidated = match movie {
+                    Some(movie) if movie.deleted_at.is_none() => {
+                        if IfNoneMatch::new(&tag).matches(&conditional::etag(movie.version)) {
+                            Revalidated::Unchanged
+                        } else {
+                            Revalidated::Changed {
+                                movie: Box::new(RatedMovie {
+                                    ratings: state.ratings.summary(&id),
+                                    expanded: None,
+                                    movie,
+                                }),
+                            }
+                        }
+                    }
+                    _ => Revalidated::Gone,
+                };
+                (id, revalidated)
+            })
+            .collect(),
+    ))
+}
@@ -0,0 +1,204 @@
+//! Saved searches under `/search`: a named `GET /movie` query that is checked
+//! once when it is saved and run against the catalogue as it is whenever its
+//! results are asked for. Searches are kept in memory, like watchlists.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::{
+    Extension,
+    extract::{Path, Query, State},
+    http::{StatusCode, Uri},
+    response::{IntoResponse, Json},
+};
+use movies::model::{Movie, NewSavedSearch, Page, SavedSearch};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::api_version::MoviePage;
+use crate::errors::{ApiError, ErrorBody, FieldError};
+use crate::extract::{JsonBody, QueryParams};
+use crate::sync::LockExt;
+use crate::{AppState, ListParams, clean_name, movie_page, validate_name};
+
+/// The `GET /movie` parameters a search keeps, besides `custom.<key>`.
+const SAVED: &[&str] = &[
+    "year",
+    "year_from",
+    "year_to",
+    "was_good",
+    "genre",
+    "sort",
+    "order",
+    "collation",
+    "per_page",
+];
+
+/// Paging is chosen each time the results are asked for.
+const PER_RUN: &[&str] = &["page", "cursor"];
+
+#[derive(Debug, Default)]
+pub struct Searches {
+    /// Ordered by id, which is creation order.
+    by_id: RwLock<BTreeMap<u64, SavedSearch>>,
+    last_id: AtomicU64,
+}
+
+/// `GET /search/{id}/results`: paging over the saved query's own.
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ResultsParams {
+    /// 1-based.
+    page: Option<usize>,
+    /// Instead of the saved one, if any.
+    per_page: Option<usize>,
+    /// The `next_cursor` of the previous page; not with `page`.
+    cursor: Option<String>,
+}
+
+fn search_not_found(id: u64) -> ApiError {
+    ApiError::not_found("search", id.to_string())
+}
+
+/// `query` read the way `GET /movie` reads its own, with every key either a
+/// listing parameter or a `custom.<key>` filter.
+fn parse(query: &str) -> Result<(ListParams, HashMap<String, String>), ApiError> {
+    let uri: Uri = format!("/?{query}")
+        .parse()
+        .map_err(|_| ApiError::validation("query", "not a valid query string"))?;
+    let Query(pairs) = Query::<HashMap<String, String>>::try_from_uri(&uri)
+        .map_err(|rejection| ApiError::validation("query", rejection.body_text()))?;
+
+    let mut errors = Vec::new();
+    for key in pairs.keys() {
+        let message = if PER_RUN.contains(&key.as_str()) || key == "stream" {
+            format!("{key} is chosen when the results are asked for, not saved")
+        } else if SAVED.contains(&key.as_str())
+            || key
+                .strip_prefix("custom.")
+                .is_some_and(|key| !key.is_empty())
+        {
+            continue;
+        } else {
+            format!("{key} is not a parameter of GET /movie")
+        };
+        errors.push(FieldError::new(format!("query.{key}"), message));
+    }
+    if !errors.is_empty() {
+        errors.sort_by(|a, b| a.field.cmp(&b.field));
+        return Err(ApiError::Validation(errors));
+    }
+
+    let Query(params) = Query::<ListParams>::try_from_uri(&uri)
+        .map_err(|rejection| ApiError::validation("query", rejection.body_text()))?;
+    Ok((params, pairs))
+}
+
+#[utoipa::path(
+    post,
+    path = "/search",
+    tag = "movies",
+    summary = "Save a search",
+    operation_id = "create_search",
+    request_body = NewSavedSearch,
+    responses(
+        (status = CREATED, description = "The saved search", body = SavedSearch),
+        (status = UNPROCESSABLE_ENTITY, description = "A blank name, or a query with unknown parameters or invalid filters", body = ErrorBody),
+    )
+)]
+pub async fn create(
+    State(state): State<AppState>,
+    JsonBody(payload): JsonBody<NewSavedSearch>,
+) -> Result<impl IntoResponse, ApiError> {
+    if let Some(error) = validate_name(&payload.name, "name".to_string()) {
+        return Err(ApiError::Validation(vec![error]));
+    }
+    // Checked as a listing would check it, so running it later cannot fail.
+    let (params, pairs) = parse(&payload.query)?;
+    params
+        .check(&pairs, &state.genres)
+        .map_err(|error| match error {
+            ApiError::BadRequest(message) => ApiError::validation("query", message),
+            error => error,
+        })?;
+
+    let search = SavedSearch {
+        id: state.searches.last_id.fetch_add(1, Ordering::Relaxed) + 1,
+        name: clean_name(&payload.name),
+        query: payload.query,
+    };
+    state
+        .searches
+        .by_id
+        .write_or_recover()
+        .insert(search.id, search.clone());
+
+    Ok((StatusCode::CREATED, Json(search)))
+}
+
+/// Every saved search, oldest first.
+#[utoipa::path(
+    get,
+    path = "/search",
+    tag = "movies",
+    summary = "List saved searches",
+    operation_id = "list_searches",
+    responses(
+        (status = OK, description = "Every saved search, oldest first", body = Vec<SavedSearch>),
+    )
+)]
+pub async fn list(State(state): State<AppState>) -> Json<Vec<SavedSearch>> {
+    Json(
+        state
+            .searches
+            .by_id
+            .read_or_recover()
+            .values()
+            .cloned()
+            .collect(),
+    )
+}
+
+/// One page of the movies the saved query matches now, paged like
+/// `GET /movie`.
+#[utoipa::path(
+    get,
+    path = "/search/{id}/results",
+    tag = "movies",
+    summary = "Run a saved search",
+    operation_id = "search_results",
+    params(
+        ("id" = u64, Path, description = "The id of the saved search"),
+        ResultsParams,
+    ),
+    responses(
+        (status = OK, description = "A page of the matching movies", body = Page<Movie>),
+        (status = BAD_REQUEST, description = "A zero page or an invalid cursor", body = ErrorBody),
+        (status = NOT_FOUND, description = "No such saved search", body = ErrorBody),
+    )
+)]
+pub async fn results(
+    Path(id): Path<u64>,
+    QueryParams(overrides): QueryParams<ResultsParams>,
+    State(state): State<AppState>,
+) -> Result<(Extension<MoviePage>, Json<Page<Movie>>), ApiError> {
+    let search = state
+        .searches
+        .by_id
+        .read_or_recover()
+        .get(&id)
+        .cloned()
+        .ok_or_else(|| search_not_found(id))?;
+
+    let (mut params, pairs) = parse(&search.query)?;
+    params.page = overrides.page.unwrap_or(params.page);
+    params.per_page = overrides.per_page.unwrap_or(params.per_page);
+    params.cursor = overrides.cursor;
+    let checked = params.check(&pairs, &state.genres)?;
+
+    Ok((
+        Extension(MoviePage),
+        Json(movie_page(&state, params.page, &checked).await?),
+    ))
+}
@@ -0,0 +1,245 @@
+//! On-disk copy of the whole store as one JSON document, rewritten whenever
+//! `storage` compacts its log through a temporary file renamed over the old
+//! one, so a crash mid-write leaves the previous snapshot intact and movies
+//! and people always come from the same generation. With a key, it is
+//! sealed, see `encryption`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use movies::model::{Movie, Person};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::encryption::{self, Key};
+
+/// The movies and people of a store, by id.
+pub type Stored = (HashMap<String, Movie>, BTreeMap<String, Person>);
+
+/// The format snapshots are saved in. Version 0, never written down, is a
+/// bare array of movies, with people saved separately in
+/// `<snapshot>.people` once they were stored.
+const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Document<'a> {
+    schema_version: u32,
+    movies: Vec<&'a Movie>,
+    people: Vec<&'a Person>,
+}
+
+#[derive(Deserialize, Default)]
+struct Saved {
+    schema_version: u32,
+    movies: Vec<Movie>,
+    people: Vec<Person>,
+}
+
+impl Saved {
+    /// Brings a snapshot in an earlier format up to the current one, a
+    /// version at a time.
+    fn migrate(mut self) -> Self {
+        if self.schema_version == 0 {
+            // The movies as `GET /movie` listed them, the earliest from
+            // before movies had versions and slugs.
+            let mut slugs = self
+                .movies
+                .iter()
+                .filter(|movie| !movie.slug.is_empty())
+                .map(|movie| (movie.slug.clone(), movie.id.clone()))
+                .collect();
+            for movie in &mut self.movies {
+                movie.version = movie.version.max(1);
+                if movie.slug.is_empty() {
+                    crate::refresh_slug(&mut slugs, None, movie);
+                }
+            }
+            self.schema_version = 1;
+        }
+        self
+    }
+}
+
+/// The ids that appear more than once, in order.
+fn repeated<'a>(ids: impl Iterator<Item = &'a String>) -> Vec<&'a str> {
+    let mut seen = BTreeMap::new();
+    for id in ids {
+        *seen.entry(id.as_str()).or_insert(0) += 1;
+    }
+    seen.into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|(id, _)| id)
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct Snapshot {
+    path: PathBuf,
+    /// Where snapshots in the earlier format kept their people.
+    people_path: PathBuf,
+    key: Option<Key>,
+    /// A file was read as plaintext though there is a key.
+    unsealed: bool,
+}
+
+impl Snapshot {
+    /// Loads the snapshot at `path`, creating an empty one when there is
+    /// none yet. A snapshot that cannot be parsed or repeats an id is an
+    /// error rather than an empty store, so a damaged file is never silently
+    /// overwritten. One in an earlier format is migrated, and saved in the
+    /// current one next time. Sealed files need `key`; plaintext ones are
+    /// read either way.
+    pub fn open(path: PathBuf, key: Option<Key>) -> io::Result<(Self, Stored)> {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".people");
+        let mut snapshot = Snapshot {
+            people_path: path.with_file_name(name),
+            path,
+            key,
+            unsealed: false,
+        };
+
+        let mut plaintext = false;
+        let saved = match snapshot.read_file(&snapshot.path, &mut plaintext)? {
+            None => {
+                snapshot.save([], [])?;
+                Saved {
+                    schema_version: SCHEMA_VERSION,
+                    ..Saved::default()
+                }
+            }
+            Some(json) if json.trim_ascii_start().starts_with(b"[") => Saved {
+                schema_version: 0,
+                movies: snapshot.parse(&snapshot.path, &json)?,
+                people: match snapshot.read_file(&snapshot.people_path, &mut plaintext)? {
+                    Some(json) => snapshot.parse(&snapshot.people_path, &json)?,
+                    None => Vec::new(),
+                },
+            },
+            Some(json) => snapshot.parse(&snapshot.path, &json)?,
+        };
+        snapshot.unsealed = plaintext;
+
+        if saved.schema_version > SCHEMA_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "snapshot {} has schema_version {}, newer than this server reads ({SCHEMA_VERSION})",
+                    snapshot.path.display(),
+                    saved.schema_version
+                ),
+            ));
+        }
+        let from = saved.schema_version;
+        let Saved { movies, people, .. } = saved.migrate();
+        if from < SCHEMA_VERSION {
+            tracing::info!(
+                event = "snapshot.migrated",
+                path = %snapshot.path.display(),
+                from,
+                to = SCHEMA_VERSION,
+                "read a snapshot in an earlier format; it is saved in the current one at the next snapshot"
+            );
+        }
+        for (kind, ids) in [
+            ("movie", repeated(movies.iter().map(|movie| &movie.id))),
+            ("person", repeated(people.iter().map(|person| &person.id))),
+        ] {
+            if !ids.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "snapshot {} repeats {kind} ids: {}",
+                        snapshot.path.display(),
+                        ids.join(", ")
+                    ),
+                ));
+            }
+        }
+
+        let movies = movies
+            .into_iter()
+            .map(|movie| (movie.id.clone(), movie))
+            .collect();
+        let people = people
+            .into_iter()
+            .map(|person| (person.id.clone(), person))
+            .collect();
+        Ok((snapshot, (movies, people)))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn key(&self) -> Option<&Key> {
+        self.key.as_ref()
+    }
+
+    /// Whether a file was found in plaintext though there is a key, so it
+    /// should be saved again to be sealed.
+    pub fn unsealed(&self) -> bool {
+        self.key.is_some() && self.unsealed
+    }
+
+    /// Replaces the snapshot with `movies` and `people`, each in id order so
+    /// the files diff cleanly, in one rename. The people file of the earlier
+    /// format is removed once it is no longer read.
+    pub fn save<'a>(
+        &self,
+        movies: impl IntoIterator<Item = &'a Movie>,
+        people: impl IntoIterator<Item = &'a Person>,
+    ) -> io::Result<()> {
+        let mut movies: Vec<&Movie> = movies.into_iter().collect();
+        movies.sort_by(|a, b| a.id.cmp(&b.id));
+        let mut people: Vec<&Person> = people.into_iter().collect();
+        people.sort_by(|a, b| a.id.cmp(&b.id));
+
+        self.write(&Document {
+            schema_version: SCHEMA_VERSION,
+            movies,
+            people,
+        })?;
+        match fs::remove_file(&self.people_path) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    /// The JSON in the file at `path`, `None` when there is no such file.
+    /// Notes whether it was plaintext.
+    fn read_file(&self, path: &Path, plaintext: &mut bool) -> io::Result<Option<Vec<u8>>> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let unsealed = encryption::open_file(self.key(), path, &bytes)?;
+        *plaintext |= unsealed.plaintext;
+        Ok(Some(unsealed.json.into_owned()))
+    }
+
+    fn parse<T: DeserializeOwned>(&self, path: &Path, json: &[u8]) -> io::Result<T> {
+        serde_json::from_slice(json).map_err(|error| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("corrupt snapshot {}: {error}", path.display()),
+            )
+        })
+    }
+
+    fn write(&self, document: &Document) -> io::Result<()> {
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(".tmp");
+        let temp = self.path.with_file_name(name);
+
+        let mut json = serde_json::to_vec_pretty(document)?;
+        json.push(b'\n');
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(&encryption::seal_file(self.key(), json))?;
+        file.sync_all()?;
+        fs::rename(&temp, &self.path)
+    }
+}
@@ -0,0 +1,127 @@
+//! `GET /movie/stats`: aggregate figures over the movies not in the trash,
+//! gathered in one pass over the stored movies. An empty catalogue reports
+//! zero counts and `null` years and average rather than failing. The
+//! largest movies and those over their budgets are reported alongside, to
+//! find what slows listings and snapshots down.
+
+use std::collections::{BTreeMap, HashMap};
+
+use axum::{extract::State, response::Json};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::errors::ApiError;
+use crate::ratings::RatingSummary;
+use crate::{AppState, Config, GenreCount, Movie, over_budget, serialized_size};
+
+/// How many of the largest movies are reported.
+const LARGEST: usize = 5;
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct MovieStats {
+    pub total: usize,
+    pub good: usize,
+    pub bad: usize,
+    /// `None` while there are no movies, like `latest_year`.
+    pub earliest_year: Option<u16>,
+    pub latest_year: Option<u16>,
+    /// Movies per decade, keyed like `"1990s"`; decades without movies are
+    /// left out.
+    pub by_decade: BTreeMap<String, usize>,
+    /// Movies per genre, by genre, as `GET /genre` lists them.
+    pub by_genre: Vec<GenreCount>,
+    /// Over every rating of every movie counted.
+    #[serde(flatten)]
+    pub ratings: RatingSummary,
+    /// The largest movies serialized, largest first.
+    pub largest: Vec<MovieSize>,
+    /// Ids of movies over `MOVIE_MAX_BYTES` or `MOVIE_MAX_LIST_ITEMS`, stored
+    /// before the budgets were lowered, by id.
+    pub over_budget: Vec<String>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct MovieSize {
+    pub id: String,
+    /// Serialized as JSON.
+    pub bytes: usize,
+}
+
+/// `2000` is in the `"2000s"` and `1999` in the `"1990s"`.
+fn decade(year: u16) -> String {
+    format!("{}s", year / 10 * 10)
+}
+
+impl MovieStats {
+    fn of(movies: &[Movie], ratings: RatingSummary, config: &Config) -> Self {
+        let mut stats = MovieStats {
+            total: 0,
+            good: 0,
+            bad: 0,
+            earliest_year: None,
+            latest_year: None,
+            by_decade: BTreeMap::new(),
+            by_genre: Vec::new(),
+            ratings,
+            largest: Vec::new(),
+            over_budget: over_budget(movies, config),
+        };
+        let mut genres: HashMap<&str, usize> = HashMap::new();
+
+        for movie in movies {
+            stats.total += 1;
+            if movie.was_good {
+                stats.good += 1;
+            } else {
+                stats.bad += 1;
+            }
+            stats.earliest_year = Some(
+                stats
+                    .earliest_year
+                    .map_or(movie.year, |y| y.min(movie.year)),
+            );
+            stats.latest_year = Some(stats.latest_year.map_or(movie.year, |y| y.max(movie.year)));
+            *stats.by_decade.entry(decade(movie.year)).or_default() += 1;
+            for genre in &movie.genres {
+                *genres.entry(genre).or_default() += 1;
+            }
+            stats.largest.push(MovieSize {
+                id: movie.id.clone(),
+                bytes: serialized_size(movie),
+            });
+        }
+        stats
+            .largest
+            .sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.id.cmp(&b.id)));
+        stats.largest.truncate(LARGEST);
+
+        stats.by_genre = genres
+            .into_iter()
+            .map(|(genre, count)| GenreCount {
+                genre: genre.to_string(),
+                count,
+            })
+            .collect();
+        stats.by_genre.sort_by(|a, b| a.genre.cmp(&b.genre));
+
+        stats
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/movie/stats",
+    tag = "movies",
+    summary = "Summarize the catalogue",
+    responses(
+        (status = OK, description = "Counts by verdict, decade and genre, the year range and the average rating", body = MovieStats),
+    )
+)]
+pub async fn movie_stats(State(state): State<AppState>) -> Result<Json<MovieStats>, ApiError> {
+    let movies = state.live_movies().await?;
+    let ratings = state
+        .ratings
+        .overall(movies.iter().map(|movie| movie.id.as_str()));
+
+    Ok(Json(MovieStats::of(&movies, ratings, &state.config)))
+}
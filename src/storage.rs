@@ -0,0 +1,606 @@
+//! Durable storage for the in-memory repository: a [`Snapshot`] of the whole
+//! store plus a write-ahead log next to it, `<snapshot>.wal`. Every write is
+//! appended to the log as one JSON line and fsynced before it is applied, so
+//! an acknowledged write survives a crash without rewriting the snapshot.
+//! Every so often `compact` folds the log into a fresh snapshot and drops
+//! the lines it covers. On open, the snapshot is loaded and the log replayed
+//! on top of it.
+//!
+//! Replaying a line the snapshot already covers changes nothing, since each
+//! line stores whole movies and people, so a crash at any point of a
+//! compaction still recovers every write.
+//!
+//! With an `ENCRYPTION_KEY`, the snapshot and every line of the log are
+//! sealed. A store written in plaintext is still loaded, and sealed on the
+//! spot: the snapshot is saved again with everything replayed, then the log
+//! emptied.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use movies::model::{Movie, Person};
+use serde::{Deserialize, Serialize};
+
+use crate::encryption::{self, Key};
+use crate::repo::{MovieRepository, Write};
+use crate::snapshot::{Snapshot, Stored};
+
+/// One change as it is logged; a line holds the changes of one write call.
+#[derive(Serialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Logged<'a> {
+    Put { movie: &'a Movie },
+    Delete { id: &'a str },
+    Clear,
+    PutPerson { person: &'a Person },
+    DeletePerson { id: &'a str },
+}
+
+/// The owned counterpart of `Logged`, read back on open.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Replayed {
+    Put { movie: Box<Movie> },
+    Delete { id: String },
+    Clear,
+    PutPerson { person: Person },
+    DeletePerson { id: String },
+}
+
+#[derive(Debug)]
+struct Wal {
+    file: File,
+    /// Bytes in the file, all of them whole lines.
+    len: u64,
+}
+
+#[derive(Debug)]
+pub struct Storage {
+    snapshot: Snapshot,
+    wal_path: PathBuf,
+    wal: Mutex<Wal>,
+    compacting: tokio::sync::Mutex<()>,
+}
+
+impl Storage {
+    /// Loads the snapshot at `path`, creating an empty one when there is
+    /// none yet, and replays its log. A torn last line, left by a crash in
+    /// the middle of an append, is skipped with a warning and cut off; any
+    /// other line that cannot be read is an error, like a corrupt snapshot,
+    /// and so is a sealed one `key` does not open.
+    pub fn open(path: PathBuf, key: Option<Key>) -> io::Result<(Self, Stored)> {
+        let (snapshot, mut stored) = Snapshot::open(path, key)?;
+
+        let mut name = snapshot
+            .path()
+            .file_name()
+            .unwrap_or_default()
+            .to_os_string();
+        name.push(".wal");
+        let wal_path = snapshot.path().with_file_name(name);
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&wal_path)?;
+        let mut log = Vec::new();
+        file.read_to_end(&mut log)?;
+
+        let (mut len, plaintext) = replay(&wal_path, snapshot.key(), &log, &mut stored)?;
+        if len < log.len() as u64 {
+            file.set_len(len)?;
+            file.sync_all()?;
+        }
+        if snapshot.unsealed() || (snapshot.key().is_some() && plaintext) {
+            let (movies, people) = &stored;
+            snapshot.save(movies.values(), people.values())?;
+            file.set_len(0)?;
+            file.sync_all()?;
+            len = 0;
+            tracing::info!(
+                event = "store.encrypted",
+                path = %snapshot.path().display(),
+                "sealed a store written in plaintext with ENCRYPTION_KEY"
+            );
+        }
+
+        let storage = Storage {
+            snapshot,
+            wal_path,
+            wal: Mutex::new(Wal { file, len }),
+            compacting: tokio::sync::Mutex::default(),
+        };
+        Ok((storage, stored))
+    }
+
+    pub fn path(&self) -> &Path {
+        self.snapshot.path()
+    }
+
+    /// Logs `writes` as one line, left to `sync` to reach the disk. A
+    /// failed append is cut off again, so the log stays whole lines.
+    pub fn append(&self, writes: &[Write]) -> io::Result<()> {
+        let changes: Vec<Logged> = writes
+            .iter()
+            .map(|write| match write {
+                Write::Insert(movie) | Write::Update(movie) | Write::Upsert(movie) => {
+                    Logged::Put { movie }
+                }
+                Write::Delete(id, _) => Logged::Delete { id },
+                Write::Clear => Logged::Clear,
+            })
+            .collect();
+        self.append_line(&changes)
+    }
+
+    /// Logs that `person` was stored, or with `None` that the person `id`
+    /// was removed, like `append` logs movies.
+    pub fn append_person(&self, id: &str, person: Option<&Person>) -> io::Result<()> {
+        let change = match person {
+            Some(person) => Logged::PutPerson { person },
+            None => Logged::DeletePerson { id },
+        };
+        self.append_line(&[change])
+    }
+
+    fn append_line(&self, changes: &[Logged]) -> io::Result<()> {
+        let line = encryption::seal_line(self.snapshot.key(), serde_json::to_vec(changes)?);
+
+        let mut wal = self.wal.lock().unwrap_or_else(PoisonError::into_inner);
+        match wal.file.write_all(&line) {
+            Ok(()) => {
+                wal.len += line.len() as u64;
+                Ok(())
+            }
+            Err(error) => {
+                let len = wal.len;
+                let _ = wal.file.set_len(len);
+                Err(error)
+            }
+        }
+    }
+
+    /// Waits for every line logged so far to reach the disk, holding off
+    /// appends meanwhile.
+    pub fn sync(&self) -> io::Result<()> {
+        let wal = self.wal.lock().unwrap_or_else(PoisonError::into_inner);
+        wal.file.sync_data()
+    }
+
+    /// Held through a whole compaction, from copying the store to cutting
+    /// the log, so a periodic one and the one at shutdown never interleave.
+    pub async fn compacting(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.compacting.lock().await
+    }
+
+    /// How much of the log has been written, for `compact` to tell what a
+    /// copy of the store covers.
+    pub fn logged(&self) -> u64 {
+        self.wal.lock().unwrap_or_else(PoisonError::into_inner).len
+    }
+
+    /// Saves `movies` and `people`, the store as of the first `covered`
+    /// bytes of the log, as the snapshot and drops those bytes from the log.
+    /// Lines appended since are kept.
+    pub fn compact<'a>(
+        &self,
+        movies: impl IntoIterator<Item = &'a Movie>,
+        people: impl IntoIterator<Item = &'a Person>,
+        covered: u64,
+    ) -> io::Result<()> {
+        self.snapshot.save(movies, people)?;
+
+        let mut wal = self.wal.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut tail = Vec::new();
+        let mut reader = File::open(&self.wal_path)?;
+        reader.seek(SeekFrom::Start(covered))?;
+        reader.read_to_end(&mut tail)?;
+
+        let mut name = self.wal_path.file_name().unwrap_or_default().to_os_string();
+        name.push(".tmp");
+        let temp = self.wal_path.with_file_name(name);
+        let mut file = File::create(&temp)?;
+        file.write_all(&tail)?;
+        file.sync_all()?;
+        fs::rename(&temp, &self.wal_path)?;
+
+        wal.file = OpenOptions::new().append(true).open(&self.wal_path)?;
+        wal.len = tail.len() as u64;
+        Ok(())
+    }
+}
+
+/// Applies the lines of `log` to `stored` and returns how many bytes of it
+/// are whole, readable lines, and whether any of them was plaintext.
+fn replay(
+    path: &Path,
+    key: Option<&Key>,
+    log: &[u8],
+    (movies, people): &mut Stored,
+) -> io::Result<(u64, bool)> {
+    let mut offset = 0;
+    let mut plaintext = false;
+    let mut lines = log.split_inclusive(|byte| *byte == b'\n').peekable();
+
+    while let Some(line) = lines.next() {
+        let last = lines.peek().is_none();
+        let unsealed = encryption::open_line(key, path, line)?;
+        plaintext |= unsealed.plaintext;
+        let changes = match serde_json::from_slice::<Vec<Replayed>>(&unsealed.json) {
+            Ok(changes) if line.ends_with(b"\n") => changes,
+            _ if last => {
+                tracing::warn!(
+                    path = %path.display(),
+                    offset,
+                    "skipping a torn last line of the write-ahead log"
+                );
+                break;
+            }
+            Err(error) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "corrupt write-ahead log {} at byte {offset}: {error}",
+                        path.display()
+                    ),
+                ));
+            }
+            Ok(_) => unreachable!("only the last line can lack its newline"),
+        };
+
+        for change in changes {
+            match change {
+                Replayed::Put { movie } => {
+                    movies.insert(movie.id.clone(), *movie);
+                }
+                Replayed::Delete { id } => {
+                    movies.remove(&id);
+                }
+                Replayed::Clear => movies.clear(),
+                Replayed::PutPerson { person } => {
+                    people.insert(person.id.clone(), person);
+                }
+                Replayed::DeletePerson { id } => {
+                    people.remove(&id);
+                }
+            }
+        }
+        offset += line.len() as u64;
+    }
+
+    Ok((offset, plaintext))
+}
+
+/// Compacts `repo` every `interval` until the task is aborted. Failures are
+/// logged and retried at the next tick; the log keeps every write meanwhile.
+pub async fn compact_periodically(repo: Arc<dyn MovieRepository>, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    // The first tick completes at once, and there is nothing to fold yet.
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        if let Err(error) = repo.compact().await {
+            tracing::error!(%error, "failed to compact the store");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::repo::InMemoryRepository;
+
+    fn movie(id: &str, name: &str) -> Movie {
+        serde_json::from_value(
+            json!({ "id": id, "name": name, "year": 1995, "was_good": true, "version": 1 }),
+        )
+        .unwrap()
+    }
+
+    async fn names(repo: &InMemoryRepository) -> Vec<(String, String)> {
+        let mut movies: Vec<_> = repo
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|movie| (movie.id, movie.name))
+            .collect();
+        movies.sort();
+        movies
+    }
+
+    /// The snapshot document at `path`.
+    fn saved(path: &Path) -> serde_json::Value {
+        serde_json::from_slice(&fs::read(path).unwrap()).unwrap()
+    }
+
+    fn pairs(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+        expected
+            .iter()
+            .map(|(id, name)| (id.to_string(), name.to_string()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn acknowledged_writes_survive_a_crash() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movies.json");
+
+        let repo = InMemoryRepository::open(path.clone(), None).unwrap();
+        repo.insert(movie("1", "Heat")).await.unwrap();
+        repo.insert(movie("2", "Ronin")).await.unwrap();
+        let mut heat = movie("1", "Heat (1995)");
+        heat.version = 2;
+        repo.update(heat).await.unwrap();
+        repo.apply(vec![
+            Write::Insert(movie("3", "Thief")),
+            Write::Delete("2".to_string(), None),
+        ])
+        .await
+        .unwrap();
+        repo.delete("3", None).await.unwrap();
+        repo.insert(movie("4", "Collateral")).await.unwrap();
+        // Refused writes are not logged.
+        assert!(repo.insert(movie("1", "Manhunter")).await.is_err());
+        // Gone without a flush, and nothing was compacted.
+        drop(repo);
+        assert_eq!(
+            saved(&path),
+            json!({"schema_version": 1, "movies": [], "people": []})
+        );
+
+        let repo = InMemoryRepository::open(path.clone(), None).unwrap();
+        assert_eq!(
+            names(&repo).await,
+            pairs(&[("1", "Heat (1995)"), ("4", "Collateral")])
+        );
+
+        // A clear replays too, dropping everything before it.
+        repo.apply(vec![Write::Clear, Write::Insert(movie("5", "Blackhat"))])
+            .await
+            .unwrap();
+        drop(repo);
+        let repo = InMemoryRepository::open(path, None).unwrap();
+        assert_eq!(names(&repo).await, pairs(&[("5", "Blackhat")]));
+    }
+
+    #[tokio::test]
+    async fn a_write_given_up_on_is_still_applied_once_logged() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movies.json");
+
+        let repo = InMemoryRepository::open(path.clone(), None).unwrap();
+        // Dropped after its first poll, while the log waits for the disk.
+        let _ = tokio::time::timeout(Duration::ZERO, repo.insert(movie("1", "Heat"))).await;
+        // Compaction waits for writes in flight, so the store and the
+        // snapshot both have it.
+        repo.compact().await.unwrap();
+        assert_eq!(names(&repo).await, pairs(&[("1", "Heat")]));
+        drop(repo);
+        let repo = InMemoryRepository::open(path, None).unwrap();
+        assert_eq!(names(&repo).await, pairs(&[("1", "Heat")]));
+    }
+
+    #[tokio::test]
+    async fn a_torn_last_line_is_skipped_and_cut_off() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movies.json");
+        let wal_path = dir.path().join("movies.json.wal");
+
+        let repo = InMemoryRepository::open(path.clone(), None).unwrap();
+        repo.insert(movie("1", "Heat")).await.unwrap();
+        drop(repo);
+        let whole = fs::metadata(&wal_path).unwrap().len();
+        let mut wal = OpenOptions::new().append(true).open(&wal_path).unwrap();
+        wal.write_all(br#"[{"op":"put","movie":{"id":"2","na"#)
+            .unwrap();
+        drop(wal);
+
+        let repo = InMemoryRepository::open(path.clone(), None).unwrap();
+        assert_eq!(names(&repo).await, pairs(&[("1", "Heat")]));
+        assert_eq!(fs::metadata(&wal_path).unwrap().len(), whole);
+
+        // Later writes start on a fresh line.
+        repo.insert(movie("3", "Thief")).await.unwrap();
+        drop(repo);
+        let repo = InMemoryRepository::open(path, None).unwrap();
+        assert_eq!(names(&repo).await, pairs(&[("1", "Heat"), ("3", "Thief")]));
+    }
+
+    #[tokio::test]
+    async fn a_damaged_line_before_the_end_fails_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movies.json");
+        let wal_path = dir.path().join("movies.json.wal");
+        let line = serde_json::to_string(&[Logged::Put {
+            movie: &movie("1", "Heat"),
+        }])
+        .unwrap();
+        fs::write(&wal_path, format!("[{{\"op\":\n{line}\n")).unwrap();
+
+        let error = InMemoryRepository::open(path, None).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(
+            error.to_string().contains("corrupt write-ahead log"),
+            "{error}"
+        );
+        assert!(
+            fs::read_to_string(&wal_path)
+                .unwrap()
+                .starts_with("[{\"op\":\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn compaction_shrinks_the_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movies.json");
+        let wal_path = dir.path().join("movies.json.wal");
+
+        let repo = InMemoryRepository::open(path.clone(), None).unwrap();
+        for id in 0..20 {
+            repo.insert(movie(&id.to_string(), "Heat")).await.unwrap();
+        }
+        let before = fs::read(&wal_path).unwrap();
+        assert_eq!(before.iter().filter(|byte| **byte == b'\n').count(), 20);
+
+        repo.compact().await.unwrap();
+        assert_eq!(fs::metadata(&wal_path).unwrap().len(), 0);
+        assert_eq!(saved(&path)["movies"].as_array().unwrap().len(), 20);
+
+        repo.delete("0", None).await.unwrap();
+        let after = fs::metadata(&wal_path).unwrap().len();
+        assert!(after > 0 && after < before.len() as u64 / 10, "{after}");
+        drop(repo);
+        let repo = InMemoryRepository::open(path.clone(), None).unwrap();
+        assert_eq!(repo.list().await.unwrap().len(), 19);
+
+        // As if the process died after saving the snapshot but before the
+        // log was cut: replaying what the snapshot holds changes nothing.
+        drop(repo);
+        let mut stale = before;
+        stale.extend(fs::read(&wal_path).unwrap());
+        fs::write(&wal_path, stale).unwrap();
+        let repo = InMemoryRepository::open(path, None).unwrap();
+        assert_eq!(repo.list().await.unwrap().len(), 19);
+        assert!(repo.get("0").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn movies_and_people_are_saved_in_one_document() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movies.json");
+        let people_path = dir.path().join("movies.json.people");
+
+        let repo = InMemoryRepository::open(path.clone(), None).unwrap();
+        repo.insert(movie("1", "Heat")).await.unwrap();
+        let mann: Person =
+            serde_json::from_value(json!({ "id": "p1", "name": "Michael Mann" })).unwrap();
+        repo.put_person(&mann).await.unwrap();
+        repo.compact().await.unwrap();
+        drop(repo);
+
+        let document = saved(&path);
+        assert_eq!(document["schema_version"], 1);
+        assert_eq!(document["movies"][0]["name"], "Heat");
+        assert_eq!(document["people"][0]["name"], "Michael Mann");
+        let files: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files.len(), 2, "{files:?}");
+
+        // People left next to a snapshot in the earlier format, as if the
+        // process died before removing them: the document wins.
+        fs::write(&people_path, "[]").unwrap();
+        let repo = InMemoryRepository::open(path.clone(), None).unwrap();
+        assert_eq!(repo.people().await.unwrap().len(), 1);
+        repo.compact().await.unwrap();
+        assert!(!people_path.exists());
+    }
+
+    fn key(byte: u8) -> Key {
+        use base64::Engine;
+        Key::from_base64(&base64::engine::general_purpose::STANDARD.encode([byte; 32])).unwrap()
+    }
+
+    /// Every file of the store at `dir`, concatenated.
+    fn stored_bytes(dir: &Path) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for entry in fs::read_dir(dir).unwrap() {
+            bytes.extend(fs::read(entry.unwrap().path()).unwrap());
+        }
+        bytes
+    }
+
+    fn contains(haystack: &[u8], needle: &str) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle.as_bytes())
+    }
+
+    #[tokio::test]
+    async fn an_encrypted_store_reads_back_with_its_key_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movies.json");
+
+        let repo = InMemoryRepository::open(path.clone(), Some(key(7))).unwrap();
+        repo.insert(movie("1", "Heat")).await.unwrap();
+        let mann: Person =
+            serde_json::from_value(json!({ "id": "p1", "name": "Michael Mann" })).unwrap();
+        repo.put_person(&mann).await.unwrap();
+        repo.compact().await.unwrap();
+        repo.insert(movie("2", "Ronin")).await.unwrap();
+        drop(repo);
+
+        let bytes = stored_bytes(dir.path());
+        for plaintext in ["Heat", "Ronin", "Michael Mann", "\"name\""] {
+            assert!(!contains(&bytes, plaintext), "{plaintext} is readable");
+        }
+
+        let repo = InMemoryRepository::open(path.clone(), Some(key(7))).unwrap();
+        assert_eq!(names(&repo).await, pairs(&[("1", "Heat"), ("2", "Ronin")]));
+        assert_eq!(repo.people().await.unwrap().len(), 1);
+        drop(repo);
+
+        for wrong in [Some(key(8)), None] {
+            let error = InMemoryRepository::open(path.clone(), wrong).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert!(error.to_string().contains("ENCRYPTION_KEY"), "{error}");
+        }
+        // Refusing to open changed nothing.
+        let repo = InMemoryRepository::open(path, Some(key(7))).unwrap();
+        assert_eq!(names(&repo).await, pairs(&[("1", "Heat"), ("2", "Ronin")]));
+    }
+
+    #[tokio::test]
+    async fn a_wrong_key_is_refused_by_the_log_too() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movies.json");
+        let wal_path = dir.path().join("movies.json.wal");
+
+        let repo = InMemoryRepository::open(path.clone(), Some(key(7))).unwrap();
+        repo.insert(movie("1", "Heat")).await.unwrap();
+        drop(repo);
+        // A line sealed with another key, as if the key changed between
+        // writes: a whole line that cannot be opened is not a torn one.
+        let (other, _) = Storage::open(dir.path().join("other.json"), Some(key(8))).unwrap();
+        other.append(&[Write::Insert(movie("2", "Ronin"))]).unwrap();
+        let mut log = fs::read(&wal_path).unwrap();
+        log.extend(fs::read(dir.path().join("other.json.wal")).unwrap());
+        fs::write(&wal_path, &log).unwrap();
+
+        let error = InMemoryRepository::open(path, Some(key(7))).err().unwrap();
+        assert!(error.to_string().contains("cannot decrypt"), "{error}");
+        assert_eq!(fs::read(&wal_path).unwrap(), log);
+    }
+
+    #[tokio::test]
+    async fn a_plaintext_store_is_encrypted_when_opened_with_a_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movies.json");
+
+        let repo = InMemoryRepository::open(path.clone(), None).unwrap();
+        repo.insert(movie("1", "Heat")).await.unwrap();
+        repo.compact().await.unwrap();
+        repo.insert(movie("2", "Ronin")).await.unwrap();
+        drop(repo);
+        assert!(contains(&stored_bytes(dir.path()), "Ronin"));
+
+        let repo = InMemoryRepository::open(path.clone(), Some(key(7))).unwrap();
+        assert_eq!(names(&repo).await, pairs(&[("1", "Heat"), ("2", "Ronin")]));
+        let bytes = stored_bytes(dir.path());
+        assert!(!contains(&bytes, "Heat") && !contains(&bytes, "Ronin"));
+        drop(repo);
+
+        assert!(InMemoryRepository::open(path.clone(), None).is_err());
+        let repo = InMemoryRepository::open(path, Some(key(7))).unwrap();
+        assert_eq!(names(&repo).await, pairs(&[("1", "Heat"), ("2", "Ronin")]));
+    }
+}
@@ -0,0 +1,31 @@
+//! Lock access that survives poisoning. A handler panicking while holding a
+//! lock poisons it, and unwrapping the poison would then panic every later
+//! request touching the same lock. Each write leaves the maps consistent
+//! entry by entry, so the data is recovered and served instead.
+
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+pub trait LockExt<T> {
+    fn read_or_recover(&self) -> RwLockReadGuard<'_, T>;
+    fn write_or_recover(&self) -> RwLockWriteGuard<'_, T>;
+}
+
+impl<T> LockExt<T> for RwLock<T> {
+    fn read_or_recover(&self) -> RwLockReadGuard<'_, T> {
+        self.read()
+            .unwrap_or_else(|poisoned| recover(self, poisoned))
+    }
+
+    fn write_or_recover(&self) -> RwLockWriteGuard<'_, T> {
+        self.write()
+            .unwrap_or_else(|poisoned| recover(self, poisoned))
+    }
+}
+
+/// Logs the poisoning once and clears it, so later accesses take the fast
+/// path again.
+fn recover<T, G>(lock: &RwLock<T>, poisoned: PoisonError<G>) -> G {
+    tracing::error!("a task panicked while holding a lock, continuing with its data");
+    lock.clear_poison();
+    poisoned.into_inner()
+}
@@ -0,0 +1,279 @@
+//! Named lists of movies to watch next, under `/watchlist`. Lists are kept in
+//! memory and hold movie ids only; deleting a movie leaves its id in place,
+//! and the expanded view reports it as missing instead of failing.
+
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Json},
+};
+use movies::model::{NewWatchlist, Watchlist};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::errors::{ApiError, ErrorBody};
+use crate::extract::JsonBody;
+use crate::sync::LockExt;
+use crate::{AppState, Movie, clean_name, validate_name};
+
+#[derive(Debug, Default)]
+pub struct Watchlists {
+    /// Ordered by id, which is creation order.
+    by_id: RwLock<BTreeMap<u64, Watchlist>>,
+    last_id: AtomicU64,
+}
+
+impl Watchlists {
+    fn create(&self, name: String) -> Watchlist {
+        let watchlist = Watchlist {
+            id: self.last_id.fetch_add(1, Ordering::Relaxed) + 1,
+            name,
+            movie_ids: Vec::new(),
+        };
+        self.by_id
+            .write_or_recover()
+            .insert(watchlist.id, watchlist.clone());
+
+        watchlist
+    }
+
+    fn get(&self, id: u64) -> Option<Watchlist> {
+        self.by_id.read_or_recover().get(&id).cloned()
+    }
+
+    /// Changes the list with `id` in place and returns its new state.
+    fn modify(&self, id: u64, f: impl FnOnce(&mut Watchlist)) -> Option<Watchlist> {
+        let mut by_id = self.by_id.write_or_recover();
+        let watchlist = by_id.get_mut(&id)?;
+        f(watchlist);
+
+        Some(watchlist.clone())
+    }
+
+    /// Points every entry for a re-keyed movie at its new id.
+    pub fn rename_movie(&self, from: &str, to: &str) {
+        for watchlist in self.by_id.write_or_recover().values_mut() {
+            for movie_id in &mut watchlist.movie_ids {
+                if movie_id == from {
+                    *movie_id = to.to_string();
+                }
+            }
+        }
+    }
+
+    /// Points every entry for a movie in `from` at `to`, which keeps the
+    /// place of whichever came first on each list.
+    pub fn merge_movies(&self, from: &[String], to: &str) {
+        for watchlist in self.by_id.write_or_recover().values_mut() {
+            let mut seen = false;
+            watchlist.movie_ids.retain_mut(|movie_id| {
+                if *movie_id != to && !from.contains(movie_id) {
+                    return true;
+                }
+                *movie_id = to.to_string();
+                !std::mem::replace(&mut seen, true)
+            });
+        }
+    }
+}
+
+/// `GET /watchlist/{id}`: the list with its movies looked up.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ExpandedWatchlist {
+    pub id: u64,
+    pub name: String,
+    /// The movies still in the catalogue, in list order.
+    pub movies: Vec<Movie>,
+    /// Ids on the list whose movie has been deleted since.
+    pub missing: Vec<String>,
+}
+
+fn watchlist_not_found(id: u64) -> ApiError {
+    ApiError::not_found("watchlist", id.to_string())
+}
+
+#[utoipa::path(
+    post,
+    path = "/watchlist",
+    tag = "watchlists",
+    summary = "Create a watchlist",
+    operation_id = "create_watchlist",
+    request_body = NewWatchlist,
+    responses(
+        (status = CREATED, description = "The new, empty watchlist", body = Watchlist),
+        (status = UNPROCESSABLE_ENTITY, description = "A blank or too long name", body = ErrorBody),
+    )
+)]
+pub async fn create(
+    State(state): State<AppState>,
+    JsonBody(payload): JsonBody<NewWatchlist>,
+) -> Result<impl IntoResponse, ApiError> {
+    if let Some(error) = validate_name(&payload.name, "name".to_string()) {
+        return Err(ApiError::Validation(vec![error]));
+    }
+
+    let watchlist = state.watchlists.create(clean_name(&payload.name));
+    Ok((StatusCode::CREATED, Json(watchlist)))
+}
+
+/// Every list with its movie ids, oldest first.
+#[utoipa::path(
+    get,
+    path = "/watchlist",
+    tag = "watchlists",
+    summary = "List watchlists",
+    operation_id = "list_watchlists",
+    responses(
+        (status = OK, description = "Every watchlist, oldest first", body = Vec<Watchlist>),
+    )
+)]
+pub async fn list(State(state): State<AppState>) -> Json<Vec<Watchlist>> {
+    Json(
+        state
+            .watchlists
+            .by_id
+            .read_or_recover()
+            .values()
+            .cloned()
+            .collect(),
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/watchlist/{id}",
+    tag = "watchlists",
+    summary = "Get a watchlist with its movies",
+    operation_id = "get_watchlist",
+    params(("id" = u64, Path, description = "The id of the watchlist")),
+    responses(
+        (status = OK, description = "The watchlist with its movies looked up", body = ExpandedWatchlist),
+        (status = NOT_FOUND, description = "No such watchlist", body = ErrorBody),
+    )
+)]
+pub async fn get(
+    Path(id): Path<u64>,
+    State(state): State<AppState>,
+) -> Result<Json<ExpandedWatchlist>, ApiError> {
+    let watchlist = state
+        .watchlists
+        .get(id)
+        .ok_or_else(|| watchlist_not_found(id))?;
+
+    let _rekeying = state.rekeying.read().await;
+    let mut movies = Vec::new();
+    let mut missing = Vec::new();
+    for movie_id in watchlist.movie_ids {
+        match state.live_movie(&movie_id).await? {
+            Some(movie) => movies.push(movie),
+            None => missing.push(movie_id),
+        }
+    }
+
+    Ok(Json(ExpandedWatchlist {
+        id: watchlist.id,
+        name: watchlist.name,
+        movies,
+        missing,
+    }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/watchlist/{id}",
+    tag = "watchlists",
+    summary = "Delete a watchlist",
+    operation_id = "delete_watchlist",
+    params(("id" = u64, Path, description = "The id of the watchlist")),
+    responses(
+        (status = NO_CONTENT, description = "The watchlist was deleted"),
+        (status = NOT_FOUND, description = "No such watchlist", body = ErrorBody),
+    )
+)]
+pub async fn delete(
+    Path(id): Path<u64>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, ApiError> {
+    state
+        .watchlists
+        .by_id
+        .write_or_recover()
+        .remove(&id)
+        .ok_or_else(|| watchlist_not_found(id))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Adds the movie to the end of the list; a movie already on it stays where
+/// it is.
+#[utoipa::path(
+    put,
+    path = "/watchlist/{id}/movie/{movie_id}",
+    tag = "watchlists",
+    summary = "Add a movie to a watchlist",
+    operation_id = "add_watchlist_movie",
+    params(
+        ("id" = u64, Path, description = "The id of the watchlist"),
+        ("movie_id" = String, Path, description = "The id of the movie"),
+    ),
+    responses(
+        (status = OK, description = "The watchlist", body = Watchlist),
+        (status = NOT_FOUND, description = "No such watchlist or movie", body = ErrorBody),
+    )
+)]
+pub async fn add_movie(
+    Path((id, movie_id)): Path<(u64, String)>,
+    State(state): State<AppState>,
+) -> Result<Json<Watchlist>, ApiError> {
+    if state.watchlists.get(id).is_none() {
+        return Err(watchlist_not_found(id));
+    }
+    let _fence = state.fence().await?;
+    let _rekeying = state.rekeying.read().await;
+    if state.live_movie(&movie_id).await?.is_none() {
+        return Err(ApiError::movie_not_found(movie_id));
+    }
+
+    state
+        .watchlists
+        .modify(id, |watchlist| {
+            if !watchlist.movie_ids.contains(&movie_id) {
+                watchlist.movie_ids.push(movie_id);
+            }
+        })
+        .map(Json)
+        .ok_or_else(|| watchlist_not_found(id))
+}
+
+/// Removing a movie that is not on the list changes nothing.
+#[utoipa::path(
+    delete,
+    path = "/watchlist/{id}/movie/{movie_id}",
+    tag = "watchlists",
+    summary = "Remove a movie from a watchlist",
+    operation_id = "remove_watchlist_movie",
+    params(
+        ("id" = u64, Path, description = "The id of the watchlist"),
+        ("movie_id" = String, Path, description = "The id of the movie"),
+    ),
+    responses(
+        (status = OK, description = "The watchlist", body = Watchlist),
+        (status = NOT_FOUND, description = "No such watchlist", body = ErrorBody),
+    )
+)]
+pub async fn remove_movie(
+    Path((id, movie_id)): Path<(u64, String)>,
+    State(state): State<AppState>,
+) -> Result<Json<Watchlist>, ApiError> {
+    state
+        .watchlists
+        .modify(id, |watchlist| {
+            watchlist.movie_ids.retain(|m| *m != movie_id)
+        })
+        .map(Json)
+        .ok_or_else(|| watchlist_not_found(id))
+}